@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use pepper::{
+    buffer::BufferContent,
+    buffer_position::{BufferPosition, BufferRange},
+};
+
+const LINE_COUNTS: &[usize] = &[10, 1_000, 100_000, 1_000_000];
+const EDIT_COUNT: usize = 1_000;
+
+// stand-in for the previous line storage where all lines lived in a single vec
+struct FlatLines {
+    lines: Vec<String>,
+}
+
+impl FlatLines {
+    fn new(line_count: usize) -> Self {
+        let mut lines = Vec::with_capacity(line_count);
+        for i in 0..line_count {
+            lines.push(line_text(i));
+        }
+        Self { lines }
+    }
+
+    fn insert_line(&mut self, line_index: usize) {
+        self.lines.insert(line_index, String::new());
+    }
+
+    fn delete_line(&mut self, line_index: usize) {
+        let removed = self.lines.remove(line_index);
+        self.lines[line_index - 1].push_str(&removed);
+    }
+}
+
+fn line_text(line_index: usize) -> String {
+    format!("line {} with some text in it", line_index)
+}
+
+fn buffer_content(line_count: usize) -> BufferContent {
+    let mut text = String::new();
+    for i in 0..line_count {
+        if i > 0 {
+            text.push('\n');
+        }
+        text.push_str(&line_text(i));
+    }
+
+    let mut content = BufferContent::new();
+    content.read(&mut text.as_bytes()).unwrap();
+    content
+}
+
+fn edit_line_indexes(line_count: usize) -> impl Iterator<Item = usize> {
+    let mut seed: usize = 0x9e37_79b9;
+    std::iter::repeat_with(move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        1 + seed % (line_count - 1)
+    })
+    .take(EDIT_COUNT)
+}
+
+fn measure<F>(f: F) -> Duration
+where
+    F: FnOnce(),
+{
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, line_count: usize, flat: Duration, chunked: Duration) {
+    println!(
+        "{:<16} {:>9} lines   flat: {:>10.3?}   chunked: {:>10.3?}",
+        name, line_count, flat, chunked,
+    );
+}
+
+fn main() {
+    for &line_count in LINE_COUNTS {
+        let mut flat = FlatLines::new(line_count);
+        let mut content = buffer_content(line_count);
+
+        let flat_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                flat.insert_line(line_index);
+            }
+        });
+        let chunked_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                let position = BufferPosition::line_col(line_index as _, 0);
+                content.insert_text(position, "\n");
+            }
+        });
+        report("insert line", line_count, flat_duration, chunked_duration);
+
+        let flat_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                flat.delete_line(line_index);
+            }
+        });
+        let chunked_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                let line_index = line_index as _;
+                let from = BufferPosition::line_col(line_index - 1, 0);
+                let from = BufferPosition::line_col(
+                    from.line_index,
+                    content.lines()[from.line_index as usize].as_str().len() as _,
+                );
+                let to = BufferPosition::line_col(line_index, 0);
+                content.delete_range(BufferRange::between(from, to));
+            }
+        });
+        report("delete line", line_count, flat_duration, chunked_duration);
+
+        let mut flat_total = 0;
+        let mut chunked_total = 0;
+        let flat_duration = measure(|| {
+            for line in &flat.lines {
+                flat_total += line.len();
+            }
+        });
+        let chunked_duration = measure(|| {
+            for line in content.lines() {
+                chunked_total += line.as_str().len();
+            }
+        });
+        assert_eq!(flat_total, chunked_total);
+        report("iterate lines", line_count, flat_duration, chunked_duration);
+
+        let flat_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                flat_total += flat.lines[line_index].len();
+            }
+        });
+        let chunked_duration = measure(|| {
+            for line_index in edit_line_indexes(line_count) {
+                chunked_total += content.lines()[line_index].as_str().len();
+            }
+        });
+        report("index lines", line_count, flat_duration, chunked_duration);
+    }
+}
@@ -1,34 +1,66 @@
 use std::{
+    cmp::Ordering,
     fmt,
-    fs::File,
-    io,
-    ops::{Add, Range, RangeBounds, Sub},
+    fs::{self, File},
+    io, iter,
+    ops::{Add, Bound, Index, Range, RangeBounds, Sub},
     path::{Component, Path, PathBuf},
     process::{Command, Stdio},
+    slice,
     str::CharIndices,
 };
 
 use crate::{
-    buffer_history::{BufferHistory, Edit, EditKind},
+    buffer_history::{
+        BufferHistory, Edit, EditKind, HistoryBranchTip, HistoryJump, HistoryJumpError,
+    },
     buffer_position::{BufferPosition, BufferPositionIndex, BufferRange},
+    config::{Config, Wrap},
     cursor::Cursor,
+    directory::DirectoryListing,
     editor_utils::{find_delimiter_pair_at, ResidualStrBytes},
     events::{
         BufferEditMutGuard, BufferRangeDeletesMutGuard, BufferTextInsertsMutGuard, EditorEvent,
         EditorEventTextInsert, EditorEventWriter,
     },
+    fold::Fold,
     help,
     pattern::Pattern,
     platform::{Platform, PlatformProcessHandle, PlatformRequest, PooledBuf, ProcessTag},
     plugin::PluginHandle,
-    syntax::{HighlightResult, HighlightedBuffer, SyntaxCollection, SyntaxHandle},
+    syntax::{
+        HighlightResult, HighlightedBuffer, SemanticTokensMutGuard, SyntaxCollection, SyntaxHandle,
+        TokenKind,
+    },
+    unicode::{WIDE_RANGES, ZERO_WIDTH_RANGES},
     word_database::{WordDatabase, WordIter, WordKind},
 };
 
-// TODO: parse unicode database and implement this
 // reference algorithm: https://www.cl.cam.ac.uk/~mgk25/ucs/wcwidth.c
-pub fn char_display_len(_: char) -> u8 {
-    1
+pub fn char_display_len(c: char) -> u8 {
+    fn is_in_ranges(c: char, ranges: &[(char, char)]) -> bool {
+        ranges
+            .binary_search_by(|&(from, to)| {
+                if to < c {
+                    Ordering::Less
+                } else if c < from {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    if c < '\u{300}' {
+        1
+    } else if is_in_ranges(c, ZERO_WIDTH_RANGES) {
+        0
+    } else if is_in_ranges(c, WIDE_RANGES) {
+        2
+    } else {
+        1
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -126,6 +158,169 @@ impl<'a> DoubleEndedIterator for CharDisplayDistances<'a> {
     }
 }
 
+// lays out the chars of a line in rows of `width` cells the same way they're drawn
+pub struct LineWrap {
+    wrap: Wrap,
+    width: usize,
+    tab_size: u8,
+    pub indent: usize,
+    pub row: usize,
+    pub x: usize,
+}
+impl LineWrap {
+    pub fn new(config: &Config, wrap: Wrap, width: usize, line: &str) -> Self {
+        let tab_size = config.tab_size.max(1);
+        let width = width.max(1);
+
+        // continuation rows start at the line's indentation if that leaves enough room
+        let mut indent = 0;
+        if config.wrap_indent && wrap != Wrap::None {
+            for c in line.chars() {
+                match c {
+                    ' ' => indent += 1,
+                    '\t' => indent += tab_size as usize,
+                    _ => break,
+                }
+            }
+            if indent > width / 2 {
+                indent = 0;
+            }
+        }
+
+        Self {
+            wrap,
+            width,
+            tab_size,
+            indent,
+            row: 0,
+            x: 0,
+        }
+    }
+
+    pub fn char_len(&self, c: char) -> usize {
+        match c {
+            '\t' => self.tab_size as _,
+            _ => char_display_len(c) as _,
+        }
+    }
+
+    pub fn new_row(&mut self) {
+        self.row += 1;
+        self.x = self.indent;
+    }
+
+    // places the char at `char_index` (the line end being a '\n' at `line.len()`)
+    // returns true if a new row was started before it
+    pub fn advance(&mut self, line: &str, char_index: usize, c: char) -> bool {
+        let len = self.char_len(c);
+        let row_start = if self.row == 0 { 0 } else { self.indent };
+        let wraps = self.x > row_start
+            && match self.wrap {
+                Wrap::None => false,
+                Wrap::Chars => self.x + len > self.width,
+                Wrap::Words => {
+                    let is_word_start = !c.is_whitespace()
+                        && line[..char_index]
+                            .chars()
+                            .next_back()
+                            .filter(|c| !c.is_whitespace())
+                            .is_none();
+                    let word_len = if is_word_start {
+                        line[char_index..]
+                            .chars()
+                            .take_while(|c| !c.is_whitespace())
+                            .map(|c| self.char_len(c))
+                            .sum()
+                    } else {
+                        len
+                    };
+                    // words that would not fit in a whole row are broken anywhere
+                    self.x + len > self.width
+                        || (self.x + word_len > self.width && self.indent + word_len <= self.width)
+                }
+            };
+        if wraps {
+            self.new_row();
+        }
+        self.x += len;
+        wraps
+    }
+
+    // places a char of an annotation which, not being part of any word, may wrap anywhere
+    // returns true if a new row was started before it
+    pub fn advance_annotation(&mut self, c: char) -> bool {
+        let len = char_display_len(c) as usize;
+        let row_start = if self.row == 0 { 0 } else { self.indent };
+        let wraps = self.wrap != Wrap::None && self.x > row_start && self.x + len > self.width;
+        if wraps {
+            self.new_row();
+        }
+        self.x += len;
+        wraps
+    }
+
+    // places the annotations drawn right before the char at `char_index`
+    // skipping over the ones from `line_annotations` that come before it
+    pub fn advance_annotations(
+        &mut self,
+        annotations: &BufferAnnotationCollection,
+        line_annotations: &mut &[BufferAnnotation],
+        char_index: usize,
+    ) {
+        while let Some((annotation, rest)) = line_annotations.split_first() {
+            let annotation_index = annotation.position.column_byte_index as usize;
+            if annotation_index > char_index {
+                break;
+            }
+            if annotation_index == char_index {
+                for c in annotation.text(annotations).chars() {
+                    self.advance_annotation(c);
+                }
+            }
+            *line_annotations = rest;
+        }
+    }
+
+    // how many rows the whole line takes (including its annotations)
+    pub fn line_height(
+        config: &Config,
+        wrap: Wrap,
+        width: usize,
+        line: &BufferLine,
+        line_index: BufferPositionIndex,
+        annotations: &BufferAnnotationCollection,
+    ) -> usize {
+        // most lines fit in a single row so they don't need to be laid out
+        if wrap == Wrap::None
+            || (line.display_len().total_len(config.tab_size.max(1)) < width
+                && annotations.line(line_index).is_empty())
+        {
+            return 1;
+        }
+        let line = line.as_str();
+        Self::new(config, wrap, width, line).row_at(line, line_index, annotations, line.len()) + 1
+    }
+
+    // the row where the char at `column_byte_index` is drawn
+    pub fn row_at(
+        mut self,
+        line: &str,
+        line_index: BufferPositionIndex,
+        annotations: &BufferAnnotationCollection,
+        column_byte_index: usize,
+    ) -> usize {
+        let mut line_annotations = annotations.line(line_index);
+        for (char_index, c) in line.char_indices().chain(iter::once((line.len(), '\n'))) {
+            self.advance_annotations(annotations, &mut line_annotations, char_index);
+            self.advance(line, char_index, c);
+            if char_index >= column_byte_index {
+                break;
+            }
+        }
+        self.row
+    }
+}
+
 pub struct WordRefWithIndex<'a> {
     pub kind: WordKind,
     pub text: &'a str,
@@ -155,10 +350,22 @@ impl<'a> WordRefWithPosition<'a> {
     }
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BufferLintSeverity {
+    #[default]
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
 pub struct BufferLint {
     pub message_range: Range<u32>,
     pub range: BufferRange,
+    pub severity: BufferLintSeverity,
     pub plugin_handle: PluginHandle,
+    // lets a plugin keep lints from several sources (like lsp servers) apart
+    pub source: u32,
 }
 impl BufferLint {
     pub fn message<'a>(&self, buffer_lints: &'a BufferLintCollection) -> &'a str {
@@ -197,6 +404,14 @@ impl BufferLintCollection {
     }
 
     pub fn mut_guard(&mut self, plugin_handle: PluginHandle) -> BufferLintCollectionMutGuard {
+        self.source_mut_guard(plugin_handle, 0)
+    }
+
+    pub fn source_mut_guard(
+        &mut self,
+        plugin_handle: PluginHandle,
+        source: u32,
+    ) -> BufferLintCollectionMutGuard<'_> {
         let min_messages_len = plugin_handle.0 as usize + 1;
         if self.plugin_messages.len() < min_messages_len {
             self.plugin_messages.resize(min_messages_len, String::new());
@@ -204,6 +419,7 @@ impl BufferLintCollection {
         BufferLintCollectionMutGuard {
             inner: self,
             plugin_handle,
+            source,
         }
     }
 }
@@ -211,18 +427,31 @@ impl BufferLintCollection {
 pub struct BufferLintCollectionMutGuard<'a> {
     inner: &'a mut BufferLintCollection,
     plugin_handle: PluginHandle,
+    source: u32,
 }
 impl<'a> BufferLintCollectionMutGuard<'a> {
     pub fn clear(&mut self) {
-        self.inner.plugin_messages[self.plugin_handle.0 as usize].clear();
-        for i in (0..self.inner.lints.len()).rev() {
-            if self.inner.lints[i].plugin_handle == self.plugin_handle {
-                self.inner.lints.swap_remove(i);
+        let plugin_handle = self.plugin_handle;
+        let source = self.source;
+        self.inner
+            .lints
+            .retain(|l| l.plugin_handle != plugin_handle || l.source != source);
+
+        // compact the messages left by the plugin's other sources
+        let plugin_messages = &mut self.inner.plugin_messages[plugin_handle.0 as usize];
+        let previous_messages = std::mem::take(plugin_messages);
+        for lint in &mut self.inner.lints {
+            if lint.plugin_handle == plugin_handle {
+                let message_range =
+                    lint.message_range.start as usize..lint.message_range.end as usize;
+                let message_start = plugin_messages.len() as _;
+                plugin_messages.push_str(&previous_messages[message_range]);
+                lint.message_range = message_start..plugin_messages.len() as _;
             }
         }
     }
 
-    pub fn add(&mut self, message: &str, range: BufferRange) {
+    pub fn add(&mut self, message: &str, range: BufferRange, severity: BufferLintSeverity) {
         let plugin_messages = &mut self.inner.plugin_messages[self.plugin_handle.0 as usize];
         let message_start = plugin_messages.len() as _;
         plugin_messages.push_str(message);
@@ -231,7 +460,9 @@ impl<'a> BufferLintCollectionMutGuard<'a> {
         self.inner.lints.push(BufferLint {
             message_range: message_start..message_end,
             range,
+            severity,
             plugin_handle: self.plugin_handle,
+            source: self.source,
         });
     }
 }
@@ -241,6 +472,335 @@ impl<'a> Drop for BufferLintCollectionMutGuard<'a> {
     }
 }
 
+// text that is not part of the buffer content but is drawn inside it (like inlay hints)
+pub struct BufferAnnotation {
+    pub text_range: Range<u32>,
+    pub position: BufferPosition,
+    pub plugin_handle: PluginHandle,
+}
+impl BufferAnnotation {
+    pub fn text<'a>(&self, buffer_annotations: &'a BufferAnnotationCollection) -> &'a str {
+        let text_range = self.text_range.start as usize..self.text_range.end as usize;
+        let plugin_texts = &buffer_annotations.plugin_texts[self.plugin_handle.0 as usize];
+        &plugin_texts[text_range]
+    }
+}
+
+#[derive(Default)]
+pub struct BufferAnnotationCollection {
+    annotations: Vec<BufferAnnotation>,
+    plugin_texts: Vec<String>,
+}
+impl BufferAnnotationCollection {
+    pub fn all(&self) -> &[BufferAnnotation] {
+        &self.annotations
+    }
+
+    pub fn line(&self, line_index: BufferPositionIndex) -> &[BufferAnnotation] {
+        let start = self
+            .annotations
+            .partition_point(|a| a.position.line_index < line_index);
+        let len = self.annotations[start..]
+            .iter()
+            .take_while(|a| a.position.line_index == line_index)
+            .count();
+        &self.annotations[start..start + len]
+    }
+
+    fn clear(&mut self) {
+        self.annotations.clear();
+    }
+
+    fn insert_range(&mut self, range: BufferRange) {
+        for annotation in &mut self.annotations {
+            annotation.position = annotation.position.insert(range);
+        }
+    }
+
+    fn delete_range(&mut self, range: BufferRange) {
+        for annotation in &mut self.annotations {
+            annotation.position = annotation.position.delete(range);
+        }
+    }
+
+    pub fn mut_guard(
+        &mut self,
+        plugin_handle: PluginHandle,
+    ) -> BufferAnnotationCollectionMutGuard<'_> {
+        let min_texts_len = plugin_handle.0 as usize + 1;
+        if self.plugin_texts.len() < min_texts_len {
+            self.plugin_texts.resize(min_texts_len, String::new());
+        }
+        BufferAnnotationCollectionMutGuard {
+            inner: self,
+            plugin_handle,
+        }
+    }
+}
+
+pub struct BufferAnnotationCollectionMutGuard<'a> {
+    inner: &'a mut BufferAnnotationCollection,
+    plugin_handle: PluginHandle,
+}
+impl<'a> BufferAnnotationCollectionMutGuard<'a> {
+    pub fn clear(&mut self) {
+        self.inner.plugin_texts[self.plugin_handle.0 as usize].clear();
+        for i in (0..self.inner.annotations.len()).rev() {
+            if self.inner.annotations[i].plugin_handle == self.plugin_handle {
+                self.inner.annotations.swap_remove(i);
+            }
+        }
+    }
+
+    pub fn add(&mut self, text: &str, position: BufferPosition) {
+        let plugin_texts = &mut self.inner.plugin_texts[self.plugin_handle.0 as usize];
+        let text_start = plugin_texts.len() as _;
+        plugin_texts.push_str(text);
+        let text_end = plugin_texts.len() as _;
+
+        self.inner.annotations.push(BufferAnnotation {
+            text_range: text_start..text_end,
+            position,
+            plugin_handle: self.plugin_handle,
+        });
+    }
+}
+impl<'a> Drop for BufferAnnotationCollectionMutGuard<'a> {
+    fn drop(&mut self) {
+        self.inner.annotations.sort_by_key(|a| a.position);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BufferRangeHighlight {
+    pub range: BufferRange,
+    pub plugin_handle: PluginHandle,
+}
+
+// ranges that plugins want to stand out (like every occurrence of the symbol under the cursor)
+#[derive(Default)]
+pub struct BufferRangeHighlightCollection {
+    highlights: Vec<BufferRangeHighlight>,
+}
+impl BufferRangeHighlightCollection {
+    pub fn all(&self) -> &[BufferRangeHighlight] {
+        &self.highlights
+    }
+
+    fn clear(&mut self) {
+        self.highlights.clear();
+    }
+
+    fn insert_range(&mut self, range: BufferRange) {
+        for highlight in &mut self.highlights {
+            highlight.range.from = highlight.range.from.insert(range);
+            highlight.range.to = highlight.range.to.insert(range);
+        }
+    }
+
+    fn delete_range(&mut self, range: BufferRange) {
+        for highlight in &mut self.highlights {
+            highlight.range.from = highlight.range.from.delete(range);
+            highlight.range.to = highlight.range.to.delete(range);
+        }
+    }
+
+    pub fn mut_guard(
+        &mut self,
+        plugin_handle: PluginHandle,
+    ) -> BufferRangeHighlightCollectionMutGuard<'_> {
+        BufferRangeHighlightCollectionMutGuard {
+            inner: self,
+            plugin_handle,
+        }
+    }
+}
+
+pub struct BufferRangeHighlightCollectionMutGuard<'a> {
+    inner: &'a mut BufferRangeHighlightCollection,
+    plugin_handle: PluginHandle,
+}
+impl<'a> BufferRangeHighlightCollectionMutGuard<'a> {
+    pub fn clear(&mut self) {
+        let plugin_handle = self.plugin_handle;
+        self.inner
+            .highlights
+            .retain(|h| h.plugin_handle != plugin_handle);
+    }
+
+    pub fn add(&mut self, range: BufferRange) {
+        self.inner.highlights.push(BufferRangeHighlight {
+            range,
+            plugin_handle: self.plugin_handle,
+        });
+    }
+}
+impl<'a> Drop for BufferRangeHighlightCollectionMutGuard<'a> {
+    fn drop(&mut self) {
+        self.inner.highlights.sort_by_key(|h| h.range.from);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BufferSign {
+    pub line_index: BufferPositionIndex,
+    pub sign: char,
+    pub token_kind: TokenKind,
+    pub plugin_handle: PluginHandle,
+}
+
+// single char marks that plugins draw in the gutter next to a line (like vcs diff markers)
+#[derive(Default)]
+pub struct BufferSignCollection {
+    signs: Vec<BufferSign>,
+}
+impl BufferSignCollection {
+    pub fn all(&self) -> &[BufferSign] {
+        &self.signs
+    }
+
+    fn clear(&mut self) {
+        self.signs.clear();
+    }
+
+    fn insert_range(&mut self, range: BufferRange) {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count == 0 {
+            return;
+        }
+
+        let from = range.from;
+        for sign in &mut self.signs {
+            if from.line_index < sign.line_index
+                || from.line_index == sign.line_index && from.column_byte_index == 0
+            {
+                sign.line_index += line_count;
+            }
+        }
+    }
+
+    fn delete_range(&mut self, range: BufferRange) {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count == 0 {
+            return;
+        }
+
+        self.signs.retain(|s| {
+            s.line_index <= range.from.line_index || range.to.line_index <= s.line_index
+        });
+        for sign in &mut self.signs {
+            if range.to.line_index <= sign.line_index {
+                sign.line_index -= line_count;
+            }
+        }
+    }
+
+    pub fn mut_guard(&mut self, plugin_handle: PluginHandle) -> BufferSignCollectionMutGuard<'_> {
+        BufferSignCollectionMutGuard {
+            inner: self,
+            plugin_handle,
+        }
+    }
+}
+
+pub struct BufferSignCollectionMutGuard<'a> {
+    inner: &'a mut BufferSignCollection,
+    plugin_handle: PluginHandle,
+}
+impl<'a> BufferSignCollectionMutGuard<'a> {
+    pub fn clear(&mut self) {
+        let plugin_handle = self.plugin_handle;
+        self.inner
+            .signs
+            .retain(|s| s.plugin_handle != plugin_handle);
+    }
+
+    pub fn add(&mut self, line_index: BufferPositionIndex, sign: char, token_kind: TokenKind) {
+        self.inner.signs.push(BufferSign {
+            line_index,
+            sign,
+            token_kind,
+            plugin_handle: self.plugin_handle,
+        });
+    }
+}
+impl<'a> Drop for BufferSignCollectionMutGuard<'a> {
+    fn drop(&mut self) {
+        self.inner.signs.sort_by_key(|s| s.line_index);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BufferFoldRange {
+    pub fold: Fold,
+    pub plugin_handle: PluginHandle,
+}
+
+// line ranges that plugins know can be folded (like lsp folding ranges)
+#[derive(Default)]
+pub struct BufferFoldRangeCollection {
+    ranges: Vec<BufferFoldRange>,
+}
+impl BufferFoldRangeCollection {
+    pub fn all(&self) -> &[BufferFoldRange] {
+        &self.ranges
+    }
+
+    fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    fn insert_range(&mut self, range: BufferRange) {
+        for fold_range in &mut self.ranges {
+            fold_range.fold.insert_range(range);
+        }
+    }
+
+    fn delete_range(&mut self, range: BufferRange) {
+        self.ranges.retain_mut(|r| r.fold.delete_range(range));
+    }
+
+    pub fn mut_guard(
+        &mut self,
+        plugin_handle: PluginHandle,
+    ) -> BufferFoldRangeCollectionMutGuard<'_> {
+        BufferFoldRangeCollectionMutGuard {
+            inner: self,
+            plugin_handle,
+        }
+    }
+}
+
+pub struct BufferFoldRangeCollectionMutGuard<'a> {
+    inner: &'a mut BufferFoldRangeCollection,
+    plugin_handle: PluginHandle,
+}
+impl<'a> BufferFoldRangeCollectionMutGuard<'a> {
+    pub fn clear(&mut self) {
+        let plugin_handle = self.plugin_handle;
+        self.inner
+            .ranges
+            .retain(|r| r.plugin_handle != plugin_handle);
+    }
+
+    // ranges that would not hide any line are ignored
+    pub fn add(
+        &mut self,
+        start_line_index: BufferPositionIndex,
+        end_line_index: BufferPositionIndex,
+    ) {
+        if start_line_index < end_line_index {
+            self.inner.ranges.push(BufferFoldRange {
+                fold: Fold {
+                    start_line_index,
+                    end_line_index,
+                },
+                plugin_handle: self.plugin_handle,
+            });
+        }
+    }
+}
+
 #[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct BufferBreakpointId(pub u32);
 
@@ -478,7 +1038,9 @@ impl BufferLinePool {
     pub fn acquire(&mut self) -> BufferLine {
         match self.pool.pop() {
             Some(mut line) => {
-                line.0.clear();
+                line.text.clear();
+                line.display_len = DisplayLen::zero();
+                line.ending = None;
                 line
             }
             None => BufferLine::new(),
@@ -490,15 +1052,28 @@ impl BufferLinePool {
     }
 }
 
-pub struct BufferLine(String);
+pub struct BufferLine {
+    text: String,
+    display_len: DisplayLen,
+    // the ending it was read with. `None` for lines created while editing and for the last line
+    ending: Option<BufferLineEnding>,
+}
 
 impl BufferLine {
     fn new() -> Self {
-        Self(String::new())
+        Self {
+            text: String::new(),
+            display_len: DisplayLen::zero(),
+            ending: None,
+        }
     }
 
     pub fn as_str(&self) -> &str {
-        &self.0
+        &self.text
+    }
+
+    pub fn display_len(&self) -> DisplayLen {
+        self.display_len
     }
 
     pub fn chars_from(
@@ -508,7 +1083,7 @@ impl BufferLine {
         impl '_ + Iterator<Item = (usize, char)>,
         impl '_ + Iterator<Item = (usize, char)>,
     ) {
-        let (left, right) = self.0.split_at(index);
+        let (left, right) = self.text.split_at(index);
         let left_chars = left.char_indices().rev();
         let right_chars = right.char_indices().map(move |(i, c)| (index + i, c));
         (left_chars, right_chars)
@@ -526,8 +1101,8 @@ impl BufferLine {
         let mid_start_index = mid_word.index;
         let mid_end_index = mid_start_index + mid_word.text.len();
 
-        let left = &self.0[..mid_start_index];
-        let right = &self.0[mid_end_index..];
+        let left = &self.text[..mid_start_index];
+        let right = &self.text[mid_end_index..];
 
         let mut left_column_index = mid_start_index;
         let left_words = WordIter(left).rev().map(move |w| {
@@ -554,7 +1129,7 @@ impl BufferLine {
     }
 
     pub fn word_at(&self, index: usize) -> WordRefWithIndex {
-        let (before, after) = self.0.split_at(index);
+        let (before, after) = self.text.split_at(index);
         match WordIter(after).next() {
             Some(right) => match WordIter(before).next_back() {
                 Some(left) => {
@@ -563,7 +1138,7 @@ impl BufferLine {
                         let index = index - left.text.len();
                         WordRefWithIndex {
                             kind: left.kind,
-                            text: &self.0[index..end_index],
+                            text: &self.text[index..end_index],
                             index,
                         }
                     } else {
@@ -588,51 +1163,352 @@ impl BufferLine {
         }
     }
 
-    pub fn split_off(
-        &mut self,
-        self_display_len: &mut DisplayLen,
-        other: &mut BufferLine,
-        other_display_len: &mut DisplayLen,
-        index: usize,
-    ) {
-        other.0.clear();
-        other.0.push_str(&self.0[index..]);
+    // the line break that is inserted is new so the ending goes with the text after it
+    fn split_off(&mut self, other: &mut BufferLine, index: usize) {
+        other.text.clear();
+        other.text.push_str(&self.text[index..]);
+        other.ending = self.ending.take();
 
-        if index < other.0.len() {
-            let display_len = DisplayLen::from(&self.0[..index]);
-            *other_display_len = *self_display_len - display_len;
-            *self_display_len = display_len;
+        if index < other.text.len() {
+            let display_len = DisplayLen::from(&self.text[..index]);
+            other.display_len = self.display_len - display_len;
+            self.display_len = display_len;
         } else {
-            *other_display_len = DisplayLen::from(&other.0[..]);
-            *self_display_len = *self_display_len - *other_display_len;
+            other.display_len = DisplayLen::from(&other.text[..]);
+            self.display_len = self.display_len - other.display_len;
         }
 
-        self.0.truncate(index);
+        self.text.truncate(index);
     }
 
-    pub fn insert_text(&mut self, display_len: &mut DisplayLen, index: usize, text: &str) {
-        self.0.insert_str(index, text);
-        *display_len = *display_len + DisplayLen::from(text);
+    fn insert_text(&mut self, index: usize, text: &str) {
+        self.text.insert_str(index, text);
+        self.display_len = self.display_len + DisplayLen::from(text);
     }
 
-    pub fn push_text(&mut self, display_len: &mut DisplayLen, text: &str) {
+    fn push_text(&mut self, text: &str) {
         let text = match text.strip_suffix('\r') {
             Some(text) => text,
             None => text,
         };
-        self.0.push_str(text);
-        *display_len = *display_len + DisplayLen::from(text);
+        self.text.push_str(text);
+        self.display_len = self.display_len + DisplayLen::from(text);
     }
 
-    pub fn delete_range<R>(&mut self, display_len: &mut DisplayLen, range: R)
+    fn delete_range<R>(&mut self, range: R)
     where
         R: RangeBounds<usize>,
     {
-        let deleted = self.0.drain(range);
-        *display_len = *display_len - DisplayLen::from(deleted.as_str());
+        let deleted = self.text.drain(range);
+        self.display_len = self.display_len - DisplayLen::from(deleted.as_str());
     }
 }
 
+const MAX_LINES_PER_CHUNK: usize = 1024;
+const MIN_LINES_PER_CHUNK: usize = MAX_LINES_PER_CHUNK / 4;
+
+// lines are stored in chunks so that inserting or removing lines
+// only shifts the lines of a single chunk instead of the whole buffer
+pub struct BufferLines {
+    chunks: Vec<Vec<BufferLine>>,
+    chunk_start_indexes: Vec<usize>,
+    len: usize,
+}
+
+impl BufferLines {
+    const fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            chunk_start_indexes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> BufferLinesIter<'_> {
+        self.range(..)
+    }
+
+    pub fn range<R>(&self, range: R) -> BufferLinesIter<'_>
+    where
+        R: RangeBounds<usize>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(&i) => i,
+            Bound::Excluded(&i) => i + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&i) => i + 1,
+            Bound::Excluded(&i) => i,
+            Bound::Unbounded => self.len,
+        };
+        assert!(start <= end && end <= self.len);
+
+        if start == end {
+            return BufferLinesIter {
+                chunks: [].iter(),
+                front: [].iter(),
+                back: [].iter(),
+                len: 0,
+            };
+        }
+
+        let (start_chunk_index, start_index) = self.chunk_position(start);
+        let (end_chunk_index, end_index) = self.chunk_position(end - 1);
+        let end_index = end_index + 1;
+
+        let (front, chunks, back) = if start_chunk_index == end_chunk_index {
+            let front = &self.chunks[start_chunk_index][start_index..end_index];
+            (
+                front,
+                &self.chunks[..0],
+                &self.chunks[start_chunk_index][..0],
+            )
+        } else {
+            let front = &self.chunks[start_chunk_index][start_index..];
+            let chunks = &self.chunks[start_chunk_index + 1..end_chunk_index];
+            let back = &self.chunks[end_chunk_index][..end_index];
+            (front, chunks, back)
+        };
+
+        BufferLinesIter {
+            chunks: chunks.iter(),
+            front: front.iter(),
+            back: back.iter(),
+            len: end - start,
+        }
+    }
+
+    fn chunk_position(&self, index: usize) -> (usize, usize) {
+        let chunk_index = self
+            .chunk_start_indexes
+            .partition_point(|&i| i <= index)
+            .saturating_sub(1);
+        (chunk_index, index - self.chunk_start_indexes[chunk_index])
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut BufferLine {
+        let (chunk_index, index) = self.chunk_position(index);
+        &mut self.chunks[chunk_index][index]
+    }
+
+    fn shift_chunk_start_indexes(&mut self, from_chunk_index: usize) {
+        let mut start_index = match from_chunk_index.checked_sub(1) {
+            Some(i) => self.chunk_start_indexes[i] + self.chunks[i].len(),
+            None => 0,
+        };
+        for (chunk, chunk_start_index) in self.chunks[from_chunk_index..]
+            .iter()
+            .zip(&mut self.chunk_start_indexes[from_chunk_index..])
+        {
+            *chunk_start_index = start_index;
+            start_index += chunk.len();
+        }
+    }
+
+    fn push(&mut self, line: BufferLine) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < MAX_LINES_PER_CHUNK => chunk.push(line),
+            _ => {
+                self.chunks.push(vec![line]);
+                self.chunk_start_indexes.push(self.len);
+            }
+        }
+        self.len += 1;
+    }
+
+    fn insert(&mut self, index: usize, line: BufferLine) {
+        self.insert_many(index, iter::once(line));
+    }
+
+    fn insert_many<I>(&mut self, index: usize, lines: I)
+    where
+        I: Iterator<Item = BufferLine>,
+    {
+        if index == self.len {
+            for line in lines {
+                self.push(line);
+            }
+            return;
+        }
+
+        let (chunk_index, index) = self.chunk_position(index);
+        let chunk = &mut self.chunks[chunk_index];
+        let previous_chunk_len = chunk.len();
+        chunk.splice(index..index, lines);
+        self.len += chunk.len() - previous_chunk_len;
+
+        if chunk.len() > MAX_LINES_PER_CHUNK {
+            let split_len = MAX_LINES_PER_CHUNK / 2;
+            let mut split_lines = chunk.split_off(split_len).into_iter();
+            let mut split_chunk_index = chunk_index + 1;
+            loop {
+                let split_chunk: Vec<_> = split_lines.by_ref().take(split_len).collect();
+                if split_chunk.is_empty() {
+                    break;
+                }
+                self.chunks.insert(split_chunk_index, split_chunk);
+                self.chunk_start_indexes.insert(split_chunk_index, 0);
+                split_chunk_index += 1;
+            }
+        }
+        self.shift_chunk_start_indexes(chunk_index + 1);
+    }
+
+    fn remove(&mut self, index: usize) -> BufferLine {
+        let (chunk_index, index) = self.chunk_position(index);
+        let line = self.chunks[chunk_index].remove(index);
+        self.len -= 1;
+
+        if self.chunks[chunk_index].is_empty() {
+            self.chunks.remove(chunk_index);
+            self.chunk_start_indexes.remove(chunk_index);
+        } else {
+            self.merge_small_chunk(chunk_index);
+        }
+        self.shift_chunk_start_indexes(chunk_index);
+        line
+    }
+
+    fn remove_range(&mut self, range: Range<usize>, pool: &mut BufferLinePool) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let (mut chunk_index, mut index) = self.chunk_position(range.start);
+        let first_chunk_index = chunk_index;
+        let mut remove_count = range.end - range.start;
+        self.len -= remove_count;
+
+        while remove_count > 0 {
+            let chunk = &mut self.chunks[chunk_index];
+            let chunk_remove_count = remove_count.min(chunk.len() - index);
+            for line in chunk.drain(index..index + chunk_remove_count) {
+                pool.release(line);
+            }
+            remove_count -= chunk_remove_count;
+
+            if chunk.is_empty() {
+                self.chunks.remove(chunk_index);
+                self.chunk_start_indexes.remove(chunk_index);
+            } else {
+                chunk_index += 1;
+            }
+            index = 0;
+        }
+
+        // only the chunks where the range started and ended may have been left partially removed
+        self.merge_small_chunk(first_chunk_index + 1);
+        self.merge_small_chunk(first_chunk_index);
+        self.shift_chunk_start_indexes(first_chunk_index);
+    }
+
+    // merges a chunk that became too small into one of its neighbors
+    // so that removing lines does not leave lots of tiny chunks behind.
+    // chunk start indexes are left for the caller to shift
+    fn merge_small_chunk(&mut self, chunk_index: usize) {
+        let len = match self.chunks.get(chunk_index) {
+            Some(chunk) if chunk.len() < MIN_LINES_PER_CHUNK => chunk.len(),
+            _ => return,
+        };
+
+        let fits = |chunk: &Vec<BufferLine>| chunk.len() + len <= MAX_LINES_PER_CHUNK;
+        let next_index = chunk_index + 1;
+        if matches!(self.chunks.get(next_index), Some(chunk) if fits(chunk)) {
+            let next_chunk = self.chunks.remove(next_index);
+            self.chunk_start_indexes.remove(next_index);
+            self.chunks[chunk_index].extend(next_chunk);
+        } else if chunk_index > 0 && fits(&self.chunks[chunk_index - 1]) {
+            let chunk = self.chunks.remove(chunk_index);
+            self.chunk_start_indexes.remove(chunk_index);
+            self.chunks[chunk_index - 1].extend(chunk);
+        }
+    }
+
+    fn clear(&mut self, pool: &mut BufferLinePool) {
+        for chunk in self.chunks.drain(..) {
+            for line in chunk {
+                pool.release(line);
+            }
+        }
+        self.chunk_start_indexes.clear();
+        self.len = 0;
+    }
+}
+
+impl Index<usize> for BufferLines {
+    type Output = BufferLine;
+    fn index(&self, index: usize) -> &Self::Output {
+        let (chunk_index, index) = self.chunk_position(index);
+        &self.chunks[chunk_index][index]
+    }
+}
+
+impl<'a> IntoIterator for &'a BufferLines {
+    type Item = &'a BufferLine;
+    type IntoIter = BufferLinesIter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct BufferLinesIter<'a> {
+    chunks: slice::Iter<'a, Vec<BufferLine>>,
+    front: slice::Iter<'a, BufferLine>,
+    back: slice::Iter<'a, BufferLine>,
+    len: usize,
+}
+impl<'a> Iterator for BufferLinesIter<'a> {
+    type Item = &'a BufferLine;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.front.next() {
+                self.len -= 1;
+                return Some(line);
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.front = chunk.iter(),
+                None => {
+                    let line = self.back.next()?;
+                    self.len -= 1;
+                    return Some(line);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+impl<'a> DoubleEndedIterator for BufferLinesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.back.next_back() {
+                self.len -= 1;
+                return Some(line);
+            }
+            match self.chunks.next_back() {
+                Some(chunk) => self.back = chunk.iter(),
+                None => {
+                    let line = self.front.next_back()?;
+                    self.len -= 1;
+                    return Some(line);
+                }
+            }
+        }
+    }
+}
+impl<'a> ExactSizeIterator for BufferLinesIter<'a> {}
+
 pub struct TextRangeIter<'a> {
     content: &'a BufferContent,
     from: BufferPosition,
@@ -665,28 +1541,24 @@ impl<'a> Iterator for TextRangeIter<'a> {
 }
 
 pub struct BufferContent {
-    lines: Vec<BufferLine>,
-    line_display_lens: Vec<DisplayLen>,
+    lines: BufferLines,
     line_pool: BufferLinePool,
 }
 
 impl BufferContent {
     pub fn new() -> Self {
+        let mut lines = BufferLines::new();
+        lines.push(BufferLine::new());
         Self {
-            lines: vec![BufferLine::new()],
-            line_display_lens: vec![DisplayLen::zero()],
+            lines,
             line_pool: BufferLinePool::new(),
         }
     }
 
-    pub fn lines(&self) -> &[BufferLine] {
+    pub fn lines(&self) -> &BufferLines {
         &self.lines
     }
 
-    pub fn line_display_lens(&self) -> &[DisplayLen] {
-        &self.line_display_lens
-    }
-
     pub fn end(&self) -> BufferPosition {
         let last_line_index = self.lines.len() - 1;
         BufferPosition::line_col(
@@ -695,20 +1567,19 @@ impl BufferContent {
         )
     }
 
-    pub fn read(&mut self, read: &mut dyn io::BufRead) -> io::Result<()> {
-        for line in self.lines.drain(..) {
-            self.line_pool.release(line);
-        }
-        self.line_display_lens.clear();
+    // reads everything up front as the chunked lines only make editing large buffers faster
+    pub fn read(&mut self, read: &mut dyn io::BufRead) -> io::Result<BufferFileFormat> {
+        self.lines.clear(&mut self.line_pool);
 
+        let mut lf_count = 0;
+        let mut crlf_count = 0;
         let mut push_empty = true;
         loop {
             let mut line = self.line_pool.acquire();
-            match read.read_line(&mut line.0) {
+            match read.read_line(&mut line.text) {
                 Ok(0) => {
                     if push_empty {
                         self.lines.push(line);
-                        self.line_display_lens.push(DisplayLen::zero());
                     } else {
                         self.line_pool.release(line);
                     }
@@ -717,51 +1588,94 @@ impl BufferContent {
                 Ok(_) => {
                     push_empty = false;
 
-                    if line.0.ends_with('\n') {
+                    if line.text.ends_with('\n') {
                         push_empty = true;
-                        line.0.pop();
+                        line.text.pop();
+                        if line.text.ends_with('\r') {
+                            crlf_count += 1;
+                            line.ending = Some(BufferLineEnding::CrLf);
+                        } else {
+                            lf_count += 1;
+                            line.ending = Some(BufferLineEnding::Lf);
+                        }
                     }
-                    if line.0.ends_with('\r') {
-                        line.0.pop();
+                    if line.text.ends_with('\r') {
+                        line.text.pop();
                     }
-                    let display_len = DisplayLen::from(&line.0[..]);
+                    line.display_len = DisplayLen::from(&line.text[..]);
 
                     self.lines.push(line);
-                    self.line_display_lens.push(display_len);
                 }
                 Err(e) => {
-                    for line in self.lines.drain(..) {
-                        self.line_pool.release(line);
-                    }
+                    self.lines.clear(&mut self.line_pool);
                     self.lines.push(self.line_pool.acquire());
-                    self.line_display_lens.clear();
-                    self.line_display_lens.push(DisplayLen::zero());
                     return Err(e);
                 }
             }
         }
 
-        let byte_order_mark = b"\xef\xbb\xbf";
-        if self.lines[0]
-            .as_str()
-            .as_bytes()
-            .starts_with(byte_order_mark)
-        {
-            self.lines[0].delete_range(&mut self.line_display_lens[0], ..byte_order_mark.len());
+        let mut format = BufferFileFormat::default();
+        if lf_count < crlf_count {
+            format.line_ending = BufferLineEnding::CrLf;
         }
 
-        Ok(())
+        let first_line = self.lines.get_mut(0);
+        if first_line.as_str().as_bytes().starts_with(BYTE_ORDER_MARK) {
+            first_line.delete_range(..BYTE_ORDER_MARK.len());
+            format.byte_order_mark = true;
+        }
+
+        Ok(format)
     }
 
-    pub fn write(&self, write: &mut dyn io::Write) -> io::Result<()> {
+    // when `keep_line_endings` is true, lines are written with the ending they were read with
+    // so that unedited lines stay the same even in files with mixed line endings
+    // otherwise, or for lines created while editing, `format.line_ending` is used
+    pub fn write(
+        &self,
+        write: &mut dyn io::Write,
+        format: BufferFileFormat,
+        keep_line_endings: bool,
+    ) -> io::Result<()> {
+        if format.byte_order_mark {
+            write.write_all(BYTE_ORDER_MARK)?;
+        }
         let end_index = self.lines.len() - 1;
-        for line in &self.lines[..end_index] {
-            write!(write, "{}\n", line.as_str())?;
+        for line in self.lines.range(..end_index) {
+            let line_ending = match line.ending {
+                Some(ending) if keep_line_endings => ending,
+                _ => format.line_ending,
+            };
+            write!(write, "{}{}", line.as_str(), line_ending.as_str())?;
         }
         write!(write, "{}", self.lines[end_index].as_str())?;
         Ok(())
     }
 
+    pub fn has_final_newline(&self) -> bool {
+        self.lines.len() > 1 && self.lines[self.lines.len() - 1].as_str().is_empty()
+    }
+
+    // fnv-1a over the lines joined by '\n' so it does not depend on the file format
+    pub fn content_hash(&self) -> u64 {
+        fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64 {
+            for &b in bytes {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+            hash
+        }
+
+        let mut hash = 0xcbf2_9ce4_8422_2325;
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                hash = hash_bytes(hash, b"\n");
+            }
+            hash = hash_bytes(hash, line.as_str().as_bytes());
+        }
+        hash
+    }
+
     pub fn saturate_position(&self, mut position: BufferPosition) -> BufferPosition {
         position.line_index = position.line_index.min((self.lines.len() - 1) as _);
         let line = self.lines[position.line_index as usize].as_str();
@@ -796,11 +1710,10 @@ impl BufferContent {
 
     pub fn insert_text(&mut self, position: BufferPosition, text: &str) -> BufferRange {
         if !text.contains(&['\n', '\r']) {
-            let line = &mut self.lines[position.line_index as usize];
-            let display_len = &mut self.line_display_lens[position.line_index as usize];
+            let line = self.lines.get_mut(position.line_index as _);
 
             let previous_len = line.as_str().len();
-            line.insert_text(display_len, position.column_byte_index as _, text);
+            line.insert_text(position.column_byte_index as _, text);
             let len_diff = line.as_str().len() - previous_len;
 
             let end_position = BufferPosition::line_col(
@@ -810,51 +1723,40 @@ impl BufferContent {
             BufferRange::between(position, end_position)
         } else {
             let mut split_line = self.line_pool.acquire();
-            let mut split_display_len = DisplayLen::zero();
 
-            let position_line = &mut self.lines[position.line_index as usize];
-            let position_display_len = &mut self.line_display_lens[position.line_index as usize];
+            let position_line = self.lines.get_mut(position.line_index as _);
+            position_line.split_off(&mut split_line, position.column_byte_index as _);
 
-            position_line.split_off(
-                position_display_len,
-                &mut split_line,
-                &mut split_display_len,
-                position.column_byte_index as _,
-            );
-
-            let mut line_count = 0 as BufferPositionIndex;
             let mut lines = text.lines();
             if let Some(line) = lines.next() {
-                position_line.push_text(position_display_len, line);
+                position_line.push_text(line);
             }
-            for line_text in lines {
-                line_count += 1;
-
-                let mut line = self.line_pool.acquire();
-                let mut display_len = DisplayLen::zero();
-                line.push_text(&mut display_len, line_text);
 
-                let insert_index = (position.line_index + line_count) as _;
-                self.lines.insert(insert_index, line);
-                self.line_display_lens.insert(insert_index, display_len);
-            }
+            let previous_lines_len = self.lines.len();
+            let line_pool = &mut self.line_pool;
+            self.lines.insert_many(
+                position.line_index as usize + 1,
+                lines.map(|line_text| {
+                    let mut line = line_pool.acquire();
+                    line.push_text(line_text);
+                    line
+                }),
+            );
+            let mut line_count = (self.lines.len() - previous_lines_len) as BufferPositionIndex;
 
             let end_position = if text.ends_with('\n') {
                 line_count += 1;
 
                 let insert_index = (position.line_index + line_count) as _;
                 self.lines.insert(insert_index, split_line);
-                self.line_display_lens
-                    .insert(insert_index, split_display_len);
 
                 BufferPosition::line_col(position.line_index + line_count, 0)
             } else {
-                let index = (position.line_index + line_count) as usize;
-                let line = &mut self.lines[index];
-                let display_len = &mut self.line_display_lens[index];
+                let line = self.lines.get_mut((position.line_index + line_count) as _);
 
                 let column_byte_index = line.as_str().len() as _;
-                line.push_text(display_len, split_line.as_str());
+                line.push_text(split_line.as_str());
+                line.ending = split_line.ending;
 
                 self.line_pool.release(split_line);
                 BufferPosition::line_col(position.line_index + line_count, column_byte_index)
@@ -869,49 +1771,31 @@ impl BufferContent {
         let to = range.to;
 
         if from.line_index == to.line_index {
-            let line = &mut self.lines[from.line_index as usize];
-            let display_len = &mut self.line_display_lens[from.line_index as usize];
-
-            line.delete_range(
-                display_len,
-                from.column_byte_index as usize..to.column_byte_index as usize,
-            );
+            let line = self.lines.get_mut(from.line_index as _);
+            line.delete_range(from.column_byte_index as usize..to.column_byte_index as usize);
         } else {
-            let from_line = &mut self.lines[from.line_index as usize];
-            let from_display_len = &mut self.line_display_lens[from.line_index as usize];
-            from_line.delete_range(from_display_len, from.column_byte_index as usize..);
+            let from_line = self.lines.get_mut(from.line_index as _);
+            from_line.delete_range(from.column_byte_index as usize..);
 
             let lines_range = (from.line_index as usize + 1)..to.line_index as usize;
-            if lines_range.start < lines_range.end {
-                for line in self.lines.drain(lines_range.clone()) {
-                    self.line_pool.release(line);
-                }
-                self.line_display_lens.drain(lines_range);
-            }
+            self.lines.remove_range(lines_range, &mut self.line_pool);
 
             let to_line_index = from.line_index as usize + 1;
             if to_line_index < self.lines.len() {
                 let to_line = self.lines.remove(to_line_index);
-                self.line_display_lens.remove(to_line_index);
 
-                let from_line = &mut self.lines[from.line_index as usize];
-                let from_display_len = &mut self.line_display_lens[from.line_index as usize];
+                let from_line = self.lines.get_mut(from.line_index as _);
+                from_line.push_text(&to_line.as_str()[to.column_byte_index as usize..]);
+                from_line.ending = to_line.ending;
 
-                from_line.push_text(
-                    from_display_len,
-                    &to_line.as_str()[to.column_byte_index as usize..],
-                );
+                self.line_pool.release(to_line);
             }
         }
     }
 
     pub fn clear(&mut self) {
-        for line in self.lines.drain(..) {
-            self.line_pool.release(line);
-        }
+        self.lines.clear(&mut self.line_pool);
         self.lines.push(self.line_pool.acquire());
-        self.line_display_lens.clear();
-        self.line_display_lens.push(DisplayLen::zero());
     }
 
     pub fn words_from(
@@ -1072,7 +1956,7 @@ impl BufferContent {
 impl fmt::Display for BufferContent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let end_index = self.lines.len() - 1;
-        for line in &self.lines[..end_index] {
+        for line in self.lines.range(..end_index) {
             f.write_str(line.as_str())?;
             f.write_str("\n")?;
         }
@@ -1080,9 +1964,40 @@ impl fmt::Display for BufferContent {
     }
 }
 
+static BYTE_ORDER_MARK: &[u8] = b"\xef\xbb\xbf";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BufferLineEnding {
+    Lf,
+    CrLf,
+}
+impl BufferLineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct BufferFileFormat {
+    pub line_ending: BufferLineEnding,
+    pub byte_order_mark: bool,
+}
+impl Default for BufferFileFormat {
+    fn default() -> Self {
+        Self {
+            line_ending: BufferLineEnding::Lf,
+            byte_order_mark: false,
+        }
+    }
+}
+
 pub enum BufferReadError {
     FileNotFound,
     InvalidData,
+    InvalidHistoryFile,
     Other,
 }
 impl fmt::Display for BufferReadError {
@@ -1090,6 +2005,7 @@ impl fmt::Display for BufferReadError {
         match self {
             Self::FileNotFound => f.write_str("file not found"),
             Self::InvalidData => f.write_str("invalid data while reading from file"),
+            Self::InvalidHistoryFile => f.write_str("discarded stale or invalid undo history file"),
             Self::Other => f.write_str("could not read from file"),
         }
     }
@@ -1107,18 +2023,25 @@ impl From<io::Error> for BufferReadError {
 pub enum BufferWriteError {
     SavingDisabled,
     CouldNotWriteToFile,
+    DirectoryNotEmpty,
 }
 impl fmt::Display for BufferWriteError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::SavingDisabled => f.write_str("buffer has saving disabled"),
             Self::CouldNotWriteToFile => f.write_str("could not write to file"),
+            Self::DirectoryNotEmpty => {
+                f.write_str("directory to delete is not empty (save with '!' to delete it anyway)")
+            }
         }
     }
 }
 impl From<io::Error> for BufferWriteError {
-    fn from(_: io::Error) -> Self {
-        Self::CouldNotWriteToFile
+    fn from(other: io::Error) -> Self {
+        match other.kind() {
+            io::ErrorKind::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            _ => Self::CouldNotWriteToFile,
+        }
     }
 }
 
@@ -1128,6 +2051,11 @@ pub struct BufferProperties {
     pub saving_enabled: bool,
     pub file_backed_enabled: bool,
     pub word_database_enabled: bool,
+    // when `None`, whatever was detected when reading the file is written back
+    pub line_ending: Option<BufferLineEnding>,
+    pub byte_order_mark: Option<bool>,
+    // when `None`, the `wrap` config is used
+    pub wrap: Option<Wrap>,
 }
 impl BufferProperties {
     pub fn text() -> Self {
@@ -1136,6 +2064,9 @@ impl BufferProperties {
             saving_enabled: true,
             file_backed_enabled: true,
             word_database_enabled: true,
+            line_ending: None,
+            byte_order_mark: None,
+            wrap: None,
         }
     }
 
@@ -1145,6 +2076,9 @@ impl BufferProperties {
             saving_enabled: false,
             file_backed_enabled: false,
             word_database_enabled: false,
+            line_ending: None,
+            byte_order_mark: None,
+            wrap: None,
         }
     }
 
@@ -1154,6 +2088,9 @@ impl BufferProperties {
             saving_enabled: false,
             file_backed_enabled: true,
             word_database_enabled: false,
+            line_ending: None,
+            byte_order_mark: None,
+            wrap: None,
         }
     }
 
@@ -1163,6 +2100,9 @@ impl BufferProperties {
             saving_enabled: false,
             file_backed_enabled: false,
             word_database_enabled: false,
+            line_ending: None,
+            byte_order_mark: None,
+            wrap: None,
         }
     }
 }
@@ -1182,9 +2122,16 @@ pub struct Buffer {
     highlighted: HighlightedBuffer,
     history: BufferHistory,
     pub lints: BufferLintCollection,
+    pub annotations: BufferAnnotationCollection,
+    pub range_highlights: BufferRangeHighlightCollection,
+    pub signs: BufferSignCollection,
+    pub fold_ranges: BufferFoldRangeCollection,
     breakpoints: BufferBreakpointCollection,
     search_ranges: Vec<BufferRange>,
     needs_save: bool,
+    file_format: BufferFileFormat,
+    // `Some` when the buffer lists the entries of the directory at its path
+    directory: Option<DirectoryListing>,
     pub properties: BufferProperties,
 }
 
@@ -1199,9 +2146,15 @@ impl Buffer {
             highlighted: HighlightedBuffer::new(),
             history: BufferHistory::new(),
             lints: BufferLintCollection::default(),
+            annotations: BufferAnnotationCollection::default(),
+            range_highlights: BufferRangeHighlightCollection::default(),
+            signs: BufferSignCollection::default(),
+            fold_ranges: BufferFoldRangeCollection::default(),
             breakpoints: BufferBreakpointCollection::default(),
             search_ranges: Vec::new(),
             needs_save: false,
+            file_format: BufferFileFormat::default(),
+            directory: None,
             properties: BufferProperties::default(),
         }
     }
@@ -1216,15 +2169,21 @@ impl Buffer {
         self.syntax_handle = SyntaxHandle::default();
         self.history.clear();
         self.lints.clear();
+        self.annotations.clear();
+        self.range_highlights.clear();
+        self.signs.clear();
+        self.fold_ranges.clear();
         self.breakpoints.clear();
         self.search_ranges.clear();
         self.needs_save = false;
+        self.file_format = BufferFileFormat::default();
+        self.directory = None;
         self.properties = BufferProperties::default();
     }
 
     fn remove_all_words_from_database(&mut self, word_database: &mut WordDatabase) {
         if self.properties.word_database_enabled {
-            for line in &self.content.lines {
+            for line in self.content.lines() {
                 for word in WordIter(line.as_str()).of_kind(WordKind::Identifier) {
                     word_database.remove(word);
                 }
@@ -1250,13 +2209,24 @@ impl Buffer {
         &self.content
     }
 
+    pub fn syntax_handle(&self) -> SyntaxHandle {
+        self.syntax_handle
+    }
+
     pub fn highlighted(&self) -> &HighlightedBuffer {
         &self.highlighted
     }
 
+    pub fn semantic_tokens_mut_guard(&mut self) -> SemanticTokensMutGuard<'_> {
+        self.highlighted.semantic_tokens_mut_guard()
+    }
+
     pub fn update_highlighting(&mut self, syntaxes: &SyntaxCollection) -> HighlightResult {
-        self.highlighted
-            .highlight_dirty_lines(syntaxes.get(self.syntax_handle), &self.content)
+        self.highlighted.highlight_dirty_lines(
+            syntaxes,
+            syntaxes.get(self.syntax_handle),
+            &self.content,
+        )
     }
 
     pub fn refresh_syntax(&mut self, syntaxes: &SyntaxCollection) {
@@ -1290,6 +2260,93 @@ impl Buffer {
         self.properties.saving_enabled && self.needs_save
     }
 
+    // for when its file changed without the buffer knowing (like when it gets deleted)
+    pub fn set_needs_save(&mut self, needs_save: bool) {
+        self.needs_save = needs_save;
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    pub fn history_file_path(&self) -> Option<PathBuf> {
+        if !self.properties.history_enabled
+            || !self.properties.saving_enabled
+            || !self.properties.file_backed_enabled
+            || self.directory.is_some()
+        {
+            return None;
+        }
+
+        let file_name = self.path.file_name()?;
+        let mut history_file_name = std::ffi::OsString::from(".");
+        history_file_name.push(file_name);
+        history_file_name.push(".pepper-undo");
+        Some(self.path.with_file_name(history_file_name))
+    }
+
+    pub fn read_history_from_file(&mut self) -> Result<(), BufferReadError> {
+        if !self.history.is_empty() {
+            return Ok(());
+        }
+        let path = match self.history_file_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let content_hash = self.content.content_hash();
+        if self
+            .history
+            .deserialize(content_hash, &mut &bytes[..])
+            .is_err()
+        {
+            let _ = fs::remove_file(&path);
+            return Err(BufferReadError::InvalidHistoryFile);
+        }
+
+        Ok(())
+    }
+
+    pub fn write_history_to_file(&mut self) -> Result<(), BufferWriteError> {
+        let path = match self.history_file_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if self.history.is_empty() {
+            match fs::remove_file(&path) {
+                Ok(()) => return Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        let mut bytes = Vec::new();
+        let content_hash = self.content.content_hash();
+        self.history.serialize(content_hash, &mut bytes);
+        fs::write(&path, &bytes)?;
+        Ok(())
+    }
+
+    pub fn file_format(&self) -> BufferFileFormat {
+        BufferFileFormat {
+            line_ending: self
+                .properties
+                .line_ending
+                .unwrap_or(self.file_format.line_ending),
+            byte_order_mark: self
+                .properties
+                .byte_order_mark
+                .unwrap_or(self.file_format.byte_order_mark),
+        }
+    }
+
     pub fn insert_text(
         &mut self,
         word_database: &mut WordDatabase,
@@ -1344,8 +2401,9 @@ impl Buffer {
         let range = content.insert_text(position, text);
 
         if let Some(word_database) = &mut word_database {
-            for line in
-                &content.lines()[range.from.line_index as usize..=range.to.line_index as usize]
+            for line in content
+                .lines()
+                .range(range.from.line_index as usize..=range.to.line_index as usize)
             {
                 for word in WordIter(line.as_str()).of_kind(WordKind::Identifier) {
                     word_database.add(word);
@@ -1433,8 +2491,9 @@ impl Buffer {
         range: BufferRange,
     ) {
         if let Some(word_database) = &mut word_database {
-            for line in
-                &content.lines()[range.from.line_index as usize..=range.to.line_index as usize]
+            for line in content
+                .lines()
+                .range(range.from.line_index as usize..=range.to.line_index as usize)
             {
                 for word in WordIter(line.as_str()).of_kind(WordKind::Identifier) {
                     word_database.remove(word);
@@ -1464,7 +2523,7 @@ impl Buffer {
         }
 
         let mut previous_line_text = "";
-        for line in self.content.lines[..line_index as usize].iter().rev() {
+        for line in self.content.lines.range(..line_index as usize).rev() {
             previous_line_text = line.as_str();
             if !previous_line_text.is_empty() {
                 break;
@@ -1496,8 +2555,7 @@ impl Buffer {
             indentation += 1;
         }
 
-        let line = &mut self.content.lines[line_index as usize];
-        let display_lens = &mut self.content.line_display_lens[line_index as usize];
+        let line = self.content.lines.get_mut(line_index as _);
         let first_word = line.word_at(0);
         let delete_len = match first_word.kind {
             WordKind::Whitespace => first_word.text.len(),
@@ -1519,13 +2577,13 @@ impl Buffer {
             });
         }
 
-        line.delete_range(display_lens, ..delete_len);
+        line.delete_range(..delete_len);
 
-        if line.0.trim_start().starts_with(&[')', ']', '}', '>']) {
+        if line.text.trim_start().starts_with(&[')', ']', '}', '>']) {
             indentation = indentation.saturating_sub(1);
         }
 
-        let insert_len = if line.0.is_empty() {
+        let insert_len = if line.text.is_empty() {
             0
         } else {
             let (insert_byte, insert_len) = if indentation_config.indent_with_tabs {
@@ -1534,7 +2592,7 @@ impl Buffer {
                 (b' ', indentation * indentation_config.tab_size as usize)
             };
 
-            let line_vec = unsafe { line.0.as_mut_vec() };
+            let line_vec = unsafe { line.text.as_mut_vec() };
             line_vec.splice(..0, std::iter::repeat(insert_byte).take(insert_len));
 
             let insert_display_len = if indentation_config.indent_with_tabs {
@@ -1549,8 +2607,7 @@ impl Buffer {
                 }
             };
 
-            let line_display_len = &mut self.content.line_display_lens[line_index as usize];
-            *line_display_len = *line_display_len + insert_display_len;
+            line.display_len = line.display_len + insert_display_len;
 
             insert_len
         };
@@ -1592,6 +2649,25 @@ impl Buffer {
         self.apply_history_edits(word_database, events, BufferHistory::redo_edits)
     }
 
+    pub fn history_current_state(&self) -> usize {
+        self.history.current_state()
+    }
+
+    pub fn history_state_timestamp(&self, state: usize) -> Option<u64> {
+        self.history.state_timestamp(state)
+    }
+
+    pub fn history_branch_tips(&self) -> Vec<HistoryBranchTip> {
+        self.history.branch_tips()
+    }
+
+    pub fn prepare_history_jump(
+        &mut self,
+        jump: HistoryJump,
+    ) -> Result<(usize, usize), HistoryJumpError> {
+        self.history.prepare_jump(jump)
+    }
+
     fn apply_history_edits<'a, F, I>(
         &'a mut self,
         word_database: &mut WordDatabase,
@@ -1660,6 +2736,7 @@ impl Buffer {
         self.needs_save = false;
         self.history.clear();
         self.search_ranges.clear();
+        self.directory = None;
 
         events.enqueue(EditorEvent::BufferRead {
             handle: self.handle,
@@ -1674,7 +2751,7 @@ impl Buffer {
 
         if let Some((name, mut reader)) = help_page {
             clear_buffer(self, word_database);
-            self.content.read(&mut reader)?;
+            self.file_format = self.content.read(&mut reader)?;
 
             let path = std::mem::take(&mut self.path);
             let mut path = path.into_os_string();
@@ -1684,12 +2761,16 @@ impl Buffer {
             self.path = path.into();
         } else if self.path.as_os_str().is_empty() {
             return Err(BufferReadError::FileNotFound);
+        } else if self.path.is_dir() {
+            clear_buffer(self, word_database);
+            self.properties.word_database_enabled = false;
+            self.read_directory()?;
         } else {
             match File::open(&self.path) {
                 Ok(file) => {
                     clear_buffer(self, word_database);
                     let mut reader = io::BufReader::new(file);
-                    self.content.read(&mut reader)?;
+                    self.file_format = self.content.read(&mut reader)?;
                 }
                 Err(error) => {
                     if self.properties.saving_enabled {
@@ -1702,7 +2783,7 @@ impl Buffer {
         }
 
         if self.properties.word_database_enabled {
-            for line in &self.content.lines {
+            for line in self.content.lines() {
                 for word in WordIter(line.as_str()).of_kind(WordKind::Identifier) {
                     word_database.add(word);
                 }
@@ -1712,9 +2793,20 @@ impl Buffer {
         Ok(())
     }
 
+    fn read_directory(&mut self) -> io::Result<()> {
+        let mut directory = self.directory.take().unwrap_or_default();
+        let mut text = String::new();
+        directory.read(&self.path, &mut text)?;
+        self.file_format = self.content.read(&mut io::Cursor::new(text))?;
+        self.directory = Some(directory);
+        Ok(())
+    }
+
+    // `force` allows saving a directory listing to delete directories that still have entries
     pub fn write_to_file(
         &mut self,
         new_path: Option<&Path>,
+        force: bool,
         events: &mut EditorEventWriter,
     ) -> Result<(), BufferWriteError> {
         let new_path = match new_path {
@@ -1722,6 +2814,8 @@ impl Buffer {
                 self.properties.saving_enabled = true;
                 self.properties.file_backed_enabled = true;
                 self.set_path(path);
+                // saving a directory listing somewhere else just writes its text
+                self.directory = None;
                 true
             }
             None => false,
@@ -1731,9 +2825,28 @@ impl Buffer {
             return Err(BufferWriteError::SavingDisabled);
         }
 
-        if self.properties.file_backed_enabled {
+        if let Some(directory) = &self.directory {
+            // nothing was touched if a change can not be made so the edits are kept to be fixed.
+            // otherwise the listing is read again even if some changes failed so it shows what's actually there
+            let result = directory
+                .prepare_write(&self.path, &self.content, force)?
+                .apply();
+            self.content.clear();
+            self.highlighted.clear();
+            self.history.clear();
+            self.search_ranges.clear();
+            self.read_directory()?;
+            self.needs_save = false;
+            events.enqueue(EditorEvent::BufferRead {
+                handle: self.handle,
+            });
+            result?;
+        } else if self.properties.file_backed_enabled {
             let file = File::create(&self.path)?;
-            self.content.write(&mut io::BufWriter::new(file))?;
+            let format = self.file_format();
+            let keep_line_endings = self.properties.line_ending.is_none();
+            self.content
+                .write(&mut io::BufWriter::new(file), format, keep_line_endings)?;
         }
 
         self.needs_save = false;
@@ -1859,6 +2972,10 @@ impl BufferCollection {
             buffer.dispose(word_database);
         }
 
+        self.kill_insert_processes(platform, handle);
+    }
+
+    pub(crate) fn kill_insert_processes(&mut self, platform: &mut Platform, handle: BufferHandle) {
         for process in &mut self.insert_processes {
             if process.buffer_handle != handle {
                 continue;
@@ -1885,6 +3002,13 @@ impl BufferCollection {
             let range = insert.range;
             buffer.highlighted.insert_range(range);
             buffer.lints.insert_range(range);
+            buffer.annotations.insert_range(range);
+            buffer.range_highlights.insert_range(range);
+            buffer.signs.insert_range(range);
+            buffer.fold_ranges.insert_range(range);
+            if let Some(directory) = &mut buffer.directory {
+                directory.insert_range(range);
+            }
             if buffer.breakpoints.insert_range(range) {
                 breakpoints_changed = true;
             }
@@ -1919,6 +3043,13 @@ impl BufferCollection {
         for &range in deletes {
             buffer.highlighted.delete_range(range);
             buffer.lints.delete_range(range);
+            buffer.annotations.delete_range(range);
+            buffer.range_highlights.delete_range(range);
+            buffer.signs.delete_range(range);
+            buffer.fold_ranges.delete_range(range);
+            if let Some(directory) = &mut buffer.directory {
+                directory.delete_range(range);
+            }
             if buffer.breakpoints.delete_range(range) {
                 breakpoints_changed = true;
             }
@@ -2078,6 +3209,90 @@ mod tests {
         assert_eq!(6, display_len("xx\t"));
         assert_eq!(7, display_len("xxx\t"));
         assert_eq!(8, display_len("xxxx\t"));
+        assert_eq!(2, display_len("漢"));
+        assert_eq!(5, display_len("a漢字"));
+        assert_eq!(2, display_len("😀"));
+        assert_eq!(1, display_len("e\u{301}"));
+        assert_eq!(7, display_len("漢\tx"));
+    }
+
+    #[test]
+    fn char_display_len_table() {
+        assert_eq!(1, char_display_len('a'));
+        assert_eq!(1, char_display_len('ç'));
+        assert_eq!(1, char_display_len('\u{ff61}'));
+        assert_eq!(0, char_display_len('\u{300}'));
+        assert_eq!(0, char_display_len('\u{200b}'));
+        assert_eq!(0, char_display_len('\u{fe0f}'));
+        assert_eq!(2, char_display_len('\u{1100}'));
+        assert_eq!(2, char_display_len('あ'));
+        assert_eq!(2, char_display_len('한'));
+        assert_eq!(2, char_display_len('\u{ff01}'));
+        assert_eq!(2, char_display_len('🦀'));
+        assert_eq!(2, char_display_len('\u{20000}'));
+    }
+
+    #[test]
+    fn line_wrap() {
+        fn row_at(config: &Config, wrap: Wrap, width: usize, line: &str, index: usize) -> usize {
+            let annotations = BufferAnnotationCollection::default();
+            LineWrap::new(config, wrap, width, line).row_at(line, 0, &annotations, index)
+        }
+
+        let mut config = Config::default();
+
+        let line = "abcdefgh";
+        assert_eq!(0, row_at(&config, Wrap::Chars, 4, line, 3));
+        assert_eq!(1, row_at(&config, Wrap::Chars, 4, line, 4));
+        assert_eq!(2, row_at(&config, Wrap::Chars, 4, line, line.len()));
+        assert_eq!(0, row_at(&config, Wrap::None, 4, line, line.len()));
+
+        let line = "ab cdef gh";
+        assert_eq!(0, row_at(&config, Wrap::Chars, 6, line, 3));
+        assert_eq!(1, row_at(&config, Wrap::Chars, 6, line, 6));
+        assert_eq!(1, row_at(&config, Wrap::Words, 6, line, 3));
+        assert_eq!(1, row_at(&config, Wrap::Words, 6, line, 7));
+        assert_eq!(2, row_at(&config, Wrap::Words, 6, line, 8));
+        assert_eq!(2, row_at(&config, Wrap::Words, 6, line, line.len()));
+
+        let line = "a bcdefg";
+        assert_eq!(0, row_at(&config, Wrap::Words, 4, line, 3));
+        assert_eq!(1, row_at(&config, Wrap::Words, 4, line, 4));
+
+        let line = "  abcdefghijklm";
+        assert_eq!(1, row_at(&config, Wrap::Chars, 8, line, 8));
+        assert_eq!(1, row_at(&config, Wrap::Chars, 8, line, line.len()));
+        config.wrap_indent = true;
+        assert_eq!(1, row_at(&config, Wrap::Chars, 8, line, 8));
+        assert_eq!(2, row_at(&config, Wrap::Chars, 8, line, 14));
+        assert_eq!(2, row_at(&config, Wrap::Chars, 8, line, line.len()));
+        assert_eq!(2, LineWrap::new(&config, Wrap::Chars, 8, line).indent);
+        assert_eq!(0, LineWrap::new(&config, Wrap::Chars, 3, line).indent);
+        assert_eq!(0, LineWrap::new(&config, Wrap::None, 8, line).indent);
+
+        let mut annotations = BufferAnnotationCollection::default();
+        annotations
+            .mut_guard(PluginHandle(0))
+            .add(": u32", BufferPosition::line_col(0, 1));
+        let line = "abc";
+        let row_at = |wrap, index| {
+            LineWrap::new(&config, wrap, 4, line).row_at(line, 0, &annotations, index)
+        };
+        assert_eq!(0, row_at(Wrap::Chars, 0));
+        assert_eq!(1, row_at(Wrap::Chars, 1));
+        assert_eq!(2, row_at(Wrap::Chars, line.len()));
+        assert_eq!(0, row_at(Wrap::None, line.len()));
+
+        let mut buffer_line = BufferLine::new();
+        buffer_line.push_text(line);
+        assert_eq!(
+            3,
+            LineWrap::line_height(&config, Wrap::Chars, 4, &buffer_line, 0, &annotations)
+        );
+        assert_eq!(
+            1,
+            LineWrap::line_height(&config, Wrap::Chars, 4, &buffer_line, 1, &annotations)
+        );
     }
 
     fn buffer_from_str(text: &str) -> BufferContent {
@@ -2099,6 +3314,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn buffer_content_read_write_file_format() {
+        fn read_write(text: &[u8]) -> (BufferFileFormat, String, Vec<u8>) {
+            let mut buffer = BufferContent::new();
+            let format = buffer.read(&mut io::Cursor::new(text)).unwrap();
+            let mut written = Vec::new();
+            buffer.write(&mut written, format, true).unwrap();
+            (format, buffer.to_string(), written)
+        }
+
+        let (format, content, written) = read_write(b"first\nsecond\n");
+        assert!(format == BufferFileFormat::default());
+        assert_eq!("first\nsecond\n", content);
+        assert_eq!(b"first\nsecond\n", &written[..]);
+
+        let (format, content, written) = read_write(b"first\r\nsecond\r\n");
+        assert!(format.line_ending == BufferLineEnding::CrLf);
+        assert!(!format.byte_order_mark);
+        assert_eq!("first\nsecond\n", content);
+        assert_eq!(b"first\r\nsecond\r\n", &written[..]);
+
+        let (format, content, written) = read_write(b"\xef\xbb\xbffirst\r\nsecond");
+        assert!(format.line_ending == BufferLineEnding::CrLf);
+        assert!(format.byte_order_mark);
+        assert_eq!("first\nsecond", content);
+        assert_eq!(b"\xef\xbb\xbffirst\r\nsecond", &written[..]);
+
+        let (format, _, written) = read_write(b"a\r\nb\nc\r\n");
+        assert!(format.line_ending == BufferLineEnding::CrLf);
+        assert_eq!(b"a\r\nb\nc\r\n", &written[..]);
+
+        let mut buffer = BufferContent::new();
+        let format = buffer
+            .read(&mut io::Cursor::new(b"a\r\nb\nc\r\nd"))
+            .unwrap();
+        buffer.insert_text(BufferPosition::line_col(1, 1), "\nx");
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(3, 1),
+            BufferPosition::line_col(4, 0),
+        ));
+        let mut written = Vec::new();
+        buffer.write(&mut written, format, true).unwrap();
+        assert_eq!(b"a\r\nb\r\nx\ncd", &written[..]);
+        written.clear();
+        buffer.write(&mut written, format, false).unwrap();
+        assert_eq!(b"a\r\nb\r\nx\r\ncd", &written[..]);
+
+        let mut buffer = BufferContent::new();
+        buffer.read(&mut io::Cursor::new(b"first\nsecond")).unwrap();
+        assert!(!buffer.has_final_newline());
+        buffer
+            .read(&mut io::Cursor::new(b"first\nsecond\n"))
+            .unwrap();
+        assert!(buffer.has_final_newline());
+    }
+
     #[test]
     fn buffer_content_insert_text() {
         let mut buffer = BufferContent::new();
@@ -2224,6 +3495,116 @@ mod tests {
         assert_eq!("first line\n\nthird line", buffer.to_string());
     }
 
+    #[test]
+    fn buffer_content_hash() {
+        let hash = buffer_from_str("first\nsecond").content_hash();
+        assert_eq!(hash, buffer_from_str("first\r\nsecond").content_hash());
+        assert_ne!(hash, buffer_from_str("first\nsecond\n").content_hash());
+        assert_ne!(hash, buffer_from_str("firstsecond").content_hash());
+    }
+
+    #[test]
+    fn buffer_content_many_lines() {
+        fn line_index_of(line: &BufferLine) -> usize {
+            line.as_str().parse().unwrap()
+        }
+
+        let line_count = MAX_LINES_PER_CHUNK * 3 + 7;
+        let mut text = String::new();
+        for i in 0..line_count {
+            if i > 0 {
+                text.push('\n');
+            }
+            text.push_str(&i.to_string());
+        }
+
+        let mut buffer = buffer_from_str(&text);
+        assert_eq!(line_count, buffer.lines().len());
+        assert_eq!(text, buffer.to_string());
+        for (i, line) in buffer.lines().iter().enumerate() {
+            assert_eq!(i, line_index_of(line));
+        }
+        for (i, line) in buffer.lines().iter().enumerate().rev() {
+            assert_eq!(i, line_index_of(line));
+        }
+
+        let range_start = MAX_LINES_PER_CHUNK - 3;
+        let range_end = MAX_LINES_PER_CHUNK * 2 + 5;
+        let lines = buffer.lines().range(range_start..range_end);
+        assert_eq!(range_end - range_start, lines.len());
+        assert!(lines.map(line_index_of).eq(range_start..range_end));
+        let lines = buffer.lines().range(range_start..range_end).rev();
+        assert!(lines.map(line_index_of).eq((range_start..range_end).rev()));
+        assert_eq!(0, buffer.lines().range(range_start..range_start).len());
+
+        let mut inserted_text = String::new();
+        for _ in 0..MAX_LINES_PER_CHUNK * 2 {
+            inserted_text.push_str("\nx");
+        }
+        buffer.insert_text(BufferPosition::line_col(10, 1), &inserted_text);
+        assert_eq!(line_count + MAX_LINES_PER_CHUNK * 2, buffer.lines().len());
+        assert_eq!("1", buffer.lines()[10].as_str());
+        assert_eq!("x0", buffer.lines()[10 + MAX_LINES_PER_CHUNK * 2].as_str());
+        assert_eq!(
+            (line_count - 1).to_string(),
+            buffer.lines()[buffer.lines().len() - 1].as_str()
+        );
+
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(10, 1),
+            BufferPosition::line_col((10 + MAX_LINES_PER_CHUNK * 2) as _, 1),
+        ));
+        assert_eq!(line_count, buffer.lines().len());
+        assert_eq!(text, buffer.to_string());
+        for (i, line) in buffer.lines().iter().enumerate() {
+            assert_eq!(i, line_index_of(line));
+        }
+
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(2, 0),
+            BufferPosition::line_col((line_count - 2) as _, 0),
+        ));
+        assert_eq!(4, buffer.lines().len());
+        let expected = format!("0\n1\n{}\n{}", line_count - 2, line_count - 1);
+        assert_eq!(expected, buffer.to_string());
+        assert_eq!(1, buffer.lines().chunks.len());
+    }
+
+    #[test]
+    fn buffer_content_merge_small_chunks() {
+        let mut text = String::new();
+        for _ in 0..MAX_LINES_PER_CHUNK * 3 {
+            text.push_str("x\n");
+        }
+        let mut buffer = buffer_from_str(&text);
+        assert_eq!(4, buffer.lines().chunks.len());
+
+        let third_chunk_index = MAX_LINES_PER_CHUNK * 2;
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(third_chunk_index as _, 0),
+            BufferPosition::line_col((third_chunk_index + 600) as _, 0),
+        ));
+        assert_eq!(3, buffer.lines().chunks.len());
+
+        for _ in 0..800 {
+            let line_index = MAX_LINES_PER_CHUNK as _;
+            buffer.delete_range(BufferRange::between(
+                BufferPosition::line_col(line_index, 0),
+                BufferPosition::line_col(line_index + 1, 0),
+            ));
+        }
+        assert_eq!(2, buffer.lines().chunks.len());
+        assert_eq!(MAX_LINES_PER_CHUNK * 3 + 1 - 1400, buffer.lines().len());
+
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(10, 0),
+            BufferPosition::line_col((buffer.lines().len() - 10) as _, 0),
+        ));
+        assert_eq!(20, buffer.lines().len());
+        assert_eq!(1, buffer.lines().chunks.len());
+        assert_eq!(20, buffer.lines().chunks[0].len());
+    }
+
     #[test]
     fn buffer_delete_undo_redo_single_line() {
         let mut word_database = WordDatabase::new();
@@ -2381,6 +3762,58 @@ mod tests {
         for _ in ranges {}
     }
 
+    #[test]
+    fn buffer_directory_save_conflict_keeps_edits() {
+        let mut word_database = WordDatabase::new();
+        let mut events = EditorEventQueue::default();
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("pepper-directory-save-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("a.txt"), "").unwrap();
+        fs::write(path.join("b.txt"), "").unwrap();
+
+        let mut buffer = Buffer::new(BufferHandle(0));
+        buffer.properties = BufferProperties::text();
+        buffer.set_path(&path);
+        assert!(buffer
+            .read_from_file(&mut word_database, events.writer())
+            .is_ok());
+        assert_eq!("../\na.txt\nb.txt", buffer.content.to_string());
+
+        // renaming `a.txt` to `b.txt` conflicts with the existing `b.txt`
+        buffer.delete_range(
+            &mut word_database,
+            BufferRange::between(
+                BufferPosition::line_col(1, 0),
+                BufferPosition::line_col(1, 1),
+            ),
+            &mut events
+                .writer()
+                .buffer_range_deletes_mut_guard(buffer.handle()),
+        );
+        buffer.insert_text(
+            &mut word_database,
+            BufferPosition::line_col(1, 0),
+            "b",
+            &mut events
+                .writer()
+                .buffer_text_inserts_mut_guard(buffer.handle()),
+        );
+        buffer.commit_edits();
+
+        let result = buffer.write_to_file(None, false, events.writer());
+        assert!(matches!(result, Err(BufferWriteError::CouldNotWriteToFile)));
+        assert_eq!("../\nb.txt\nb.txt", buffer.content.to_string());
+        assert!(buffer.needs_save());
+        assert!(path.join("a.txt").exists());
+        assert_eq!(2, buffer.undo(&mut word_database, events.writer()).count());
+        assert_eq!("../\na.txt\nb.txt", buffer.content.to_string());
+
+        let _ = fs::remove_dir_all(&path);
+    }
+
     #[test]
     fn buffer_content_text_range() {
         let buffer = buffer_from_str("abc\ndef\nghi");
@@ -2513,7 +3946,7 @@ mod tests {
     #[test]
     fn buffer_display_len() {
         fn len(buffer: &BufferContent, line: usize) -> usize {
-            buffer.line_display_lens()[line].total_len(4)
+            buffer.lines()[line].display_len().total_len(4)
         }
 
         let mut buffer = buffer_from_str("abc\tdef");
@@ -2545,6 +3978,19 @@ mod tests {
         assert_eq!(0, len(&buffer, 0));
         assert_eq!(6, len(&buffer, 1));
         assert_eq!(3, len(&buffer, 2));
+
+        let mut buffer = buffer_from_str("a漢\te\u{301}字");
+        assert_eq!(10, len(&buffer, 0));
+
+        buffer.insert_text(BufferPosition::line_col(0, 4), "\n");
+        assert_eq!(3, len(&buffer, 0));
+        assert_eq!(7, len(&buffer, 1));
+
+        buffer.delete_range(BufferRange::between(
+            BufferPosition::line_col(1, 1),
+            BufferPosition::line_col(1, 4),
+        ));
+        assert_eq!(6, len(&buffer, 1));
     }
 
     #[test]
@@ -2681,4 +4127,151 @@ mod tests {
         buffer.fix_line_indentation(indentation_config, 1, &mut events);
         assert_eq!("        second", buffer.content().lines()[1].as_str());
     }
+
+    #[test]
+    fn buffer_lint_sources() {
+        let plugin_handle = PluginHandle(0);
+        let mut lints = BufferLintCollection::default();
+        let range = BufferRange::between(
+            BufferPosition::line_col(0, 0),
+            BufferPosition::line_col(0, 1),
+        );
+        {
+            let mut lints = lints.source_mut_guard(plugin_handle, 0);
+            lints.add("first", range, BufferLintSeverity::Error);
+        }
+        {
+            let mut lints = lints.source_mut_guard(plugin_handle, 1);
+            lints.add("second", range, BufferLintSeverity::Warning);
+        }
+        assert_eq!(2, lints.all().len());
+
+        lints.source_mut_guard(plugin_handle, 0).clear();
+        assert_eq!(1, lints.all().len());
+        assert_eq!(1, lints.all()[0].source);
+        assert_eq!("second", lints.all()[0].message(&lints));
+
+        lints.source_mut_guard(plugin_handle, 1).clear();
+        assert!(lints.all().is_empty());
+    }
+
+    #[test]
+    fn buffer_annotations_follow_edits() {
+        let plugin_handle = PluginHandle(0);
+        let mut annotations = BufferAnnotationCollection::default();
+        {
+            let mut annotations = annotations.mut_guard(plugin_handle);
+            annotations.add(": u32", BufferPosition::line_col(1, 5));
+            annotations.add("a: ", BufferPosition::line_col(0, 4));
+        }
+
+        let all = annotations.all();
+        assert_eq!(2, all.len());
+        assert_eq!("a: ", all[0].text(&annotations));
+        assert_eq!(": u32", all[1].text(&annotations));
+
+        annotations.insert_range(BufferRange::between(
+            BufferPosition::line_col(0, 0),
+            BufferPosition::line_col(1, 2),
+        ));
+        annotations.delete_range(BufferRange::between(
+            BufferPosition::line_col(2, 1),
+            BufferPosition::line_col(2, 3),
+        ));
+        assert_eq!(
+            BufferPosition::line_col(1, 6),
+            annotations.all()[0].position
+        );
+        assert_eq!(
+            BufferPosition::line_col(2, 3),
+            annotations.all()[1].position
+        );
+
+        annotations.mut_guard(plugin_handle).clear();
+        assert!(annotations.all().is_empty());
+    }
+
+    #[test]
+    fn buffer_signs_follow_line_edits() {
+        let plugin_handle = PluginHandle(0);
+        let other_plugin_handle = PluginHandle(1);
+        let mut signs = BufferSignCollection::default();
+        {
+            let mut signs = signs.mut_guard(plugin_handle);
+            signs.add(4, '~', TokenKind::Keyword);
+            signs.add(1, '+', TokenKind::String);
+            signs.add(2, '+', TokenKind::String);
+        }
+        signs
+            .mut_guard(other_plugin_handle)
+            .add(0, '!', TokenKind::Text);
+
+        let line_indexes = |signs: &BufferSignCollection| {
+            signs.all().iter().map(|s| s.line_index).collect::<Vec<_>>()
+        };
+        assert_eq!(vec![0, 1, 2, 4], line_indexes(&signs));
+
+        signs.insert_range(BufferRange::between(
+            BufferPosition::line_col(1, 0),
+            BufferPosition::line_col(2, 0),
+        ));
+        assert_eq!(vec![0, 2, 3, 5], line_indexes(&signs));
+
+        signs.delete_range(BufferRange::between(
+            BufferPosition::line_col(2, 3),
+            BufferPosition::line_col(4, 1),
+        ));
+        assert_eq!(vec![0, 2, 3], line_indexes(&signs));
+        assert_eq!('~', signs.all()[2].sign);
+
+        signs.mut_guard(plugin_handle).clear();
+        assert_eq!(1, signs.all().len());
+        assert_eq!('!', signs.all()[0].sign);
+    }
+
+    #[test]
+    fn buffer_range_highlights() {
+        let plugin_handle = PluginHandle(0);
+        let other_plugin_handle = PluginHandle(1);
+        let mut highlights = BufferRangeHighlightCollection::default();
+        {
+            let mut highlights = highlights.mut_guard(plugin_handle);
+            highlights.add(BufferRange::between(
+                BufferPosition::line_col(2, 0),
+                BufferPosition::line_col(2, 3),
+            ));
+            highlights.add(BufferRange::between(
+                BufferPosition::line_col(0, 4),
+                BufferPosition::line_col(0, 7),
+            ));
+        }
+        highlights
+            .mut_guard(other_plugin_handle)
+            .add(BufferRange::between(
+                BufferPosition::line_col(1, 0),
+                BufferPosition::line_col(1, 1),
+            ));
+
+        let all = highlights.all();
+        assert_eq!(3, all.len());
+        assert_eq!(BufferPosition::line_col(0, 4), all[0].range.from);
+        assert_eq!(BufferPosition::line_col(1, 0), all[1].range.from);
+        assert_eq!(BufferPosition::line_col(2, 0), all[2].range.from);
+
+        highlights.insert_range(BufferRange::between(
+            BufferPosition::line_col(0, 0),
+            BufferPosition::line_col(0, 2),
+        ));
+        assert_eq!(
+            BufferRange::between(
+                BufferPosition::line_col(0, 6),
+                BufferPosition::line_col(0, 9)
+            ),
+            highlights.all()[0].range
+        );
+
+        highlights.mut_guard(plugin_handle).clear();
+        assert_eq!(1, highlights.all().len());
+        assert!(highlights.all()[0].plugin_handle == other_plugin_handle);
+    }
 }
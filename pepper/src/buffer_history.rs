@@ -1,6 +1,16 @@
-use std::ops::Range;
+use std::{
+    fmt,
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::buffer_position::{BufferPosition, BufferRange};
+use crate::{
+    buffer_position::{BufferPosition, BufferRange},
+    serialization::{DeserializeError, Deserializer, Serialize, Serializer},
+};
+
+const HISTORY_FILE_MAGIC: &[u8] = b"pepper-undo";
+const HISTORY_FILE_VERSION: u32 = 2;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditKind {
@@ -8,6 +18,24 @@ pub enum EditKind {
     Delete,
 }
 
+impl<'de> Serialize<'de> for EditKind {
+    fn serialize(&self, serializer: &mut dyn Serializer) {
+        match self {
+            Self::Insert => 0u8.serialize(serializer),
+            Self::Delete => 1u8.serialize(serializer),
+        }
+    }
+
+    fn deserialize(deserializer: &mut dyn Deserializer<'de>) -> Result<Self, DeserializeError> {
+        let discriminant = u8::deserialize(deserializer)?;
+        match discriminant {
+            0 => Ok(Self::Insert),
+            1 => Ok(Self::Delete),
+            _ => Err(DeserializeError::InvalidData),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Edit<'a> {
     pub kind: EditKind,
@@ -36,15 +64,99 @@ impl EditInternal {
     }
 }
 
+impl<'de> Serialize<'de> for EditInternal {
+    fn serialize(&self, serializer: &mut dyn Serializer) {
+        self.kind.serialize(serializer);
+        self.buffer_range.from.line_index.serialize(serializer);
+        self.buffer_range
+            .from
+            .column_byte_index
+            .serialize(serializer);
+        self.buffer_range.to.line_index.serialize(serializer);
+        self.buffer_range.to.column_byte_index.serialize(serializer);
+        self.text_range.start.serialize(serializer);
+        self.text_range.end.serialize(serializer);
+    }
+
+    fn deserialize(deserializer: &mut dyn Deserializer<'de>) -> Result<Self, DeserializeError> {
+        let kind = EditKind::deserialize(deserializer)?;
+        let from_line_index = u32::deserialize(deserializer)?;
+        let from_column_byte_index = u32::deserialize(deserializer)?;
+        let to_line_index = u32::deserialize(deserializer)?;
+        let to_column_byte_index = u32::deserialize(deserializer)?;
+        let text_range_start = u32::deserialize(deserializer)?;
+        let text_range_end = u32::deserialize(deserializer)?;
+
+        let from = BufferPosition::line_col(from_line_index, from_column_byte_index);
+        let to = BufferPosition::line_col(to_line_index, to_column_byte_index);
+        if to < from || text_range_end < text_range_start {
+            return Err(DeserializeError::InvalidData);
+        }
+
+        Ok(Self {
+            kind,
+            buffer_range: BufferRange::between(from, to),
+            text_range: text_range_start..text_range_end,
+        })
+    }
+}
+
+pub fn timestamp_now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum HistoryJump {
+    Earlier(usize),
+    Later(usize),
+    Timestamp(u64),
+    NextBranch,
+    PreviousBranch,
+    State(usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HistoryJumpError {
+    NoOtherBranch,
+    NoSuchState(usize),
+}
+impl fmt::Display for HistoryJumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoOtherBranch => f.write_str("no other undo branch to switch to"),
+            Self::NoSuchState(state) => write!(f, "no such undo state {}", state),
+        }
+    }
+}
+
+pub struct HistoryBranchTip {
+    pub state: usize,
+    pub timestamp: u64,
+}
+
+// history states are numbered in the order they were created
+// state `0` is the buffer before any edit and state `n` is the one after applying group `n - 1`
+struct HistoryGroup {
+    edit_range: Range<usize>,
+    parent_state: usize,
+    redo_state: usize,
+    timestamp: u64,
+}
+
 enum HistoryState {
-    IterIndex { group_index: usize },
+    Idle,
     InsertGroup { edit_index: usize },
 }
 
 pub(crate) struct BufferHistory {
     texts: String,
     edits: Vec<EditInternal>,
-    group_ranges: Vec<Range<usize>>,
+    groups: Vec<HistoryGroup>,
+    root_redo_state: usize,
+    current_state: usize,
     state: HistoryState,
 }
 
@@ -53,32 +165,254 @@ impl BufferHistory {
         Self {
             texts: String::new(),
             edits: Vec::new(),
-            group_ranges: Vec::new(),
-            state: HistoryState::IterIndex { group_index: 0 },
+            groups: Vec::new(),
+            root_redo_state: 0,
+            current_state: 0,
+            state: HistoryState::Idle,
         }
     }
 
     pub fn clear(&mut self) {
         self.texts.clear();
         self.edits.clear();
-        self.group_ranges.clear();
-        self.state = HistoryState::IterIndex { group_index: 0 };
+        self.groups.clear();
+        self.root_redo_state = 0;
+        self.current_state = 0;
+        self.state = HistoryState::Idle;
     }
 
-    pub fn add_edit(&mut self, edit: Edit) {
-        let current_group_start = match self.state {
-            HistoryState::IterIndex { group_index } => {
-                let edit_index = match self.group_ranges.get(group_index) {
-                    Some(range) => range.start,
-                    None => self.edits.len(),
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn current_state(&self) -> usize {
+        self.current_state
+    }
+
+    pub fn state_timestamp(&self, state: usize) -> Option<u64> {
+        match state {
+            0 => None,
+            _ => self.groups.get(state - 1).map(|g| g.timestamp),
+        }
+    }
+
+    pub fn branch_tips(&self) -> Vec<HistoryBranchTip> {
+        let mut has_children = vec![false; self.groups.len() + 1];
+        for group in &self.groups {
+            has_children[group.parent_state] = true;
+        }
+
+        let mut tips = Vec::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            if !has_children[i + 1] {
+                tips.push(HistoryBranchTip {
+                    state: i + 1,
+                    timestamp: group.timestamp,
+                });
+            }
+        }
+        tips
+    }
+
+    fn parent_state(&self, state: usize) -> usize {
+        self.groups[state - 1].parent_state
+    }
+
+    fn redo_state_mut(&mut self, state: usize) -> &mut usize {
+        match state {
+            0 => &mut self.root_redo_state,
+            _ => &mut self.groups[state - 1].redo_state,
+        }
+    }
+
+    // returns how many undos followed by how many redos reach the jump target
+    pub fn prepare_jump(&mut self, jump: HistoryJump) -> Result<(usize, usize), HistoryJumpError> {
+        self.commit_edits();
+
+        let current_state = self.current_state;
+        let state_count = self.groups.len() + 1;
+        let target_state = match jump {
+            HistoryJump::Earlier(count) => current_state.saturating_sub(count),
+            HistoryJump::Later(count) => (current_state + count).min(state_count - 1),
+            HistoryJump::Timestamp(timestamp) => self
+                .groups
+                .iter()
+                .rposition(|g| g.timestamp <= timestamp)
+                .map(|i| i + 1)
+                .unwrap_or(0),
+            HistoryJump::NextBranch | HistoryJump::PreviousBranch => {
+                if current_state == 0 {
+                    return Err(HistoryJumpError::NoOtherBranch);
+                }
+                let parent_state = self.parent_state(current_state);
+                let mut siblings =
+                    (1..state_count).filter(|&s| self.parent_state(s) == parent_state);
+                let sibling = if let HistoryJump::NextBranch = jump {
+                    let first = siblings.clone().next();
+                    siblings.find(|&s| s > current_state).or(first)
+                } else {
+                    let last = siblings.clone().next_back();
+                    siblings.rev().find(|&s| s < current_state).or(last)
                 };
-                self.edits.truncate(edit_index);
-                match self.edits.last() {
-                    Some(edit) => self.texts.truncate(edit.text_range.end as _),
-                    None => self.texts.clear(),
+                match sibling {
+                    Some(state) if state != current_state => state,
+                    _ => return Err(HistoryJumpError::NoOtherBranch),
                 }
+            }
+            HistoryJump::State(state) if state < state_count => state,
+            HistoryJump::State(state) => return Err(HistoryJumpError::NoSuchState(state)),
+        };
+
+        let mut target_path = Vec::new();
+        let mut is_in_target_path = vec![false; state_count];
+        let mut state = target_state;
+        loop {
+            target_path.push(state);
+            is_in_target_path[state] = true;
+            if state == 0 {
+                break;
+            }
+            state = self.parent_state(state);
+        }
+
+        let mut undo_count = 0;
+        let mut state = current_state;
+        while !is_in_target_path[state] {
+            state = self.parent_state(state);
+            undo_count += 1;
+        }
+
+        let redo_count = target_path.iter().position(|&s| s == state).unwrap_or(0);
+        for &state in target_path[..redo_count].iter().rev() {
+            let parent_state = self.parent_state(state);
+            *self.redo_state_mut(parent_state) = state;
+        }
+
+        Ok((undo_count, redo_count))
+    }
+
+    pub fn serialize(&mut self, content_hash: u64, serializer: &mut dyn Serializer) {
+        self.commit_edits();
+
+        serializer.write(HISTORY_FILE_MAGIC);
+        HISTORY_FILE_VERSION.serialize(serializer);
+        content_hash.serialize(serializer);
+
+        self.texts.as_str().serialize(serializer);
+        (self.edits.len() as u32).serialize(serializer);
+        for edit in &self.edits {
+            edit.serialize(serializer);
+        }
+        (self.groups.len() as u32).serialize(serializer);
+        for group in &self.groups {
+            (group.edit_range.start as u32).serialize(serializer);
+            (group.edit_range.end as u32).serialize(serializer);
+            (group.parent_state as u32).serialize(serializer);
+            (group.redo_state as u32).serialize(serializer);
+            group.timestamp.serialize(serializer);
+        }
+        (self.root_redo_state as u32).serialize(serializer);
+        (self.current_state as u32).serialize(serializer);
+    }
+
+    // on error, the history is left cleared
+    pub fn deserialize<'de>(
+        &mut self,
+        content_hash: u64,
+        deserializer: &mut dyn Deserializer<'de>,
+    ) -> Result<(), DeserializeError> {
+        self.clear();
+        let result = self.deserialize_unchecked(content_hash, deserializer);
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
+    fn deserialize_unchecked<'de>(
+        &mut self,
+        content_hash: u64,
+        deserializer: &mut dyn Deserializer<'de>,
+    ) -> Result<(), DeserializeError> {
+        if deserializer.read(HISTORY_FILE_MAGIC.len())? != HISTORY_FILE_MAGIC
+            || u32::deserialize(deserializer)? != HISTORY_FILE_VERSION
+            || u64::deserialize(deserializer)? != content_hash
+        {
+            return Err(DeserializeError::InvalidData);
+        }
+
+        let texts = <&str>::deserialize(deserializer)?;
+        self.texts.push_str(texts);
+
+        let edit_count = u32::deserialize(deserializer)?;
+        for _ in 0..edit_count {
+            let edit = EditInternal::deserialize(deserializer)?;
+            let text_range = edit.text_range();
+            if text_range.end > texts.len()
+                || !texts.is_char_boundary(text_range.start)
+                || !texts.is_char_boundary(text_range.end)
+            {
+                return Err(DeserializeError::InvalidData);
+            }
+            self.edits.push(edit);
+        }
+
+        let group_count = u32::deserialize(deserializer)? as usize;
+        let mut previous_group_end = 0;
+        for i in 0..group_count {
+            let start = u32::deserialize(deserializer)? as usize;
+            let end = u32::deserialize(deserializer)? as usize;
+            let parent_state = u32::deserialize(deserializer)? as usize;
+            let redo_state = u32::deserialize(deserializer)? as usize;
+            let timestamp = u64::deserialize(deserializer)?;
+            if start < previous_group_end
+                || end < start
+                || end > self.edits.len()
+                || parent_state > i
+                || redo_state > group_count
+            {
+                return Err(DeserializeError::InvalidData);
+            }
+            self.groups.push(HistoryGroup {
+                edit_range: start..end,
+                parent_state,
+                redo_state,
+                timestamp,
+            });
+            previous_group_end = end;
+        }
+
+        let root_redo_state = u32::deserialize(deserializer)? as usize;
+        let current_state = u32::deserialize(deserializer)? as usize;
+        if root_redo_state > group_count || current_state > group_count {
+            return Err(DeserializeError::InvalidData);
+        }
+        self.root_redo_state = root_redo_state;
+        self.current_state = current_state;
+
+        for state in 0..=group_count {
+            let redo_state = *self.redo_state_mut(state);
+            if redo_state != 0 && self.parent_state(redo_state) != state {
+                return Err(DeserializeError::InvalidData);
+            }
+        }
+        let mut state = current_state;
+        while state != 0 {
+            let parent_state = self.parent_state(state);
+            if *self.redo_state_mut(parent_state) != state {
+                return Err(DeserializeError::InvalidData);
+            }
+            state = parent_state;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_edit(&mut self, edit: Edit) {
+        let current_group_start = match self.state {
+            HistoryState::Idle => {
+                let edit_index = self.edits.len();
                 self.state = HistoryState::InsertGroup { edit_index };
-                self.group_ranges.truncate(group_index);
                 edit_index
             }
             HistoryState::InsertGroup { edit_index } => edit_index,
@@ -366,10 +700,20 @@ impl BufferHistory {
 
     pub fn commit_edits(&mut self) {
         if let HistoryState::InsertGroup { edit_index } = self.state {
-            self.group_ranges.push(edit_index..self.edits.len());
-            self.state = HistoryState::IterIndex {
-                group_index: self.group_ranges.len(),
-            };
+            self.state = HistoryState::Idle;
+            if edit_index == self.edits.len() {
+                return;
+            }
+
+            let parent_state = self.current_state;
+            self.groups.push(HistoryGroup {
+                edit_range: edit_index..self.edits.len(),
+                parent_state,
+                redo_state: 0,
+                timestamp: timestamp_now(),
+            });
+            self.current_state = self.groups.len();
+            *self.redo_state_mut(parent_state) = self.current_state;
         }
     }
 
@@ -378,16 +722,14 @@ impl BufferHistory {
     ) -> impl Clone + ExactSizeIterator<Item = Edit> + DoubleEndedIterator<Item = Edit> {
         self.commit_edits();
 
-        let range = match &mut self.state {
-            HistoryState::IterIndex { group_index } => {
-                if *group_index > 0 {
-                    *group_index -= 1;
-                    self.group_ranges[*group_index].clone()
-                } else {
-                    0..0
-                }
+        let range = match self.current_state {
+            0 => 0..0,
+            state => {
+                // the parent's redo state already points to the current state
+                let group = &self.groups[state - 1];
+                self.current_state = group.parent_state;
+                group.edit_range.clone()
             }
-            _ => unreachable!(),
         };
 
         let texts = &self.texts;
@@ -406,17 +748,12 @@ impl BufferHistory {
     ) -> impl Clone + ExactSizeIterator<Item = Edit> + DoubleEndedIterator<Item = Edit> {
         self.commit_edits();
 
-        let range = match &mut self.state {
-            HistoryState::IterIndex { group_index } => {
-                if *group_index < self.group_ranges.len() {
-                    let range = self.group_ranges[*group_index].clone();
-                    *group_index += 1;
-                    range
-                } else {
-                    0..0
-                }
+        let range = match *self.redo_state_mut(self.current_state) {
+            0 => 0..0,
+            state => {
+                self.current_state = state;
+                self.groups[state - 1].edit_range.clone()
             }
-            _ => unreachable!(),
         };
 
         let texts = &self.texts;
@@ -1287,4 +1624,166 @@ mod tests {
             assert!(edits.next().is_none());
         }
     }
+
+    fn add_insert_group(history: &mut BufferHistory, text: &str) {
+        history.add_edit(Edit {
+            kind: EditKind::Insert,
+            range: buffer_range((0, 0), (0, text.len())),
+            text,
+        });
+        history.commit_edits();
+    }
+
+    fn jump(
+        history: &mut BufferHistory,
+        jump: HistoryJump,
+    ) -> Result<(usize, usize), HistoryJumpError> {
+        let counts = history.prepare_jump(jump)?;
+        for _ in 0..counts.0 {
+            let _ = history.undo_edits();
+        }
+        for _ in 0..counts.1 {
+            let _ = history.redo_edits();
+        }
+        Ok(counts)
+    }
+
+    #[test]
+    fn undo_tree_keeps_branches() {
+        let mut history = BufferHistory::new();
+        add_insert_group(&mut history, "a");
+        add_insert_group(&mut history, "b");
+        assert_eq!(1, history.undo_edits().count());
+        add_insert_group(&mut history, "c");
+        assert_eq!(3, history.current_state());
+
+        let tips: Vec<_> = history.branch_tips().iter().map(|t| t.state).collect();
+        assert_eq!(&[2, 3], &tips[..]);
+
+        assert_eq!(0, history.redo_edits().count());
+        assert_eq!("c", history.undo_edits().next().unwrap().text);
+        assert_eq!("c", history.redo_edits().next().unwrap().text);
+
+        assert_eq!(Ok((1, 1)), jump(&mut history, HistoryJump::PreviousBranch));
+        assert_eq!(2, history.current_state());
+        assert_eq!("b", history.undo_edits().next().unwrap().text);
+        assert_eq!("b", history.redo_edits().next().unwrap().text);
+
+        assert_eq!(Ok((1, 1)), jump(&mut history, HistoryJump::NextBranch));
+        assert_eq!(3, history.current_state());
+        assert_eq!(Ok((1, 1)), jump(&mut history, HistoryJump::NextBranch));
+        assert_eq!(2, history.current_state());
+
+        assert_eq!(Ok((2, 0)), jump(&mut history, HistoryJump::State(0)));
+        assert_eq!(0, history.current_state());
+        assert_eq!(
+            Err(HistoryJumpError::NoOtherBranch),
+            jump(&mut history, HistoryJump::NextBranch)
+        );
+        assert_eq!(
+            Err(HistoryJumpError::NoSuchState(4)),
+            jump(&mut history, HistoryJump::State(4))
+        );
+
+        assert_eq!(Ok((0, 2)), jump(&mut history, HistoryJump::State(3)));
+        assert_eq!(3, history.current_state());
+        assert_eq!(0, history.redo_edits().count());
+    }
+
+    #[test]
+    fn undo_tree_jump_in_time() {
+        let mut history = BufferHistory::new();
+        add_insert_group(&mut history, "a");
+        add_insert_group(&mut history, "b");
+        assert_eq!(1, history.undo_edits().count());
+        add_insert_group(&mut history, "c");
+        for (i, group) in history.groups.iter_mut().enumerate() {
+            group.timestamp = 100 * (i as u64 + 1);
+        }
+
+        assert_eq!(Ok((1, 0)), jump(&mut history, HistoryJump::Earlier(2)));
+        assert_eq!(1, history.current_state());
+        assert_eq!(Ok((0, 1)), jump(&mut history, HistoryJump::Later(1)));
+        assert_eq!(2, history.current_state());
+        assert_eq!(Ok((1, 1)), jump(&mut history, HistoryJump::Later(5)));
+        assert_eq!(3, history.current_state());
+
+        assert_eq!(Ok((1, 1)), jump(&mut history, HistoryJump::Timestamp(250)));
+        assert_eq!(2, history.current_state());
+        assert_eq!(Ok((2, 0)), jump(&mut history, HistoryJump::Timestamp(50)));
+        assert_eq!(0, history.current_state());
+        assert_eq!(Ok((0, 2)), jump(&mut history, HistoryJump::Timestamp(300)));
+        assert_eq!(3, history.current_state());
+    }
+
+    #[test]
+    fn serialize_deserialize_history() {
+        let mut history = BufferHistory::new();
+        history.add_edit(Edit {
+            kind: EditKind::Insert,
+            range: buffer_range((0, 0), (0, 3)),
+            text: "abc",
+        });
+        history.commit_edits();
+        history.add_edit(Edit {
+            kind: EditKind::Delete,
+            range: buffer_range((0, 1), (0, 2)),
+            text: "b",
+        });
+        history.commit_edits();
+        assert_eq!(1, history.undo_edits().count());
+
+        let mut bytes = Vec::new();
+        history.serialize(7, &mut bytes);
+
+        let mut loaded = BufferHistory::new();
+        assert!(loaded.deserialize(7, &mut &bytes[..]).is_ok());
+
+        let mut edits = loaded.redo_edits();
+        let edit = edits.next().unwrap();
+        assert_eq!(EditKind::Delete, edit.kind);
+        assert_eq!("b", edit.text);
+        assert_eq!(buffer_range((0, 1), (0, 2)), edit.range);
+        assert!(edits.next().is_none());
+        drop(edits);
+
+        assert_eq!(1, loaded.undo_edits().count());
+        let mut edits = loaded.undo_edits();
+        let edit = edits.next().unwrap();
+        assert_eq!(EditKind::Delete, edit.kind);
+        assert_eq!("abc", edit.text);
+        assert_eq!(buffer_range((0, 0), (0, 3)), edit.range);
+        assert!(edits.next().is_none());
+        drop(edits);
+        assert_eq!(0, loaded.undo_edits().count());
+    }
+
+    #[test]
+    fn deserialize_stale_or_invalid_history() {
+        let mut history = BufferHistory::new();
+        history.add_edit(Edit {
+            kind: EditKind::Insert,
+            range: buffer_range((0, 0), (0, 1)),
+            text: "a",
+        });
+
+        let mut bytes = Vec::new();
+        history.serialize(7, &mut bytes);
+
+        let mut loaded = BufferHistory::new();
+        assert!(loaded.deserialize(8, &mut &bytes[..]).is_err());
+        assert!(loaded.is_empty());
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(loaded.deserialize(7, &mut &truncated[..]).is_err());
+        assert!(loaded.is_empty());
+
+        let mut invalid = bytes.clone();
+        invalid[0] = b'x';
+        assert!(loaded.deserialize(7, &mut &invalid[..]).is_err());
+        assert!(loaded.is_empty());
+
+        assert!(loaded.deserialize(7, &mut &bytes[..]).is_ok());
+        assert!(!loaded.is_empty());
+    }
 }
@@ -1,12 +1,14 @@
 use crate::{
     buffer::{
-        Buffer, BufferCollection, BufferHandle, BufferIndentationConfig, CharDisplayDistances,
+        char_display_len, Buffer, BufferCollection, BufferHandle, BufferIndentationConfig,
+        CharDisplayDistances,
     },
-    buffer_history::EditKind,
+    buffer_history::{EditKind, HistoryJump, HistoryJumpError},
     buffer_position::{BufferPosition, BufferPositionIndex, BufferRange},
     client::ClientHandle,
     cursor::{Cursor, CursorCollection},
     events::{BufferEditMutGuard, EditorEventTextInsert, EditorEventWriter},
+    fold::FoldCollection,
     word_database::{WordDatabase, WordIter, WordKind},
 };
 
@@ -36,8 +38,13 @@ pub struct BufferView {
     handle: BufferViewHandle,
     pub client_handle: ClientHandle,
     pub buffer_handle: BufferHandle,
+    // the pane of its client it was opened in
+    pub(crate) pane_id: u32,
     pub cursors: CursorCollection,
+    pub folds: FoldCollection,
     pub(crate) scroll: BufferPositionIndex,
+    // only used when lines are not wrapped
+    pub(crate) horizontal_scroll: BufferPositionIndex,
 }
 
 impl BufferView {
@@ -45,11 +52,13 @@ impl BufferView {
         self.handle
     }
 
-    fn reset(&mut self, client_handle: ClientHandle, buffer_handle: BufferHandle) {
+    fn reset(&mut self, client_handle: ClientHandle, pane_id: u32, buffer_handle: BufferHandle) {
         self.alive = true;
         self.client_handle = client_handle;
         self.buffer_handle = buffer_handle;
+        self.pane_id = pane_id;
         self.cursors.mut_guard().clear();
+        self.folds.clear();
     }
 
     pub fn move_cursors(
@@ -71,6 +80,10 @@ impl BufferView {
             Err(n)
         }
 
+        fn has_display_len(&(_, c): &(usize, char)) -> bool {
+            char_display_len(c) > 0
+        }
+
         let buffer = buffers.get(self.buffer_handle).content();
 
         let mut cursors = self.cursors.mut_guard();
@@ -80,7 +93,9 @@ impl BufferView {
                 for c in &mut cursors[..] {
                     let line = buffer.lines()[c.position.line_index as usize].as_str();
                     match try_nth(
-                        line[c.position.column_byte_index as usize..].char_indices(),
+                        line[c.position.column_byte_index as usize..]
+                            .char_indices()
+                            .filter(has_display_len),
                         n,
                     ) {
                         Ok((i, _)) => c.position.column_byte_index += i as BufferPositionIndex,
@@ -96,7 +111,7 @@ impl BufferView {
 
                                 c.position.line_index += 1;
                                 let line = buffer.lines()[c.position.line_index as usize].as_str();
-                                match try_nth(line.char_indices(), n) {
+                                match try_nth(line.char_indices().filter(has_display_len), n) {
                                     Ok((i, _)) => {
                                         c.position.column_byte_index = i as _;
                                         break;
@@ -123,7 +138,8 @@ impl BufferView {
                     match try_nth(
                         line[..c.position.column_byte_index as usize]
                             .char_indices()
-                            .rev(),
+                            .rev()
+                            .filter(has_display_len),
                         n,
                     ) {
                         Ok((i, _)) => c.position.column_byte_index = i as _,
@@ -148,7 +164,8 @@ impl BufferView {
 
                                 c.position.line_index -= 1;
                                 let line = buffer.lines()[c.position.line_index as usize].as_str();
-                                match try_nth(line.char_indices().rev(), n) {
+                                match try_nth(line.char_indices().rev().filter(has_display_len), n)
+                                {
                                     Ok((i, _)) => {
                                         c.position.column_byte_index = i as _;
                                         break;
@@ -178,12 +195,10 @@ impl BufferView {
                 for i in 0..cursors[..].len() {
                     let saved_display_distance = cursors.get_saved_display_distance(i);
                     let c = &mut cursors[i];
-                    c.position.line_index = buffer
-                        .lines()
-                        .len()
-                        .saturating_sub(1)
-                        .min(c.position.line_index as usize + n)
-                        as _;
+                    let last_line_index = buffer.lines().len().saturating_sub(1);
+                    c.position.line_index =
+                        self.folds
+                            .lines_forward(c.position.line_index, n, last_line_index as _);
                     if let Some(distance) = saved_display_distance {
                         let line = buffer.lines()[c.position.line_index as usize].as_str();
                         c.position.column_byte_index = CharDisplayDistances::new(line, tab_size)
@@ -200,7 +215,7 @@ impl BufferView {
                 for i in 0..cursors[..].len() {
                     let saved_display_distance = cursors.get_saved_display_distance(i);
                     let c = &mut cursors[i];
-                    c.position.line_index = c.position.line_index.saturating_sub(n as _);
+                    c.position.line_index = self.folds.lines_backward(c.position.line_index, n);
                     if let Some(distance) = saved_display_distance {
                         let line = buffer.lines()[c.position.line_index as usize].as_str();
                         c.position.column_byte_index = CharDisplayDistances::new(line, tab_size)
@@ -570,6 +585,26 @@ impl BufferView {
             last_edit_kind = Some(edit.kind);
         }
     }
+
+    pub fn jump_history(
+        &mut self,
+        buffers: &mut BufferCollection,
+        word_database: &mut WordDatabase,
+        events: &mut EditorEventWriter,
+        jump: HistoryJump,
+    ) -> Result<(), HistoryJumpError> {
+        let (undo_count, redo_count) = buffers
+            .get_mut(self.buffer_handle)
+            .prepare_history_jump(jump)?;
+
+        for _ in 0..undo_count {
+            self.undo(buffers, word_database, events);
+        }
+        for _ in 0..redo_count {
+            self.redo(buffers, word_database, events);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -578,17 +613,36 @@ pub struct BufferViewHandle(u32);
 #[derive(Default)]
 pub struct BufferViewCollection {
     buffer_views: Vec<BufferView>,
+    // indexed by client handle
+    focused_pane_ids: Vec<u32>,
 }
 
 impl BufferViewCollection {
+    // buffer views are opened in the focused pane of their client
+    pub(crate) fn set_focused_pane_id(&mut self, client_handle: ClientHandle, pane_id: u32) {
+        let index = client_handle.0 as usize;
+        if index >= self.focused_pane_ids.len() {
+            self.focused_pane_ids.resize(index + 1, 0);
+        }
+        self.focused_pane_ids[index] = pane_id;
+    }
+
+    fn focused_pane_id(&self, client_handle: ClientHandle) -> u32 {
+        self.focused_pane_ids
+            .get(client_handle.0 as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
     pub fn add_new(
         &mut self,
         client_handle: ClientHandle,
         buffer_handle: BufferHandle,
     ) -> BufferViewHandle {
+        let pane_id = self.focused_pane_id(client_handle);
         for (i, view) in self.buffer_views.iter_mut().enumerate() {
             if !view.alive {
-                view.reset(client_handle, buffer_handle);
+                view.reset(client_handle, pane_id, buffer_handle);
                 return BufferViewHandle(i as _);
             }
         }
@@ -598,8 +652,11 @@ impl BufferViewCollection {
             handle,
             client_handle,
             buffer_handle,
+            pane_id,
             cursors: CursorCollection::new(),
+            folds: FoldCollection::default(),
             scroll: 0,
+            horizontal_scroll: 0,
         });
         handle
     }
@@ -610,6 +667,19 @@ impl BufferViewCollection {
                 view.alive = false;
             }
         }
+        self.set_focused_pane_id(client_handle, 0);
+    }
+
+    pub(crate) fn remove_buffer_views_with_pane(
+        &mut self,
+        client_handle: ClientHandle,
+        pane_id: u32,
+    ) {
+        for view in &mut self.buffer_views {
+            if view.alive && view.client_handle == client_handle && view.pane_id == pane_id {
+                view.alive = false;
+            }
+        }
     }
 
     pub fn remove_buffer_views_with_buffer(&mut self, buffer_handle: BufferHandle) {
@@ -641,11 +711,15 @@ impl BufferViewCollection {
         client_handle: ClientHandle,
         buffer_handle: BufferHandle,
     ) -> BufferViewHandle {
+        let pane_id = self.focused_pane_id(client_handle);
         let current_buffer_view_handle = self
             .buffer_views
             .iter()
             .position(|v| {
-                v.alive && v.buffer_handle == buffer_handle && v.client_handle == client_handle
+                v.alive
+                    && v.buffer_handle == buffer_handle
+                    && v.client_handle == client_handle
+                    && v.pane_id == pane_id
             })
             .map(|i| BufferViewHandle(i as _));
 
@@ -668,6 +742,7 @@ impl BufferViewCollection {
                     for c in &mut cursors[..] {
                         c.insert(range);
                     }
+                    view.folds.insert_range(range);
                 }
             }
         }
@@ -685,6 +760,7 @@ impl BufferViewCollection {
                     for c in &mut cursors[..] {
                         c.delete(range);
                     }
+                    view.folds.delete_range(range);
                 }
             }
         }
@@ -700,6 +776,7 @@ impl BufferViewCollection {
                     c.anchor = buffer.saturate_position(c.anchor);
                     c.position = buffer.saturate_position(c.position);
                 }
+                view.folds.clear();
             }
         }
     }
@@ -833,5 +910,30 @@ mod tests {
         assert_movement(&mut ctx, 1..9, 2..0, CursorMovement::WordsForward(1));
         assert_movement(&mut ctx, 1..2, 1..0, CursorMovement::WordsBackward(1));
         assert_movement(&mut ctx, 2..0, 1..9, CursorMovement::WordsBackward(1));
+
+        fn lines_forward() -> CursorMovement {
+            CursorMovement::LinesForward {
+                count: 1,
+                tab_size: 4,
+            }
+        }
+        fn lines_backward() -> CursorMovement {
+            CursorMovement::LinesBackward {
+                count: 1,
+                tab_size: 4,
+            }
+        }
+
+        let mut ctx = TestContext::with_buffer("abcdef\n漢字かな\nxe\u{301}yz");
+        assert_movement(&mut ctx, 0..2, 1..3, lines_forward());
+        assert_movement(&mut ctx, 0..3, 1..3, lines_forward());
+        assert_movement(&mut ctx, 0..4, 1..6, lines_forward());
+        assert_movement(&mut ctx, 1..6, 2..6, lines_forward());
+        assert_movement(&mut ctx, 2..4, 1..3, lines_backward());
+        assert_movement(&mut ctx, 1..9, 0..6, lines_backward());
+
+        assert_movement(&mut ctx, 2..1, 2..4, CursorMovement::ColumnsForward(1));
+        assert_movement(&mut ctx, 2..4, 2..1, CursorMovement::ColumnsBackward(1));
+        assert_movement(&mut ctx, 1..0, 1..6, CursorMovement::ColumnsForward(2));
     }
 }
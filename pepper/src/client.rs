@@ -1,13 +1,16 @@
 use std::{fmt, path::Path};
 
 use crate::{
-    buffer::{BufferCollection, BufferHandle, BufferProperties, CharDisplayDistances},
+    buffer::{BufferCollection, BufferHandle, BufferProperties, DisplayLen, LineWrap},
     buffer_position::BufferPositionIndex,
     buffer_view::{BufferView, BufferViewCollection, BufferViewHandle},
+    config::{Config, Wrap},
     editor::Editor,
     editor_utils::ResidualStrBytes,
+    layout::{FocusDirection, Layout, Rect, SplitDirection},
     navigation_history::{NavigationHistory, NavigationMovement},
     serialization::{DeserializeError, Deserializer, Serialize, Serializer},
+    ui,
 };
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -37,7 +40,8 @@ pub struct Client {
 
     pub(crate) navigation_history: NavigationHistory,
 
-    buffer_view_handle: Option<BufferViewHandle>,
+    // the focused pane holds the current buffer view
+    layout: Layout,
     stdin_buffer_handle: Option<BufferHandle>,
     stdin_residual_bytes: ResidualStrBytes,
 }
@@ -52,7 +56,7 @@ impl Client {
 
             navigation_history: NavigationHistory::default(),
 
-            buffer_view_handle: None,
+            layout: Layout::default(),
             stdin_buffer_handle: None,
             stdin_residual_bytes: ResidualStrBytes::default(),
         }
@@ -65,7 +69,7 @@ impl Client {
 
         self.navigation_history.clear();
 
-        self.buffer_view_handle = None;
+        self.layout = Layout::default();
         self.stdin_buffer_handle = None;
         self.stdin_residual_bytes = ResidualStrBytes::default();
     }
@@ -75,7 +79,11 @@ impl Client {
     }
 
     pub fn buffer_view_handle(&self) -> Option<BufferViewHandle> {
-        self.buffer_view_handle
+        self.layout.focused_buffer_view_handle()
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
     }
 
     pub fn stdin_buffer_handle(&self) -> Option<BufferHandle> {
@@ -92,20 +100,97 @@ impl Client {
     }
 
     pub(crate) fn set_buffer_view_handle_no_history(&mut self, handle: Option<BufferViewHandle>) {
-        self.buffer_view_handle = handle;
+        self.layout.set_focused_buffer_view_handle(handle);
     }
 
     pub fn has_ui(&self) -> bool {
         self.viewport_size.0 != 0 && self.viewport_size.1 != 0
     }
 
+    // the area where panes are drawn which is everything but the statusbar
+    pub fn layout_area(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.viewport_size.0,
+            height: self.viewport_size.1.saturating_sub(1),
+        }
+    }
+
+    // the size of the focused pane's buffer view
+    pub fn view_size(&self) -> (u16, u16) {
+        let view = self.layout.focused_pane_rect(self.layout_area()).view;
+        (view.width, view.height)
+    }
+
+    // splits the focused pane into a new one that displays the same buffer with its own cursors and scroll
+    pub fn split(&mut self, buffer_views: &mut BufferViewCollection, direction: SplitDirection) {
+        let buffer_view_handle = self.buffer_view_handle().map(|handle| {
+            let buffer_view = buffer_views.get(handle);
+            let buffer_handle = buffer_view.buffer_handle;
+            let scroll = buffer_view.scroll;
+            let horizontal_scroll = buffer_view.horizontal_scroll;
+            let folds = buffer_view.folds.clone();
+            let main_cursor_index = buffer_view.cursors.main_cursor_index();
+            let cursors: Vec<_> = buffer_view.cursors[..].to_vec();
+
+            let new_handle = buffer_views.add_new(self.handle, buffer_handle);
+            let buffer_view = buffer_views.get_mut(new_handle);
+            buffer_view.scroll = scroll;
+            buffer_view.horizontal_scroll = horizontal_scroll;
+            buffer_view.folds = folds;
+            let mut new_cursors = buffer_view.cursors.mut_guard();
+            new_cursors.clear();
+            for cursor in cursors {
+                new_cursors.add(cursor);
+            }
+            new_cursors.set_main_cursor_index(main_cursor_index);
+            new_handle
+        });
+        self.layout.split(direction, buffer_view_handle);
+
+        let pane_id = self.layout.focused_pane_id();
+        buffer_views.set_focused_pane_id(self.handle, pane_id);
+        if let Some(handle) = buffer_view_handle {
+            buffer_views.get_mut(handle).pane_id = pane_id;
+        }
+    }
+
+    // returns false if the focused pane is the only one
+    // otherwise the buffer views opened in it are removed
+    pub fn close_pane(&mut self, buffer_views: &mut BufferViewCollection) -> bool {
+        let pane_id = self.layout.focused_pane_id();
+        if !self.layout.close() {
+            return false;
+        }
+        buffer_views.remove_buffer_views_with_pane(self.handle, pane_id);
+        buffer_views.set_focused_pane_id(self.handle, self.layout.focused_pane_id());
+        true
+    }
+
+    pub fn resize_pane(&mut self, amount: i32) -> bool {
+        self.layout.resize(amount)
+    }
+
+    pub fn focus_pane(
+        &mut self,
+        buffer_views: &mut BufferViewCollection,
+        direction: FocusDirection,
+    ) -> bool {
+        let area = self.layout_area();
+        let focused = self.layout.focus(direction, area);
+        buffer_views.set_focused_pane_id(self.handle, self.layout.focused_pane_id());
+        focused
+    }
+
     pub fn set_view_anchor(&self, editor: &mut Editor, anchor: ViewAnchor) {
         if !self.has_ui() {
             return;
         }
 
-        if let Some(buffer_view_handle) = self.buffer_view_handle {
-            let height = self.viewport_size.1.saturating_sub(1) as usize;
+        if let Some(buffer_view_handle) = self.buffer_view_handle() {
+            let (width, height) = self.view_size();
+            let height = height as usize;
             let height_offset = match anchor {
                 ViewAnchor::Top => 0,
                 ViewAnchor::Center => height / 2,
@@ -113,54 +198,14 @@ impl Client {
             };
 
             let buffer_view = editor.buffer_views.get_mut(buffer_view_handle);
-            let main_cursor_padding_top = self.find_main_cursor_padding_top(
-                buffer_view,
-                &editor.buffers,
-                editor.config.tab_size,
-            );
+            let buffer = editor.buffers.get(buffer_view.buffer_handle);
+            let width = width - ui::gutter_width(&editor.config, buffer, width);
+            let main_cursor_padding_top =
+                find_main_cursor_padding_top(buffer_view, &editor.buffers, &editor.config, width);
             buffer_view.scroll = main_cursor_padding_top.saturating_sub(height_offset) as _;
         }
     }
 
-    pub(crate) fn scroll_to_main_cursor(
-        &self,
-        buffer_views: &mut BufferViewCollection,
-        buffers: &BufferCollection,
-        tab_size: u8,
-        margin_bottom: usize,
-    ) -> BufferPositionIndex {
-        if !self.has_ui() {
-            return 0;
-        }
-
-        let height = self.viewport_size.1.saturating_sub(1) as usize;
-        let height = height.saturating_sub(margin_bottom);
-        let half_height = height / 2;
-
-        match self.buffer_view_handle {
-            Some(buffer_view_handle) => {
-                let buffer_view = buffer_views.get_mut(buffer_view_handle);
-                let main_cursor_padding_top =
-                    self.find_main_cursor_padding_top(buffer_view, buffers, tab_size);
-
-                let mut scroll = buffer_view.scroll as usize;
-                if main_cursor_padding_top < scroll.saturating_sub(half_height) {
-                    scroll = main_cursor_padding_top.saturating_sub(half_height) as _;
-                } else if main_cursor_padding_top < scroll {
-                    scroll = main_cursor_padding_top as _;
-                } else if main_cursor_padding_top >= scroll + height + half_height {
-                    scroll = (main_cursor_padding_top + 1 - half_height) as _;
-                } else if main_cursor_padding_top >= scroll + height {
-                    scroll = (main_cursor_padding_top + 1 - height) as _;
-                }
-                let scroll = scroll as _;
-                buffer_view.scroll = scroll;
-                scroll
-            }
-            None => 0,
-        }
-    }
-
     pub(crate) fn on_stdin_input(&mut self, editor: &mut Editor, bytes: &[u8]) {
         let mut buf = Default::default();
         let texts = self.stdin_residual_bytes.receive_bytes(&mut buf, bytes);
@@ -204,10 +249,19 @@ impl Client {
         self.navigation_history
             .remove_snapshots_with_buffer_handle(buffer_handle);
 
-        if let Some(handle) = self.buffer_view_handle {
+        self.layout
+            .for_each_unfocused_buffer_view_handle_mut(|handle| {
+                if let Some(buffer_view_handle) = *handle {
+                    if editor.buffer_views.get(buffer_view_handle).buffer_handle == buffer_handle {
+                        *handle = None;
+                    }
+                }
+            });
+
+        if let Some(handle) = self.buffer_view_handle() {
             let buffer_view = editor.buffer_views.get(handle);
             if buffer_view.buffer_handle == buffer_handle {
-                self.buffer_view_handle = None;
+                self.set_buffer_view_handle_no_history(None);
                 NavigationHistory::move_in_history(self, editor, NavigationMovement::Backward);
                 NavigationHistory::move_in_history(self, editor, NavigationMovement::Forward);
             }
@@ -217,31 +271,102 @@ impl Client {
             self.stdin_buffer_handle = None;
         }
     }
+}
 
-    fn find_main_cursor_padding_top(
-        &self,
-        buffer_view: &BufferView,
-        buffers: &BufferCollection,
-        tab_size: u8,
-    ) -> usize {
-        let width = self.viewport_size.0 as usize;
+// scrolls the view so that its main cursor is visible inside a `size` view
+pub(crate) fn scroll_to_main_cursor(
+    buffer_view: &mut BufferView,
+    buffers: &BufferCollection,
+    config: &Config,
+    size: (u16, u16),
+    margin_bottom: usize,
+) -> BufferPositionIndex {
+    let height = (size.1 as usize).saturating_sub(margin_bottom);
+    let half_height = height / 2;
+
+    let main_cursor_line_index = buffer_view.cursors.main_cursor().position.line_index;
+    buffer_view.folds.reveal_line(main_cursor_line_index);
+
+    let main_cursor_padding_top =
+        find_main_cursor_padding_top(buffer_view, buffers, config, size.0);
+
+    let mut scroll = buffer_view.scroll as usize;
+    if main_cursor_padding_top < scroll.saturating_sub(half_height) {
+        scroll = main_cursor_padding_top.saturating_sub(half_height) as _;
+    } else if main_cursor_padding_top < scroll {
+        scroll = main_cursor_padding_top as _;
+    } else if main_cursor_padding_top >= scroll + height + half_height {
+        scroll = (main_cursor_padding_top + 1 - half_height) as _;
+    } else if main_cursor_padding_top >= scroll + height {
+        scroll = (main_cursor_padding_top + 1 - height) as _;
+    }
+    let scroll = scroll as _;
+    buffer_view.scroll = scroll;
+
+    let buffer = buffers.get(buffer_view.buffer_handle);
+    buffer_view.horizontal_scroll = match buffer.properties.wrap.unwrap_or(config.wrap) {
+        Wrap::None => {
+            let width = size.0 as usize;
+            let half_width = width / 2;
+
+            let position = buffer_view.cursors.main_cursor().position;
+            let line = buffer.content().lines()[position.line_index as usize].as_str();
+            let line = &line[..position.column_byte_index as usize];
+            let column = DisplayLen::from(line).total_len(config.tab_size.max(1));
+
+            let mut horizontal_scroll = buffer_view.horizontal_scroll as usize;
+            if column < horizontal_scroll.saturating_sub(half_width) {
+                horizontal_scroll = column.saturating_sub(half_width);
+            } else if column < horizontal_scroll {
+                horizontal_scroll = column;
+            } else if column >= horizontal_scroll + width + half_width {
+                horizontal_scroll = column + 1 - half_width;
+            } else if column >= horizontal_scroll + width {
+                horizontal_scroll = column + 1 - width;
+            }
+            horizontal_scroll as _
+        }
+        _ => 0,
+    };
 
-        let buffer = buffers.get(buffer_view.buffer_handle).content();
-        let position = buffer_view.cursors.main_cursor().position;
+    scroll
+}
 
-        let mut height = position.line_index as usize;
-        for display_len in &buffer.line_display_lens()[..position.line_index as usize] {
-            height += display_len.total_len(tab_size) / width;
+fn find_main_cursor_padding_top(
+    buffer_view: &BufferView,
+    buffers: &BufferCollection,
+    config: &Config,
+    width: u16,
+) -> usize {
+    let width = width.max(1) as usize;
+
+    let buffer = buffers.get(buffer_view.buffer_handle);
+    let wrap = buffer.properties.wrap.unwrap_or(config.wrap);
+    let content = buffer.content();
+    let position = buffer_view.cursors.main_cursor().position;
+
+    let mut height = 0;
+    for (line_index, line) in content
+        .lines()
+        .range(..position.line_index as usize)
+        .enumerate()
+    {
+        if !buffer_view.folds.is_line_hidden(line_index as _) {
+            let annotations = &buffer.annotations;
+            height +=
+                LineWrap::line_height(config, wrap, width, line, line_index as _, annotations);
         }
+    }
 
-        let cursor_line = buffer.lines()[position.line_index as usize].as_str();
-        let cursor_line = &cursor_line[..position.column_byte_index as usize];
-        if let Some(d) = CharDisplayDistances::new(cursor_line, tab_size).last() {
-            height += d.distance as usize / width;
-        }
+    let cursor_line = content.lines()[position.line_index as usize].as_str();
+    height += LineWrap::new(config, wrap, width, cursor_line).row_at(
+        cursor_line,
+        position.line_index,
+        &buffer.annotations,
+        position.column_byte_index as _,
+    );
 
-        height
-    }
+    height
 }
 
 #[derive(Default)]
@@ -2,6 +2,7 @@ use std::{collections::VecDeque, fmt, ops::Range};
 
 use crate::{
     buffer::{Buffer, BufferHandle, BufferReadError, BufferWriteError},
+    buffer_history::HistoryJumpError,
     buffer_view::{BufferView, BufferViewHandle},
     client::ClientHandle,
     config::ParseConfigError,
@@ -42,11 +43,14 @@ pub enum CommandError {
     KeyParseError(KeyParseAllError),
     InvalidRegisterKey,
     InvalidTokenKind,
+    UnknownTokenKind,
     PatternError(PatternError),
     InvalidEnvironmentVariable,
     InvalidProcessCommand,
     InvalidIfOp,
     InvalidGlob(InvalidGlobError),
+    InvalidHistoryJump,
+    HistoryJumpError(HistoryJumpError),
     OtherStatic(&'static str),
     OtherOwned(String),
 }
@@ -75,11 +79,16 @@ impl fmt::Display for CommandError {
             Self::KeyParseError(error) => write!(f, "key parse error: {}", error),
             Self::InvalidRegisterKey => f.write_str("invalid register key"),
             Self::InvalidTokenKind => f.write_str("invalid token kind"),
+            Self::UnknownTokenKind => {
+                f.write_str("unknown token kind (set its `token_<kind>` color first)")
+            }
             Self::PatternError(error) => write!(f, "pattern error: {}", error),
             Self::InvalidEnvironmentVariable => f.write_str("invalid environment variable"),
             Self::InvalidProcessCommand => f.write_str("invalid process command"),
             Self::InvalidIfOp => f.write_str("invalid if comparison operator"),
             Self::InvalidGlob(error) => write!(f, "glob error: {}", error),
+            Self::InvalidHistoryJump => f.write_str("invalid undo count or duration"),
+            Self::HistoryJumpError(error) => write!(f, "undo error: {}", error),
             Self::OtherStatic(error) => f.write_str(error),
             Self::OtherOwned(error) => f.write_str(&error),
         }
@@ -1,10 +1,17 @@
-use std::{env, path::Path, process::Stdio};
+use std::{
+    env,
+    ops::Range,
+    path::Path,
+    process::{Command, Stdio},
+};
 
 use crate::{
-    buffer::{BufferProperties, BufferReadError, BufferWriteError},
+    buffer::{BufferLineEnding, BufferProperties, BufferReadError, BufferWriteError},
+    buffer_history::{timestamp_now, HistoryJump},
     buffer_position::{BufferPosition, BufferPositionIndex, BufferRange},
+    buffer_view::BufferViewHandle,
     command::{CommandError, CommandIO, CommandManager, CompletionSource},
-    config::{ParseConfigError, CONFIG_NAMES},
+    config::{ParseConfigError, Wrap, CONFIG_NAMES},
     cursor::Cursor,
     editor::{EditorContext, EditorFlow},
     editor_utils::{
@@ -13,9 +20,12 @@ use crate::{
     },
     events::BufferEditMutGuard,
     help,
+    layout::{FocusDirection, SplitDirection},
     mode::{picker, readline, ModeKind},
+    pattern::{expand_replace_template, Pattern},
     platform::{PlatformRequest, ProcessTag},
-    syntax::TokenKind,
+    plugin::PluginCollection,
+    syntax::{is_valid_custom_token_kind_name, TokenKind},
     theme::{Color, THEME_COLOR_NAMES},
     word_database::{WordIndicesIter, WordKind},
 };
@@ -40,6 +50,9 @@ pub fn register_commands(commands: &mut CommandManager) {
             saving_enabled: false,
             file_backed_enabled: true,
             word_database_enabled: false,
+            line_ending: None,
+            byte_order_mark: None,
+            wrap: None,
         };
 
         let result = ctx.editor.buffer_view_handle_from_path(
@@ -131,6 +144,8 @@ pub fn register_commands(commands: &mut CommandManager) {
     r("quit", &[], |ctx, io| {
         io.args.assert_empty()?;
         if ctx.clients.iter().count() == 1 {
+            // saves that are waiting to be formatted would otherwise never happen
+            PluginCollection::on_flush_saves(ctx);
             io.assert_can_discard_all_buffers(ctx)?;
         }
         io.flow = EditorFlow::Quit;
@@ -139,6 +154,7 @@ pub fn register_commands(commands: &mut CommandManager) {
 
     r("quit-all", &[], |ctx, io| {
         io.args.assert_empty()?;
+        PluginCollection::on_flush_saves(ctx);
         io.assert_can_discard_all_buffers(ctx)?;
         io.flow = EditorFlow::QuitAll;
         Ok(())
@@ -162,6 +178,13 @@ pub fn register_commands(commands: &mut CommandManager) {
                 "file-backed-disabled" => properties.file_backed_enabled = false,
                 "word-database-enabled" => properties.word_database_enabled = true,
                 "word-database-disabled" => properties.word_database_enabled = false,
+                "line-ending-lf" => properties.line_ending = Some(BufferLineEnding::Lf),
+                "line-ending-crlf" => properties.line_ending = Some(BufferLineEnding::CrLf),
+                "byte-order-mark-enabled" => properties.byte_order_mark = Some(true),
+                "byte-order-mark-disabled" => properties.byte_order_mark = Some(false),
+                "wrap-none" => properties.wrap = Some(Wrap::None),
+                "wrap-chars" => properties.wrap = Some(Wrap::Chars),
+                "wrap-words" => properties.wrap = Some(Wrap::Words),
                 _ => return Err(CommandError::NoSuchBufferProperty),
             }
             path = arg;
@@ -205,10 +228,14 @@ pub fn register_commands(commands: &mut CommandManager) {
         io.args.assert_empty()?;
 
         let buffer_handle = io.current_buffer_handle(ctx)?;
-        let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+        // the plugin reports when the buffer is actually saved
+        if path.is_none() && PluginCollection::on_buffer_save(ctx, buffer_handle) {
+            return Ok(());
+        }
 
+        let buffer = ctx.editor.buffers.get_mut(buffer_handle);
         buffer
-            .write_to_file(path, ctx.editor.events.writer())
+            .write_to_file(path, io.bang, ctx.editor.events.writer())
             .map_err(CommandError::BufferWriteError)?;
 
         ctx.editor
@@ -221,10 +248,19 @@ pub fn register_commands(commands: &mut CommandManager) {
     r("save-all", &[], |ctx, io| {
         io.args.assert_empty()?;
 
+        let buffer_handles: Vec<_> = ctx.editor.buffers.iter().map(|b| b.handle()).collect();
+
         let mut count = 0;
+        let mut deferred_count = 0;
         let mut maybe_error = None;
-        for buffer in ctx.editor.buffers.iter_mut() {
-            match buffer.write_to_file(None, ctx.editor.events.writer()) {
+        for buffer_handle in buffer_handles {
+            if PluginCollection::on_buffer_save(ctx, buffer_handle) {
+                deferred_count += 1;
+                continue;
+            }
+
+            let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+            match buffer.write_to_file(None, io.bang, ctx.editor.events.writer()) {
                 Ok(()) => count += 1,
                 Err(BufferWriteError::SavingDisabled) => (),
                 Err(error) => maybe_error = Some(CommandError::BufferWriteError(error)),
@@ -235,10 +271,11 @@ pub fn register_commands(commands: &mut CommandManager) {
             return Err(error);
         }
 
-        ctx.editor
-            .logger
-            .write(LogKind::Status)
-            .fmt(format_args!("{} buffers saved", count));
+        let mut status = ctx.editor.logger.write(LogKind::Status);
+        status.fmt(format_args!("{} buffers saved", count));
+        if deferred_count > 0 {
+            status.fmt(format_args!(", {} waiting to be formatted", deferred_count));
+        }
         Ok(())
     });
 
@@ -325,6 +362,61 @@ pub fn register_commands(commands: &mut CommandManager) {
         Ok(())
     });
 
+    r("split-horizontal", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        let client_handle = io.client_handle()?;
+        let client = ctx.clients.get_mut(client_handle);
+        client.split(&mut ctx.editor.buffer_views, SplitDirection::Horizontal);
+        Ok(())
+    });
+
+    r("split-vertical", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        let client_handle = io.client_handle()?;
+        let client = ctx.clients.get_mut(client_handle);
+        client.split(&mut ctx.editor.buffer_views, SplitDirection::Vertical);
+        Ok(())
+    });
+
+    r("split-close", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        let client_handle = io.client_handle()?;
+        let client = ctx.clients.get_mut(client_handle);
+        if client.close_pane(&mut ctx.editor.buffer_views) {
+            Ok(())
+        } else {
+            Err(CommandError::OtherStatic("there is only one pane"))
+        }
+    });
+
+    r("split-resize", &[], |ctx, io| {
+        let amount = io.args.next()?;
+        io.args.assert_empty()?;
+
+        let amount: i32 = amount
+            .parse()
+            .map_err(|_| CommandError::OtherOwned(format!("invalid resize amount '{}'", amount)))?;
+        let client_handle = io.client_handle()?;
+        ctx.clients.get_mut(client_handle).resize_pane(amount);
+        Ok(())
+    });
+
+    static SPLIT_FOCUS_COMPLETIONS: &[CompletionSource] = &[CompletionSource::Custom(&[
+        "left", "right", "up", "down", "next", "previous",
+    ])];
+    r("split-focus", SPLIT_FOCUS_COMPLETIONS, |ctx, io| {
+        let direction = io.args.next()?;
+        io.args.assert_empty()?;
+
+        let direction = FocusDirection::from_name(direction).ok_or_else(|| {
+            CommandError::OtherOwned(format!("invalid direction '{}'", direction))
+        })?;
+        let client_handle = io.client_handle()?;
+        let client = ctx.clients.get_mut(client_handle);
+        client.focus_pane(&mut ctx.editor.buffer_views, direction);
+        Ok(())
+    });
+
     static CONFIG_COMPLETIONS: &[CompletionSource] = &[CompletionSource::Custom(CONFIG_NAMES)];
     r("config", CONFIG_COMPLETIONS, |ctx, io| {
         let key = io.args.next()?;
@@ -355,24 +447,29 @@ pub fn register_commands(commands: &mut CommandManager) {
         let value = io.args.try_next();
         io.args.assert_empty()?;
 
-        let color = ctx
-            .editor
-            .theme
-            .color_from_name(key)
-            .ok_or(CommandError::NoSuchColor)?;
-
         match value {
             Some(value) => {
+                let color = ctx
+                    .editor
+                    .theme
+                    .color_from_name(key)
+                    .ok_or(CommandError::NoSuchColor)?;
                 let value = value.strip_prefix("0x").ok_or(CommandError::InvalidColorValue)?;
                 let encoded =
                     u32::from_str_radix(value, 16).map_err(|_| CommandError::InvalidColorValue)?;
                 *color = Color::from_u32(encoded);
             }
-            None => ctx
-                .editor
-                .logger
-                .write(LogKind::Status)
-                .fmt(format_args!("0x{:0<6x}", color.into_u32())),
+            None => {
+                let color = ctx
+                    .editor
+                    .theme
+                    .color_by_name(key)
+                    .ok_or(CommandError::NoSuchColor)?;
+                ctx.editor
+                    .logger
+                    .write(LogKind::Status)
+                    .fmt(format_args!("0x{:0<6x}", color.into_u32()));
+            }
         }
 
         Ok(())
@@ -403,10 +500,42 @@ pub fn register_commands(commands: &mut CommandManager) {
     });
 
     static SYNTAX_COMPLETIONS: &[CompletionSource] = &[CompletionSource::Custom(&[
-        "keywords", "types", "symbols", "literals", "strings", "comments", "texts",
+        "keywords", "types", "symbols", "literals", "strings", "comments", "texts", "embed",
+        "folds",
     ])];
     r("syntax", SYNTAX_COMPLETIONS, |ctx, io| {
         let arg = io.args.next()?;
+
+        match arg {
+            "embed" => {
+                let syntax_path = io.args.next()?;
+                let begin = io.args.next()?;
+                let end = io.args.try_next().unwrap_or("");
+                io.args.assert_empty()?;
+
+                return match ctx
+                    .editor
+                    .syntaxes
+                    .get_last()
+                    .add_region(syntax_path, begin, end)
+                {
+                    Ok(()) => Ok(()),
+                    Err(error) => Err(CommandError::PatternError(error)),
+                };
+            }
+            "folds" => {
+                let begin = io.args.next()?;
+                let end = io.args.next()?;
+                io.args.assert_empty()?;
+
+                return match ctx.editor.syntaxes.get_last().add_fold_patterns(begin, end) {
+                    Ok(()) => Ok(()),
+                    Err(error) => Err(CommandError::PatternError(error)),
+                };
+            }
+            _ => (),
+        }
+
         let pattern = io.args.try_next();
         io.args.assert_empty()?;
 
@@ -426,7 +555,18 @@ pub fn register_commands(commands: &mut CommandManager) {
             "strings" => TokenKind::String,
             "comments" => TokenKind::Comment,
             "texts" => TokenKind::Text,
-            _ => return Err(CommandError::InvalidTokenKind),
+            _ => {
+                if !is_valid_custom_token_kind_name(arg) {
+                    return Err(CommandError::InvalidTokenKind);
+                }
+                if !ctx.editor.theme.is_known_custom_token_kind(arg) {
+                    return Err(CommandError::UnknownTokenKind);
+                }
+                ctx.editor
+                    .syntaxes
+                    .custom_token_kind(arg)
+                    .ok_or(CommandError::InvalidTokenKind)?
+            }
         };
 
         match ctx.editor.syntaxes.get_last().set_rule(token_kind, pattern) {
@@ -435,6 +575,18 @@ pub fn register_commands(commands: &mut CommandManager) {
         }
     });
 
+    r("snippet", &[], |ctx, io| {
+        let glob = io.args.next()?;
+        let trigger = io.args.next()?;
+        let body = io.args.next()?;
+        io.args.assert_empty()?;
+
+        match ctx.editor.snippets.add(glob, trigger, body) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(CommandError::InvalidGlob(error)),
+        }
+    });
+
     r("list-buffers", &[], |ctx, io| {
         io.args.assert_empty()?;
         let client_handle = io.client_handle()?;
@@ -451,10 +603,15 @@ pub fn register_commands(commands: &mut CommandManager) {
             content.push_str(buffer_path);
 
             let props = &buffer.properties;
+            let file_format = buffer.file_format();
+            let is_crlf = file_format.line_ending == BufferLineEnding::CrLf;
             if !props.history_enabled
                 || !props.saving_enabled
                 || !props.file_backed_enabled
                 || !props.word_database_enabled
+                || is_crlf
+                || file_format.byte_order_mark
+                || props.wrap.is_some()
             {
                 content.push_str(" (");
                 if !props.history_enabled {
@@ -469,9 +626,25 @@ pub fn register_commands(commands: &mut CommandManager) {
                 if !props.word_database_enabled {
                     content.push_str("word-database-disabled, ");
                 }
+                if is_crlf {
+                    content.push_str("line-ending-crlf, ");
+                }
+                if file_format.byte_order_mark {
+                    content.push_str("byte-order-mark-enabled, ");
+                }
+                if let Some(wrap) = props.wrap {
+                    let _ = write!(content, "wrap-{}, ", wrap);
+                }
                 content.truncate(content.len() - 2);
                 content.push(')');
             }
+            let buffer_content = buffer.content();
+            if props.saving_enabled
+                && buffer_content.end() != BufferPosition::zero()
+                && !buffer_content.has_final_newline()
+            {
+                content.push_str(" (no final newline)");
+            }
             if buffer.needs_save() {
                 content.push_str(" (needs save)");
             }
@@ -727,6 +900,99 @@ pub fn register_commands(commands: &mut CommandManager) {
         Ok(())
     });
 
+    r("undo-earlier", &[], |ctx, io| {
+        jump_history_in_time(ctx, io, false)
+    });
+    r("undo-later", &[], |ctx, io| {
+        jump_history_in_time(ctx, io, true)
+    });
+
+    r("undo-branch-next", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        jump_history(ctx, io, HistoryJump::NextBranch)
+    });
+
+    r("undo-branch-previous", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        jump_history(ctx, io, HistoryJump::PreviousBranch)
+    });
+
+    r("undo-branches", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        picker::undo_branches::enter_mode(ctx, buffer_view_handle);
+        Ok(())
+    });
+
+    fn jump_history_in_time(
+        ctx: &mut EditorContext,
+        io: &mut CommandIO,
+        later: bool,
+    ) -> Result<(), CommandError> {
+        let arg = io.args.try_next().unwrap_or("1");
+        io.args.assert_empty()?;
+
+        let unit_index = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+        let (value, unit) = arg.split_at(unit_index);
+        let value: usize = value
+            .parse()
+            .map_err(|_| CommandError::InvalidHistoryJump)?;
+        let secs = match unit {
+            "" => {
+                let jump = if later {
+                    HistoryJump::Later(value)
+                } else {
+                    HistoryJump::Earlier(value)
+                };
+                return jump_history(ctx, io, jump);
+            }
+            "s" => value as u64,
+            "m" => value as u64 * 60,
+            "h" => value as u64 * 60 * 60,
+            "d" => value as u64 * 60 * 60 * 24,
+            _ => return Err(CommandError::InvalidHistoryJump),
+        };
+
+        // going back in time is measured from now (the text as it was that long ago)
+        // while going forward can only be measured from when the current state was made
+        let timestamp = if later {
+            let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+            let buffer_handle = ctx
+                .editor
+                .buffer_views
+                .get(buffer_view_handle)
+                .buffer_handle;
+            let buffer = ctx.editor.buffers.get(buffer_handle);
+            let current_state = buffer.history_current_state();
+            buffer
+                .history_state_timestamp(current_state)
+                .or_else(|| buffer.history_state_timestamp(1))
+                .unwrap_or(0)
+                .saturating_add(secs)
+        } else {
+            timestamp_now().saturating_sub(secs)
+        };
+
+        jump_history(ctx, io, HistoryJump::Timestamp(timestamp))
+    }
+
+    fn jump_history(
+        ctx: &mut EditorContext,
+        io: &mut CommandIO,
+        jump: HistoryJump,
+    ) -> Result<(), CommandError> {
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        let buffer_view = ctx.editor.buffer_views.get_mut(buffer_view_handle);
+        buffer_view
+            .jump_history(
+                &mut ctx.editor.buffers,
+                &mut ctx.editor.word_database,
+                ctx.editor.events.writer(),
+                jump,
+            )
+            .map_err(CommandError::HistoryJumpError)
+    }
+
     fn change_case(
         ctx: &mut EditorContext,
         io: &mut CommandIO,
@@ -991,6 +1257,253 @@ pub fn register_commands(commands: &mut CommandManager) {
         Ok(())
     });
 
+    r("replace", &[], |ctx, io| {
+        let pattern = io.args.next()?;
+        let template = io.args.next()?;
+        io.args.assert_empty()?;
+
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        let mut replacements = Vec::new();
+        let mut replacement_texts = ctx.editor.string_pool.acquire();
+        find_replacements(
+            ctx,
+            buffer_view_handle,
+            pattern,
+            template,
+            &mut replacements,
+            &mut replacement_texts,
+        )?;
+
+        let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+        let buffer = ctx.editor.buffers.get_mut(buffer_view.buffer_handle);
+
+        let mut events = BufferEditMutGuard::new(ctx.editor.events.writer(), buffer.handle());
+        for (range, text_range) in replacements.iter().rev() {
+            buffer.delete_range(
+                &mut ctx.editor.word_database,
+                *range,
+                events.to_range_deletes(),
+            );
+            buffer.insert_text(
+                &mut ctx.editor.word_database,
+                range.from,
+                &replacement_texts[text_range.clone()],
+                events.to_text_inserts(),
+            );
+        }
+        buffer.commit_edits();
+
+        ctx.editor.string_pool.release(replacement_texts);
+        ctx.editor
+            .logger
+            .write(LogKind::Status)
+            .fmt(format_args!("{} replacements", replacements.len()));
+        Ok(())
+    });
+
+    r("replace-preview", &[], |ctx, io| {
+        let pattern = io.args.next()?;
+        let template = io.args.next()?;
+        io.args.assert_empty()?;
+
+        let client_handle = io.client_handle()?;
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        let mut replacements = Vec::new();
+        let mut replacement_texts = ctx.editor.string_pool.acquire();
+        find_replacements(
+            ctx,
+            buffer_view_handle,
+            pattern,
+            template,
+            &mut replacements,
+            &mut replacement_texts,
+        )?;
+
+        let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+        let buffer = ctx.editor.buffers.get(buffer_view.buffer_handle);
+        let buffer_path = buffer.path.to_str().unwrap_or("");
+
+        let mut content = ctx.editor.string_pool.acquire();
+        let mut replaced_line = ctx.editor.string_pool.acquire();
+        let mut line_replacements = &replacements[..];
+        while let Some(&(first, _)) = line_replacements.first() {
+            use std::fmt::Write;
+
+            let position = first.from;
+            let line_len = line_replacements
+                .iter()
+                .take_while(|(r, _)| r.from.line_index == position.line_index)
+                .count();
+            let (current, next) = line_replacements.split_at(line_len);
+            line_replacements = next;
+
+            let line = buffer.content().lines()[position.line_index as usize].as_str();
+
+            replaced_line.clear();
+            let mut index = 0;
+            for (range, text_range) in current {
+                replaced_line.push_str(&line[index..range.from.column_byte_index as usize]);
+                for c in replacement_texts[text_range.clone()].chars() {
+                    match c {
+                        '\n' => replaced_line.push_str("\\n"),
+                        c => replaced_line.push(c),
+                    }
+                }
+                index = range.to.column_byte_index as _;
+            }
+            replaced_line.push_str(&line[index..]);
+
+            let _ = writeln!(content, "{}:{}:- {}", buffer_path, position, line);
+            let _ = writeln!(content, "{}:{}:+ {}", buffer_path, position, &replaced_line);
+        }
+        if content.ends_with('\n') {
+            content.pop();
+        }
+        ctx.editor.string_pool.release(replaced_line);
+        ctx.editor.string_pool.release(replacement_texts);
+
+        let preview_buffer_view_handle = match ctx.editor.buffer_view_handle_from_path(
+            client_handle,
+            Path::new("replace.refs"),
+            BufferProperties::scratch(),
+            true,
+        ) {
+            Ok(handle) => handle,
+            Err(error) => {
+                ctx.editor.string_pool.release(content);
+                return Err(CommandError::BufferReadError(error));
+            }
+        };
+
+        let buffer_handle = ctx
+            .editor
+            .buffer_views
+            .get(preview_buffer_view_handle)
+            .buffer_handle;
+        let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+        let range = BufferRange::between(BufferPosition::zero(), buffer.content().end());
+        buffer.delete_range(
+            &mut ctx.editor.word_database,
+            range,
+            &mut ctx
+                .editor
+                .events
+                .writer()
+                .buffer_range_deletes_mut_guard(buffer_handle),
+        );
+        buffer.insert_text(
+            &mut ctx.editor.word_database,
+            BufferPosition::zero(),
+            &content,
+            &mut ctx
+                .editor
+                .events
+                .writer()
+                .buffer_text_inserts_mut_guard(buffer_handle),
+        );
+        ctx.editor.string_pool.release(content);
+
+        let client = ctx.clients.get_mut(client_handle);
+        client.set_buffer_view_handle(Some(preview_buffer_view_handle), &ctx.editor.buffer_views);
+
+        ctx.editor
+            .logger
+            .write(LogKind::Status)
+            .fmt(format_args!("{} replacements", replacements.len()));
+        Ok(())
+    });
+
+    // finds every `pattern` match inside the buffer view's selections (or the whole buffer if there's none)
+    // and pushes their ranges along with the range of their expanded `template` inside `replacement_texts`
+    fn find_replacements(
+        ctx: &mut EditorContext,
+        buffer_view_handle: BufferViewHandle,
+        pattern: &str,
+        template: &str,
+        replacements: &mut Vec<(BufferRange, Range<usize>)>,
+        replacement_texts: &mut String,
+    ) -> Result<(), CommandError> {
+        ctx.editor
+            .aux_pattern
+            .compile_searcher(pattern)
+            .map_err(CommandError::PatternError)?;
+        let pattern = &ctx.editor.aux_pattern;
+
+        let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+        let content = ctx.editor.buffers.get(buffer_view.buffer_handle).content();
+
+        let whole_buffer = BufferRange::between(BufferPosition::zero(), content.end());
+        let has_selection = buffer_view.cursors[..]
+            .iter()
+            .any(|c| c.anchor != c.position);
+        let ranges = buffer_view.cursors[..]
+            .iter()
+            .map(|c| c.to_range())
+            .filter(|_| has_selection);
+        let ranges = ranges.chain(std::iter::once(whole_buffer).filter(|_| !has_selection));
+
+        let search_anchor = pattern.search_anchor();
+        let mut captures = Vec::new();
+        for range in ranges {
+            for line_index in range.from.line_index..=range.to.line_index {
+                let line = content.lines()[line_index as usize].as_str();
+                let mut index = 0;
+                if line_index == range.from.line_index {
+                    index = range.from.column_byte_index as _;
+                }
+                // the whole line is matched so that anchors see the text around the selection
+                // but only the matches inside it are replaced
+                let end = if line_index == range.to.line_index {
+                    range.to.column_byte_index as usize
+                } else {
+                    line.len()
+                };
+
+                loop {
+                    if let Some(anchor) = search_anchor {
+                        match line[index..].find(anchor) {
+                            Some(i) => index += i,
+                            None => break,
+                        }
+                    }
+                    if index >= end {
+                        break;
+                    }
+
+                    if pattern.captures(line, index, &mut captures) {
+                        if let Some(Some(matched)) = captures.first() {
+                            if matched.end > index && matched.end <= end {
+                                let text_from = replacement_texts.len();
+                                expand_replace_template(
+                                    template,
+                                    line,
+                                    &captures,
+                                    replacement_texts,
+                                );
+                                replacements.push((
+                                    BufferRange::between(
+                                        BufferPosition::line_col(line_index, index as _),
+                                        BufferPosition::line_col(line_index, matched.end as _),
+                                    ),
+                                    text_from..replacement_texts.len(),
+                                ));
+                                index = matched.end;
+                                continue;
+                            }
+                        }
+                    }
+
+                    match line[index..].chars().next() {
+                        Some(c) => index += c.len_utf8(),
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     r("replace-with-output", &[], |ctx, io| {
         let command_text = io.args.next()?;
         io.args.assert_empty()?;
@@ -1050,6 +1563,73 @@ pub fn register_commands(commands: &mut CommandManager) {
         Ok(())
     });
 
+    r("search-workspace", &[], |ctx, io| {
+        let pattern = io.args.next()?;
+        io.args.assert_empty()?;
+
+        Pattern::new()
+            .compile_searcher(pattern)
+            .map_err(CommandError::PatternError)?;
+        let executable_path = env::current_exe()
+            .map_err(|_| CommandError::OtherStatic("could not find editor executable"))?;
+
+        let client_handle = io.client_handle()?;
+        let mut path = ctx.editor.string_pool.acquire_with(pattern);
+        path.push_str(".refs");
+        let buffer_view_handle = ctx.editor.buffer_view_handle_from_path(
+            client_handle,
+            Path::new(&path),
+            BufferProperties::scratch(),
+            true,
+        );
+        ctx.editor.string_pool.release(path);
+        let buffer_view_handle = buffer_view_handle.map_err(CommandError::BufferReadError)?;
+
+        let buffer_handle = ctx
+            .editor
+            .buffer_views
+            .get(buffer_view_handle)
+            .buffer_handle;
+        ctx.editor
+            .buffers
+            .kill_insert_processes(&mut ctx.platform, buffer_handle);
+
+        let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+        let range = BufferRange::between(BufferPosition::zero(), buffer.content().end());
+        buffer.delete_range(
+            &mut ctx.editor.word_database,
+            range,
+            &mut ctx
+                .editor
+                .events
+                .writer()
+                .buffer_range_deletes_mut_guard(buffer_handle),
+        );
+
+        let mut command = Command::new(executable_path);
+        command.arg("--search-workspace").arg(pattern);
+        ctx.editor.buffers.spawn_insert_process(
+            &mut ctx.platform,
+            command,
+            buffer_handle,
+            BufferPosition::zero(),
+            None,
+        );
+
+        let client = ctx.clients.get_mut(client_handle);
+        client.set_buffer_view_handle(Some(buffer_view_handle), &ctx.editor.buffer_views);
+        Ok(())
+    });
+
+    r("search-workspace-cancel", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        let buffer_handle = io.current_buffer_handle(ctx)?;
+        ctx.editor
+            .buffers
+            .kill_insert_processes(&mut ctx.platform, buffer_handle);
+        Ok(())
+    });
+
     r("command", &[], |ctx, io| {
         let name = io.args.next()?;
         let source = io.args.next()?;
@@ -182,6 +182,14 @@ pub fn register_expansions(commands: &mut CommandManager) {
         Ok(())
     });
 
+    r("progress", |ctx, io| {
+        io.args.assert_empty()?;
+        for progress in ctx.editor.progresses.iter() {
+            let _ = writeln!(io.output, "{}", progress);
+        }
+        Ok(())
+    });
+
     r("register", |ctx, io| {
         let key = io.args.next()?;
         io.args.assert_empty()?;
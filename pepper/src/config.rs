@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 pub enum ParseConfigError {
     NoSuchConfig,
@@ -13,6 +13,62 @@ impl fmt::Display for ParseConfigError {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    None,
+    Absolute,
+    Relative,
+}
+impl FromStr for LineNumbers {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            _ => Err(()),
+        }
+    }
+}
+impl fmt::Display for LineNumbers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Absolute => f.write_str("absolute"),
+            Self::Relative => f.write_str("relative"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    None,
+    Chars,
+    Words,
+}
+impl FromStr for Wrap {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "chars" => Ok(Self::Chars),
+            "words" => Ok(Self::Words),
+            _ => Err(()),
+        }
+    }
+}
+impl fmt::Display for Wrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Chars => f.write_str("chars"),
+            Self::Words => f.write_str("words"),
+        }
+    }
+}
+
 macro_rules! config_values {
     ($($name:ident: $type:ty = $default:expr,)*) => {
         pub static CONFIG_NAMES: &[&str] = &[$(stringify!($name),)*];
@@ -77,4 +133,12 @@ config_values! {
     completion_min_len: u8 = 3,
     picker_max_height: u8 = 8,
     status_bar_max_height: u8 = 8,
+
+    persistent_undo: bool = false,
+
+    line_numbers: LineNumbers = LineNumbers::None,
+    gutter_signs: bool = false,
+
+    wrap: Wrap = Wrap::Chars,
+    wrap_indent: bool = false,
 }
@@ -0,0 +1,475 @@
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    buffer::BufferContent,
+    buffer_position::{BufferPositionIndex, BufferRange},
+};
+
+// the line that navigates to the parent directory. it's not an entry so it's never renamed or deleted
+pub static PARENT_DIRECTORY_LINE: &str = "../";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectoryEntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+pub struct DirectoryEntry {
+    pub name: String,
+    pub kind: DirectoryEntryKind,
+    // `None` once its line was deleted
+    line_index: Option<BufferPositionIndex>,
+}
+
+#[derive(Default)]
+pub struct DirectoryChanges<'a> {
+    pub deletes: Vec<&'a DirectoryEntry>,
+    pub renames: Vec<(&'a DirectoryEntry, &'a str)>,
+    pub creates: Vec<&'a str>,
+}
+
+// the entries a directory had when it was listed into a buffer
+// each one follows its line through edits so that saving can tell renames, deletions and creations apart
+#[derive(Default)]
+pub struct DirectoryListing {
+    entries: Vec<DirectoryEntry>,
+}
+impl DirectoryListing {
+    // directories (and symlinks to them) come first and end with a '/'
+    pub fn read(&mut self, path: &Path, text: &mut String) -> io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let mut name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            // the file type of a symlink is not the one of its target
+            let file_type = entry.file_type()?;
+            let kind = if file_type.is_symlink() {
+                DirectoryEntryKind::Symlink
+            } else if file_type.is_dir() {
+                DirectoryEntryKind::Directory
+            } else {
+                DirectoryEntryKind::File
+            };
+            if entry.path().is_dir() {
+                name.push('/');
+            }
+            entries.push((name, kind));
+        }
+        entries.sort_unstable_by(|(a, _), (b, _)| {
+            b.ends_with('/')
+                .cmp(&a.ends_with('/'))
+                .then_with(|| a.cmp(b))
+        });
+
+        self.entries.clear();
+        text.push_str(PARENT_DIRECTORY_LINE);
+        for (name, kind) in entries {
+            text.push('\n');
+            text.push_str(&name);
+            self.entries.push(DirectoryEntry {
+                name,
+                kind,
+                line_index: Some(self.entries.len() as BufferPositionIndex + 1),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn insert_range(&mut self, range: BufferRange) {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count == 0 {
+            return;
+        }
+
+        let from = range.from;
+        for line_index in self
+            .entries
+            .iter_mut()
+            .filter_map(|e| e.line_index.as_mut())
+        {
+            if from.line_index < *line_index
+                || from.line_index == *line_index && from.column_byte_index == 0
+            {
+                *line_index += line_count;
+            }
+        }
+    }
+
+    pub(crate) fn delete_range(&mut self, range: BufferRange) {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count == 0 {
+            return;
+        }
+
+        // the line left where the range was keeps the entry of the line whose text survived
+        let surviving_line_index = if range.from.column_byte_index == 0 {
+            range.to.line_index
+        } else {
+            range.from.line_index
+        };
+
+        for entry in &mut self.entries {
+            if let Some(line_index) = entry.line_index {
+                if range.to.line_index < line_index {
+                    entry.line_index = Some(line_index - line_count);
+                } else if line_index == surviving_line_index {
+                    entry.line_index = Some(range.from.line_index);
+                } else if range.from.line_index <= line_index {
+                    entry.line_index = None;
+                }
+            }
+        }
+    }
+
+    // only blank lines count as deleted. otherwise names are taken as they are, whitespace included
+    pub fn changes<'a>(&'a self, content: &'a BufferContent) -> DirectoryChanges<'a> {
+        let lines = content.lines();
+        let mut changes = DirectoryChanges::default();
+
+        let mut lines_with_entry = vec![false; lines.len()];
+        for entry in &self.entries {
+            let line = match entry.line_index {
+                Some(line_index) if (line_index as usize) < lines.len() => {
+                    lines_with_entry[line_index as usize] = true;
+                    lines[line_index as usize].as_str()
+                }
+                _ => "",
+            };
+
+            if line.trim().is_empty() {
+                changes.deletes.push(entry);
+            } else if line != entry.name {
+                changes.renames.push((entry, line));
+            }
+        }
+
+        for (line, has_entry) in lines.iter().zip(lines_with_entry) {
+            let line = line.as_str();
+            if has_entry || line.trim().is_empty() || line == PARENT_DIRECTORY_LINE {
+                continue;
+            }
+
+            // a deleted line that was pasted back is left untouched
+            match changes.deletes.iter().position(|e| e.name == line) {
+                Some(index) => {
+                    changes.deletes.swap_remove(index);
+                }
+                None => changes.creates.push(line),
+            }
+        }
+
+        changes
+    }
+
+    // every change is checked before touching the filesystem so that a bad one does not leave it half done.
+    // directories that still have entries are only deleted when `force` is set
+    pub fn prepare_write(
+        &self,
+        path: &Path,
+        content: &BufferContent,
+        force: bool,
+    ) -> io::Result<DirectoryWrite> {
+        let changes = self.changes(content);
+
+        let deletes: Vec<_> = changes
+            .deletes
+            .iter()
+            .map(|e| (join_name(path, &e.name), e.kind))
+            .collect();
+        let renames: Vec<_> = changes
+            .renames
+            .iter()
+            .map(|(e, to)| (join_name(path, &e.name), join_name(path, to)))
+            .filter(|(from, to)| from != to)
+            .collect();
+
+        let is_freed = |entry_path: &Path| {
+            deletes.iter().any(|(p, _)| p == entry_path)
+                || renames.iter().any(|(from, _)| from == entry_path)
+        };
+
+        for (entry_path, kind) in &deletes {
+            if !exists(entry_path) {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            if *kind == DirectoryEntryKind::Directory
+                && !force
+                && fs::read_dir(entry_path)?.next().is_some()
+            {
+                return Err(io::Error::from(io::ErrorKind::DirectoryNotEmpty));
+            }
+        }
+        for (i, (from, to)) in renames.iter().enumerate() {
+            if !exists(from) {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            if (exists(to) && !is_freed(to)) || renames[..i].iter().any(|(_, t)| t == to) {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            check_parent_directory(to)?;
+        }
+
+        let mut creates = Vec::new();
+        for name in &changes.creates {
+            let entry_path = join_name(path, name);
+            if (exists(&entry_path) && !is_freed(&entry_path))
+                || creates.iter().any(|(p, _)| *p == entry_path)
+            {
+                continue;
+            }
+            if renames.iter().any(|(_, to)| *to == entry_path) {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            check_parent_directory(&entry_path)?;
+            creates.push((entry_path, name.ends_with('/')));
+        }
+
+        // entries renamed to the name of another renamed entry (like when swapping names)
+        // are moved out of the way first to a name nothing else uses
+        let mut temp_renames = Vec::new();
+        let mut temp_index = 0;
+        for (i, (from, _)) in renames.iter().enumerate() {
+            let is_rename_target = renames
+                .iter()
+                .enumerate()
+                .any(|(j, (_, to))| j != i && to == from);
+            if !is_rename_target {
+                continue;
+            }
+
+            let temp_path = loop {
+                let temp_path = path.join(format!(".pepper-rename-{}", temp_index));
+                temp_index += 1;
+                if !exists(&temp_path)
+                    && !renames.iter().any(|(_, to)| *to == temp_path)
+                    && !creates.iter().any(|(p, _)| *p == temp_path)
+                {
+                    break temp_path;
+                }
+            };
+            temp_renames.push((i, temp_path));
+        }
+
+        Ok(DirectoryWrite {
+            deletes,
+            renames,
+            temp_renames,
+            creates,
+        })
+    }
+}
+
+// the filesystem operations that save a directory listing once they were all checked
+pub struct DirectoryWrite {
+    deletes: Vec<(PathBuf, DirectoryEntryKind)>,
+    renames: Vec<(PathBuf, PathBuf)>,
+    temp_renames: Vec<(usize, PathBuf)>,
+    creates: Vec<(PathBuf, bool)>,
+}
+impl DirectoryWrite {
+    // deletions go first so their names can be reused by renames and creations
+    pub fn apply(mut self) -> io::Result<()> {
+        // symlinks are removed without following them
+        for (entry_path, kind) in &self.deletes {
+            match kind {
+                DirectoryEntryKind::Directory => fs::remove_dir_all(entry_path)?,
+                DirectoryEntryKind::File | DirectoryEntryKind::Symlink => {
+                    fs::remove_file(entry_path)?
+                }
+            }
+        }
+
+        for (i, temp_path) in self.temp_renames {
+            fs::rename(&self.renames[i].0, &temp_path)?;
+            self.renames[i].0 = temp_path;
+        }
+        for (from, to) in &self.renames {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(from, to)?;
+        }
+
+        for (entry_path, is_directory) in &self.creates {
+            if *is_directory {
+                fs::create_dir_all(entry_path)?;
+            } else {
+                if let Some(parent) = entry_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(entry_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// the '/' that marks directories is left out so that symlinks are never followed
+fn join_name(directory: &Path, name: &str) -> PathBuf {
+    directory.join(name.trim_end_matches('/'))
+}
+
+// also true for broken symlinks
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+// the closest ancestor that exists must be a directory for the path to be created
+fn check_parent_directory(path: &Path) -> io::Result<()> {
+    for ancestor in path.ancestors().skip(1) {
+        if let Ok(metadata) = fs::metadata(ancestor) {
+            if metadata.is_dir() {
+                return Ok(());
+            }
+            return Err(io::Error::from(io::ErrorKind::NotADirectory));
+        }
+    }
+    Ok(())
+}
+
+// the path of an entry of the `directory` listing without any '..' left that could be resolved
+pub fn entry_path(directory: &Path, name: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    for component in directory.components().chain(Path::new(name).components()) {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match path.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    path.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => (),
+                _ => path.push(".."),
+            },
+            _ => path.push(component),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        path.push(".");
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer_position::BufferPosition;
+
+    fn listing(names: &[&str]) -> (DirectoryListing, BufferContent) {
+        let mut listing = DirectoryListing::default();
+        let mut text = String::from(PARENT_DIRECTORY_LINE);
+        for (i, &name) in names.iter().enumerate() {
+            text.push('\n');
+            text.push_str(name);
+            let kind = if name.ends_with('/') {
+                DirectoryEntryKind::Directory
+            } else {
+                DirectoryEntryKind::File
+            };
+            listing.entries.push(DirectoryEntry {
+                name: name.into(),
+                kind,
+                line_index: Some(i as BufferPositionIndex + 1),
+            });
+        }
+        let mut content = BufferContent::new();
+        content.insert_text(BufferPosition::zero(), &text);
+        (listing, content)
+    }
+
+    fn insert(
+        listing: &mut DirectoryListing,
+        content: &mut BufferContent,
+        position: BufferPosition,
+        text: &str,
+    ) {
+        let range = content.insert_text(position, text);
+        listing.insert_range(range);
+    }
+
+    fn delete(listing: &mut DirectoryListing, content: &mut BufferContent, range: BufferRange) {
+        content.delete_range(range);
+        listing.delete_range(range);
+    }
+
+    fn names<'a>(entries: &[&'a DirectoryEntry]) -> Vec<&'a str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    fn renames<'a>(renames: &[(&'a DirectoryEntry, &'a str)]) -> Vec<(&'a str, &'a str)> {
+        renames
+            .iter()
+            .map(|(e, to)| (e.name.as_str(), *to))
+            .collect()
+    }
+
+    #[test]
+    fn directory_changes() {
+        let pos = BufferPosition::line_col;
+
+        let (mut listing, mut content) =
+            listing(&["dir/", "a.txt", "b.txt", "c.txt", " spaced.txt "]);
+        let changes = listing.changes(&content);
+        assert!(changes.deletes.is_empty());
+        assert!(changes.renames.is_empty());
+        assert!(changes.creates.is_empty());
+
+        insert(&mut listing, &mut content, pos(0, 0), "new/\n");
+        insert(&mut listing, &mut content, pos(3, 5), "\nd.txt");
+        delete(
+            &mut listing,
+            &mut content,
+            BufferRange::between(pos(2, 0), pos(3, 0)),
+        );
+        insert(&mut listing, &mut content, pos(4, 0), "x");
+        let changes = listing.changes(&content);
+        assert_eq!(["dir/"], &names(&changes.deletes)[..]);
+        assert_eq!([("b.txt", "xb.txt")], &renames(&changes.renames)[..]);
+        assert_eq!(&["new/", "d.txt"], &changes.creates[..]);
+
+        delete(
+            &mut listing,
+            &mut content,
+            BufferRange::between(pos(4, 6), pos(5, 5)),
+        );
+        let changes = listing.changes(&content);
+        assert_eq!(["dir/", "c.txt"], &names(&changes.deletes)[..]);
+
+        insert(&mut listing, &mut content, pos(5, 12), "\n  ");
+        let changes = listing.changes(&content);
+        assert_eq!([("b.txt", "xb.txt")], &renames(&changes.renames)[..]);
+        assert_eq!(&["new/", "d.txt"], &changes.creates[..]);
+
+        insert(&mut listing, &mut content, pos(0, 0), "c.txt\n");
+        let changes = listing.changes(&content);
+        assert_eq!(["dir/"], &names(&changes.deletes)[..]);
+        assert_eq!([("b.txt", "xb.txt")], &renames(&changes.renames)[..]);
+        assert_eq!(&["new/", "d.txt"], &changes.creates[..]);
+    }
+
+    #[test]
+    fn directory_entry_path() {
+        assert_eq!(
+            Path::new("src/main.rs"),
+            entry_path(Path::new("src"), "main.rs")
+        );
+        assert_eq!(Path::new("src/dir"), entry_path(Path::new("src/"), "dir/"));
+        assert_eq!(Path::new("."), entry_path(Path::new("src"), "../"));
+        assert_eq!(Path::new(".."), entry_path(Path::new("."), "../"));
+        assert_eq!(Path::new("../.."), entry_path(Path::new(".."), "../"));
+        assert_eq!(Path::new("/"), entry_path(Path::new("/"), "../"));
+    }
+}
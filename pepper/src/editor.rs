@@ -7,12 +7,12 @@ use crate::{
     buffer::{BufferCollection, BufferHandle, BufferProperties, BufferReadError},
     buffer_position::{BufferPosition, BufferRange},
     buffer_view::{BufferViewCollection, BufferViewHandle},
-    client::{ClientHandle, ClientManager},
+    client::{self, ClientHandle, ClientManager},
     command::CommandManager,
     config::Config,
     editor_utils::{
-        KeyMapCollection, Logger, LoggerStatusBarDisplay, MatchResult, PickerEntriesProcessBuf,
-        RegisterCollection, RegisterKey, StringPool,
+        KeyMapCollection, LogKind, Logger, LoggerStatusBarDisplay, MatchResult,
+        PickerEntriesProcessBuf, RegisterCollection, RegisterKey, StringPool,
     },
     events::{
         ClientEvent, EditorEvent, EditorEventIter, EditorEventQueue, KeyParseAllError, KeyParser,
@@ -23,6 +23,8 @@ use crate::{
     picker::Picker,
     platform::{Key, KeyCode, Platform, PlatformRequest},
     plugin::{PluginCollection, PluginHandle},
+    progress::ProgressCollection,
+    snippet::SnippetCollection,
     syntax::{HighlightResult, SyntaxCollection},
     theme::Theme,
     ui,
@@ -101,6 +103,15 @@ impl EditorContext {
         let focused_client = self.clients.focused_client();
 
         let mut status_bar_lines_buf = [""; u8::MAX as _];
+        let mut panes = Vec::new();
+
+        // resolving a custom token kind color walks its scopes so it's only done once per frame
+        let custom_token_colors: Vec<_> = self
+            .editor
+            .syntaxes
+            .custom_token_kind_names()
+            .map(|name| self.editor.theme.custom_token_color(name))
+            .collect();
 
         let mut needs_redraw = false;
         for c in self.clients.iter_mut() {
@@ -108,15 +119,6 @@ impl EditorContext {
                 continue;
             }
 
-            if let Some(handle) = c.buffer_view_handle() {
-                let buffer_view = self.editor.buffer_views.get(handle);
-                let buffer = self.editor.buffers.get_mut(buffer_view.buffer_handle);
-                if let HighlightResult::Pending = buffer.update_highlighting(&self.editor.syntaxes)
-                {
-                    needs_redraw = true;
-                }
-            }
-
             let has_focus = focused_client == Some(c.handle());
 
             let (status_bar_display, margin_bottom) = if has_focus {
@@ -131,18 +133,52 @@ impl EditorContext {
                 let status_bar_height =
                     status_bar_display.lines.len() + status_bar_display.prefix_is_line as usize;
 
-                let margin_bottom = status_bar_height.saturating_sub(1).max(picker_height);
+                let margin_bottom = status_bar_height
+                    .saturating_sub(1)
+                    .saturating_sub(picker_height);
                 (status_bar_display, margin_bottom)
             } else {
                 (LoggerStatusBarDisplay::default(), 0)
             };
 
-            let scroll = c.scroll_to_main_cursor(
-                &mut self.editor.buffer_views,
-                &self.editor.buffers,
-                self.editor.config.tab_size,
-                margin_bottom,
-            );
+            let mut area = c.layout_area();
+            if has_focus {
+                area.height = area.height.saturating_sub(picker_height as _);
+            }
+            c.layout().compute_pane_rects(area, &mut panes);
+
+            for pane in &panes {
+                let handle = match pane.buffer_view_handle {
+                    Some(handle) => handle,
+                    None => continue,
+                };
+                if pane.view.width == 0 || pane.view.height == 0 {
+                    continue;
+                }
+
+                let buffer_view = self.editor.buffer_views.get_mut(handle);
+                let buffer = self.editor.buffers.get_mut(buffer_view.buffer_handle);
+                if let HighlightResult::Pending = buffer.update_highlighting(&self.editor.syntaxes)
+                {
+                    needs_redraw = true;
+                }
+
+                // only the panes at the bottom of the layout are covered by the status bar
+                let margin_bottom = if pane.rect.y + pane.rect.height == area.y + area.height {
+                    margin_bottom
+                } else {
+                    0
+                };
+                let width = pane.view.width
+                    - ui::gutter_width(&self.editor.config, buffer, pane.view.width);
+                client::scroll_to_main_cursor(
+                    buffer_view,
+                    &self.editor.buffers,
+                    &self.editor.config,
+                    (width, pane.view.height),
+                    margin_bottom,
+                );
+            }
 
             let mut buf = self.platform.buf_pool.acquire();
             let write = buf.write_with_len(ServerEvent::bytes_variant_header_len());
@@ -150,10 +186,10 @@ impl EditorContext {
                 editor: &self.editor,
                 status_bar_display: &status_bar_display,
                 viewport_size: c.viewport_size,
-                scroll,
                 has_focus,
+                custom_token_colors: &custom_token_colors,
             };
-            ui::draw(&ctx, c.buffer_view_handle(), write);
+            ui::draw(&ctx, &panes, write);
             ServerEvent::Display(&[]).serialize_bytes_variant_header(write);
 
             let handle = c.handle();
@@ -213,6 +249,16 @@ impl EditorContext {
                         let buffer = self.editor.buffers.get_mut(handle);
                         buffer.refresh_syntax(&self.editor.syntaxes);
                         self.editor.buffer_views.on_buffer_read(buffer);
+
+                        if self.editor.config.persistent_undo {
+                            if let Err(error) = buffer.read_history_from_file() {
+                                self.editor.logger.write(LogKind::Error).fmt(format_args!(
+                                    "{}: {}",
+                                    buffer.path.display(),
+                                    error
+                                ));
+                            }
+                        }
                     }
                     EditorEvent::BufferWrite { handle, new_path } => {
                         let buffer = self.editor.buffers.get_mut(handle);
@@ -220,6 +266,16 @@ impl EditorContext {
                             buffer.refresh_syntax(&self.editor.syntaxes);
                         }
 
+                        if self.editor.config.persistent_undo {
+                            if let Err(error) = buffer.write_history_to_file() {
+                                self.editor.logger.write(LogKind::Error).fmt(format_args!(
+                                    "{}: could not write undo history: {}",
+                                    buffer.path.display(),
+                                    error
+                                ));
+                            }
+                        }
+
                         for client in self.clients.iter() {
                             if client.stdin_buffer_handle() == Some(buffer.handle()) {
                                 let mut buf = self.platform.buf_pool.acquire();
@@ -289,6 +345,7 @@ pub struct Editor {
     pub config: Config,
     pub theme: Theme,
     pub syntaxes: SyntaxCollection,
+    pub snippets: SnippetCollection,
     pub keymaps: KeyMapCollection,
 
     pub mode: Mode,
@@ -303,6 +360,7 @@ pub struct Editor {
     pub string_pool: StringPool,
 
     pub logger: Logger,
+    pub progresses: ProgressCollection,
     pub aux_pattern: Pattern,
 
     pub commands: CommandManager,
@@ -319,6 +377,7 @@ impl Editor {
             config: Config::default(),
             theme: Theme::default(),
             syntaxes: SyntaxCollection::new(),
+            snippets: SnippetCollection::default(),
             keymaps: KeyMapCollection::default(),
 
             mode: Mode::default(),
@@ -334,6 +393,7 @@ impl Editor {
             string_pool: StringPool::default(),
 
             logger: Logger::new(),
+            progresses: ProgressCollection::default(),
             aux_pattern: Pattern::new(),
 
             commands: CommandManager::new(),
@@ -0,0 +1,416 @@
+use crate::{
+    buffer::Buffer,
+    buffer_position::{BufferPositionIndex, BufferRange},
+    pattern::Pattern,
+    syntax::{SyntaxCollection, TokenKind},
+};
+
+// a range of lines where the first one stays visible and the rest can be hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fold {
+    pub start_line_index: BufferPositionIndex,
+    pub end_line_index: BufferPositionIndex,
+}
+impl Fold {
+    pub fn contains_line(&self, line_index: BufferPositionIndex) -> bool {
+        self.start_line_index <= line_index && line_index <= self.end_line_index
+    }
+
+    pub fn hidden_line_count(&self) -> BufferPositionIndex {
+        self.end_line_index - self.start_line_index
+    }
+
+    pub(crate) fn insert_range(&mut self, range: BufferRange) {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count == 0 {
+            return;
+        }
+
+        let from = range.from;
+        if from.line_index < self.start_line_index
+            || from.line_index == self.start_line_index && from.column_byte_index == 0
+        {
+            self.start_line_index += line_count;
+            self.end_line_index += line_count;
+        } else if from.line_index <= self.end_line_index {
+            self.end_line_index += line_count;
+        }
+    }
+
+    // returns false when the fold no longer spans more than one line
+    pub(crate) fn delete_range(&mut self, range: BufferRange) -> bool {
+        let line_count = range.to.line_index - range.from.line_index;
+        if line_count > 0 {
+            let delete_line = |line_index: BufferPositionIndex| {
+                if line_index <= range.from.line_index {
+                    line_index
+                } else if range.to.line_index <= line_index {
+                    line_index - line_count
+                } else {
+                    range.from.line_index
+                }
+            };
+            self.start_line_index = delete_line(self.start_line_index);
+            self.end_line_index = delete_line(self.end_line_index);
+        }
+        self.start_line_index < self.end_line_index
+    }
+}
+
+// the closed folds of a buffer view. they're sorted and never overlap
+#[derive(Default, Clone)]
+pub struct FoldCollection {
+    folds: Vec<Fold>,
+}
+impl FoldCollection {
+    pub fn all(&self) -> &[Fold] {
+        &self.folds
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    // closing a fold opens the ones it overlaps with
+    pub fn fold(&mut self, fold: Fold) {
+        self.folds.retain(|f| {
+            f.end_line_index < fold.start_line_index || fold.end_line_index < f.start_line_index
+        });
+        let index = self
+            .folds
+            .partition_point(|f| f.start_line_index < fold.start_line_index);
+        self.folds.insert(index, fold);
+    }
+
+    // returns the closed fold whose lines contain `line_index` (including its visible first line)
+    pub fn fold_at(&self, line_index: BufferPositionIndex) -> Option<Fold> {
+        let index = self
+            .folds
+            .partition_point(|f| f.start_line_index <= line_index);
+        let fold = *self.folds.get(index.checked_sub(1)?)?;
+        if fold.end_line_index >= line_index {
+            Some(fold)
+        } else {
+            None
+        }
+    }
+
+    pub fn unfold_at(&mut self, line_index: BufferPositionIndex) -> bool {
+        match self.fold_at(line_index) {
+            Some(fold) => {
+                self.folds.retain(|f| *f != fold);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_line_hidden(&self, line_index: BufferPositionIndex) -> bool {
+        match self.fold_at(line_index) {
+            Some(fold) => fold.start_line_index < line_index,
+            None => false,
+        }
+    }
+
+    // opens the fold that hides `line_index` if any
+    pub fn reveal_line(&mut self, line_index: BufferPositionIndex) {
+        if self.is_line_hidden(line_index) {
+            self.unfold_at(line_index);
+        }
+    }
+
+    pub fn lines_forward(
+        &self,
+        line_index: BufferPositionIndex,
+        count: usize,
+        last_line_index: BufferPositionIndex,
+    ) -> BufferPositionIndex {
+        let mut line_index = line_index;
+        for _ in 0..count {
+            let next_line_index = match self.fold_at(line_index) {
+                Some(fold) => fold.end_line_index + 1,
+                None => line_index + 1,
+            };
+            if next_line_index > last_line_index {
+                break;
+            }
+            line_index = next_line_index;
+        }
+        line_index
+    }
+
+    pub fn lines_backward(
+        &self,
+        line_index: BufferPositionIndex,
+        count: usize,
+    ) -> BufferPositionIndex {
+        let mut line_index = line_index;
+        for _ in 0..count {
+            if line_index == 0 {
+                break;
+            }
+            line_index -= 1;
+            if let Some(fold) = self.fold_at(line_index) {
+                line_index = fold.start_line_index;
+            }
+        }
+        line_index
+    }
+
+    pub(crate) fn insert_range(&mut self, range: BufferRange) {
+        for fold in &mut self.folds {
+            fold.insert_range(range);
+        }
+    }
+
+    // folds around the deleted lines may end up touching so they get merged into a single one
+    pub(crate) fn delete_range(&mut self, range: BufferRange) {
+        self.folds.retain_mut(|f| f.delete_range(range));
+        self.folds.dedup_by(|next, previous| {
+            if next.start_line_index <= previous.end_line_index {
+                previous.end_line_index = previous.end_line_index.max(next.end_line_index);
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+// finds the innermost foldable range that contains `line_index`.
+// ranges provided by plugins (like lsp servers) come first, then the buffer syntax fold patterns
+// and, at last, the indentation
+pub fn find_fold_range(
+    buffer: &Buffer,
+    syntaxes: &SyntaxCollection,
+    tab_size: u8,
+    line_index: BufferPositionIndex,
+) -> Option<Fold> {
+    plugin_fold_range(buffer, line_index)
+        .or_else(|| {
+            let syntax = syntaxes.get(buffer.syntax_handle());
+            syntax_fold_range(buffer, syntax.fold_patterns(), line_index)
+        })
+        .or_else(|| indentation_fold_range(buffer, tab_size, line_index))
+}
+
+fn plugin_fold_range(buffer: &Buffer, line_index: BufferPositionIndex) -> Option<Fold> {
+    buffer
+        .fold_ranges
+        .all()
+        .iter()
+        .map(|r| r.fold)
+        .filter(|f| f.contains_line(line_index))
+        .min_by_key(|f| f.hidden_line_count())
+}
+
+fn syntax_fold_range(
+    buffer: &Buffer,
+    patterns: &[(Pattern, Pattern)],
+    line_index: BufferPositionIndex,
+) -> Option<Fold> {
+    if patterns.is_empty() {
+        return None;
+    }
+
+    let highlighted = buffer.highlighted();
+    let mut open_line_indices = Vec::new();
+    let mut matches = Vec::new();
+    for (i, line) in buffer.content().lines().iter().enumerate() {
+        let line = line.as_str();
+
+        matches.clear();
+        for (begin, end) in patterns {
+            let begins = begin.match_indices(line, begin.search_anchor());
+            matches.extend(begins.map(|r| (r.start, true)));
+            let ends = end.match_indices(line, end.search_anchor());
+            matches.extend(ends.map(|r| (r.start, false)));
+        }
+        matches.sort_unstable_by_key(|&(index, _)| index);
+
+        let tokens = highlighted.line_tokens(i);
+        for &(index, is_begin) in &matches {
+            let is_inside_string_or_comment = tokens.iter().any(|t| {
+                matches!(t.kind, TokenKind::String | TokenKind::Comment) && t.contains(index as _)
+            });
+            if is_inside_string_or_comment {
+                continue;
+            }
+
+            if is_begin {
+                open_line_indices.push(i as BufferPositionIndex);
+            } else if let Some(start_line_index) = open_line_indices.pop() {
+                // inner ranges close first so the first one to contain the line is the innermost
+                let fold = Fold {
+                    start_line_index,
+                    end_line_index: i as _,
+                };
+                if start_line_index < fold.end_line_index && fold.contains_line(line_index) {
+                    return Some(fold);
+                }
+            }
+        }
+
+        if i >= line_index as usize && open_line_indices.is_empty() {
+            break;
+        }
+    }
+
+    None
+}
+
+fn indentation_fold_range(
+    buffer: &Buffer,
+    tab_size: u8,
+    line_index: BufferPositionIndex,
+) -> Option<Fold> {
+    // blank lines have no indentation of their own
+    fn indentation(line: &str, tab_size: u8) -> Option<usize> {
+        let mut len = 0;
+        for c in line.chars() {
+            match c {
+                ' ' => len += 1,
+                '\t' => len += tab_size.max(1) as usize,
+                _ => return Some(len),
+            }
+        }
+        None
+    }
+
+    let lines = buffer.content().lines();
+    let line_index = line_index as usize;
+    let next_indentation = lines
+        .range(line_index + 1..)
+        .find_map(|l| indentation(l.as_str(), tab_size));
+
+    let start_line_index = match indentation(lines[line_index].as_str(), tab_size) {
+        Some(level) if next_indentation.map(|l| l > level).unwrap_or(false) => line_index,
+        level => {
+            let level = level.or(next_indentation)?;
+            (0..line_index).rev().find(|&i| {
+                indentation(lines[i].as_str(), tab_size)
+                    .map(|l| l < level)
+                    .unwrap_or(false)
+            })?
+        }
+    };
+
+    let start_level = indentation(lines[start_line_index].as_str(), tab_size)?;
+    let mut end_line_index = start_line_index;
+    for (i, line) in lines.iter().enumerate().skip(start_line_index + 1) {
+        match indentation(line.as_str(), tab_size) {
+            Some(level) if level > start_level => end_line_index = i,
+            Some(_) => break,
+            None => (),
+        }
+    }
+
+    if start_line_index < end_line_index {
+        Some(Fold {
+            start_line_index: start_line_index as _,
+            end_line_index: end_line_index as _,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        buffer::{BufferCollection, BufferProperties},
+        buffer_position::BufferPosition,
+        events::EditorEventQueue,
+        word_database::WordDatabase,
+    };
+
+    fn fold(start_line_index: BufferPositionIndex, end_line_index: BufferPositionIndex) -> Fold {
+        Fold {
+            start_line_index,
+            end_line_index,
+        }
+    }
+
+    fn range(from_line_index: u32, to_line_index: u32) -> BufferRange {
+        BufferRange::between(
+            BufferPosition::line_col(from_line_index, 1),
+            BufferPosition::line_col(to_line_index, 1),
+        )
+    }
+
+    #[test]
+    fn fold_collection() {
+        let mut folds = FoldCollection::default();
+        folds.fold(fold(6, 8));
+        folds.fold(fold(1, 3));
+        folds.fold(fold(2, 3));
+        assert_eq!(&[fold(2, 3), fold(6, 8)], folds.all());
+
+        folds.fold(fold(1, 4));
+        assert_eq!(&[fold(1, 4), fold(6, 8)], folds.all());
+
+        assert_eq!(None, folds.fold_at(0));
+        assert_eq!(Some(fold(1, 4)), folds.fold_at(1));
+        assert!(!folds.is_line_hidden(1));
+        assert!(folds.is_line_hidden(4));
+        assert!(!folds.is_line_hidden(5));
+
+        assert_eq!(5, folds.lines_forward(0, 2, 10));
+        assert_eq!(9, folds.lines_forward(5, 2, 10));
+        assert_eq!(9, folds.lines_forward(6, 5, 9));
+        assert_eq!(6, folds.lines_backward(9, 1));
+        assert_eq!(1, folds.lines_backward(6, 2));
+        assert_eq!(0, folds.lines_backward(6, 5));
+
+        folds.insert_range(range(2, 4));
+        assert_eq!(&[fold(1, 6), fold(8, 10)], folds.all());
+        folds.delete_range(range(0, 7));
+        assert_eq!(&[fold(1, 3)], folds.all());
+
+        folds.reveal_line(1);
+        assert_eq!(1, folds.all().len());
+        folds.reveal_line(2);
+        assert!(folds.all().is_empty());
+
+        folds.fold(fold(1, 3));
+        folds.fold(fold(5, 7));
+        folds.delete_range(range(2, 6));
+        assert_eq!(&[fold(1, 3)], folds.all());
+    }
+
+    #[test]
+    fn fold_ranges() {
+        let mut events = EditorEventQueue::default();
+        let mut word_database = WordDatabase::new();
+        let mut buffers = BufferCollection::default();
+        let buffer = buffers.add_new();
+        buffer.properties = BufferProperties::text();
+        buffer.insert_text(
+            &mut word_database,
+            BufferPosition::zero(),
+            "fn a() {\n    if b {\n        c();\n\n    }\n    d();\n}\n",
+            &mut events
+                .writer()
+                .buffer_text_inserts_mut_guard(buffer.handle()),
+        );
+
+        assert_eq!(Some(fold(1, 2)), indentation_fold_range(buffer, 4, 1));
+        assert_eq!(Some(fold(1, 2)), indentation_fold_range(buffer, 4, 2));
+        assert_eq!(Some(fold(0, 5)), indentation_fold_range(buffer, 4, 3));
+        assert_eq!(Some(fold(0, 5)), indentation_fold_range(buffer, 4, 4));
+        assert_eq!(Some(fold(0, 5)), indentation_fold_range(buffer, 4, 0));
+        assert_eq!(None, indentation_fold_range(buffer, 4, 6));
+
+        let mut begin = Pattern::new();
+        begin.compile("%{").unwrap();
+        let mut end = Pattern::new();
+        end.compile("%}").unwrap();
+        let patterns = [(begin, end)];
+        assert_eq!(Some(fold(1, 4)), syntax_fold_range(buffer, &patterns, 2));
+        assert_eq!(Some(fold(1, 4)), syntax_fold_range(buffer, &patterns, 1));
+        assert_eq!(Some(fold(0, 6)), syntax_fold_range(buffer, &patterns, 5));
+        assert_eq!(None, syntax_fold_range(buffer, &patterns, 7));
+    }
+}
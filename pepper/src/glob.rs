@@ -13,6 +13,7 @@ impl From<TryFromIntError> for InvalidGlobError {
     }
 }
 
+#[derive(Clone)]
 enum Op {
     Slice { from: u16, to: u16 },
     Separator,
@@ -25,7 +26,7 @@ enum Op {
     SubPattern { len: u16 },
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Glob {
     texts: String,
     ops: Vec<Op>,
@@ -0,0 +1,599 @@
+use crate::buffer_view::BufferViewHandle;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    // panes are placed one above the other
+    Horizontal,
+    // panes are placed side by side
+    Vertical,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FocusDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+    Next,
+    Previous,
+}
+impl FocusDirection {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(Self::Left),
+            "right" => Some(Self::Right),
+            "up" => Some(Self::Up),
+            "down" => Some(Self::Down),
+            "next" => Some(Self::Next),
+            "previous" => Some(Self::Previous),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Clone, Copy)]
+pub struct PaneRect {
+    id: u32,
+    pub buffer_view_handle: Option<BufferViewHandle>,
+    pub has_focus: bool,
+    // the whole area of the pane
+    pub rect: Rect,
+    // where the buffer view is drawn
+    pub view: Rect,
+    // the line below the view that shows what the pane is displaying (only when there are several panes)
+    pub title: Option<Rect>,
+    // the column that separates the pane from its right neighbour
+    pub separator: Option<Rect>,
+}
+
+const MIN_SPLIT_RATIO: i32 = 10;
+const MAX_SPLIT_RATIO: i32 = 90;
+
+struct Pane {
+    id: u32,
+    buffer_view_handle: Option<BufferViewHandle>,
+}
+
+enum LayoutNode {
+    Pane(Pane),
+    Split {
+        direction: SplitDirection,
+        // percentage of the space taken by the first child
+        ratio: u8,
+        children: Box<[LayoutNode; 2]>,
+    },
+}
+impl LayoutNode {
+    fn placeholder() -> Self {
+        Self::Pane(Pane {
+            id: u32::MAX,
+            buffer_view_handle: None,
+        })
+    }
+
+    fn find_pane(&self, id: u32) -> Option<&Pane> {
+        match self {
+            Self::Pane(pane) => {
+                if pane.id == id {
+                    Some(pane)
+                } else {
+                    None
+                }
+            }
+            Self::Split { children, .. } => children.iter().find_map(|c| c.find_pane(id)),
+        }
+    }
+
+    fn find_pane_mut(&mut self, id: u32) -> Option<&mut Pane> {
+        match self {
+            Self::Pane(pane) => {
+                if pane.id == id {
+                    Some(pane)
+                } else {
+                    None
+                }
+            }
+            Self::Split { children, .. } => {
+                for child in children.iter_mut() {
+                    if let Some(pane) = child.find_pane_mut(id) {
+                        return Some(pane);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn find_pane_node_mut(&mut self, id: u32) -> Option<&mut LayoutNode> {
+        if matches!(self, Self::Pane(pane) if pane.id == id) {
+            return Some(self);
+        }
+        match self {
+            Self::Pane(_) => None,
+            Self::Split { children, .. } => {
+                for child in children.iter_mut() {
+                    if let Some(node) = child.find_pane_node_mut(id) {
+                        return Some(node);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    // returns the split which has the pane with `id` as one of its direct children
+    fn find_parent_mut(&mut self, id: u32) -> Option<&mut LayoutNode> {
+        let is_parent = match self {
+            Self::Pane(_) => return None,
+            Self::Split { children, .. } => children
+                .iter()
+                .any(|c| matches!(c, Self::Pane(pane) if pane.id == id)),
+        };
+        if is_parent {
+            return Some(self);
+        }
+        match self {
+            Self::Pane(_) => None,
+            Self::Split { children, .. } => {
+                for child in children.iter_mut() {
+                    if let Some(node) = child.find_parent_mut(id) {
+                        return Some(node);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn first_pane_id(&self) -> u32 {
+        match self {
+            Self::Pane(pane) => pane.id,
+            Self::Split { children, .. } => children[0].first_pane_id(),
+        }
+    }
+
+    fn last_pane_id(&self) -> u32 {
+        match self {
+            Self::Pane(pane) => pane.id,
+            Self::Split { children, .. } => children[1].last_pane_id(),
+        }
+    }
+
+    // returns `None` if the pane is not inside this node
+    // or `Some(resized)` where `resized` tells if an enclosing split was already resized
+    fn resize(&mut self, id: u32, amount: i32) -> Option<bool> {
+        match self {
+            Self::Pane(pane) => {
+                if pane.id == id {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            Self::Split {
+                ratio, children, ..
+            } => {
+                for (i, child) in children.iter_mut().enumerate() {
+                    match child.resize(id, amount) {
+                        Some(false) => {
+                            let amount = if i == 0 { amount } else { -amount };
+                            let new_ratio = *ratio as i32 + amount;
+                            *ratio = new_ratio.clamp(MIN_SPLIT_RATIO, MAX_SPLIT_RATIO) as _;
+                            return Some(true);
+                        }
+                        Some(true) => return Some(true),
+                        None => (),
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn for_each_pane_mut<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Pane),
+    {
+        match self {
+            Self::Pane(pane) => f(pane),
+            Self::Split { children, .. } => {
+                for child in children.iter_mut() {
+                    child.for_each_pane_mut(f);
+                }
+            }
+        }
+    }
+
+    fn compute_pane_rects(
+        &self,
+        rect: Rect,
+        has_separator: bool,
+        has_title: bool,
+        focused_pane_id: u32,
+        rects: &mut Vec<PaneRect>,
+    ) {
+        match self {
+            Self::Pane(pane) => {
+                let mut view = rect;
+                let separator = if has_separator && view.width > 0 {
+                    view.width -= 1;
+                    Some(Rect {
+                        x: view.x + view.width,
+                        y: view.y,
+                        width: 1,
+                        height: view.height,
+                    })
+                } else {
+                    None
+                };
+                let title = if has_title && view.height > 0 {
+                    view.height -= 1;
+                    Some(Rect {
+                        x: view.x,
+                        y: view.y + view.height,
+                        width: view.width,
+                        height: 1,
+                    })
+                } else {
+                    None
+                };
+
+                rects.push(PaneRect {
+                    id: pane.id,
+                    buffer_view_handle: pane.buffer_view_handle,
+                    has_focus: pane.id == focused_pane_id,
+                    rect,
+                    view,
+                    title,
+                    separator,
+                });
+            }
+            Self::Split {
+                direction,
+                ratio,
+                children,
+            } => {
+                let (first, second) = match direction {
+                    SplitDirection::Horizontal => {
+                        let height = split_len(rect.height, *ratio);
+                        let first = Rect { height, ..rect };
+                        let second = Rect {
+                            y: rect.y + height,
+                            height: rect.height - height,
+                            ..rect
+                        };
+                        (first, second)
+                    }
+                    SplitDirection::Vertical => {
+                        let width = split_len(rect.width, *ratio);
+                        let first = Rect { width, ..rect };
+                        let second = Rect {
+                            x: rect.x + width,
+                            width: rect.width - width,
+                            ..rect
+                        };
+                        (first, second)
+                    }
+                };
+
+                let first_has_separator =
+                    has_separator || matches!(direction, SplitDirection::Vertical);
+                children[0].compute_pane_rects(
+                    first,
+                    first_has_separator,
+                    has_title,
+                    focused_pane_id,
+                    rects,
+                );
+                children[1].compute_pane_rects(
+                    second,
+                    has_separator,
+                    has_title,
+                    focused_pane_id,
+                    rects,
+                );
+            }
+        }
+    }
+}
+
+// each side keeps at least a line for its title and another for its view
+fn split_len(len: u16, ratio: u8) -> u16 {
+    const MIN_LEN: u16 = 2;
+    if len < MIN_LEN * 2 {
+        return len / 2;
+    }
+    let first = (len as u32 * ratio as u32 / 100) as u16;
+    first.clamp(MIN_LEN, len - MIN_LEN)
+}
+
+// a tree of panes inside a client where each pane displays a buffer view
+// panes are drawn in the order they're returned from `compute_pane_rects` which is always left to right
+pub struct Layout {
+    root: LayoutNode,
+    focused_pane_id: u32,
+    next_pane_id: u32,
+}
+
+impl Layout {
+    pub fn is_split(&self) -> bool {
+        matches!(self.root, LayoutNode::Split { .. })
+    }
+
+    pub fn focused_pane_id(&self) -> u32 {
+        self.focused_pane_id
+    }
+
+    pub fn focused_buffer_view_handle(&self) -> Option<BufferViewHandle> {
+        self.root
+            .find_pane(self.focused_pane_id)
+            .and_then(|p| p.buffer_view_handle)
+    }
+
+    pub fn set_focused_buffer_view_handle(&mut self, handle: Option<BufferViewHandle>) {
+        if let Some(pane) = self.root.find_pane_mut(self.focused_pane_id) {
+            pane.buffer_view_handle = handle;
+        }
+    }
+
+    // iterates the buffer view handles of every pane but the focused one
+    pub fn for_each_unfocused_buffer_view_handle_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Option<BufferViewHandle>),
+    {
+        let focused_pane_id = self.focused_pane_id;
+        self.root.for_each_pane_mut(&mut |pane| {
+            if pane.id != focused_pane_id {
+                f(&mut pane.buffer_view_handle);
+            }
+        });
+    }
+
+    // splits the focused pane and focuses the new one which displays `buffer_view_handle`
+    pub fn split(
+        &mut self,
+        direction: SplitDirection,
+        buffer_view_handle: Option<BufferViewHandle>,
+    ) {
+        let id = self.next_pane_id;
+        self.next_pane_id += 1;
+
+        let node = match self.root.find_pane_node_mut(self.focused_pane_id) {
+            Some(node) => node,
+            None => return,
+        };
+        let focused = std::mem::replace(node, LayoutNode::placeholder());
+        let new = LayoutNode::Pane(Pane {
+            id,
+            buffer_view_handle,
+        });
+        *node = LayoutNode::Split {
+            direction,
+            ratio: 50,
+            children: Box::new([focused, new]),
+        };
+        self.focused_pane_id = id;
+    }
+
+    // closes the focused pane and focuses its closest sibling
+    // returns false if it's the only pane
+    pub fn close(&mut self) -> bool {
+        let focused_pane_id = self.focused_pane_id;
+        let node = match self.root.find_parent_mut(focused_pane_id) {
+            Some(node) => node,
+            None => return false,
+        };
+        let children = match node {
+            LayoutNode::Split { children, .. } => std::mem::replace(
+                children,
+                Box::new([LayoutNode::placeholder(), LayoutNode::placeholder()]),
+            ),
+            LayoutNode::Pane(_) => return false,
+        };
+        let [first, second] = *children;
+        let sibling = if matches!(&first, LayoutNode::Pane(pane) if pane.id == focused_pane_id) {
+            self.focused_pane_id = second.first_pane_id();
+            second
+        } else {
+            self.focused_pane_id = first.last_pane_id();
+            first
+        };
+        *node = sibling;
+        true
+    }
+
+    // grows (or shrinks if `amount` is negative) the focused pane by `amount` percent of its enclosing split
+    pub fn resize(&mut self, amount: i32) -> bool {
+        self.root.resize(self.focused_pane_id, amount) == Some(true)
+    }
+
+    // returns false if there's no pane in that direction
+    pub fn focus(&mut self, direction: FocusDirection, area: Rect) -> bool {
+        let mut rects = Vec::new();
+        self.compute_pane_rects(area, &mut rects);
+        let focused_index = match rects.iter().position(|r| r.has_focus) {
+            Some(index) => index,
+            None => return false,
+        };
+        let focused = rects[focused_index].rect;
+
+        let overlaps = |a_start: u16, a_len: u16, b_start: u16, b_len: u16| {
+            a_start < b_start + b_len && b_start < a_start + a_len
+        };
+        let target = match direction {
+            FocusDirection::Next => Some(&rects[(focused_index + 1) % rects.len()]),
+            FocusDirection::Previous => {
+                Some(&rects[(focused_index + rects.len() - 1) % rects.len()])
+            }
+            FocusDirection::Left => rects.iter().find(|r| {
+                r.rect.x + r.rect.width == focused.x
+                    && overlaps(r.rect.y, r.rect.height, focused.y, focused.height)
+            }),
+            FocusDirection::Right => rects.iter().find(|r| {
+                r.rect.x == focused.x + focused.width
+                    && overlaps(r.rect.y, r.rect.height, focused.y, focused.height)
+            }),
+            FocusDirection::Up => rects.iter().find(|r| {
+                r.rect.y + r.rect.height == focused.y
+                    && overlaps(r.rect.x, r.rect.width, focused.x, focused.width)
+            }),
+            FocusDirection::Down => rects.iter().find(|r| {
+                r.rect.y == focused.y + focused.height
+                    && overlaps(r.rect.x, r.rect.width, focused.x, focused.width)
+            }),
+        };
+
+        match target {
+            Some(target) if target.id != self.focused_pane_id => {
+                self.focused_pane_id = target.id;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn compute_pane_rects(&self, area: Rect, rects: &mut Vec<PaneRect>) {
+        rects.clear();
+        let has_title = self.is_split();
+        self.root
+            .compute_pane_rects(area, false, has_title, self.focused_pane_id, rects);
+    }
+
+    pub fn focused_pane_rect(&self, area: Rect) -> PaneRect {
+        let mut rects = Vec::new();
+        self.compute_pane_rects(area, &mut rects);
+        let index = rects.iter().position(|r| r.has_focus).unwrap_or(0);
+        rects[index]
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            root: LayoutNode::Pane(Pane {
+                id: 0,
+                buffer_view_handle: None,
+            }),
+            focused_pane_id: 0,
+            next_pane_id: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rects(layout: &Layout, area: Rect) -> Vec<PaneRect> {
+        let mut rects = Vec::new();
+        layout.compute_pane_rects(area, &mut rects);
+        rects
+    }
+
+    #[test]
+    fn split_and_close() {
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: 81,
+            height: 20,
+        };
+
+        let mut layout = Layout::default();
+        assert!(!layout.is_split());
+        assert!(!layout.close());
+
+        let single = rects(&layout, area);
+        assert_eq!(1, single.len());
+        assert_eq!(area, single[0].view);
+        assert!(single[0].title.is_none());
+        assert!(single[0].separator.is_none());
+
+        layout.split(SplitDirection::Vertical, None);
+        layout.split(SplitDirection::Horizontal, None);
+        let panes = rects(&layout, area);
+        assert_eq!(3, panes.len());
+        assert!(panes[2].has_focus);
+
+        assert_eq!(
+            Rect {
+                x: 0,
+                y: 0,
+                width: 39,
+                height: 19
+            },
+            panes[0].view
+        );
+        assert_eq!(
+            Some(Rect {
+                x: 39,
+                y: 0,
+                width: 1,
+                height: 20
+            }),
+            panes[0].separator
+        );
+        assert_eq!(
+            Some(Rect {
+                x: 0,
+                y: 19,
+                width: 39,
+                height: 1
+            }),
+            panes[0].title
+        );
+        assert_eq!(
+            Rect {
+                x: 40,
+                y: 0,
+                width: 41,
+                height: 9
+            },
+            panes[1].view
+        );
+        assert!(panes[1].separator.is_none());
+        assert_eq!(
+            Rect {
+                x: 40,
+                y: 10,
+                width: 41,
+                height: 10
+            },
+            panes[2].rect
+        );
+
+        assert!(layout.focus(FocusDirection::Up, area));
+        assert!(rects(&layout, area)[1].has_focus);
+        assert!(!layout.focus(FocusDirection::Right, area));
+        assert!(layout.focus(FocusDirection::Left, area));
+        assert!(rects(&layout, area)[0].has_focus);
+        assert!(layout.focus(FocusDirection::Previous, area));
+        assert!(rects(&layout, area)[2].has_focus);
+        assert!(layout.focus(FocusDirection::Next, area));
+        assert!(rects(&layout, area)[0].has_focus);
+
+        assert!(layout.resize(10));
+        assert_eq!(48, rects(&layout, area)[0].rect.width);
+        assert!(layout.resize(-100));
+        assert_eq!(8, rects(&layout, area)[0].rect.width);
+
+        assert!(layout.close());
+        let panes = rects(&layout, area);
+        assert_eq!(2, panes.len());
+        assert!(panes[0].has_focus);
+        assert_eq!(0, panes[0].rect.y);
+        assert_eq!(10, panes[0].rect.height);
+
+        assert!(layout.close());
+        assert!(!layout.is_split());
+        assert_eq!(area, rects(&layout, area)[0].view);
+    }
+}
@@ -7,22 +7,30 @@ pub mod client;
 pub mod command;
 pub mod config;
 pub mod cursor;
+pub mod directory;
 pub mod editor;
 pub mod editor_utils;
 pub mod events;
+pub mod fold;
 pub mod glob;
 pub mod help;
+pub mod layout;
 pub mod mode;
 pub mod navigation_history;
 pub mod pattern;
 pub mod picker;
 pub mod platform;
 pub mod plugin;
+pub mod progress;
+pub mod regex;
 pub mod serialization;
+pub mod snippet;
 pub mod syntax;
 pub mod theme;
 pub mod ui;
+pub mod unicode;
 pub mod word_database;
+pub mod workspace_search;
 
 pub const DEFAULT_CONFIGS: ResourceFile = ResourceFile {
     name: "default_configs.pepper",
@@ -54,6 +62,7 @@ pub struct Args {
     pub server: bool,
     pub configs: Vec<ArgsConfig>,
     pub files: Vec<String>,
+    pub search_workspace: Option<String>,
 }
 
 fn print_version() {
@@ -82,6 +91,8 @@ fn print_help() {
     println!("  --server                 only run as server");
     println!("  -c, --config[!]          sources config file at path (repeatable) (server only)");
     println!("                           with `!` it will suppress the 'file not found' error");
+    println!("  --search-workspace       prints each line that matches a pattern in the files under the current");
+    println!("                           directory (honoring `.gitignore` files) and quits");
 }
 
 impl Args {
@@ -142,6 +153,10 @@ impl Args {
                         None => error(format_args!("expected config path after {}", arg)),
                     }
                 }
+                "--search-workspace" => match args.next() {
+                    Some(arg) => parsed.search_workspace = Some(arg_to_str(&arg).into()),
+                    None => error(format_args!("expected pattern after {}", arg)),
+                },
                 "--" => {
                     while let Some(arg) = args.next() {
                         let arg = arg_to_str(&arg);
@@ -248,6 +263,11 @@ pub fn init(config: &application::ApplicationConfig) {
 }
 
 pub fn run(config: application::ApplicationConfig) {
+    if let Some(pattern) = &config.args.search_workspace {
+        workspace_search::run(pattern);
+        return;
+    }
+
     init(&config);
     platform_impl::sys::main(config);
 }
@@ -1,16 +1,18 @@
 use std::fmt::Write;
 
 use crate::{
-    buffer::BufferHandle,
+    buffer::{BufferContent, BufferHandle},
     buffer_position::{BufferPosition, BufferRange},
     buffer_view::{BufferViewHandle, CursorMovement, CursorMovementKind},
     client::ClientHandle,
+    cursor::Cursor,
     editor::{Editor, EditorContext, EditorFlow, KeysIterator},
     editor_utils::REGISTER_AUTO_MACRO,
     events::EditorEventTextInsert,
     mode::{ModeKind, ModeState},
     platform::{Key, KeyCode},
     plugin::{CompletionContext, PluginHandle},
+    snippet::{Snippet, SnippetSession},
     word_database::WordKind,
 };
 
@@ -19,6 +21,9 @@ pub struct State {
     editing_buffer_handle: Option<BufferHandle>,
     completion_positions: Vec<BufferPosition>,
     completing_plugin_handle: Option<PluginHandle>,
+    snippet: Snippet,
+    snippet_session: SnippetSession,
+    replacing_tab_stop: bool,
 }
 
 impl State {
@@ -35,6 +40,7 @@ impl State {
                         *position = position.insert(range);
                     }
                 }
+                self.snippet_session.insert_range(range);
             }
         }
     }
@@ -51,6 +57,7 @@ impl State {
                         *position = position.delete(range);
                     }
                 }
+                self.snippet_session.delete_range(range);
             }
         }
     }
@@ -62,7 +69,10 @@ impl ModeState for State {
     }
 
     fn on_exit(editor: &mut Editor) {
-        editor.mode.insert_state.editing_buffer_handle = None;
+        let state = &mut editor.mode.insert_state;
+        state.editing_buffer_handle = None;
+        state.snippet_session.clear();
+        state.replacing_tab_stop = false;
         cancel_completion(editor);
     }
 
@@ -86,6 +96,10 @@ impl ModeState for State {
         let register = ctx.editor.registers.get_mut(REGISTER_AUTO_MACRO);
         let _ = write!(register, "{}", key);
 
+        // typing right after jumping to a tab stop replaces its placeholder text
+        let replacing_tab_stop =
+            std::mem::take(&mut ctx.editor.mode.insert_state.replacing_tab_stop);
+
         #[rustfmt::skip]
         match key {
             Key { code: KeyCode::Esc, shift: false, control: false, alt: false }
@@ -140,15 +154,19 @@ impl ModeState for State {
                 cancel_completion(&mut ctx.editor);
                 return Some(EditorFlow::Continue);
             }
+            Key { code: KeyCode::Char('\t'), shift, control: false, alt: false }
+                if ctx.editor.mode.insert_state.snippet_session.is_active() =>
+            {
+                move_to_next_snippet_tab_stop(&mut ctx.editor, handle, !shift);
+                return Some(EditorFlow::Continue);
+            }
+            Key { code: KeyCode::Char('\t'), shift: false, control: false, alt: false }
+                if expand_user_snippet(ctx, handle) =>
+            {
+                return Some(EditorFlow::Continue);
+            }
             Key { code: KeyCode::Char('\t'), control: false, alt: false, .. } => {
-                static SPACES_BUF: &[u8; u8::MAX as usize] = &[b' '; u8::MAX as usize];
-                let text = if ctx.editor.config.indent_with_tabs {
-                    "\t"
-                } else {
-                    let len = ctx.editor.config.tab_size as usize;
-                    unsafe { std::str::from_utf8_unchecked(&SPACES_BUF[..len]) }
-                };
-
+                let text = tab_text(&ctx.editor);
                 ctx.editor
                     .buffer_views
                     .get(handle)
@@ -192,6 +210,15 @@ impl ModeState for State {
                 ctx.editor.string_pool.release(buf);
             }
             Key { code: KeyCode::Char(c), control: false, alt: false, .. } => {
+                if replacing_tab_stop {
+                    ctx.editor.buffer_views.get(handle).delete_text_in_cursor_ranges(
+                        &mut ctx.editor.buffers,
+                        &mut ctx.editor.word_database,
+                        ctx.editor.events.writer(),
+                    );
+                    ctx.trigger_event_handlers();
+                }
+
                 let mut buf = [0; std::mem::size_of::<char>()];
                 let s = c.encode_utf8(&mut buf);
                 let buffer_view = ctx.editor.buffer_views.get(handle);
@@ -205,11 +232,13 @@ impl ModeState for State {
             Key { code: KeyCode::Backspace, shift: false, control: false, alt: false }
             | Key { code: KeyCode::Char('h'), shift: false, control: true, alt: false } => {
                 let buffer_view = ctx.editor.buffer_views.get_mut(handle);
-                buffer_view.move_cursors(
-                    &ctx.editor.buffers,
-                    CursorMovement::ColumnsBackward(1),
-                    CursorMovementKind::PositionOnly,
-                );
+                if !replacing_tab_stop {
+                    buffer_view.move_cursors(
+                        &ctx.editor.buffers,
+                        CursorMovement::ColumnsBackward(1),
+                        CursorMovementKind::PositionOnly,
+                    );
+                }
                 buffer_view.delete_text_in_cursor_ranges(
                     &mut ctx.editor.buffers,
                     &mut ctx.editor.word_database,
@@ -218,11 +247,13 @@ impl ModeState for State {
             }
             Key { code: KeyCode::Delete, shift: false, control: false, alt: false } => {
                 let buffer_view = ctx.editor.buffer_views.get_mut(handle);
-                buffer_view.move_cursors(
-                    &ctx.editor.buffers,
-                    CursorMovement::ColumnsForward(1),
-                    CursorMovementKind::PositionOnly,
-                );
+                if !replacing_tab_stop {
+                    buffer_view.move_cursors(
+                        &ctx.editor.buffers,
+                        CursorMovement::ColumnsForward(1),
+                        CursorMovementKind::PositionOnly,
+                    );
+                }
                 buffer_view.delete_text_in_cursor_ranges(
                     &mut ctx.editor.buffers,
                     &mut ctx.editor.word_database,
@@ -259,6 +290,16 @@ impl ModeState for State {
     }
 }
 
+fn tab_text(editor: &Editor) -> &'static str {
+    static SPACES_BUF: &[u8; u8::MAX as usize] = &[b' '; u8::MAX as usize];
+    if editor.config.indent_with_tabs {
+        "\t"
+    } else {
+        let len = editor.config.tab_size as usize;
+        unsafe { std::str::from_utf8_unchecked(&SPACES_BUF[..len]) }
+    }
+}
+
 fn cancel_completion(editor: &mut Editor) {
     editor.picker.clear();
     editor.mode.insert_state.completion_positions.clear();
@@ -431,6 +472,14 @@ fn apply_completion(
     };
 
     let completion = ctx.editor.string_pool.acquire_with(entry);
+    if let Some(snippet) = ctx.editor.picker.current_snippet() {
+        let snippet = ctx.editor.string_pool.acquire_with(snippet);
+        expand_snippet(ctx, buffer_view_handle, &snippet);
+        ctx.editor.string_pool.release(snippet);
+        ctx.editor.string_pool.release(completion);
+        return;
+    }
+
     let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
     buffer_view.apply_completion(
         &mut ctx.editor.buffers,
@@ -441,3 +490,140 @@ fn apply_completion(
     );
     ctx.editor.string_pool.release(completion);
 }
+
+// expands the user snippet whose trigger is the word right before the main cursor
+fn expand_user_snippet(ctx: &mut EditorContext, buffer_view_handle: BufferViewHandle) -> bool {
+    let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+    let buffer = ctx.editor.buffers.get(buffer_view.buffer_handle);
+    let path = match buffer.path.to_str() {
+        Some(path) => path,
+        None => return false,
+    };
+    let content = buffer.content();
+
+    let main_cursor_position = buffer_view.cursors.main_cursor().position;
+    let word = content.word_at(content.position_before(main_cursor_position));
+    if word.kind != WordKind::Identifier || word.end_position() != main_cursor_position {
+        return false;
+    }
+    let snippet = match ctx.editor.snippets.find(path, word.text) {
+        Some(snippet) => ctx.editor.string_pool.acquire_with(snippet),
+        None => return false,
+    };
+
+    let state = &mut ctx.editor.mode.insert_state;
+    state.completion_positions.clear();
+    for cursor in &buffer_view.cursors[..] {
+        let cursor_word = content.word_at(content.position_before(cursor.position));
+        let position =
+            if cursor_word.text == word.text && cursor_word.end_position() == cursor.position {
+                cursor_word.position
+            } else {
+                cursor.position
+            };
+        state.completion_positions.push(position);
+    }
+
+    expand_snippet(ctx, buffer_view_handle, &snippet);
+    ctx.editor.string_pool.release(snippet);
+    true
+}
+
+// replaces the text between each completion position and its cursor with the snippet
+// and then starts a snippet session at its first tab stop
+fn expand_snippet(ctx: &mut EditorContext, buffer_view_handle: BufferViewHandle, snippet: &str) {
+    let tab = tab_text(&ctx.editor);
+    let mut indentation = ctx.editor.string_pool.acquire();
+
+    let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+    let buffer_handle = buffer_view.buffer_handle;
+    let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+    let state = &mut ctx.editor.mode.insert_state;
+    state.snippet_session.clear();
+
+    for (cursor, &position) in buffer_view.cursors[..]
+        .iter()
+        .zip(state.completion_positions.iter())
+        .rev()
+    {
+        let range = BufferRange::between(position, cursor.position);
+        buffer.delete_range(
+            &mut ctx.editor.word_database,
+            range,
+            &mut ctx
+                .editor
+                .events
+                .writer()
+                .buffer_range_deletes_mut_guard(buffer_handle),
+        );
+
+        get_indentation(buffer.content(), position, &mut indentation);
+        state.snippet.parse(snippet, &indentation, tab);
+        buffer.insert_text(
+            &mut ctx.editor.word_database,
+            position,
+            &state.snippet.text,
+            &mut ctx
+                .editor
+                .events
+                .writer()
+                .buffer_text_inserts_mut_guard(buffer_handle),
+        );
+    }
+
+    ctx.trigger_event_handlers();
+
+    // completion positions were kept at the start of each inserted snippet
+    let buffer = ctx.editor.buffers.get(buffer_handle);
+    let state = &mut ctx.editor.mode.insert_state;
+    for &position in &state.completion_positions {
+        get_indentation(buffer.content(), position, &mut indentation);
+        state.snippet.parse(snippet, &indentation, tab);
+        state.snippet_session.add(&state.snippet, position);
+    }
+    ctx.editor.string_pool.release(indentation);
+
+    cancel_completion(&mut ctx.editor);
+    move_to_next_snippet_tab_stop(&mut ctx.editor, buffer_view_handle, true);
+}
+
+fn get_indentation(content: &BufferContent, position: BufferPosition, indentation: &mut String) {
+    indentation.clear();
+    let word = content.word_at(BufferPosition::line_col(position.line_index, 0));
+    if word.kind == WordKind::Whitespace {
+        let len = position.column_byte_index.min(word.text.len() as _);
+        indentation.push_str(&word.text[..len as usize]);
+    }
+}
+
+fn move_to_next_snippet_tab_stop(
+    editor: &mut Editor,
+    buffer_view_handle: BufferViewHandle,
+    forward: bool,
+) {
+    let state = &mut editor.mode.insert_state;
+    let index = match state.snippet_session.move_to_next_tab_stop(forward) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let mut cursors = editor
+        .buffer_views
+        .get_mut(buffer_view_handle)
+        .cursors
+        .mut_guard();
+    cursors.clear();
+    for range in state.snippet_session.current_tab_stop_ranges() {
+        cursors.add(Cursor {
+            anchor: range.from,
+            position: range.to,
+        });
+    }
+
+    // `$0` is the final cursor position and ends the session
+    if index == 0 {
+        state.snippet_session.clear();
+    } else {
+        state.replacing_tab_stop = true;
+    }
+}
@@ -6,11 +6,13 @@ use crate::{
     buffer_view::{BufferViewHandle, CursorMovement, CursorMovementKind},
     client::{ClientHandle, ViewAnchor},
     cursor::Cursor,
+    directory,
     editor::{Editor, EditorContext, EditorFlow, KeysIterator},
     editor_utils::{
         find_path_and_ranges_at, hash_bytes, parse_path_and_ranges, LogKind, RegisterKey,
         REGISTER_AUTO_MACRO, REGISTER_SEARCH,
     },
+    fold::find_fold_range,
     help::HELP_PREFIX,
     mode::{picker, readline, ModeKind, ModeState},
     navigation_history::{NavigationHistory, NavigationMovement},
@@ -677,22 +679,30 @@ impl State {
                             };
 
                             path_buf.clear();
-                            if Path::new(path).is_relative() {
-                                if buffer.path.starts_with(HELP_PREFIX) {
-                                    path_buf.push_str(HELP_PREFIX);
-                                } else if let Some(parent) =
-                                    buffer.path.parent().and_then(Path::to_str)
-                                {
-                                    if !parent.is_empty()
-                                        && Path::new(parent).exists()
-                                        && !Path::new(path).exists()
+                            if buffer.is_directory() {
+                                // directory entries are relative to the listed directory
+                                let path = directory::entry_path(&buffer.path, path);
+                                if let Some(path) = path.to_str() {
+                                    path_buf.push_str(path);
+                                }
+                            } else {
+                                if Path::new(path).is_relative() {
+                                    if buffer.path.starts_with(HELP_PREFIX) {
+                                        path_buf.push_str(HELP_PREFIX);
+                                    } else if let Some(parent) =
+                                        buffer.path.parent().and_then(Path::to_str)
                                     {
-                                        path_buf.push_str(parent);
-                                        path_buf.push('/');
+                                        if !parent.is_empty()
+                                            && Path::new(parent).exists()
+                                            && !Path::new(path).exists()
+                                        {
+                                            path_buf.push_str(parent);
+                                            path_buf.push('/');
+                                        }
                                     }
                                 }
+                                path_buf.push_str(path);
                             }
-                            path_buf.push_str(path);
 
                             match ctx.editor.buffer_view_handle_from_path(
                                 client_handle,
@@ -1033,6 +1043,40 @@ impl State {
                         alt: false,
                         ..
                     } => client.set_view_anchor(&mut ctx.editor, ViewAnchor::Top),
+                    Key {
+                        code: KeyCode::Char('a'),
+                        control: false,
+                        alt: false,
+                        ..
+                    } => {
+                        let buffer_view = ctx.editor.buffer_views.get_mut(handle);
+                        let line_index = buffer_view.cursors.main_cursor().position.line_index;
+                        if !buffer_view.folds.unfold_at(line_index) {
+                            close_fold_at_main_cursor(&mut ctx.editor, handle);
+                        }
+                    }
+                    Key {
+                        code: KeyCode::Char('c'),
+                        control: false,
+                        alt: false,
+                        ..
+                    } => close_fold_at_main_cursor(&mut ctx.editor, handle),
+                    Key {
+                        code: KeyCode::Char('o'),
+                        control: false,
+                        alt: false,
+                        ..
+                    } => {
+                        let buffer_view = ctx.editor.buffer_views.get_mut(handle);
+                        let line_index = buffer_view.cursors.main_cursor().position.line_index;
+                        buffer_view.folds.unfold_at(line_index);
+                    }
+                    Key {
+                        code: KeyCode::Char('R'),
+                        control: false,
+                        alt: false,
+                        ..
+                    } => ctx.editor.buffer_views.get_mut(handle).folds.clear(),
                     _ => (),
                 }
             }
@@ -1131,7 +1175,7 @@ impl State {
                 alt: false,
                 ..
             } => {
-                let half_height = ctx.clients.get(client_handle).viewport_size.1 / 2;
+                let half_height = ctx.clients.get(client_handle).view_size().1 / 2;
                 ctx.editor.buffer_views.get_mut(handle).move_cursors(
                     &ctx.editor.buffers,
                     CursorMovement::LinesForward {
@@ -1148,7 +1192,7 @@ impl State {
                 alt: false,
                 ..
             } => {
-                let half_height = ctx.clients.get(client_handle).viewport_size.1 / 2;
+                let half_height = ctx.clients.get(client_handle).view_size().1 / 2;
                 ctx.editor.buffer_views.get_mut(handle).move_cursors(
                     &ctx.editor.buffers,
                     CursorMovement::LinesBackward {
@@ -2446,6 +2490,29 @@ fn search_word_or_move_to_it(
     ctx.editor.mode.normal_state.movement_kind = CursorMovementKind::PositionAndAnchor;
 }
 
+fn close_fold_at_main_cursor(editor: &mut Editor, handle: BufferViewHandle) {
+    let buffer_view = editor.buffer_views.get_mut(handle);
+    let buffer = editor.buffers.get(buffer_view.buffer_handle);
+    let line_index = buffer_view.cursors.main_cursor().position.line_index;
+    let fold = match find_fold_range(buffer, &editor.syntaxes, editor.config.tab_size, line_index) {
+        Some(fold) => fold,
+        None => return,
+    };
+
+    buffer_view.folds.fold(fold);
+
+    // cursors can not stay inside hidden lines
+    let fold_start = BufferPosition::line_col(fold.start_line_index, 0);
+    for cursor in &mut buffer_view.cursors.mut_guard()[..] {
+        if buffer_view.folds.is_line_hidden(cursor.anchor.line_index) {
+            cursor.anchor = fold_start;
+        }
+        if buffer_view.folds.is_line_hidden(cursor.position.line_index) {
+            cursor.position = fold_start;
+        }
+    }
+}
+
 fn move_to_lint(ctx: &mut EditorContext, client_handle: ClientHandle, forward: bool) {
     let handle = match ctx.clients.get(client_handle).buffer_view_handle() {
         Some(handle) => handle,
@@ -1,5 +1,6 @@
 use crate::{
     buffer::BufferProperties,
+    buffer_view::BufferViewHandle,
     client::ClientHandle,
     command::CommandManager,
     editor::{Editor, EditorContext, EditorFlow, KeysIterator},
@@ -19,6 +20,7 @@ pub struct State {
         ReadLinePoll,
     ) -> Option<EditorFlow>,
     continuation: String,
+    buffer_view_handle: Option<BufferViewHandle>,
 }
 
 impl Default for State {
@@ -26,6 +28,7 @@ impl Default for State {
         Self {
             on_client_keys: |_, _, _, _| Some(EditorFlow::Continue),
             continuation: String::new(),
+            buffer_view_handle: None,
         }
     }
 }
@@ -240,6 +243,102 @@ pub mod opened_buffers {
     }
 }
 
+pub mod undo_branches {
+    use super::*;
+
+    use crate::buffer_history::{timestamp_now, HistoryJump};
+
+    pub fn enter_mode(ctx: &mut EditorContext, buffer_view_handle: BufferViewHandle) {
+        fn on_client_keys(
+            ctx: &mut EditorContext,
+            _: ClientHandle,
+            _: &mut KeysIterator,
+            poll: ReadLinePoll,
+        ) -> Option<EditorFlow> {
+            match poll {
+                ReadLinePoll::Pending => return Some(EditorFlow::Continue),
+                ReadLinePoll::Submitted => (),
+                ReadLinePoll::Canceled => {
+                    ctx.editor.enter_mode(ModeKind::default());
+                    return Some(EditorFlow::Continue);
+                }
+            }
+
+            let state = match ctx.editor.picker.current_entry(&ctx.editor.word_database) {
+                Some((_, entry)) => entry.split(':').next().and_then(|s| s.parse().ok()),
+                None => None,
+            };
+            // the picker was opened for this view which may not be the client's current one anymore
+            let buffer_view_handle = ctx.editor.mode.picker_state.buffer_view_handle.take();
+            if let (Some(state), Some(handle)) = (state, buffer_view_handle) {
+                let result = ctx.editor.buffer_views.get_mut(handle).jump_history(
+                    &mut ctx.editor.buffers,
+                    &mut ctx.editor.word_database,
+                    ctx.editor.events.writer(),
+                    HistoryJump::State(state),
+                );
+                if let Err(error) = result {
+                    ctx.editor
+                        .logger
+                        .write(LogKind::Error)
+                        .fmt(format_args!("{}", error));
+                }
+            }
+
+            ctx.editor.enter_mode(ModeKind::default());
+            Some(EditorFlow::Continue)
+        }
+
+        let buffer_handle = ctx
+            .editor
+            .buffer_views
+            .get(buffer_view_handle)
+            .buffer_handle;
+        let buffer = ctx.editor.buffers.get(buffer_handle);
+
+        ctx.editor
+            .registers
+            .set(REGISTER_READLINE_PROMPT, "undo branch:");
+        ctx.editor.picker.clear();
+
+        let now = timestamp_now();
+        let current_state = buffer.history_current_state();
+        for tip in buffer.history_branch_tips().iter().rev() {
+            let age = now.saturating_sub(tip.timestamp);
+            let (age, unit) = match age {
+                0..=59 => (age, "s"),
+                60..=3599 => (age / 60, "m"),
+                3600..=86399 => (age / 3600, "h"),
+                _ => (age / 86400, "d"),
+            };
+            let current = if tip.state == current_state {
+                " (current)"
+            } else {
+                ""
+            };
+            ctx.editor.picker.add_custom_entry_fmt(format_args!(
+                "{}: {}{} ago{}",
+                tip.state, age, unit, current
+            ));
+        }
+
+        ctx.editor.picker.filter(WordIndicesIter::empty(), "");
+        ctx.editor.picker.move_cursor(0);
+
+        if ctx.editor.picker.len() > 0 {
+            let state = &mut ctx.editor.mode.picker_state;
+            state.on_client_keys = on_client_keys;
+            state.buffer_view_handle = Some(buffer_view_handle);
+            ctx.editor.enter_mode(ModeKind::Picker);
+        } else {
+            ctx.editor
+                .logger
+                .write(LogKind::Error)
+                .str("buffer has no undo history");
+        }
+    }
+}
+
 pub mod custom {
     use super::*;
 
@@ -1,5 +1,7 @@
 use std::{fmt, num::TryFromIntError, ops::Range, str::Chars};
 
+use crate::regex::Regex;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MatchResult {
     Pending(PatternState),
@@ -16,6 +18,10 @@ pub enum PatternError {
     EmptyGroup,
     GroupWithElementsOfDifferentSize,
     PatternTooLong,
+    InvalidRegexEscaping(char),
+    InvalidRepetition,
+    InvalidCharClass,
+    InvalidBackreference(usize),
 }
 impl fmt::Display for PatternError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -29,6 +35,10 @@ impl fmt::Display for PatternError {
                 write!(f, "pattern group has elements of different size")
             }
             Self::PatternTooLong => write!(f, "pattern is too long"),
+            Self::InvalidRegexEscaping(c) => write!(f, "invalid escaping '\\{}'", c),
+            Self::InvalidRepetition => write!(f, "invalid repetition"),
+            Self::InvalidCharClass => write!(f, "invalid character class"),
+            Self::InvalidBackreference(group) => write!(f, "invalid backreference '\\{}'", group),
         }
     }
 }
@@ -100,6 +110,34 @@ impl<'pattern, 'text> Iterator for MatchIndices<'pattern, 'text> {
     }
 }
 
+// expands `$0` to `$9` in `template` with the text of the corresponding capture. `$$` expands to `$`
+pub fn expand_replace_template(
+    template: &str,
+    text: &str,
+    captures: &[Option<Range<usize>>],
+    output: &mut String,
+) {
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        output.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        match rest.as_bytes().first() {
+            Some(b'$') => {
+                output.push('$');
+                rest = &rest[1..];
+            }
+            Some(&b) if b.is_ascii_digit() => {
+                if let Some(Some(range)) = captures.get((b - b'0') as usize) {
+                    output.push_str(&text[range.clone()]);
+                }
+                rest = &rest[1..];
+            }
+            _ => output.push('$'),
+        }
+    }
+    output.push_str(rest);
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PatternState {
     op_jump: Jump,
@@ -118,10 +156,12 @@ impl<'a> OpsSlice<'a> {
     }
 }
 
+// patterns prefixed with `r/` or `R/` are compiled as regexes instead
 #[derive(Clone)]
 pub struct Pattern {
     ops: Vec<Op>,
     start_jump: Jump,
+    regex: Option<Regex>,
 }
 
 impl Pattern {
@@ -129,6 +169,7 @@ impl Pattern {
         Self {
             ops: vec![Op::Error],
             start_jump: Jump(0),
+            regex: None,
         }
     }
 
@@ -136,9 +177,20 @@ impl Pattern {
         self.ops.clear();
         self.ops.push(Op::Error);
         self.start_jump = Jump(0);
+        self.regex = None;
     }
 
     pub fn compile(&mut self, pattern: &str) -> Result<(), PatternError> {
+        if let Some(regex) = pattern
+            .strip_prefix("r/")
+            .or_else(|| pattern.strip_prefix("R/"))
+        {
+            self.clear();
+            self.regex = Some(Regex::new(regex)?);
+            return Ok(());
+        }
+
+        self.regex = None;
         match PatternCompiler::new(&mut self.ops, pattern).compile() {
             Ok(start_jump) => {
                 self.start_jump = start_jump;
@@ -152,6 +204,16 @@ impl Pattern {
     }
 
     pub fn compile_searcher(&mut self, pattern: &str) -> Result<(), PatternError> {
+        match pattern.as_bytes() {
+            [b'r', b'/', ..] => {
+                self.compile(pattern)?;
+                self.ignore_case();
+                return Ok(());
+            }
+            [b'R', b'/', ..] => return self.compile(pattern),
+            _ => (),
+        }
+
         let (is_literal, ignore_case, pattern) = match pattern.as_bytes() {
             [b'f', b'/', ..] => (true, true, &pattern[2..]),
             [b'F', b'/', ..] => (true, false, &pattern[2..]),
@@ -165,6 +227,7 @@ impl Pattern {
         };
 
         if is_literal {
+            self.regex = None;
             self.ops.clear();
             self.ops.push(Op::Error);
 
@@ -204,6 +267,9 @@ impl Pattern {
     }
 
     pub fn ignore_case(&mut self) {
+        if let Some(regex) = &mut self.regex {
+            regex.ignore_case = true;
+        }
         for op in &mut self.ops {
             match *op {
                 Op::Char(okj, erj, c) => *op = Op::CharCaseInsensitive(okj, erj, c),
@@ -216,11 +282,17 @@ impl Pattern {
     }
 
     pub fn is_empty(&self) -> bool {
+        if self.regex.is_some() {
+            return false;
+        }
         let ops = OpsSlice(&self.ops);
         matches!(ops.at(self.start_jump), Op::Ok | Op::Error)
     }
 
     pub fn search_anchor(&self) -> Option<char> {
+        if self.regex.is_some() {
+            return None;
+        }
         let ops = OpsSlice(&self.ops);
         let (c, erj) = match ops.at(self.start_jump) {
             Op::Error => return Some('\0'),
@@ -262,7 +334,36 @@ impl Pattern {
         )
     }
 
+    // fills `captures` with each capture group range (the whole match being the first one)
+    // non regex patterns only have the whole match capture
+    pub fn captures(
+        &self,
+        text: &str,
+        index: usize,
+        captures: &mut Vec<Option<Range<usize>>>,
+    ) -> bool {
+        captures.clear();
+        match &self.regex {
+            Some(regex) => regex.captures(text, index, captures).is_some(),
+            None => match self.matches(text, index) {
+                MatchResult::Ok(end) => {
+                    captures.push(Some(index..end));
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    // regexes never span multiple lines so they are never pending
     pub fn matches_with_state(&self, text: &str, index: usize, state: PatternState) -> MatchResult {
+        if let Some(regex) = &self.regex {
+            return match regex.matches(text, index) {
+                Some(end) => MatchResult::Ok(end),
+                None => MatchResult::Err,
+            };
+        }
+
         let mut chars = text[index..].chars();
         let ops = OpsSlice(&self.ops);
         let mut op_jump = state.op_jump;
@@ -402,6 +503,10 @@ impl fmt::Debug for Pattern {
             op_digit_count += 1;
         }
 
+        if let Some(regex) = &self.regex {
+            return fmt::Debug::fmt(regex, f);
+        }
+
         f.write_str("Pattern {\n")?;
         for (i, op) in self.ops.iter().enumerate() {
             if i == self.start_jump.0 as _ {
@@ -1628,4 +1733,51 @@ mod tests {
             Err(PatternError::UnexpectedEndOfPattern)
         ));
     }
+
+    #[test]
+    fn regex_pattern() {
+        let p = new_pattern("R/(a|ab)(c|bcd)");
+        assert!(!p.is_empty());
+        assert_eq!(None, p.search_anchor());
+        assert_eq!(MatchResult::Ok(4), p.matches("abcd", 0));
+        assert_eq!(MatchResult::Err, p.matches("ABCD", 0));
+
+        let mut captures = Vec::new();
+        assert!(p.captures("xabcd", 1, &mut captures));
+        assert_eq!(&[Some(1..5), Some(1..2), Some(2..5)], &captures[..]);
+
+        let mut p = Pattern::new();
+        p.compile_searcher("r/b+").unwrap();
+        let mut ranges = p.match_indices("abBba", None);
+        assert_eq!(Some(1..4), ranges.next());
+        assert_eq!(None, ranges.next());
+
+        let p = new_pattern("abc");
+        assert!(p.captures("abc", 0, &mut captures));
+        assert_eq!(&[Some(0..3)], &captures[..]);
+
+        assert!(matches!(
+            try_new_pattern("r/a{2,1}"),
+            Err(PatternError::InvalidRepetition)
+        ));
+    }
+
+    #[test]
+    fn replace_template() {
+        fn expand(template: &str) -> String {
+            let text = "key=value";
+            let captures = [Some(0..9), Some(0..3), None, Some(4..9)];
+            let mut output = String::new();
+            expand_replace_template(template, text, &captures, &mut output);
+            output
+        }
+
+        assert_eq!("", expand(""));
+        assert_eq!("abc", expand("abc"));
+        assert_eq!("value: key", expand("$3: $1"));
+        assert_eq!("[key=value]", expand("[$0]"));
+        assert_eq!("<>", expand("<$2$7>"));
+        assert_eq!("$1 costs $", expand("$$1 costs $"));
+        assert_eq!("$x", expand("$x"));
+    }
 }
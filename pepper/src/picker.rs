@@ -19,6 +19,7 @@ pub struct Picker {
     fuzzy_matcher: FuzzyMatcher,
     custom_entries_len: usize,
     custom_entries_buffer: Vec<String>,
+    custom_entries_snippets: Vec<String>,
     filtered_entries: Vec<FilteredEntry>,
 
     cursor: Option<usize>,
@@ -88,7 +89,9 @@ impl Picker {
     fn new_custom_entry(&mut self) -> &mut String {
         if self.custom_entries_len == self.custom_entries_buffer.len() {
             self.custom_entries_buffer.push(String::new());
+            self.custom_entries_snippets.push(String::new());
         }
+        self.custom_entries_snippets[self.custom_entries_len].clear();
         let entry = &mut self.custom_entries_buffer[self.custom_entries_len];
         self.custom_entries_len += 1;
         entry.clear();
@@ -105,6 +108,12 @@ impl Picker {
         let _ = fmt::write(entry, args);
     }
 
+    // when completing, `snippet` is expanded instead of inserting `name`
+    pub fn add_custom_entry_with_snippet(&mut self, name: &str, snippet: &str) {
+        self.add_custom_entry(name);
+        self.custom_entries_snippets[self.custom_entries_len - 1].push_str(snippet);
+    }
+
     pub fn add_custom_filtered_entries<'picker, 'pattern>(
         &'picker mut self,
         pattern: &'pattern str,
@@ -188,6 +197,15 @@ impl Picker {
         Some((source, entry))
     }
 
+    pub fn current_snippet(&self) -> Option<&str> {
+        match self.filtered_entries[self.cursor?].source {
+            EntrySource::Custom(i) if !self.custom_entries_snippets[i].is_empty() => {
+                Some(&self.custom_entries_snippets[i])
+            }
+            _ => None,
+        }
+    }
+
     pub fn entries<'a>(
         &'a self,
         words: &'a WordDatabase,
@@ -260,6 +260,10 @@ impl Terminal {
                 | &[0x1b, b'O', b'F', ref rest @ ..] => (KeyCode::End, rest),
                 &[0x1b, b'[', b'3', b'~', ref rest @ ..] => (KeyCode::Delete, rest),
                 &[0x1b, b'[', b'9', b'u', ref rest @ ..] => (KeyCode::Char('\t'), rest),
+                &[0x1b, b'[', b'Z', ref rest @ ..] => {
+                    shift = true;
+                    (KeyCode::Char('\t'), rest)
+                }
                 &[0x1b, ref rest @ ..] => (KeyCode::Esc, rest),
                 &[0x8, ref rest @ ..] => (KeyCode::Backspace, rest),
                 &[b'\r', ref rest @ ..] => (KeyCode::Char('\n'), rest),
@@ -39,6 +39,11 @@ pub struct Plugin {
         keys: &mut KeysIterator,
     ) -> Option<EditorFlow>,
     pub on_completion: fn(PluginHandle, &mut EditorContext, &CompletionContext) -> bool,
+    // called before a buffer is saved with `save` or `save-all`
+    // returning `true` means the plugin will write the buffer itself later (like after formatting it)
+    pub on_buffer_save: fn(PluginHandle, &mut EditorContext, BufferHandle) -> bool,
+    // called before the editor quits so that the buffers whose save was deferred are written right away
+    pub on_flush_saves: fn(PluginHandle, &mut EditorContext),
 }
 impl Default for Plugin {
     fn default() -> Self {
@@ -57,6 +62,8 @@ impl Default for Plugin {
 
             on_keys: |_, _, _, _| Some(EditorFlow::Continue),
             on_completion: |_, _, _| false,
+            on_buffer_save: |_, _, _| false,
+            on_flush_saves: |_, _| (),
         }
     }
 }
@@ -116,6 +123,25 @@ impl PluginCollection {
         }
     }
 
+    pub(crate) fn on_buffer_save(ctx: &mut EditorContext, buffer_handle: BufferHandle) -> bool {
+        for i in 0..ctx.plugins.plugins.len() {
+            let handle = PluginHandle(i as _);
+            let f = ctx.plugins.plugins[i].on_buffer_save;
+            if f(handle, ctx, buffer_handle) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub(crate) fn on_flush_saves(ctx: &mut EditorContext) {
+        for i in 0..ctx.plugins.plugins.len() {
+            let handle = PluginHandle(i as _);
+            let f = ctx.plugins.plugins[i].on_flush_saves;
+            f(handle, ctx);
+        }
+    }
+
     pub(crate) fn on_process_spawned(
         ctx: &mut EditorContext,
         plugin_handle: PluginHandle,
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use crate::plugin::PluginHandle;
+
+// a long running operation reported by a plugin (like a lsp server indexing a project)
+pub struct Progress {
+    plugin_handle: PluginHandle,
+    source: u32,
+    token: String,
+    pub name: String,
+    pub title: String,
+    pub message: String,
+    pub percentage: Option<u32>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        f.write_str(":")?;
+        if !self.title.is_empty() {
+            write!(f, " {}", self.title)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, " {}", self.message)?;
+        }
+        if let Some(percentage) = self.percentage {
+            write!(f, " {}%", percentage)?;
+        }
+        Ok(())
+    }
+}
+
+// progresses are identified by their plugin, a plugin defined `source` and a `token`
+#[derive(Default)]
+pub struct ProgressCollection {
+    progresses: Vec<Progress>,
+}
+
+impl ProgressCollection {
+    fn position(&self, plugin_handle: PluginHandle, source: u32, token: &str) -> Option<usize> {
+        self.progresses.iter().position(|p| {
+            p.plugin_handle == plugin_handle && p.source == source && p.token == token
+        })
+    }
+
+    pub fn begin(
+        &mut self,
+        plugin_handle: PluginHandle,
+        source: u32,
+        token: &str,
+    ) -> &mut Progress {
+        let index = match self.position(plugin_handle, source, token) {
+            Some(index) => index,
+            None => {
+                self.progresses.push(Progress {
+                    plugin_handle,
+                    source,
+                    token: token.into(),
+                    name: String::new(),
+                    title: String::new(),
+                    message: String::new(),
+                    percentage: None,
+                });
+                self.progresses.len() - 1
+            }
+        };
+
+        let progress = &mut self.progresses[index];
+        progress.name.clear();
+        progress.title.clear();
+        progress.message.clear();
+        progress.percentage = None;
+        progress
+    }
+
+    pub fn get_mut(
+        &mut self,
+        plugin_handle: PluginHandle,
+        source: u32,
+        token: &str,
+    ) -> Option<&mut Progress> {
+        let index = self.position(plugin_handle, source, token)?;
+        Some(&mut self.progresses[index])
+    }
+
+    pub fn end(&mut self, plugin_handle: PluginHandle, source: u32, token: &str) {
+        if let Some(index) = self.position(plugin_handle, source, token) {
+            self.progresses.remove(index);
+        }
+    }
+
+    pub fn clear(&mut self, plugin_handle: PluginHandle, source: u32) {
+        self.progresses
+            .retain(|p| p.plugin_handle != plugin_handle || p.source != source);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Progress> {
+        self.progresses.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_lifetime() {
+        let plugin_handle = PluginHandle(0);
+        let mut progresses = ProgressCollection::default();
+
+        let progress = progresses.begin(plugin_handle, 0, "a");
+        progress.name.push_str("server");
+        progress.title.push_str("indexing");
+        progress.percentage = Some(0);
+        progresses
+            .begin(plugin_handle, 1, "a")
+            .name
+            .push_str("other");
+
+        let progress = progresses.get_mut(plugin_handle, 0, "a").unwrap();
+        progress.message.push_str("1/4");
+        progress.percentage = Some(25);
+        assert!(progresses.get_mut(plugin_handle, 0, "b").is_none());
+
+        {
+            let mut iter = progresses.iter();
+            assert_eq!("server: indexing 1/4 25%", iter.next().unwrap().to_string());
+            assert_eq!("other:", iter.next().unwrap().to_string());
+            assert!(iter.next().is_none());
+        }
+
+        progresses.end(plugin_handle, 0, "a");
+        assert_eq!(1, progresses.iter().count());
+        progresses.clear(plugin_handle, 1);
+        assert_eq!(0, progresses.iter().count());
+    }
+}
@@ -0,0 +1,778 @@
+use std::{cell::RefCell, iter::Peekable, ops::Range, str::Chars};
+
+use crate::pattern::PatternError;
+
+const MAX_REPETITION_COUNT: u32 = 1000;
+const MAX_PROGRAM_LEN: usize = u16::MAX as _;
+const MAX_STEP_COUNT: usize = 1 << 20;
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    NotDigit,
+    Word,
+    NotWord,
+    Space,
+    NotSpace,
+    Alphabetic,
+    Alphanumeric,
+    Lower,
+    Upper,
+    Punctuation,
+    HexDigit,
+}
+impl ClassItem {
+    fn matches(self, c: char, ignore_case: bool) -> bool {
+        match self {
+            Self::Range(from, to) => {
+                let in_range = |c| from <= c && c <= to;
+                in_range(c)
+                    || ignore_case
+                        && (in_range(c.to_ascii_lowercase()) || in_range(c.to_ascii_uppercase()))
+            }
+            Self::Digit => c.is_ascii_digit(),
+            Self::NotDigit => !c.is_ascii_digit(),
+            Self::Word => is_word_char(c),
+            Self::NotWord => !is_word_char(c),
+            Self::Space => c.is_ascii_whitespace(),
+            Self::NotSpace => !c.is_ascii_whitespace(),
+            Self::Alphabetic => c.is_ascii_alphabetic(),
+            Self::Alphanumeric => c.is_ascii_alphanumeric(),
+            Self::Lower => c.is_ascii_lowercase() || ignore_case && c.is_ascii_uppercase(),
+            Self::Upper => c.is_ascii_uppercase() || ignore_case && c.is_ascii_lowercase(),
+            Self::Punctuation => c.is_ascii_punctuation(),
+            Self::HexDigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CharClass {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+impl CharClass {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        self.items.iter().any(|i| i.matches(c, ignore_case)) != self.negated
+    }
+}
+
+enum Node {
+    Empty,
+    Char(char),
+    AnyChar,
+    Class(usize),
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repetition {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+    Backreference(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Inst {
+    Match,
+    Char(char),
+    AnyChar,
+    Class(usize),
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    Save(usize),
+    Split(usize, usize),
+    Jump(usize),
+    Backreference(usize),
+    RepeatStart(usize),
+    RepeatCheck(usize),
+}
+
+enum Frame {
+    Try(usize, usize),
+    Restore(usize, Option<usize>),
+}
+
+thread_local! {
+    static SCRATCH: RefCell<(Vec<Option<usize>>, Vec<Frame>)> = const { RefCell::new((Vec::new(), Vec::new())) };
+}
+
+// backtracking regex engine for the practical subset of posix/pcre syntax:
+// literals, `.`, `^`, `$`, `[...]` classes (with `[:alpha:]` like names), `\d\w\s\b` escapes,
+// capturing `(...)` and non capturing `(?:...)` groups, `|` alternations,
+// `* + ? {n} {n,} {n,m}` greedy and lazy (`?` suffixed) repetitions and `\1`..`\9` backreferences
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    classes: Vec<CharClass>,
+    group_count: usize,
+    slot_count: usize,
+    pub ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let (ignore_case, pattern) = match pattern.strip_prefix("(?i)") {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        let mut classes = Vec::new();
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+            classes: &mut classes,
+            group_count: 0,
+        };
+        let node = parser.parse_alternation()?;
+        if let Some(c) = parser.chars.next() {
+            return Err(PatternError::Unescaped(c));
+        }
+        let group_count = parser.group_count;
+
+        let mut compiler = Compiler {
+            program: Vec::new(),
+            slot_count: (group_count + 1) * 2,
+        };
+        compiler.emit_inst(Inst::Save(0))?;
+        compiler.emit(&node)?;
+        compiler.emit_inst(Inst::Save(1))?;
+        compiler.emit_inst(Inst::Match)?;
+
+        Ok(Self {
+            program: compiler.program,
+            classes,
+            group_count,
+            slot_count: compiler.slot_count,
+            ignore_case,
+        })
+    }
+
+    // number of capture groups including the whole match one
+    pub fn capture_count(&self) -> usize {
+        self.group_count + 1
+    }
+
+    pub fn matches(&self, text: &str, index: usize) -> Option<usize> {
+        SCRATCH.with(|scratch| {
+            let (slots, stack) = &mut *scratch.borrow_mut();
+            self.run(text, index, slots, stack)
+        })
+    }
+
+    pub fn captures(
+        &self,
+        text: &str,
+        index: usize,
+        captures: &mut Vec<Option<Range<usize>>>,
+    ) -> Option<usize> {
+        SCRATCH.with(|scratch| {
+            let (slots, stack) = &mut *scratch.borrow_mut();
+            let end = self.run(text, index, slots, stack)?;
+            for group in 0..self.capture_count() {
+                let capture = match (slots[group * 2], slots[group * 2 + 1]) {
+                    (Some(from), Some(to)) => Some(from..to),
+                    _ => None,
+                };
+                captures.push(capture);
+            }
+            Some(end)
+        })
+    }
+
+    fn run(
+        &self,
+        text: &str,
+        index: usize,
+        slots: &mut Vec<Option<usize>>,
+        stack: &mut Vec<Frame>,
+    ) -> Option<usize> {
+        slots.clear();
+        slots.resize(self.slot_count, None);
+        stack.clear();
+        stack.push(Frame::Try(0, index));
+
+        let mut step_count = 0;
+        while let Some(frame) = stack.pop() {
+            let (mut pc, mut pos) = match frame {
+                Frame::Try(pc, pos) => (pc, pos),
+                Frame::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+
+            loop {
+                step_count += 1;
+                if step_count > MAX_STEP_COUNT {
+                    return None;
+                }
+
+                let matched = match self.program[pc] {
+                    Inst::Match => return Some(pos),
+                    Inst::Char(ch) => match text[pos..].chars().next() {
+                        Some(c) if c == ch || self.ignore_case && c.eq_ignore_ascii_case(&ch) => {
+                            pos += c.len_utf8();
+                            true
+                        }
+                        _ => false,
+                    },
+                    Inst::AnyChar => match text[pos..].chars().next() {
+                        Some(c) => {
+                            pos += c.len_utf8();
+                            true
+                        }
+                        None => false,
+                    },
+                    Inst::Class(class) => match text[pos..].chars().next() {
+                        Some(c) if self.classes[class].matches(c, self.ignore_case) => {
+                            pos += c.len_utf8();
+                            true
+                        }
+                        _ => false,
+                    },
+                    Inst::LineStart => pos == 0,
+                    Inst::LineEnd => pos == text.len(),
+                    Inst::WordBoundary => is_at_word_boundary(text, pos),
+                    Inst::NotWordBoundary => !is_at_word_boundary(text, pos),
+                    Inst::Save(slot) | Inst::RepeatStart(slot) => {
+                        stack.push(Frame::Restore(slot, slots[slot]));
+                        slots[slot] = Some(pos);
+                        true
+                    }
+                    // a repetition body that matched nothing would loop forever
+                    Inst::RepeatCheck(slot) => slots[slot] != Some(pos),
+                    Inst::Split(preferred, other) => {
+                        stack.push(Frame::Try(other, pos));
+                        pc = preferred;
+                        continue;
+                    }
+                    Inst::Jump(jump) => {
+                        pc = jump;
+                        continue;
+                    }
+                    Inst::Backreference(group) => match (slots[group * 2], slots[group * 2 + 1]) {
+                        (Some(from), Some(to)) => {
+                            let captured = &text.as_bytes()[from..to];
+                            let rest = &text.as_bytes()[pos..];
+                            let len = captured.len();
+                            let matched = rest.len() >= len
+                                && if self.ignore_case {
+                                    rest[..len].eq_ignore_ascii_case(captured)
+                                } else {
+                                    &rest[..len] == captured
+                                };
+                            if matched {
+                                pos += len;
+                            }
+                            matched
+                        }
+                        _ => false,
+                    },
+                };
+
+                if !matched {
+                    break;
+                }
+                pc += 1;
+            }
+        }
+
+        None
+    }
+}
+
+fn is_at_word_boundary(text: &str, index: usize) -> bool {
+    let previous = text[..index].chars().next_back().map(is_word_char);
+    let next = text[index..].chars().next().map(is_word_char);
+    previous.unwrap_or(false) != next.unwrap_or(false)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    classes: &'a mut Vec<CharClass>,
+    group_count: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<char, PatternError> {
+        self.chars
+            .next()
+            .ok_or(PatternError::UnexpectedEndOfPattern)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), PatternError> {
+        match self.chars.next() {
+            Some(next) if next == c => Ok(()),
+            _ => Err(PatternError::Expected(c)),
+        }
+    }
+
+    fn push_class(&mut self, negated: bool, items: Vec<ClassItem>) -> Node {
+        self.classes.push(CharClass { negated, items });
+        Node::Class(self.classes.len() - 1)
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, PatternError> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            alternatives.push(self.parse_concat()?);
+        }
+
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Node::Alternation(alternatives))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, PatternError> {
+        let mut nodes = Vec::new();
+        while !matches!(self.chars.peek(), None | Some('|') | Some(')')) {
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repetition(atom)?);
+        }
+
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, PatternError> {
+        let node = match self.next()? {
+            '.' => Node::AnyChar,
+            '^' => Node::LineStart,
+            '$' => Node::LineEnd,
+            '(' => {
+                let group = if self.chars.next_if_eq(&'?').is_some() {
+                    self.expect(':')?;
+                    None
+                } else {
+                    self.group_count += 1;
+                    Some(self.group_count)
+                };
+                let node = self.parse_alternation()?;
+                self.expect(')')?;
+                Node::Group(group, Box::new(node))
+            }
+            '[' => self.parse_class()?,
+            '\\' => self.parse_escape()?,
+            '*' | '+' | '?' => return Err(PatternError::InvalidRepetition),
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, PatternError> {
+        let c = self.next()?;
+        let node = match c {
+            'b' => Node::WordBoundary,
+            'B' => Node::NotWordBoundary,
+            '1'..='9' => {
+                let group = c as usize - '0' as usize;
+                if group > self.group_count {
+                    return Err(PatternError::InvalidBackreference(group));
+                }
+                Node::Backreference(group)
+            }
+            _ => match escaped_class_item(c) {
+                Some(item) => self.push_class(false, vec![item]),
+                None => Node::Char(escaped_char(c)?),
+            },
+        };
+        Ok(node)
+    }
+
+    fn parse_class(&mut self) -> Result<Node, PatternError> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut items = Vec::new();
+
+        let mut first = true;
+        loop {
+            let c = self.next()?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let from = match c {
+                '[' if self.chars.next_if_eq(&':').is_some() => {
+                    let mut name = String::new();
+                    loop {
+                        match self.next()? {
+                            ':' => break,
+                            c => name.push(c),
+                        }
+                    }
+                    self.expect(']')?;
+                    items.push(named_class_item(&name).ok_or(PatternError::InvalidCharClass)?);
+                    continue;
+                }
+                '\\' => {
+                    let c = self.next()?;
+                    if let Some(item) = escaped_class_item(c) {
+                        items.push(item);
+                        continue;
+                    }
+                    escaped_char(c)?
+                }
+                c => c,
+            };
+
+            let mut ahead = self.chars.clone();
+            let is_range = ahead.next() == Some('-') && !matches!(ahead.peek(), None | Some(']'));
+            if !is_range {
+                items.push(ClassItem::Range(from, from));
+                continue;
+            }
+
+            self.chars.next();
+            let to = match self.next()? {
+                '\\' => {
+                    let c = self.next()?;
+                    escaped_char(c)?
+                }
+                c => c,
+            };
+            if to < from {
+                return Err(PatternError::InvalidCharClass);
+            }
+            items.push(ClassItem::Range(from, to));
+        }
+
+        Ok(self.push_class(negated, items))
+    }
+
+    fn parse_repetition(&mut self, node: Node) -> Result<Node, PatternError> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match parse_counted_repetition(&mut self.chars.clone()) {
+                Some(range) => range,
+                None => return Ok(node),
+            },
+            _ => return Ok(node),
+        };
+
+        if let Some('{') = self.chars.next() {
+            while self.next()? != '}' {}
+        }
+        let greedy = self.chars.next_if_eq(&'?').is_none();
+
+        if max.map(|max| min > max).unwrap_or(false)
+            || min.max(max.unwrap_or(0)) > MAX_REPETITION_COUNT
+        {
+            return Err(PatternError::InvalidRepetition);
+        }
+        if let Some('*' | '+' | '?') = self.chars.peek() {
+            return Err(PatternError::InvalidRepetition);
+        }
+
+        Ok(Node::Repetition {
+            node: Box::new(node),
+            min,
+            max,
+            greedy,
+        })
+    }
+}
+
+// parses `{n}`, `{n,}` and `{n,m}`. anything else is matched literally
+fn parse_counted_repetition(chars: &mut Peekable<Chars>) -> Option<(u32, Option<u32>)> {
+    fn parse_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+        let mut number: Option<u32> = None;
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            chars.next();
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+        }
+        number
+    }
+
+    chars.next();
+    let min = parse_number(chars)?;
+    let max = match chars.next()? {
+        '}' => return Some((min, Some(min))),
+        ',' => parse_number(chars),
+        _ => return None,
+    };
+    match chars.next()? {
+        '}' => Some((min, max)),
+        _ => None,
+    }
+}
+
+fn escaped_class_item(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit),
+        'D' => Some(ClassItem::NotDigit),
+        'w' => Some(ClassItem::Word),
+        'W' => Some(ClassItem::NotWord),
+        's' => Some(ClassItem::Space),
+        'S' => Some(ClassItem::NotSpace),
+        _ => None,
+    }
+}
+
+fn named_class_item(name: &str) -> Option<ClassItem> {
+    match name {
+        "alpha" => Some(ClassItem::Alphabetic),
+        "alnum" => Some(ClassItem::Alphanumeric),
+        "digit" => Some(ClassItem::Digit),
+        "lower" => Some(ClassItem::Lower),
+        "upper" => Some(ClassItem::Upper),
+        "space" => Some(ClassItem::Space),
+        "punct" => Some(ClassItem::Punctuation),
+        "xdigit" => Some(ClassItem::HexDigit),
+        "word" => Some(ClassItem::Word),
+        _ => None,
+    }
+}
+
+fn escaped_char(c: char) -> Result<char, PatternError> {
+    match c {
+        'n' => Ok('\n'),
+        'r' => Ok('\r'),
+        't' => Ok('\t'),
+        '0' => Ok('\0'),
+        c if c.is_ascii_punctuation() || c == ' ' => Ok(c),
+        c => Err(PatternError::InvalidRegexEscaping(c)),
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+    slot_count: usize,
+}
+
+impl Compiler {
+    fn emit_inst(&mut self, inst: Inst) -> Result<usize, PatternError> {
+        if self.program.len() >= MAX_PROGRAM_LEN {
+            return Err(PatternError::PatternTooLong);
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn emit(&mut self, node: &Node) -> Result<(), PatternError> {
+        match node {
+            Node::Empty => (),
+            &Node::Char(c) => {
+                self.emit_inst(Inst::Char(c))?;
+            }
+            Node::AnyChar => {
+                self.emit_inst(Inst::AnyChar)?;
+            }
+            &Node::Class(class) => {
+                self.emit_inst(Inst::Class(class))?;
+            }
+            Node::LineStart => {
+                self.emit_inst(Inst::LineStart)?;
+            }
+            Node::LineEnd => {
+                self.emit_inst(Inst::LineEnd)?;
+            }
+            Node::WordBoundary => {
+                self.emit_inst(Inst::WordBoundary)?;
+            }
+            Node::NotWordBoundary => {
+                self.emit_inst(Inst::NotWordBoundary)?;
+            }
+            Node::Group(group, node) => match group {
+                Some(group) => {
+                    self.emit_inst(Inst::Save(group * 2))?;
+                    self.emit(node)?;
+                    self.emit_inst(Inst::Save(group * 2 + 1))?;
+                }
+                None => self.emit(node)?,
+            },
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.emit(node)?;
+                }
+            }
+            Node::Alternation(nodes) => {
+                let mut jumps = Vec::new();
+                let (last, nodes) = nodes.split_last().unwrap();
+                for node in nodes {
+                    let split = self.emit_inst(Inst::Split(0, 0))?;
+                    self.emit(node)?;
+                    jumps.push(self.emit_inst(Inst::Jump(0))?);
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+                self.emit(last)?;
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            &Node::Repetition {
+                ref node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..min {
+                    self.emit(node)?;
+                }
+
+                let split = |body, exit| {
+                    if greedy {
+                        Inst::Split(body, exit)
+                    } else {
+                        Inst::Split(exit, body)
+                    }
+                };
+
+                match max {
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in min..max {
+                            splits.push(self.emit_inst(Inst::Split(0, 0))?);
+                            self.emit(node)?;
+                        }
+                        let exit = self.program.len();
+                        for s in splits {
+                            self.program[s] = split(s + 1, exit);
+                        }
+                    }
+                    None => {
+                        let guard_slot = self.slot_count;
+                        self.slot_count += 1;
+
+                        let s = self.emit_inst(Inst::Split(0, 0))?;
+                        self.emit_inst(Inst::RepeatStart(guard_slot))?;
+                        self.emit(node)?;
+                        self.emit_inst(Inst::RepeatCheck(guard_slot))?;
+                        self.emit_inst(Inst::Jump(s))?;
+                        self.program[s] = split(s + 1, self.program.len());
+                    }
+                }
+            }
+            &Node::Backreference(group) => {
+                self.emit_inst(Inst::Backreference(group))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<Range<usize>> {
+        let regex = Regex::new(pattern).unwrap();
+        (0..=text.len())
+            .filter(|&i| text.is_char_boundary(i))
+            .find_map(|i| regex.matches(text, i).map(|end| i..end))
+    }
+
+    #[test]
+    fn simple_regex() {
+        assert_eq!(Some(0..0), find("", "abc"));
+        assert_eq!(Some(1..2), find("b", "abc"));
+        assert_eq!(Some(0..3), find("a.c", "abc"));
+        assert_eq!(None, find("a.c", "ac"));
+        assert_eq!(Some(2..3), find("c$", "abc"));
+        assert_eq!(None, find("^b", "abc"));
+        assert_eq!(Some(4..7), find(r"\bbar\b", "foo bar"));
+        assert_eq!(None, find(r"\Bbar", "foo bar"));
+        assert_eq!(Some(3..4), find(r"\.", "abc."));
+        assert_eq!(Some(0..3), find("a{b", "a{b"));
+    }
+
+    #[test]
+    fn alternation_regex() {
+        assert_eq!(Some(0..2), find("ab|abc", "abc"));
+        assert_eq!(Some(0..3), find("abc|ab", "abc"));
+        assert_eq!(Some(0..4), find("(fn|for|if)_x", "if_x"));
+        assert_eq!(Some(1..5), find("if|else", "xelse"));
+    }
+
+    #[test]
+    fn class_regex() {
+        assert_eq!(Some(1..4), find("[0-9]+", "a123b"));
+        assert_eq!(Some(0..1), find("[^0-9]", "a123b"));
+        assert_eq!(Some(0..2), find("[a-]+", "-a"));
+        assert_eq!(Some(1..4), find(r"\d+", "a123b"));
+        assert_eq!(Some(0..3), find(r"\w+", "a_1 b"));
+        assert_eq!(Some(3..4), find(r"\s", "a_1 b"));
+        assert_eq!(Some(0..3), find("[[:upper:]_]+", "A_B c"));
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("[[:nope:]]").is_err());
+    }
+
+    #[test]
+    fn repetition_regex() {
+        assert_eq!(Some(0..3), find("a*", "aaab"));
+        assert_eq!(Some(0..0), find("a*?", "aaab"));
+        assert_eq!(Some(0..4), find("a+b", "aaab"));
+        assert_eq!(Some(0..2), find("a{2}", "aaab"));
+        assert_eq!(Some(0..3), find("a{2,}", "aaab"));
+        assert_eq!(Some(0..2), find("a{1,2}", "aaab"));
+        assert_eq!(Some(0..1), find("a{1,2}?", "aaab"));
+        assert_eq!(Some(0..7), find("<.*>", "<a> <b> c"));
+        assert_eq!(Some(0..3), find("<.*?>", "<a> <b> c"));
+        assert_eq!(Some(0..4), find("(a*)*b", "aaab"));
+        assert_eq!(Some(0..3), find("(a|)+", "aaab"));
+        assert!(Regex::new("a**").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new("a{1001}").is_err());
+    }
+
+    #[test]
+    fn captures_regex() {
+        let regex = Regex::new(r"(\w+)=(\d+)?(?:;)").unwrap();
+        assert_eq!(3, regex.capture_count());
+
+        let mut captures = Vec::new();
+        assert_eq!(Some(6), regex.captures("ab=12;", 0, &mut captures));
+        assert_eq!(&[Some(0..6), Some(0..2), Some(3..5)], &captures[..]);
+
+        captures.clear();
+        assert_eq!(Some(4), regex.captures("ab=;", 0, &mut captures));
+        assert_eq!(&[Some(0..4), Some(0..2), None], &captures[..]);
+    }
+
+    #[test]
+    fn backreference_regex() {
+        assert_eq!(Some(4..11), find(r"(\w+) \1", "foo bar bar"));
+        assert_eq!(None, find(r"(\w+) \1\b", "foo bar baz"));
+        assert_eq!(Some(0..5), find(r#"(["']).*?\1"#, "'a\"b' c'"));
+        assert!(Regex::new(r"\1(a)").is_err());
+    }
+
+    #[test]
+    fn ignore_case_regex() {
+        assert_eq!(Some(0..3), find("(?i)abc", "ABC"));
+        assert_eq!(Some(0..1), find("(?i)[a-c]+", "Bd"));
+        assert_eq!(Some(0..7), find(r"(?i)(\w+) \1", "Foo foo"));
+        assert_eq!(None, find("abc", "ABC"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[a").is_err());
+        assert!(Regex::new(r"\q").is_err());
+        assert!(Regex::new("(?x)").is_err());
+    }
+}
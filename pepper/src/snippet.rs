@@ -0,0 +1,495 @@
+use std::ops::Range;
+
+use crate::{
+    buffer_position::{BufferPosition, BufferPositionIndex, BufferRange},
+    editor_utils::hash_bytes,
+    glob::{Glob, InvalidGlobError},
+};
+
+pub struct SnippetTabStop {
+    pub index: u32,
+    pub range: Range<u32>,
+}
+
+// a snippet (in the lsp snippet syntax) expanded into plain text
+// together with the byte ranges of its tab stops inside that text
+#[derive(Default)]
+pub struct Snippet {
+    pub text: String,
+    pub tab_stops: Vec<SnippetTabStop>,
+}
+
+impl Snippet {
+    // each new line is followed by `indentation` and each `\t` is replaced by `tab`
+    pub fn parse(&mut self, snippet: &str, indentation: &str, tab: &str) {
+        self.text.clear();
+        self.tab_stops.clear();
+
+        let mut parser = SnippetParser {
+            snippet: self,
+            indentation,
+            tab,
+        };
+        let mut rest = snippet;
+        loop {
+            rest = parser.parse_sequence(rest);
+            match rest.strip_prefix('}') {
+                Some(after) => {
+                    parser.push_char('}');
+                    rest = after;
+                }
+                None => break,
+            }
+        }
+
+        // the final tab stop is at the end when not explicitly set by `$0`
+        if !self.tab_stops.iter().any(|t| t.index == 0) {
+            let end = self.text.len() as u32;
+            self.tab_stops.push(SnippetTabStop {
+                index: 0,
+                range: end..end,
+            });
+        }
+    }
+
+    pub fn tab_stop_range(
+        &self,
+        tab_stop: &SnippetTabStop,
+        position: BufferPosition,
+    ) -> BufferRange {
+        let from = text_offset_to_position(&self.text, tab_stop.range.start as _, position);
+        let to = text_offset_to_position(&self.text, tab_stop.range.end as _, position);
+        BufferRange::between(from, to)
+    }
+}
+
+fn text_offset_to_position(text: &str, offset: usize, position: BufferPosition) -> BufferPosition {
+    let text = &text[..offset];
+    match text.rfind('\n') {
+        Some(i) => BufferPosition::line_col(
+            position.line_index + text.matches('\n').count() as BufferPositionIndex,
+            (text.len() - i - 1) as _,
+        ),
+        None => BufferPosition::line_col(
+            position.line_index,
+            position.column_byte_index + text.len() as BufferPositionIndex,
+        ),
+    }
+}
+
+struct SnippetParser<'a> {
+    snippet: &'a mut Snippet,
+    indentation: &'a str,
+    tab: &'a str,
+}
+
+impl<'a> SnippetParser<'a> {
+    fn push_char(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.snippet.text.push('\n');
+                self.snippet.text.push_str(self.indentation);
+            }
+            '\t' => self.snippet.text.push_str(self.tab),
+            c => self.snippet.text.push(c),
+        }
+    }
+
+    // stops before an unescaped `}`
+    fn parse_sequence<'s>(&mut self, mut snippet: &'s str) -> &'s str {
+        loop {
+            let mut chars = snippet.chars();
+            match chars.next() {
+                None | Some('}') => return snippet,
+                Some('\\') => match chars.next() {
+                    Some(c @ ('$' | '}' | '\\')) => {
+                        self.push_char(c);
+                        snippet = chars.as_str();
+                    }
+                    _ => {
+                        self.push_char('\\');
+                        snippet = &snippet[1..];
+                    }
+                },
+                Some('$') => {
+                    let rest = chars.as_str();
+                    let text_len = self.snippet.text.len();
+                    let tab_stops_len = self.snippet.tab_stops.len();
+                    match self.parse_dollar(rest) {
+                        Some(rest) => snippet = rest,
+                        None => {
+                            self.snippet.text.truncate(text_len);
+                            self.snippet.tab_stops.truncate(tab_stops_len);
+                            self.push_char('$');
+                            snippet = rest;
+                        }
+                    }
+                }
+                Some(c) => {
+                    self.push_char(c);
+                    snippet = chars.as_str();
+                }
+            }
+        }
+    }
+
+    fn parse_dollar<'s>(&mut self, snippet: &'s str) -> Option<&'s str> {
+        if let Some((index, rest)) = parse_int(snippet) {
+            self.add_tab_stop(index, self.snippet.text.len());
+            return Some(rest);
+        }
+        if let Some((_, rest)) = parse_variable_name(snippet) {
+            return Some(rest);
+        }
+
+        let snippet = snippet.strip_prefix('{')?;
+        let text_len = self.snippet.text.len();
+        if let Some((index, rest)) = parse_int(snippet) {
+            let mut chars = rest.chars();
+            match chars.next()? {
+                '}' => {
+                    self.add_tab_stop(index, text_len);
+                    Some(chars.as_str())
+                }
+                ':' => {
+                    let rest = self.parse_sequence(chars.as_str());
+                    let rest = rest.strip_prefix('}')?;
+                    self.add_tab_stop(index, text_len);
+                    Some(rest)
+                }
+                '|' => {
+                    let rest = self.parse_choice(chars.as_str())?;
+                    self.add_tab_stop(index, text_len);
+                    Some(rest)
+                }
+                _ => None,
+            }
+        } else {
+            // variables are not resolved so only their default text is inserted
+            let (_, rest) = parse_variable_name(snippet)?;
+            let mut chars = rest.chars();
+            match chars.next()? {
+                '}' => Some(chars.as_str()),
+                ':' => {
+                    let rest = self.parse_sequence(chars.as_str());
+                    rest.strip_prefix('}')
+                }
+                '/' => skip_transform(chars.as_str()),
+                _ => None,
+            }
+        }
+    }
+
+    // only the first choice is inserted
+    fn parse_choice<'s>(&mut self, snippet: &'s str) -> Option<&'s str> {
+        let mut first_choice = true;
+        let mut chars = snippet.chars();
+        loop {
+            match chars.next()? {
+                '\\' => {
+                    let c = chars.next()?;
+                    if first_choice {
+                        self.push_char(c);
+                    }
+                }
+                '|' => return chars.as_str().strip_prefix('}'),
+                ',' => first_choice = false,
+                c => {
+                    if first_choice {
+                        self.push_char(c);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_tab_stop(&mut self, index: u32, start: usize) {
+        let end = self.snippet.text.len();
+        self.snippet.tab_stops.push(SnippetTabStop {
+            index,
+            range: start as u32..end as u32,
+        });
+    }
+}
+
+fn parse_int(snippet: &str) -> Option<(u32, &str)> {
+    let len = snippet
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(snippet.len());
+    let index = snippet[..len].parse().ok()?;
+    Some((index, &snippet[len..]))
+}
+
+fn parse_variable_name(snippet: &str) -> Option<(&str, &str)> {
+    if !snippet.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let len = snippet
+        .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+        .unwrap_or(snippet.len());
+    Some(snippet.split_at(len))
+}
+
+// skips `regex/format/options}`
+fn skip_transform(snippet: &str) -> Option<&str> {
+    let mut slashes = 1;
+    let mut chars = snippet.chars();
+    loop {
+        match chars.next()? {
+            '\\' => {
+                chars.next()?;
+            }
+            '/' => slashes += 1,
+            '}' if slashes >= 3 => return Some(chars.as_str()),
+            _ => (),
+        }
+    }
+}
+
+struct SnippetSessionTabStop {
+    index: u32,
+    range: BufferRange,
+}
+
+// the tab stops of the snippets expanded (one for each cursor) while in insert mode
+#[derive(Default)]
+pub struct SnippetSession {
+    tab_stops: Vec<SnippetSessionTabStop>,
+    current_index: Option<u32>,
+}
+
+impl SnippetSession {
+    pub fn is_active(&self) -> bool {
+        !self.tab_stops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tab_stops.clear();
+        self.current_index = None;
+    }
+
+    pub fn add(&mut self, snippet: &Snippet, position: BufferPosition) {
+        for tab_stop in &snippet.tab_stops {
+            self.tab_stops.push(SnippetSessionTabStop {
+                index: tab_stop.index,
+                range: snippet.tab_stop_range(tab_stop, position),
+            });
+        }
+    }
+
+    pub fn insert_range(&mut self, range: BufferRange) {
+        for tab_stop in &mut self.tab_stops {
+            // text typed at the start of the current tab stop becomes part of it
+            if Some(tab_stop.index) != self.current_index || tab_stop.range.from != range.from {
+                tab_stop.range.from = tab_stop.range.from.insert(range);
+            }
+            tab_stop.range.to = tab_stop.range.to.insert(range);
+        }
+    }
+
+    pub fn delete_range(&mut self, range: BufferRange) {
+        for tab_stop in &mut self.tab_stops {
+            tab_stop.range.from = tab_stop.range.from.delete(range);
+            tab_stop.range.to = tab_stop.range.to.delete(range);
+        }
+    }
+
+    // moves to the next (or previous) tab stop index where `0` is always the last one
+    pub fn move_to_next_tab_stop(&mut self, forward: bool) -> Option<u32> {
+        fn order(index: u32) -> u32 {
+            index.wrapping_sub(1)
+        }
+
+        let current = self.current_index.map(order);
+        let next = self
+            .tab_stops
+            .iter()
+            .map(|t| order(t.index))
+            .filter(|&i| match current {
+                Some(current) if forward => i > current,
+                Some(current) => i < current,
+                None => true,
+            });
+        let next = if forward || current.is_none() {
+            next.min()
+        } else {
+            next.max()
+        }?;
+
+        let index = next.wrapping_add(1);
+        self.current_index = Some(index);
+        Some(index)
+    }
+
+    pub fn current_tab_stop_ranges(&self) -> impl '_ + Iterator<Item = BufferRange> {
+        let current_index = self.current_index;
+        self.tab_stops
+            .iter()
+            .filter(move |t| Some(t.index) == current_index)
+            .map(|t| t.range)
+    }
+}
+
+struct UserSnippet {
+    glob_hash: u64,
+    glob: Glob,
+    trigger: String,
+    body: String,
+}
+
+#[derive(Default)]
+pub struct SnippetCollection {
+    snippets: Vec<UserSnippet>,
+}
+
+impl SnippetCollection {
+    pub fn add(&mut self, glob: &str, trigger: &str, body: &str) -> Result<(), InvalidGlobError> {
+        let mut snippet_glob = Glob::default();
+        snippet_glob.compile(glob)?;
+
+        let glob_hash = hash_bytes(glob.as_bytes());
+        self.snippets
+            .retain(|s| s.glob_hash != glob_hash || s.trigger != trigger);
+        self.snippets.push(UserSnippet {
+            glob_hash,
+            glob: snippet_glob,
+            trigger: trigger.into(),
+            body: body.into(),
+        });
+        Ok(())
+    }
+
+    // the last added snippet wins when several ones match
+    pub fn find(&self, path: &str, trigger: &str) -> Option<&str> {
+        self.snippets
+            .iter()
+            .rev()
+            .find(|s| s.trigger == trigger && s.glob.matches(path))
+            .map(|s| s.body.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab_stops(snippet: &Snippet) -> Vec<(u32, &str)> {
+        snippet
+            .tab_stops
+            .iter()
+            .map(|t| {
+                (
+                    t.index,
+                    &snippet.text[t.range.start as usize..t.range.end as usize],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_snippets() {
+        let mut snippet = Snippet::default();
+
+        snippet.parse("plain text", "", "\t");
+        assert_eq!("plain text", snippet.text);
+        assert_eq!(vec![(0, "")], tab_stops(&snippet));
+
+        snippet.parse("fn ${1:name}($2) {\n\t$0\n}", "  ", "    ");
+        assert_eq!("fn name() {\n      \n  }", snippet.text);
+        assert_eq!(vec![(1, "name"), (2, ""), (0, "")], tab_stops(&snippet));
+        assert_eq!(18, snippet.tab_stops[2].range.start);
+
+        snippet.parse("${1:outer ${2:inner}} ${1}", "", "\t");
+        assert_eq!("outer inner ", snippet.text);
+        assert_eq!(
+            vec![(2, "inner"), (1, "outer inner"), (1, ""), (0, "")],
+            tab_stops(&snippet)
+        );
+
+        snippet.parse(
+            "${1|one,two\\,three|} ${TM_FILENAME} ${VAR:default}",
+            "",
+            "\t",
+        );
+        assert_eq!("one  default", snippet.text);
+        assert_eq!(vec![(1, "one"), (0, "")], tab_stops(&snippet));
+
+        snippet.parse("\\$1 $ ${x/a/b/g}\\}", "", "\t");
+        assert_eq!("$1 $ }", snippet.text);
+
+        snippet.parse("a}b${", "", "\t");
+        assert_eq!("a}b${", snippet.text);
+
+        snippet.parse("${1:unclosed $2", "", "\t");
+        assert_eq!("${1:unclosed ", snippet.text);
+        assert_eq!(vec![(2, ""), (0, "")], tab_stops(&snippet));
+    }
+
+    #[test]
+    fn snippet_tab_stop_positions() {
+        let mut snippet = Snippet::default();
+        snippet.parse("if ${1:x} {\n\t${2:y}\n}", "", "\t");
+
+        let position = BufferPosition::line_col(3, 4);
+        let ranges: Vec<_> = snippet
+            .tab_stops
+            .iter()
+            .map(|t| snippet.tab_stop_range(t, position))
+            .collect();
+        assert_eq!(
+            BufferRange::between(
+                BufferPosition::line_col(3, 7),
+                BufferPosition::line_col(3, 8)
+            ),
+            ranges[0]
+        );
+        assert_eq!(
+            BufferRange::between(
+                BufferPosition::line_col(4, 1),
+                BufferPosition::line_col(4, 2)
+            ),
+            ranges[1]
+        );
+        assert_eq!(
+            BufferRange::between(
+                BufferPosition::line_col(5, 1),
+                BufferPosition::line_col(5, 1)
+            ),
+            ranges[2]
+        );
+    }
+
+    #[test]
+    fn snippet_session_navigation() {
+        let mut snippet = Snippet::default();
+        snippet.parse("$2 $1 $0 $1", "", "\t");
+
+        let mut session = SnippetSession::default();
+        session.add(&snippet, BufferPosition::zero());
+        assert!(session.is_active());
+
+        assert_eq!(Some(1), session.move_to_next_tab_stop(true));
+        assert_eq!(2, session.current_tab_stop_ranges().count());
+        assert_eq!(Some(2), session.move_to_next_tab_stop(true));
+        assert_eq!(Some(1), session.move_to_next_tab_stop(false));
+        assert_eq!(None, session.move_to_next_tab_stop(false));
+        assert_eq!(Some(2), session.move_to_next_tab_stop(true));
+        assert_eq!(Some(0), session.move_to_next_tab_stop(true));
+        assert_eq!(None, session.move_to_next_tab_stop(true));
+    }
+
+    #[test]
+    fn user_snippets() {
+        let mut snippets = SnippetCollection::default();
+        snippets.add("**/*.rs", "fn", "fn $1() {}").unwrap();
+        snippets.add("**/*.md", "fn", "footnote").unwrap();
+        snippets.add("**/*.rs", "fn", "fn ${1:name}() {}").unwrap();
+
+        assert_eq!(
+            Some("fn ${1:name}() {}"),
+            snippets.find("src/main.rs", "fn")
+        );
+        assert_eq!(Some("footnote"), snippets.find("README.md", "fn"));
+        assert_eq!(None, snippets.find("src/main.rs", "if"));
+    }
+}
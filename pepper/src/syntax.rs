@@ -22,6 +22,21 @@ pub enum TokenKind {
     Comment,
     Text,
     Whitespace,
+    Custom(u8),
+}
+impl TokenKind {
+    fn rule_index(self) -> usize {
+        match self {
+            Self::Keyword => 0,
+            Self::Type => 1,
+            Self::Symbol => 2,
+            Self::Literal => 3,
+            Self::String => 4,
+            Self::Comment => 5,
+            Self::Text => 6,
+            Self::Whitespace | Self::Custom(_) => unreachable!(),
+        }
+    }
 }
 impl FromStr for TokenKind {
     type Err = ();
@@ -60,11 +75,19 @@ impl Default for Token {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EmbeddedParseState {
+    region_index: u32,
+    syntax_handle: SyntaxHandle,
+    pending: Option<(TokenKind, PatternState)>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum LineParseState {
     Dirty,
     Finished,
     Unfinished(TokenKind, PatternState),
+    Embedded(EmbeddedParseState),
 }
 
 impl Default for LineParseState {
@@ -73,9 +96,67 @@ impl Default for LineParseState {
     }
 }
 
+enum RulesParseResult {
+    Finished(LineParseState),
+    RegionBegin {
+        region_index: usize,
+        from: usize,
+        to: usize,
+    },
+}
+
+// a region of a line (or lines) that is highlighted with another syntax
+// it goes from a `begin` match until an `end` match (or the end of the line if `end` is empty)
+struct SyntaxRegion {
+    syntax_path: String,
+    begin: Pattern,
+    end: Pattern,
+}
+
+impl SyntaxRegion {
+    // returns where the embedded text ends and where the region itself ends (if it does in this line)
+    fn find_end(&self, line: &str, mut index: usize) -> (usize, Option<usize>) {
+        if self.end.is_empty() {
+            return (line.len(), Some(line.len()));
+        }
+
+        let anchor = self.end.search_anchor();
+        loop {
+            if let Some(anchor) = anchor {
+                match line[index..].find(anchor) {
+                    Some(i) => index += i,
+                    None => return (line.len(), None),
+                }
+            }
+
+            match self.end.matches(line, index) {
+                MatchResult::Ok(end) if end > index => return (index, Some(end)),
+                _ => match line[index..].chars().next() {
+                    Some(c) => index += c.len_utf8(),
+                    None => return (line.len(), None),
+                },
+            }
+        }
+    }
+}
+
+// custom token kinds are dot separated scopes like `function` or `keyword.control`
+pub fn is_valid_custom_token_kind_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').all(|scope| {
+            !scope.is_empty()
+                && scope
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        })
+}
+
 pub struct Syntax {
     glob: Glob,
     rules: [Pattern; 7],
+    custom_rules: Vec<(TokenKind, Pattern)>,
+    regions: Vec<SyntaxRegion>,
+    fold_patterns: Vec<(Pattern, Pattern)>,
 }
 
 impl Syntax {
@@ -93,6 +174,9 @@ impl Syntax {
                 Pattern::new(),
                 text_pattern,
             ],
+            custom_rules: Vec::new(),
+            regions: Vec::new(),
+            fold_patterns: Vec::new(),
         }
     }
 
@@ -101,11 +185,69 @@ impl Syntax {
     }
 
     pub fn set_rule(&mut self, kind: TokenKind, pattern: &str) -> Result<(), PatternError> {
-        self.rules[kind as usize].compile(pattern)
+        match kind {
+            TokenKind::Custom(_) => match self.custom_rules.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, rule)) => rule.compile(pattern),
+                None => {
+                    let mut rule = Pattern::new();
+                    rule.compile(pattern)?;
+                    self.custom_rules.push((kind, rule));
+                    Ok(())
+                }
+            },
+            _ => self.rules[kind.rule_index()].compile(pattern),
+        }
+    }
+
+    // returns `None` for a custom token kind without a rule
+    // which happens to lines left inside a custom rule of a syntax that was since redefined
+    fn rule(&self, kind: TokenKind) -> Option<&Pattern> {
+        match kind {
+            TokenKind::Custom(_) => self
+                .custom_rules
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, rule)| rule),
+            _ => Some(&self.rules[kind.rule_index()]),
+        }
+    }
+
+    pub fn add_region(
+        &mut self,
+        syntax_path: &str,
+        begin: &str,
+        end: &str,
+    ) -> Result<(), PatternError> {
+        let mut region = SyntaxRegion {
+            syntax_path: syntax_path.into(),
+            begin: Pattern::new(),
+            end: Pattern::new(),
+        };
+        region.begin.compile(begin)?;
+        if !end.is_empty() {
+            region.end.compile(end)?;
+        }
+        self.regions.push(region);
+        Ok(())
+    }
+
+    // lines between a match of `begin` and its matching `end` can be folded
+    pub fn add_fold_patterns(&mut self, begin: &str, end: &str) -> Result<(), PatternError> {
+        let mut begin_pattern = Pattern::new();
+        begin_pattern.compile(begin)?;
+        let mut end_pattern = Pattern::new();
+        end_pattern.compile(end)?;
+        self.fold_patterns.push((begin_pattern, end_pattern));
+        Ok(())
+    }
+
+    pub fn fold_patterns(&self) -> &[(Pattern, Pattern)] {
+        &self.fold_patterns
     }
 
     fn parse_line(
         &self,
+        syntaxes: &SyntaxCollection,
         line: &str,
         previous_parse_state: LineParseState,
         tokens: &mut Vec<Token>,
@@ -113,28 +255,132 @@ impl Syntax {
         tokens.clear();
 
         let mut index = 0;
+        let mut parse_state = previous_parse_state;
+        loop {
+            let embedded = match parse_state {
+                LineParseState::Dirty => unreachable!(),
+                LineParseState::Embedded(embedded) => embedded,
+                _ => match self.parse_rules(line, index, parse_state, true, tokens) {
+                    RulesParseResult::Finished(parse_state) => return parse_state,
+                    RulesParseResult::RegionBegin {
+                        region_index,
+                        from,
+                        to,
+                    } => {
+                        self.parse_rules(
+                            &line[..to],
+                            from,
+                            LineParseState::Finished,
+                            false,
+                            tokens,
+                        );
+                        index = to;
+
+                        // regions of a syntax that is not defined are parsed as the rest of the line
+                        let region = &self.regions[region_index];
+                        let syntax_handle = match syntaxes.find_handle_by_path(&region.syntax_path)
+                        {
+                            Some(handle) => handle,
+                            None => {
+                                return match self.parse_rules(
+                                    line,
+                                    to,
+                                    LineParseState::Finished,
+                                    false,
+                                    tokens,
+                                ) {
+                                    RulesParseResult::Finished(parse_state) => parse_state,
+                                    RulesParseResult::RegionBegin { .. } => unreachable!(),
+                                };
+                            }
+                        };
+                        EmbeddedParseState {
+                            region_index: region_index as _,
+                            syntax_handle,
+                            pending: None,
+                        }
+                    }
+                },
+            };
+
+            let region = &self.regions[embedded.region_index as usize];
+            let (embedded_end, region_end) = region.find_end(line, index);
+
+            let embedded_parse_state = match embedded.pending {
+                Some((kind, state)) => LineParseState::Unfinished(kind, state),
+                None => LineParseState::Finished,
+            };
+            let embedded_parse_state = match syntaxes.get(embedded.syntax_handle).parse_rules(
+                &line[..embedded_end],
+                index,
+                embedded_parse_state,
+                false,
+                tokens,
+            ) {
+                RulesParseResult::Finished(parse_state) => parse_state,
+                RulesParseResult::RegionBegin { .. } => unreachable!(),
+            };
+
+            match region_end {
+                Some(region_end) => {
+                    self.parse_rules(
+                        &line[..region_end],
+                        embedded_end,
+                        LineParseState::Finished,
+                        false,
+                        tokens,
+                    );
+                    index = region_end;
+                    parse_state = LineParseState::Finished;
+                }
+                None => {
+                    let pending = match embedded_parse_state {
+                        LineParseState::Unfinished(kind, state) => Some((kind, state)),
+                        _ => None,
+                    };
+                    return LineParseState::Embedded(EmbeddedParseState {
+                        pending,
+                        ..embedded
+                    });
+                }
+            }
+        }
+    }
 
+    fn parse_rules(
+        &self,
+        line: &str,
+        mut index: usize,
+        previous_parse_state: LineParseState,
+        check_regions: bool,
+        tokens: &mut Vec<Token>,
+    ) -> RulesParseResult {
         match previous_parse_state {
-            LineParseState::Dirty => unreachable!(),
+            LineParseState::Dirty | LineParseState::Embedded(_) => unreachable!(),
             LineParseState::Finished => (),
+            // a line left inside a rule that no longer exists is parsed as if it was finished
             LineParseState::Unfinished(kind, state) => {
-                match self.rules[kind as usize].matches_with_state(line, 0, state) {
-                    MatchResult::Ok(end) => {
-                        tokens.push(Token {
-                            kind,
-                            from: 0,
-                            to: end as _,
-                        });
-                        index = end;
-                    }
-                    MatchResult::Err => (),
-                    MatchResult::Pending(state) => {
-                        tokens.push(Token {
-                            kind,
-                            from: 0,
-                            to: line.len() as _,
-                        });
-                        return LineParseState::Unfinished(kind, state);
+                if let Some(rule) = self.rule(kind) {
+                    match rule.matches_with_state(line, index, state) {
+                        MatchResult::Ok(end) => {
+                            tokens.push(Token {
+                                kind,
+                                from: index as _,
+                                to: end as _,
+                            });
+                            index = end;
+                        }
+                        MatchResult::Err => (),
+                        MatchResult::Pending(state) => {
+                            tokens.push(Token {
+                                kind,
+                                from: index as _,
+                                to: line.len() as _,
+                            });
+                            return RulesParseResult::Finished(LineParseState::Unfinished(
+                                kind, state,
+                            ));
+                        }
                     }
                 }
             }
@@ -147,21 +393,44 @@ impl Syntax {
                 .take_while(u8::is_ascii_whitespace)
                 .count();
 
+            if check_regions && index < line.len() {
+                for (region_index, region) in self.regions.iter().enumerate() {
+                    if let MatchResult::Ok(end) = region.begin.matches(line, index) {
+                        if end > index {
+                            return RulesParseResult::RegionBegin {
+                                region_index,
+                                from,
+                                to: end,
+                            };
+                        }
+                    }
+                }
+            }
+
             let mut best_pattern_kind = TokenKind::Text;
             let mut max_end = index;
 
-            static ALL_NON_WHITESPACE_TOKEN_KINDS: [TokenKind; 7] = [
+            static BUILTIN_NON_TEXT_TOKEN_KINDS: [TokenKind; 6] = [
                 TokenKind::Keyword,
                 TokenKind::Type,
                 TokenKind::Symbol,
                 TokenKind::Literal,
                 TokenKind::String,
                 TokenKind::Comment,
-                TokenKind::Text,
             ];
 
-            for kind in ALL_NON_WHITESPACE_TOKEN_KINDS {
-                let pattern = &self.rules[kind as usize];
+            // custom kinds come after builtin ones but before text
+            // so they win ties against the (usually very broad) text rule
+            let rules = BUILTIN_NON_TEXT_TOKEN_KINDS
+                .iter()
+                .map(|&kind| (kind, &self.rules[kind.rule_index()]))
+                .chain(self.custom_rules.iter().map(|(kind, rule)| (*kind, rule)))
+                .chain(std::iter::once((
+                    TokenKind::Text,
+                    &self.rules[TokenKind::Text.rule_index()],
+                )));
+
+            for (kind, pattern) in rules {
                 match pattern.matches(line, index) {
                     MatchResult::Ok(end) => {
                         if end > max_end {
@@ -176,7 +445,7 @@ impl Syntax {
                             from: from as _,
                             to: line.len() as _,
                         });
-                        return LineParseState::Unfinished(kind, state);
+                        return RulesParseResult::Finished(LineParseState::Unfinished(kind, state));
                     }
                 }
             }
@@ -206,22 +475,51 @@ impl Syntax {
             });
         }
 
-        LineParseState::Finished
+        RulesParseResult::Finished(LineParseState::Finished)
     }
 }
 
-#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct SyntaxHandle(u32);
 
 pub struct SyntaxCollection {
     syntaxes: Vec<Syntax>,
+    custom_token_kind_names: Vec<String>,
 }
 
 impl SyntaxCollection {
     pub fn new() -> Self {
         Self {
             syntaxes: vec![Syntax::new()],
+            custom_token_kind_names: Vec::new(),
+        }
+    }
+
+    pub fn custom_token_kind(&mut self, name: &str) -> Option<TokenKind> {
+        if !is_valid_custom_token_kind_name(name) {
+            return None;
         }
+
+        let index = match self.custom_token_kind_names.iter().position(|n| n == name) {
+            Some(index) => index,
+            None => {
+                if self.custom_token_kind_names.len() > u8::MAX as _ {
+                    return None;
+                }
+                self.custom_token_kind_names.push(name.into());
+                self.custom_token_kind_names.len() - 1
+            }
+        };
+        Some(TokenKind::Custom(index as _))
+    }
+
+    pub fn custom_token_kind_name(&self, index: u8) -> &str {
+        &self.custom_token_kind_names[index as usize]
+    }
+
+    // in the order of their `TokenKind::Custom` index
+    pub fn custom_token_kind_names(&self) -> impl Iterator<Item = &str> {
+        self.custom_token_kind_names.iter().map(String::as_str)
     }
 
     pub fn find_handle_by_path(&self, path: &str) -> Option<SyntaxHandle> {
@@ -261,10 +559,40 @@ pub enum HighlightResult {
     Pending,
 }
 
+// a token that comes from outside the syntax rules (like from a language server)
+// and that is drawn on top of the syntax tokens of its line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub line_index: BufferPositionIndex,
+    pub token: Token,
+}
+
+pub struct SemanticTokensMutGuard<'a> {
+    tokens: &'a mut Vec<SemanticToken>,
+}
+impl<'a> SemanticTokensMutGuard<'a> {
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+
+    pub fn add(&mut self, line_index: BufferPositionIndex, token: Token) {
+        if token.from < token.to {
+            self.tokens.push(SemanticToken { line_index, token });
+        }
+    }
+}
+impl<'a> Drop for SemanticTokensMutGuard<'a> {
+    fn drop(&mut self) {
+        self.tokens
+            .sort_unstable_by_key(|t| (t.line_index, t.token.from));
+    }
+}
+
 pub struct HighlightedBuffer {
     highlighted_len: usize,
     lines: Vec<HighlightedLine>,
     dirty_line_indexes: Vec<BufferPositionIndex>,
+    semantic_tokens: Vec<SemanticToken>,
 }
 
 impl HighlightedBuffer {
@@ -273,15 +601,45 @@ impl HighlightedBuffer {
             highlighted_len: 1,
             lines: vec![HighlightedLine::default()],
             dirty_line_indexes: Vec::new(),
+            semantic_tokens: Vec::new(),
         }
     }
 
     pub fn clear(&mut self) {
         self.highlighted_len = 1;
         self.dirty_line_indexes.clear();
+        self.semantic_tokens.clear();
+    }
+
+    pub fn semantic_tokens_mut_guard(&mut self) -> SemanticTokensMutGuard<'_> {
+        SemanticTokensMutGuard {
+            tokens: &mut self.semantic_tokens,
+        }
+    }
+
+    // semantic tokens that end up spanning several lines or that become empty are dropped
+    // until they get replaced by fresh ones
+    fn fix_semantic_tokens<F>(&mut self, mut f: F)
+    where
+        F: FnMut(BufferPosition) -> BufferPosition,
+    {
+        self.semantic_tokens.retain_mut(|t| {
+            let from = f(BufferPosition::line_col(t.line_index, t.token.from));
+            let to = f(BufferPosition::line_col(t.line_index, t.token.to));
+            if from.line_index != to.line_index || from == to {
+                return false;
+            }
+
+            t.line_index = from.line_index;
+            t.token.from = from.column_byte_index;
+            t.token.to = to.column_byte_index;
+            true
+        });
     }
 
     pub fn insert_range(&mut self, range: BufferRange) {
+        self.fix_semantic_tokens(|p| p.insert(range));
+
         if self.highlighted_len <= range.from.line_index as _ {
             return;
         }
@@ -318,6 +676,8 @@ impl HighlightedBuffer {
     }
 
     pub fn delete_range(&mut self, range: BufferRange) {
+        self.fix_semantic_tokens(|p| p.delete(range));
+
         if self.lines.len() <= range.to.line_index as _ {
             return;
         }
@@ -344,6 +704,7 @@ impl HighlightedBuffer {
 
     pub fn highlight_dirty_lines(
         &mut self,
+        syntaxes: &SyntaxCollection,
         syntax: &Syntax,
         buffer: &BufferContent,
     ) -> HighlightResult {
@@ -388,7 +749,7 @@ impl HighlightedBuffer {
 
                 let previous_state = hline.parse_state;
                 previous_parse_state =
-                    syntax.parse_line(bline, previous_parse_state, &mut hline.tokens);
+                    syntax.parse_line(syntaxes, bline, previous_parse_state, &mut hline.tokens);
                 hline.parse_state = previous_parse_state;
 
                 index += 1;
@@ -402,9 +763,8 @@ impl HighlightedBuffer {
                     return HighlightResult::Pending;
                 }
 
-                if previous_state == LineParseState::Finished
-                    && previous_parse_state == LineParseState::Finished
-                {
+                // lines after this one only depend on its parse state
+                if previous_state == previous_parse_state {
                     break;
                 }
             }
@@ -421,6 +781,15 @@ impl HighlightedBuffer {
             &[]
         }
     }
+
+    pub fn line_semantic_tokens(&self, line_index: usize) -> &[SemanticToken] {
+        let line_index = line_index as BufferPositionIndex;
+        let start = self
+            .semantic_tokens
+            .partition_point(|t| t.line_index < line_index);
+        let len = self.semantic_tokens[start..].partition_point(|t| t.line_index == line_index);
+        &self.semantic_tokens[start..start + len]
+    }
 }
 
 #[cfg(test)]
@@ -456,10 +825,11 @@ mod tests {
 
     #[test]
     fn no_syntax() {
+        let syntaxes = SyntaxCollection::new();
         let syntax = Syntax::new();
         let mut tokens = Vec::new();
         let line = " fn main() ;  ";
-        let parse_state = syntax.parse_line(line, LineParseState::Finished, &mut tokens);
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
 
         assert_eq!(LineParseState::Finished, parse_state);
         assert_eq!(6, tokens.len());
@@ -473,12 +843,13 @@ mod tests {
 
     #[test]
     fn one_rule_syntax() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Symbol, ";").unwrap();
 
         let mut tokens = Vec::new();
         let line = " fn main() ;  ";
-        let parse_state = syntax.parse_line(line, LineParseState::Finished, &mut tokens);
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
 
         assert_eq!(LineParseState::Finished, parse_state);
         assert_eq!(6, tokens.len());
@@ -492,13 +863,14 @@ mod tests {
 
     #[test]
     fn simple_syntax() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Keyword, "fn").unwrap();
         syntax.set_rule(TokenKind::Symbol, "%(|%)").unwrap();
 
         let mut tokens = Vec::new();
         let line = " fn main() ;  ";
-        let parse_state = syntax.parse_line(line, LineParseState::Finished, &mut tokens);
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
 
         assert_eq!(LineParseState::Finished, parse_state);
         assert_eq!(6, tokens.len());
@@ -512,12 +884,13 @@ mod tests {
 
     #[test]
     fn beginning_anchor_syntax() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Keyword, "^{%w}").unwrap();
 
         let mut tokens = Vec::new();
         let line = "first second";
-        let parse_state = syntax.parse_line(line, LineParseState::Finished, &mut tokens);
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
 
         assert_eq!(LineParseState::Finished, parse_state);
         assert_eq!(2, tokens.len());
@@ -527,6 +900,7 @@ mod tests {
 
     #[test]
     fn multiline_syntax() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
 
@@ -535,7 +909,7 @@ mod tests {
         let line1 = "only comment";
         let line2 = "still comment */ after";
 
-        let line0_kind = syntax.parse_line(line0, LineParseState::Finished, &mut tokens);
+        let line0_kind = syntax.parse_line(&syntaxes, line0, LineParseState::Finished, &mut tokens);
         match line0_kind {
             LineParseState::Unfinished(i, _) => assert_eq!(TokenKind::Comment, i),
             _ => panic!("{:?}", line0_kind),
@@ -544,7 +918,7 @@ mod tests {
         assert_token("before", TokenKind::Text, line0, &tokens[0]);
         assert_token(" /* comment", TokenKind::Comment, line0, &tokens[1]);
 
-        let line1_kind = syntax.parse_line(line1, line0_kind, &mut tokens);
+        let line1_kind = syntax.parse_line(&syntaxes, line1, line0_kind, &mut tokens);
         match line1_kind {
             LineParseState::Unfinished(i, _) => assert_eq!(TokenKind::Comment, i),
             _ => panic!("{:?}", line1_kind),
@@ -552,7 +926,7 @@ mod tests {
         assert_eq!(1, tokens.len());
         assert_token("only comment", TokenKind::Comment, line1, &tokens[0]);
 
-        let line2_kind = syntax.parse_line(line2, line1_kind, &mut tokens);
+        let line2_kind = syntax.parse_line(&syntaxes, line2, line1_kind, &mut tokens);
         assert_eq!(LineParseState::Finished, line2_kind);
         assert_eq!(2, tokens.len());
         assert_token("still comment */", TokenKind::Comment, line2, &tokens[0]);
@@ -561,6 +935,7 @@ mod tests {
 
     #[test]
     fn editing_highlighted_buffer() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
         syntax.set_rule(TokenKind::String, "'{!'.$}").unwrap();
@@ -570,7 +945,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::zero(), "/*\n*/");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
         assert_eq!(buffer.lines().len(), highlighted.lines.len());
 
         {
@@ -582,7 +957,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::line_col(1, 0), "'");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
 
         {
             let mut tokens = highlighted_tokens(&highlighted);
@@ -594,6 +969,7 @@ mod tests {
 
     #[test]
     fn highlight_range_after_unfinished_line() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
 
@@ -602,7 +978,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::zero(), "/*\n\n\n*/");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
         assert_eq!(buffer.lines().len(), highlighted.lines.len());
 
         let mut tokens = highlighted_tokens(&highlighted);
@@ -615,6 +991,7 @@ mod tests {
 
     #[test]
     fn highlight_lines_after_unfinished_to_finished() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
 
@@ -623,7 +1000,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::zero(), "/*\n* /\n*/");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
 
         let range = BufferRange::between(
             BufferPosition::line_col(1, 1),
@@ -631,7 +1008,7 @@ mod tests {
         );
         buffer.delete_range(range);
         highlighted.delete_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
 
         let mut parse_states = highlighted.lines[..highlighted.highlighted_len]
             .iter()
@@ -656,6 +1033,7 @@ mod tests {
 
     #[test]
     fn highlight_lines_after_became_unfinished() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
 
@@ -664,7 +1042,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::zero(), "/ *\na\n*/");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
 
         let range = BufferRange::between(
             BufferPosition::line_col(0, 1),
@@ -672,7 +1050,7 @@ mod tests {
         );
         buffer.delete_range(range);
         highlighted.delete_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
 
         let mut tokens = highlighted_tokens(&highlighted);
         assert_next_token(&mut tokens, TokenKind::Comment, 0..2);
@@ -683,6 +1061,7 @@ mod tests {
 
     #[test]
     fn highlight_unfinished_lines_on_multiline_delete() {
+        let syntaxes = SyntaxCollection::new();
         let mut syntax = Syntax::new();
         syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
 
@@ -691,7 +1070,7 @@ mod tests {
 
         let range = buffer.insert_text(BufferPosition::zero(), "a\n/*\nb\nc*/");
         highlighted.insert_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
         assert_eq!(buffer.lines().len(), highlighted.highlighted_len);
 
         {
@@ -706,7 +1085,7 @@ mod tests {
         let range = BufferRange::between(BufferPosition::zero(), BufferPosition::line_col(1, 1));
         buffer.delete_range(range);
         highlighted.delete_range(range);
-        highlighted.highlight_dirty_lines(&syntax, &buffer);
+        highlighted.highlight_dirty_lines(&syntaxes, &syntax, &buffer);
         assert_eq!(buffer.lines().len(), highlighted.highlighted_len);
 
         {
@@ -719,4 +1098,220 @@ mod tests {
             assert_eq!(None, tokens.next());
         }
     }
+
+    #[test]
+    fn custom_token_kinds() {
+        let mut syntaxes = SyntaxCollection::new();
+        let function_kind = syntaxes.custom_token_kind("function").unwrap();
+        let constant_kind = syntaxes.custom_token_kind("literal.constant").unwrap();
+        assert_eq!(Some(function_kind), syntaxes.custom_token_kind("function"));
+        assert_eq!(None, syntaxes.custom_token_kind("literal..constant"));
+        assert_eq!("literal.constant", syntaxes.custom_token_kind_name(1));
+
+        let mut syntax = Syntax::new();
+        syntax.set_rule(TokenKind::Keyword, "fn").unwrap();
+        syntax.set_rule(TokenKind::Symbol, "%(|%)|;").unwrap();
+        syntax.set_rule(function_kind, "%a{%w_}%(").unwrap();
+        syntax.set_rule(constant_kind, "%u{%u%d_}%b").unwrap();
+
+        let mut tokens = Vec::new();
+        let line = "fn main() MAX;";
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
+
+        assert_eq!(LineParseState::Finished, parse_state);
+        assert_eq!(5, tokens.len());
+        assert_token("fn", TokenKind::Keyword, line, &tokens[0]);
+        assert_token(" main(", function_kind, line, &tokens[1]);
+        assert_token(")", TokenKind::Symbol, line, &tokens[2]);
+        assert_token(" MAX", constant_kind, line, &tokens[3]);
+        assert_token(";", TokenKind::Symbol, line, &tokens[4]);
+    }
+
+    #[test]
+    fn unfinished_custom_token_kind_without_rule() {
+        let mut syntaxes = SyntaxCollection::new();
+        let block_kind = syntaxes.custom_token_kind("comment.block").unwrap();
+
+        let mut syntax = Syntax::new();
+        syntax.set_rule(block_kind, "/*{!(*/).$}").unwrap();
+
+        let mut tokens = Vec::new();
+        let parse_state =
+            syntax.parse_line(&syntaxes, "/* a", LineParseState::Finished, &mut tokens);
+        assert!(matches!(
+            parse_state,
+            LineParseState::Unfinished(TokenKind::Custom(_), _)
+        ));
+
+        let syntax = Syntax::new();
+        let line = "b */";
+        let parse_state = syntax.parse_line(&syntaxes, line, parse_state, &mut tokens);
+        assert_eq!(LineParseState::Finished, parse_state);
+        assert_token("b", TokenKind::Text, line, &tokens[0]);
+    }
+
+    fn embedding_syntaxes() -> SyntaxCollection {
+        let mut syntaxes = SyntaxCollection::new();
+        syntaxes.add_from_glob("**/*.rs").unwrap();
+        let syntax = syntaxes.get_last();
+        syntax.set_rule(TokenKind::Keyword, "fn").unwrap();
+        syntax.set_rule(TokenKind::Comment, "/*{!(*/).$}").unwrap();
+        syntaxes.add_from_glob("**/*.md").unwrap();
+        let syntax = syntaxes.get_last();
+        syntax.set_rule(TokenKind::Symbol, "```").unwrap();
+        syntax.set_rule(TokenKind::Comment, "//{.}").unwrap();
+        syntax.add_region("a.rs", "```rs", "```").unwrap();
+        syntax.add_region("a.rs", "///", "").unwrap();
+        syntax.add_region("a.py", "```py", "```").unwrap();
+        syntaxes
+    }
+
+    #[test]
+    fn embedded_region_in_single_line() {
+        let syntaxes = embedding_syntaxes();
+        let syntax = syntaxes.get(syntaxes.find_handle_by_path("a.md").unwrap());
+
+        let mut tokens = Vec::new();
+        let line = "fn ```rs fn``` fn";
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
+
+        assert_eq!(LineParseState::Finished, parse_state);
+        assert_eq!(6, tokens.len());
+        assert_token("fn", TokenKind::Text, line, &tokens[0]);
+        assert_token(" ```", TokenKind::Symbol, line, &tokens[1]);
+        assert_token("rs", TokenKind::Text, line, &tokens[2]);
+        assert_token(" fn", TokenKind::Keyword, line, &tokens[3]);
+        assert_token("```", TokenKind::Symbol, line, &tokens[4]);
+        assert_token(" fn", TokenKind::Text, line, &tokens[5]);
+
+        let line = "/// fn";
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
+
+        assert_eq!(LineParseState::Finished, parse_state);
+        assert_eq!(2, tokens.len());
+        assert_token("///", TokenKind::Comment, line, &tokens[0]);
+        assert_token(" fn", TokenKind::Keyword, line, &tokens[1]);
+
+        // there's no syntax for `a.py` so its region is not embedded
+        let line = "```py fn // c";
+        let parse_state = syntax.parse_line(&syntaxes, line, LineParseState::Finished, &mut tokens);
+
+        assert_eq!(LineParseState::Finished, parse_state);
+        assert_eq!(4, tokens.len());
+        assert_token("```", TokenKind::Symbol, line, &tokens[0]);
+        assert_token("py", TokenKind::Text, line, &tokens[1]);
+        assert_token(" fn", TokenKind::Text, line, &tokens[2]);
+        assert_token(" // c", TokenKind::Comment, line, &tokens[3]);
+    }
+
+    #[test]
+    fn embedded_region_in_highlighted_buffer() {
+        let syntaxes = embedding_syntaxes();
+        let syntax = syntaxes.get(syntaxes.find_handle_by_path("a.md").unwrap());
+
+        let mut buffer = BufferContent::new();
+        let mut highlighted = HighlightedBuffer::new();
+
+        let range = buffer.insert_text(BufferPosition::zero(), "fn\n```rs\nfn /*\n*/ fn\n```\nfn");
+        highlighted.insert_range(range);
+        highlighted.highlight_dirty_lines(&syntaxes, syntax, &buffer);
+        assert_eq!(buffer.lines().len(), highlighted.highlighted_len);
+
+        {
+            let mut tokens = highlighted_tokens(&highlighted);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..2);
+            assert_next_token(&mut tokens, TokenKind::Symbol, 0..3);
+            assert_next_token(&mut tokens, TokenKind::Text, 3..5);
+            assert_next_token(&mut tokens, TokenKind::Keyword, 0..2);
+            assert_next_token(&mut tokens, TokenKind::Comment, 2..5);
+            assert_next_token(&mut tokens, TokenKind::Comment, 0..2);
+            assert_next_token(&mut tokens, TokenKind::Keyword, 2..5);
+            assert_next_token(&mut tokens, TokenKind::Symbol, 0..3);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..2);
+            assert_eq!(None, tokens.next());
+        }
+
+        let range = BufferRange::between(
+            BufferPosition::line_col(1, 0),
+            BufferPosition::line_col(1, 5),
+        );
+        buffer.delete_range(range);
+        highlighted.delete_range(range);
+        highlighted.highlight_dirty_lines(&syntaxes, syntax, &buffer);
+
+        {
+            let mut tokens = highlighted_tokens(&highlighted);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..2);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..2);
+            assert_next_token(&mut tokens, TokenKind::Text, 2..4);
+            assert_next_token(&mut tokens, TokenKind::Text, 4..5);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..1);
+            assert_next_token(&mut tokens, TokenKind::Text, 1..2);
+            assert_next_token(&mut tokens, TokenKind::Text, 2..5);
+            assert_next_token(&mut tokens, TokenKind::Symbol, 0..3);
+            assert_next_token(&mut tokens, TokenKind::Text, 0..2);
+            assert_eq!(None, tokens.next());
+        }
+    }
+
+    #[test]
+    fn semantic_tokens_follow_edits() {
+        fn token(kind: TokenKind, range: Range<usize>) -> Token {
+            Token {
+                kind,
+                from: range.start as _,
+                to: range.end as _,
+            }
+        }
+
+        let mut highlighted = HighlightedBuffer::new();
+        {
+            let mut semantic_tokens = highlighted.semantic_tokens_mut_guard();
+            semantic_tokens.add(2, token(TokenKind::Type, 4..8));
+            semantic_tokens.add(0, token(TokenKind::Keyword, 0..2));
+            semantic_tokens.add(0, token(TokenKind::Type, 3..7));
+            semantic_tokens.add(1, token(TokenKind::Literal, 0..0));
+        }
+
+        assert_eq!(2, highlighted.line_semantic_tokens(0).len());
+        assert_eq!(0, highlighted.line_semantic_tokens(1).len());
+        assert_eq!(
+            token(TokenKind::Keyword, 0..2),
+            highlighted.line_semantic_tokens(0)[0].token
+        );
+
+        highlighted.insert_range(BufferRange::between(
+            BufferPosition::line_col(0, 0),
+            BufferPosition::line_col(1, 1),
+        ));
+        assert_eq!(0, highlighted.line_semantic_tokens(0).len());
+        let tokens = highlighted.line_semantic_tokens(1);
+        assert_eq!(2, tokens.len());
+        assert_eq!(token(TokenKind::Keyword, 1..3), tokens[0].token);
+        assert_eq!(token(TokenKind::Type, 4..8), tokens[1].token);
+        assert_eq!(
+            token(TokenKind::Type, 4..8),
+            highlighted.line_semantic_tokens(3)[0].token
+        );
+
+        highlighted.delete_range(BufferRange::between(
+            BufferPosition::line_col(1, 2),
+            BufferPosition::line_col(1, 5),
+        ));
+        let tokens = highlighted.line_semantic_tokens(1);
+        assert_eq!(2, tokens.len());
+        assert_eq!(token(TokenKind::Keyword, 1..2), tokens[0].token);
+        assert_eq!(token(TokenKind::Type, 2..5), tokens[1].token);
+
+        highlighted.insert_range(BufferRange::between(
+            BufferPosition::line_col(1, 3),
+            BufferPosition::line_col(2, 0),
+        ));
+        assert_eq!(1, highlighted.line_semantic_tokens(1).len());
+        assert_eq!(0, highlighted.line_semantic_tokens(2).len());
+        assert_eq!(
+            token(TokenKind::Type, 4..8),
+            highlighted.line_semantic_tokens(4)[0].token
+        );
+    }
 }
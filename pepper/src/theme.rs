@@ -1,3 +1,5 @@
+use crate::syntax::is_valid_custom_token_kind_name;
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Color(pub u8, pub u8, pub u8);
 
@@ -24,13 +26,26 @@ macro_rules! theme_colors {
 
         pub struct Theme {
             $(pub $color: Color,)*
+            custom_token_colors: Vec<(String, Color)>,
         }
 
         impl Theme {
             pub fn color_from_name(&mut self, name: &str) -> Option<&mut Color> {
                 match name {
                     $(stringify!($color) => Some(&mut self.$color),)*
-                    _ => None,
+                    _ => self.custom_token_color_from_name(name),
+                }
+            }
+
+            pub fn color_by_name(&self, name: &str) -> Option<Color> {
+                match name {
+                    $(stringify!($color) => Some(self.$color),)*
+                    _ => match name.strip_prefix("token_") {
+                        Some(kind) if is_valid_custom_token_kind_name(kind) => {
+                            Some(self.custom_token_color(kind))
+                        }
+                        _ => None,
+                    },
                 }
             }
         }
@@ -48,6 +63,16 @@ theme_colors! {
     inactive_cursor,
     statusbar_active_background,
     statusbar_inactive_background,
+    virtual_text,
+    range_highlight,
+    gutter_background,
+    gutter_line_number,
+    gutter_active_line_number,
+    gutter_breakpoint,
+    gutter_lint_error,
+    gutter_lint_warning,
+    gutter_lint_info,
+    gutter_lint_hint,
 
     token_whitespace,
     token_text,
@@ -59,6 +84,96 @@ theme_colors! {
     token_literal,
 }
 
+// when a theme does not define a color for these custom token kinds, use these builtin ones instead
+static CUSTOM_TOKEN_KIND_FALLBACKS: &[(&str, &str)] = &[
+    ("macro", "keyword"),
+    ("attribute", "symbol"),
+    ("constant", "literal"),
+    ("todo", "comment"),
+];
+
+impl Theme {
+    fn custom_token_color_from_name(&mut self, name: &str) -> Option<&mut Color> {
+        let kind = name.strip_prefix("token_")?;
+        if !is_valid_custom_token_kind_name(kind) {
+            return None;
+        }
+
+        let index = match self.custom_token_colors.iter().position(|(k, _)| k == kind) {
+            Some(index) => index,
+            None => {
+                let color = self.custom_token_color(kind);
+                self.custom_token_colors.push((kind.into(), color));
+                self.custom_token_colors.len() - 1
+            }
+        };
+        Some(&mut self.custom_token_colors[index].1)
+    }
+
+    fn token_color(&self, kind: &str) -> Option<Color> {
+        match kind {
+            "whitespace" => Some(self.token_whitespace),
+            "text" => Some(self.token_text),
+            "comment" => Some(self.token_comment),
+            "keyword" => Some(self.token_keyword),
+            "type" => Some(self.token_type),
+            "symbol" => Some(self.token_symbol),
+            "string" => Some(self.token_string),
+            "literal" => Some(self.token_literal),
+            _ => self
+                .custom_token_colors
+                .iter()
+                .find(|(k, _)| k == kind)
+                .map(|(_, c)| *c),
+        }
+    }
+
+    // a custom token kind is known when the theme colors it or one of its enclosing scopes,
+    // or when its outermost scope has a builtin fallback. a builtin token kind by itself
+    // (like `keyword`) is not a custom one since it's most likely a misspelled `keywords`
+    pub fn is_known_custom_token_kind(&self, kind: &str) -> bool {
+        let mut scope = kind;
+        loop {
+            if self.custom_token_colors.iter().any(|(k, _)| k == scope) {
+                return true;
+            }
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => break,
+            }
+        }
+
+        (scope.len() < kind.len() && self.token_color(scope).is_some())
+            || CUSTOM_TOKEN_KIND_FALLBACKS.iter().any(|&(k, _)| k == scope)
+    }
+
+    // resolves the color of a custom token kind such as `keyword.control.flow` by trying
+    // `token_keyword.control.flow`, then `token_keyword.control` and then `token_keyword`.
+    // if none is defined, the outermost scope may have a builtin fallback, otherwise it's `token_text`
+    pub fn custom_token_color(&self, kind: &str) -> Color {
+        let mut scope = kind;
+        loop {
+            if let Some(color) = self.token_color(scope) {
+                return color;
+            }
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => break,
+            }
+        }
+
+        for &(kind, fallback) in CUSTOM_TOKEN_KIND_FALLBACKS {
+            if scope == kind {
+                if let Some(color) = self.token_color(fallback) {
+                    return color;
+                }
+            }
+        }
+
+        self.token_text
+    }
+}
+
 impl Default for Theme {
     fn default() -> Self {
         gruvbox_theme()
@@ -77,6 +192,16 @@ pub fn gruvbox_theme() -> Theme {
         inactive_cursor: Color::from_u32(0x504945),
         statusbar_active_background: Color::from_u32(0x504945),
         statusbar_inactive_background: Color::from_u32(0x282828),
+        virtual_text: Color::from_u32(0x665c54),
+        range_highlight: Color::from_u32(0x3c3836),
+        gutter_background: Color::from_u32(0x1d2021),
+        gutter_line_number: Color::from_u32(0x665c54),
+        gutter_active_line_number: Color::from_u32(0xa89984),
+        gutter_breakpoint: Color::from_u32(0xcc241d),
+        gutter_lint_error: Color::from_u32(0xfb4934),
+        gutter_lint_warning: Color::from_u32(0xfabd2f),
+        gutter_lint_info: Color::from_u32(0x83a598),
+        gutter_lint_hint: Color::from_u32(0x8ec07c),
 
         token_whitespace: Color::from_u32(0x504945),
         token_text: Color::from_u32(0xebdbb2),
@@ -86,5 +211,53 @@ pub fn gruvbox_theme() -> Theme {
         token_symbol: Color::from_u32(0xa89984),
         token_string: Color::from_u32(0xb8bb26),
         token_literal: Color::from_u32(0xd3869b),
+
+        custom_token_colors: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_token_color_fallback() {
+        let mut theme = gruvbox_theme();
+        assert_eq!(
+            theme.token_keyword,
+            theme.custom_token_color("keyword.control")
+        );
+        assert_eq!(theme.token_literal, theme.custom_token_color("constant"));
+        assert_eq!(theme.token_text, theme.custom_token_color("unknown.scope"));
+
+        *theme.color_from_name("token_keyword.control").unwrap() = Color(1, 2, 3);
+        assert_eq!(
+            Color(1, 2, 3),
+            theme.custom_token_color("keyword.control.flow")
+        );
+        assert_eq!(
+            theme.token_keyword,
+            theme.custom_token_color("keyword.other")
+        );
+        assert_eq!(
+            Some(Color(1, 2, 3)),
+            theme.color_by_name("token_keyword.control")
+        );
+        assert_eq!(None, theme.color_by_name("token_keyword..control"));
+        assert_eq!(None, theme.color_by_name("not_a_color"));
+    }
+
+    #[test]
+    fn known_custom_token_kinds() {
+        let mut theme = gruvbox_theme();
+        assert!(theme.is_known_custom_token_kind("keyword.control"));
+        assert!(theme.is_known_custom_token_kind("constant.numeric"));
+        assert!(!theme.is_known_custom_token_kind("keyword"));
+        assert!(!theme.is_known_custom_token_kind("function"));
+        assert!(!theme.is_known_custom_token_kind("unknown.scope"));
+
+        *theme.color_from_name("token_function").unwrap() = Color(1, 2, 3);
+        assert!(theme.is_known_custom_token_kind("function"));
+        assert!(theme.is_known_custom_token_kind("function.method"));
     }
 }
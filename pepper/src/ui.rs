@@ -1,12 +1,14 @@
 use std::{io, iter};
 
 use crate::{
-    buffer::CharDisplayDistances,
+    buffer::{char_display_len, Buffer, BufferLintSeverity, LineWrap},
     buffer_position::{BufferPosition, BufferPositionIndex, BufferRange},
     buffer_view::{BufferViewHandle, CursorMovementKind},
+    config::{Config, LineNumbers, Wrap},
     cursor::Cursor,
     editor::Editor,
     editor_utils::{LoggerStatusBarDisplay, REGISTER_READLINE_INPUT, REGISTER_READLINE_PROMPT},
+    layout::{PaneRect, Rect},
     mode::ModeKind,
     syntax::{Token, TokenKind},
     theme::Color,
@@ -36,6 +38,14 @@ pub fn move_cursor_to(buf: &mut Vec<u8>, x: usize, y: usize) {
     let _ = write!(buf, "\x1b[{};{}H", x, y);
 }
 
+fn move_cursor_to_rect_line(buf: &mut Vec<u8>, rect: Rect, line: u16) {
+    move_cursor_to(
+        buf,
+        rect.y as usize + line as usize + 1,
+        rect.x as usize + 1,
+    );
+}
+
 pub fn move_cursor_to_next_line(buf: &mut Vec<u8>) {
     buf.extend_from_slice(b"\x1b[1E");
 }
@@ -67,18 +77,37 @@ pub struct RenderContext<'a> {
     pub editor: &'a Editor,
     pub status_bar_display: &'a LoggerStatusBarDisplay<'a, 'a>,
     pub viewport_size: (u16, u16),
-    pub scroll: BufferPositionIndex,
     pub has_focus: bool,
+    // indexed by `TokenKind::Custom`
+    pub custom_token_colors: &'a [Color],
 }
 
-pub fn draw(ctx: &RenderContext, buffer_view_handle: Option<BufferViewHandle>, buf: &mut Vec<u8>) {
-    draw_buffer_view(ctx, buffer_view_handle, buf);
+// panes are expected to come in the order they're computed by the layout
+// as each line of a pane may clear the rest of the screen line which is then redrawn by the panes to its right
+pub fn draw(ctx: &RenderContext, panes: &[PaneRect], buf: &mut Vec<u8>) {
+    let mut buffer_view_handle = None;
+    let mut layout_height = 0;
+    for pane in panes {
+        draw_buffer_view(ctx, pane, buf);
+        if let Some(rect) = pane.title {
+            draw_pane_title(ctx, pane, rect, buf);
+        }
+        if let Some(rect) = pane.separator {
+            draw_pane_separator(ctx, rect, buf);
+        }
+
+        if pane.has_focus {
+            buffer_view_handle = pane.buffer_view_handle;
+        }
+        layout_height = layout_height.max(pane.rect.y + pane.rect.height);
+    }
+
+    move_cursor_to(buf, layout_height as usize + 1, 1);
     draw_picker(ctx, buf);
     draw_statusbar(ctx, buffer_view_handle, buf);
 }
 
-fn draw_empty_view(ctx: &RenderContext, buf: &mut Vec<u8>) {
-    move_cursor_to(buf, 0, 0);
+fn draw_empty_view(ctx: &RenderContext, rect: Rect, buf: &mut Vec<u8>) {
     buf.extend_from_slice(RESET_STYLE_CODE);
     set_background_color(buf, ctx.editor.theme.normal_background);
     set_foreground_color(buf, ctx.editor.theme.token_whitespace);
@@ -93,22 +122,11 @@ fn draw_empty_view(ctx: &RenderContext, buf: &mut Vec<u8>) {
         "or `:quit<enter>` to quit",
     ];
 
-    let width = ctx.viewport_size.0 as usize;
-    let height = ctx.viewport_size.1.saturating_sub(1) as usize;
+    let width = rect.width as usize;
+    let height = rect.height as usize;
+    let message_lines = &message_lines[..message_lines.len().min(height)];
 
     let margin_top = (height.saturating_sub(message_lines.len())) / 2;
-    let margin_bottom = height - margin_top - message_lines.len();
-
-    let margin_bottom = if ctx.has_focus {
-        let picker_height = ctx
-            .editor
-            .picker
-            .len()
-            .min(ctx.editor.config.picker_max_height as _);
-        margin_bottom.saturating_sub(picker_height)
-    } else {
-        margin_bottom
-    };
 
     let mut visual_empty = [0; 4];
     let visual_empty = ctx
@@ -118,39 +136,244 @@ fn draw_empty_view(ctx: &RenderContext, buf: &mut Vec<u8>) {
         .encode_utf8(&mut visual_empty)
         .as_bytes();
 
-    for _ in 0..margin_top {
+    for line in 0..height {
+        move_cursor_to_rect_line(buf, rect, line as _);
         buf.extend_from_slice(visual_empty);
+
+        if let Some(message) = line
+            .checked_sub(margin_top)
+            .and_then(|i| message_lines.get(i))
+        {
+            let message = &message[..message.len().min(width.saturating_sub(1))];
+            let margin_left = (width.saturating_sub(message.len())) / 2;
+            let margin_left = margin_left.min(width.saturating_sub(message.len() + 1));
+            buf.extend(std::iter::repeat(b' ').take(margin_left));
+            buf.extend_from_slice(message.as_bytes());
+        }
+
         clear_until_new_line(buf);
-        move_cursor_to_next_line(buf);
     }
+}
 
-    for line in message_lines {
-        buf.extend_from_slice(visual_empty);
+fn draw_pane_title(ctx: &RenderContext, pane: &PaneRect, rect: Rect, buf: &mut Vec<u8>) {
+    use io::Write;
 
-        let margin_left = (width.saturating_sub(line.len())) / 2;
-        buf.extend(std::iter::repeat(b' ').take(margin_left));
-        buf.extend_from_slice(line.as_bytes());
+    if ctx.has_focus && pane.has_focus {
+        set_background_color(buf, ctx.editor.theme.statusbar_active_background);
+    } else {
+        set_background_color(buf, ctx.editor.theme.statusbar_inactive_background);
+    }
+    set_foreground_color(buf, ctx.editor.theme.token_text);
+    move_cursor_to_rect_line(buf, rect, 0);
 
-        clear_until_new_line(buf);
-        move_cursor_to_next_line(buf);
+    let title_start_index = buf.len();
+    match pane.buffer_view_handle {
+        Some(handle) => {
+            let buffer_view = ctx.editor.buffer_views.get(handle);
+            let buffer = ctx.editor.buffers.get(buffer_view.buffer_handle);
+            if buffer.needs_save() {
+                buf.push(b'*');
+            }
+            let _ = write!(
+                buf,
+                "{}:{}",
+                buffer.path.to_str().unwrap_or(""),
+                buffer_view.cursors.main_cursor().position
+            );
+        }
+        None => buf.extend_from_slice(b"no buffer"),
     }
 
-    for _ in 0..margin_bottom {
-        buf.extend_from_slice(visual_empty);
-        clear_until_new_line(buf);
-        move_cursor_to_next_line(buf);
+    let mut x = 0;
+    let mut title_len = buf.len() - title_start_index;
+    if let Ok(title) = std::str::from_utf8(&buf[title_start_index..]) {
+        for (i, c) in title.char_indices() {
+            let char_len = char_display_len(c) as usize;
+            if x + char_len > rect.width as usize {
+                title_len = i;
+                break;
+            }
+            x += char_len;
+        }
+    }
+    buf.truncate(title_start_index + title_len);
+    buf.resize(buf.len() + (rect.width as usize).saturating_sub(x), b' ');
+}
+
+fn draw_pane_separator(ctx: &RenderContext, rect: Rect, buf: &mut Vec<u8>) {
+    set_background_color(buf, ctx.editor.theme.normal_background);
+    set_foreground_color(buf, ctx.editor.theme.token_whitespace);
+    for line in 0..rect.height {
+        move_cursor_to_rect_line(buf, rect, line);
+        buf.extend_from_slice("\u{2502}".as_bytes());
+    }
+}
+
+// width of the line numbers and signs drawn to the left of a buffer's text
+// it's zero when there would be no room left for the text
+pub fn gutter_width(config: &Config, buffer: &Buffer, view_width: u16) -> u16 {
+    let mut width = 0;
+    if config.gutter_signs {
+        width += 2;
+    }
+    if config.line_numbers != LineNumbers::None {
+        let mut digit_count = 1;
+        let mut line_count = buffer.content().lines().len();
+        while line_count >= 10 {
+            line_count /= 10;
+            digit_count += 1;
+        }
+        width += digit_count.max(3) + 1;
+    }
+
+    if width < view_width {
+        width
+    } else {
+        0
+    }
+}
+
+fn token_kind_color(ctx: &RenderContext, token_kind: TokenKind) -> Color {
+    match token_kind {
+        TokenKind::Keyword => ctx.editor.theme.token_keyword,
+        TokenKind::Type => ctx.editor.theme.token_type,
+        TokenKind::Symbol => ctx.editor.theme.token_symbol,
+        TokenKind::Literal => ctx.editor.theme.token_literal,
+        TokenKind::String => ctx.editor.theme.token_string,
+        TokenKind::Comment => ctx.editor.theme.token_comment,
+        TokenKind::Text => ctx.editor.theme.token_text,
+        TokenKind::Whitespace => ctx.editor.theme.token_whitespace,
+        TokenKind::Custom(index) => match ctx.custom_token_colors.get(index as usize) {
+            Some(&color) => color,
+            None => ctx.editor.theme.token_text,
+        },
     }
 }
 
-fn draw_buffer_view(
+// `row_line_indexes` holds the buffer line index of each row that starts a line
+// and `BufferPositionIndex::MAX` for the rows that continue a wrapped line
+fn draw_gutter(
     ctx: &RenderContext,
-    buffer_view_handle: Option<BufferViewHandle>,
+    buffer: &Buffer,
+    rect: Rect,
+    active_line_index: usize,
+    row_line_indexes: &[BufferPositionIndex],
     buf: &mut Vec<u8>,
 ) {
-    let buffer_view_handle = match buffer_view_handle {
+    use io::Write;
+
+    let config = &ctx.editor.config;
+    let theme = &ctx.editor.theme;
+
+    let signs = buffer.signs.all();
+    let mut sign_index = 0;
+    let breakpoints = buffer.breakpoints();
+    let mut breakpoint_index = 0;
+    let lints = buffer.lints.all();
+    let mut lint_index = 0;
+
+    let signs_width = if config.gutter_signs { 2 } else { 0 };
+    let number_width = (rect.width as usize).saturating_sub(signs_width + 1);
+
+    set_background_color(buf, theme.gutter_background);
+    set_not_underlined(buf);
+
+    for row in 0..rect.height {
+        move_cursor_to_rect_line(buf, rect, row);
+
+        let line_index = match row_line_indexes.get(row as usize) {
+            Some(&line_index) if line_index != BufferPositionIndex::MAX => line_index,
+            _ => {
+                buf.resize(buf.len() + rect.width as usize, b' ');
+                continue;
+            }
+        };
+
+        if config.gutter_signs {
+            while sign_index < signs.len() && signs[sign_index].line_index < line_index {
+                sign_index += 1;
+            }
+            match signs.get(sign_index).filter(|s| s.line_index == line_index) {
+                Some(sign) => {
+                    set_foreground_color(buf, token_kind_color(ctx, sign.token_kind));
+                    let mut char_buf = [0; std::mem::size_of::<char>()];
+                    buf.extend_from_slice(sign.sign.encode_utf8(&mut char_buf).as_bytes());
+                }
+                None => buf.push(b' '),
+            }
+
+            while breakpoint_index < breakpoints.len()
+                && breakpoints[breakpoint_index].line_index < line_index
+            {
+                breakpoint_index += 1;
+            }
+            while lint_index < lints.len() && lints[lint_index].range.from.line_index < line_index {
+                lint_index += 1;
+            }
+            let lint_severity = lints[lint_index..]
+                .iter()
+                .take_while(|l| l.range.from.line_index == line_index)
+                .map(|l| l.severity)
+                .min();
+
+            if breakpoints
+                .get(breakpoint_index)
+                .map(|b| b.line_index == line_index)
+                .unwrap_or(false)
+            {
+                set_foreground_color(buf, theme.gutter_breakpoint);
+                buf.extend_from_slice("\u{25cf}".as_bytes());
+            } else {
+                match lint_severity {
+                    Some(BufferLintSeverity::Error) => {
+                        set_foreground_color(buf, theme.gutter_lint_error);
+                        buf.push(b'E');
+                    }
+                    Some(BufferLintSeverity::Warning) => {
+                        set_foreground_color(buf, theme.gutter_lint_warning);
+                        buf.push(b'W');
+                    }
+                    Some(BufferLintSeverity::Info) => {
+                        set_foreground_color(buf, theme.gutter_lint_info);
+                        buf.push(b'I');
+                    }
+                    Some(BufferLintSeverity::Hint) => {
+                        set_foreground_color(buf, theme.gutter_lint_hint);
+                        buf.push(b'H');
+                    }
+                    None => buf.push(b' '),
+                }
+            }
+        }
+
+        if config.line_numbers != LineNumbers::None {
+            let line_index = line_index as usize;
+            let line_number = match config.line_numbers {
+                LineNumbers::Relative if line_index != active_line_index => {
+                    line_index.abs_diff(active_line_index)
+                }
+                _ => line_index + 1,
+            };
+            if line_index == active_line_index {
+                set_foreground_color(buf, theme.gutter_active_line_number);
+            } else {
+                set_foreground_color(buf, theme.gutter_line_number);
+            }
+            let _ = write!(buf, "{:>width$} ", line_number, width = number_width);
+        }
+    }
+}
+
+fn draw_buffer_view(ctx: &RenderContext, pane: &PaneRect, buf: &mut Vec<u8>) {
+    let rect = pane.view;
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+
+    let buffer_view_handle = match pane.buffer_view_handle {
         Some(handle) => handle,
         None => {
-            draw_empty_view(ctx, buf);
+            draw_empty_view(ctx, rect, buf);
             return;
         }
     };
@@ -160,22 +383,31 @@ fn draw_buffer_view(
     let cursors = &buffer_view.cursors[..];
     let active_line_index = buffer_view.cursors.main_cursor().position.line_index as usize;
 
-    let tab_size = ctx.editor.config.tab_size.max(1);
-
-    let draw_width = ctx.viewport_size.0 as usize;
-    let draw_height = ctx.viewport_size.1.saturating_sub(1);
-    let draw_height = if ctx.has_focus {
-        let picker_height = ctx
-            .editor
-            .picker
-            .len()
-            .min(ctx.editor.config.picker_max_height as _);
-        draw_height.saturating_sub(picker_height as _)
-    } else {
-        draw_height
+    let gutter_width = gutter_width(&ctx.editor.config, buffer, rect.width);
+    let gutter_rect = Rect {
+        width: gutter_width,
+        ..rect
+    };
+    let rect = Rect {
+        x: rect.x + gutter_width,
+        width: rect.width - gutter_width,
+        ..rect
+    };
+    let mut row_line_indexes = Vec::new();
+    if gutter_width > 0 {
+        row_line_indexes.reserve(rect.height as _);
+    }
+
+    let draw_width = rect.width as usize;
+    let draw_height = rect.height;
+
+    let wrap = buffer.properties.wrap.unwrap_or(ctx.editor.config.wrap);
+    let horizontal_scroll = match wrap {
+        Wrap::None => buffer_view.horizontal_scroll as usize,
+        _ => 0,
     };
 
-    let cursor_color = if ctx.has_focus {
+    let cursor_color = if ctx.has_focus && pane.has_focus {
         match ctx.editor.mode.kind() {
             ModeKind::Insert => ctx.editor.theme.insert_cursor,
             _ => match ctx.editor.mode.normal_state.movement_kind {
@@ -197,30 +429,50 @@ fn draw_buffer_view(
     let lints = buffer.lints.all();
     let lints_end_index = lints.len().saturating_sub(1);
 
+    let annotations = buffer.annotations.all();
+    let mut current_annotation_index = 0;
+
+    let range_highlights = buffer.range_highlights.all();
+    let range_highlights_end_index = range_highlights.len().saturating_sub(1);
+
     let breakpoints = buffer.breakpoints();
     let breakpoints_end_index = breakpoints.len().saturating_sub(1);
 
     let mut scroll_offset = BufferPosition::zero();
-    let mut scroll_padding_top = ctx.scroll as usize;
-    for (line_index, display_len) in buffer_content.line_display_lens().iter().enumerate() {
+    let mut scroll_padding_top = buffer_view.scroll as usize;
+    for (line_index, line) in buffer_content.lines().iter().enumerate() {
+        if buffer_view.folds.is_line_hidden(line_index as _) {
+            continue;
+        }
         scroll_offset.line_index = line_index as _;
 
         if scroll_padding_top == 0 {
             break;
         }
 
-        let line_height = 1 + display_len.total_len(tab_size) / draw_width;
+        let line_height = LineWrap::line_height(
+            &ctx.editor.config,
+            wrap,
+            draw_width,
+            line,
+            line_index as _,
+            &buffer.annotations,
+        );
         if line_height <= scroll_padding_top {
             scroll_padding_top -= line_height;
             continue;
         }
 
-        let line = buffer_content.lines()[line_index].as_str();
-        let target_display_len = (scroll_padding_top * draw_width) as _;
-        for d in CharDisplayDistances::new(line, tab_size) {
-            if d.distance >= target_display_len {
-                let index = d.char_index as usize + d.char.len_utf8();
-                scroll_offset.column_byte_index = index as _;
+        // the view starts at the first char of this line's `scroll_padding_top` row
+        let line = line.as_str();
+        let mut line_wrap = LineWrap::new(&ctx.editor.config, wrap, draw_width, line);
+        let mut line_annotations = buffer.annotations.line(line_index as _);
+        scroll_offset.column_byte_index = line.len() as _;
+        for (char_index, c) in line.char_indices() {
+            line_wrap.advance_annotations(&buffer.annotations, &mut line_annotations, char_index);
+            line_wrap.advance(line, char_index, c);
+            if line_wrap.row >= scroll_padding_top {
+                scroll_offset.column_byte_index = char_index as _;
                 break;
             }
         }
@@ -261,6 +513,16 @@ fn draw_buffer_view(
         }
     }
 
+    let mut current_range_highlight_index = range_highlights.len();
+    let mut current_range_highlight = BufferRange::zero();
+    for (i, highlight) in range_highlights.iter().enumerate() {
+        if scroll_offset < highlight.range.to {
+            current_range_highlight_index = i;
+            current_range_highlight = highlight.range;
+            break;
+        }
+    }
+
     let mut current_breakpoint_index = breakpoints.len();
     let mut current_breakpoint_line_index = BufferPositionIndex::MAX;
     for (i, breakpoint) in breakpoints.iter().enumerate() {
@@ -271,7 +533,6 @@ fn draw_buffer_view(
         }
     }
 
-    move_cursor_to(buf, 0, 0);
     set_background_color(buf, ctx.editor.theme.normal_background);
     set_not_underlined(buf);
 
@@ -321,22 +582,43 @@ fn draw_buffer_view(
             Token(TokenKind),
             Selection(TokenKind),
             Highlight,
+            RangeHighlight(TokenKind),
             Cursor,
+            Annotation,
         }
 
+        if buffer_view.folds.is_line_hidden(line_index as _) {
+            continue;
+        }
         if lines_drawn_count == draw_height {
             break;
         }
+        move_cursor_to_rect_line(buf, rect, lines_drawn_count);
         lines_drawn_count += 1;
+        if gutter_width > 0 {
+            row_line_indexes.push(line_index as _);
+        }
+
+        let line = line.as_str();
+        let line_start_index = scroll_offset.column_byte_index as usize;
+        let mut line_wrap = LineWrap::new(&ctx.editor.config, wrap, draw_width, line);
+        if line_start_index > 0 {
+            for (char_index, c) in line[..line_start_index].char_indices() {
+                line_wrap.advance(line, char_index, c);
+            }
+            line_wrap.new_row();
+        }
 
-        let line = &line.as_str()[scroll_offset.column_byte_index as usize..];
         let mut draw_state = DrawState::Token(TokenKind::Text);
         let mut was_inside_lint_range = false;
         let mut x = 0;
         let mut last_line_token = Token::default();
         let mut line_tokens = highlighted_buffer.line_tokens(line_index).iter();
+        let line_semantic_tokens = highlighted_buffer.line_semantic_tokens(line_index);
+        let mut line_semantic_token_index = 0;
 
-        if (current_breakpoint_line_index as usize) < line_index
+        // ranges that are inside folded lines are skipped all at once
+        while (current_breakpoint_line_index as usize) < line_index
             && current_breakpoint_index < breakpoints_end_index
         {
             current_breakpoint_index += 1;
@@ -355,12 +637,29 @@ fn draw_buffer_view(
         set_background_color(buf, background_color);
         set_foreground_color(buf, ctx.editor.theme.token_text);
 
-        for (char_index, c) in line.char_indices().chain(iter::once((line.len(), '\n'))) {
-            let char_index = char_index + scroll_offset.column_byte_index as usize;
+        let line_end_index = line.len() - line_start_index;
+        'chars_loop: for (char_index, c) in line[line_start_index..]
+            .char_indices()
+            .chain(iter::once((line_end_index, '\n')))
+        {
+            let char_index = char_index + line_start_index;
             let char_position = BufferPosition::line_col(line_index as _, char_index as _);
 
+            while let Some(token) = line_semantic_tokens.get(line_semantic_token_index) {
+                if token.token.to as usize <= char_index {
+                    line_semantic_token_index += 1;
+                } else {
+                    break;
+                }
+            }
+            let semantic_token = line_semantic_tokens
+                .get(line_semantic_token_index)
+                .filter(|t| t.token.contains(char_index as _));
+
             let token_kind = if c.is_ascii_whitespace() {
                 TokenKind::Whitespace
+            } else if let Some(token) = semantic_token {
+                token.token.kind
             } else {
                 if !last_line_token.contains(char_index as _) {
                     while let Some(token) = line_tokens.next() {
@@ -373,18 +672,11 @@ fn draw_buffer_view(
                 last_line_token.kind
             };
 
-            let text_color = match token_kind {
-                TokenKind::Keyword => ctx.editor.theme.token_keyword,
-                TokenKind::Type => ctx.editor.theme.token_type,
-                TokenKind::Symbol => ctx.editor.theme.token_symbol,
-                TokenKind::Literal => ctx.editor.theme.token_literal,
-                TokenKind::String => ctx.editor.theme.token_string,
-                TokenKind::Comment => ctx.editor.theme.token_comment,
-                TokenKind::Text => ctx.editor.theme.token_text,
-                TokenKind::Whitespace => ctx.editor.theme.token_whitespace,
-            };
+            let text_color = token_kind_color(ctx, token_kind);
 
-            if current_cursor_index < cursors_end_index && current_cursor_range.to < char_position {
+            while current_cursor_index < cursors_end_index
+                && current_cursor_range.to < char_position
+            {
                 current_cursor_index += 1;
                 let cursor = cursors[current_cursor_index];
                 current_cursor_position = cursor.position;
@@ -393,7 +685,7 @@ fn draw_buffer_view(
             let inside_cursor_range = current_cursor_range.from <= char_position
                 && char_position < current_cursor_range.to;
 
-            if current_search_range.to <= char_position
+            while current_search_range.to <= char_position
                 && current_search_range_index < search_ranges_end_index
             {
                 current_search_range_index += 1;
@@ -402,13 +694,74 @@ fn draw_buffer_view(
             let inside_search_range = current_search_range.from <= char_position
                 && char_position < current_search_range.to;
 
-            if current_lint_range.to < char_position && current_lint_index < lints_end_index {
+            while current_lint_range.to < char_position && current_lint_index < lints_end_index {
                 current_lint_index += 1;
                 current_lint_range = lints[current_lint_index].range;
             }
             let inside_lint_range =
                 current_lint_range.from <= char_position && char_position < current_lint_range.to;
 
+            while current_range_highlight.to <= char_position
+                && current_range_highlight_index < range_highlights_end_index
+            {
+                current_range_highlight_index += 1;
+                current_range_highlight = range_highlights[current_range_highlight_index].range;
+            }
+            let inside_range_highlight = current_range_highlight.from <= char_position
+                && char_position < current_range_highlight.to;
+
+            while let Some(annotation) = annotations.get(current_annotation_index) {
+                if annotation.position < char_position {
+                    current_annotation_index += 1;
+                    continue;
+                }
+                if annotation.position > char_position {
+                    break;
+                }
+                current_annotation_index += 1;
+
+                if draw_state != DrawState::Annotation {
+                    draw_state = DrawState::Annotation;
+                    set_background_color(buf, background_color);
+                    set_foreground_color(buf, ctx.editor.theme.virtual_text);
+                }
+                if was_inside_lint_range {
+                    was_inside_lint_range = false;
+                    set_not_underlined(buf);
+                }
+
+                // annotations wrap just like the line chars so they are laid out the same way
+                for c in annotation.text(&buffer.annotations).chars() {
+                    let c = if c.is_ascii_whitespace() { ' ' } else { c };
+                    let char_len = char_display_len(c) as usize;
+                    if line_wrap.advance_annotation(c) {
+                        if lines_drawn_count == draw_height {
+                            break 'chars_loop;
+                        }
+                        buf.resize(buf.len() + draw_width.saturating_sub(x), b' ');
+                        move_cursor_to_rect_line(buf, rect, lines_drawn_count);
+                        lines_drawn_count += 1;
+                        if gutter_width > 0 {
+                            row_line_indexes.push(BufferPositionIndex::MAX);
+                        }
+                        x = 0;
+                    }
+
+                    let char_start = line_wrap.x - char_len;
+                    if char_start < horizontal_scroll {
+                        continue;
+                    }
+                    let draw_start = char_start - horizontal_scroll;
+                    let draw_end = line_wrap.x - horizontal_scroll;
+                    if draw_width < draw_end {
+                        break 'chars_loop;
+                    }
+                    buf.resize(buf.len() + draw_start.saturating_sub(x), b' ');
+                    x = draw_end;
+                    buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes());
+                }
+            }
+
             if inside_lint_range != was_inside_lint_range {
                 was_inside_lint_range = inside_lint_range;
                 if inside_lint_range {
@@ -436,68 +789,108 @@ fn draw_buffer_view(
                     set_background_color(buf, ctx.editor.theme.highlight);
                     set_foreground_color(buf, background_color);
                 }
+            } else if inside_range_highlight {
+                if draw_state != DrawState::RangeHighlight(token_kind) {
+                    draw_state = DrawState::RangeHighlight(token_kind);
+                    set_background_color(buf, ctx.editor.theme.range_highlight);
+                    set_foreground_color(buf, text_color);
+                }
             } else if draw_state != DrawState::Token(token_kind) {
                 draw_state = DrawState::Token(token_kind);
                 set_background_color(buf, background_color);
                 set_foreground_color(buf, text_color);
             }
 
-            let previous_x = x;
-            let previous_buf_len = buf.len();
-
-            match c {
-                '\n' => {
-                    x += 1;
-                    buf.push(b' ');
+            // lines are wrapped explicitly as the pane may not span the whole screen width
+            let char_len = line_wrap.char_len(c);
+            if line_wrap.advance(line, char_index, c) {
+                if lines_drawn_count == draw_height {
+                    break;
                 }
-                ' ' => {
-                    x += 1;
-                    buf.extend_from_slice(visual_space);
+                buf.resize(buf.len() + draw_width.saturating_sub(x), b' ');
+                move_cursor_to_rect_line(buf, rect, lines_drawn_count);
+                lines_drawn_count += 1;
+                if gutter_width > 0 {
+                    row_line_indexes.push(BufferPositionIndex::MAX);
                 }
-                '\t' => {
-                    x += tab_size as usize;
+                x = 0;
+            }
 
-                    buf.extend_from_slice(visual_tab_first);
-                    for _ in 0..tab_size - 1 {
-                        buf.extend_from_slice(visual_tab_repeat);
-                    }
-                }
-                _ => {
-                    x += 1;
-                    buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes());
-                }
+            // chars scrolled out of the view to the left are not drawn
+            let char_start = line_wrap.x - char_len;
+            if char_start < horizontal_scroll && line_wrap.x <= horizontal_scroll {
+                continue;
             }
+            let hidden_len = horizontal_scroll.saturating_sub(char_start);
+            let draw_start = char_start.saturating_sub(horizontal_scroll);
+            let draw_end = line_wrap.x - horizontal_scroll;
+            if draw_width < draw_end {
+                break;
+            }
+            // continuation rows may start indented
+            buf.resize(buf.len() + draw_start.saturating_sub(x), b' ');
+            x = draw_end;
 
-            if x > ctx.viewport_size.0 as _ {
-                x -= ctx.viewport_size.0 as usize;
-                lines_drawn_count += 1;
-                if lines_drawn_count > draw_height {
-                    lines_drawn_count = draw_height;
-                    buf.truncate(previous_buf_len);
-                    x = previous_x;
-                    break;
+            match c {
+                '\n' => buf.push(b' '),
+                ' ' => buf.extend_from_slice(visual_space),
+                '\t' => {
+                    for i in hidden_len..char_len {
+                        if i == 0 {
+                            buf.extend_from_slice(visual_tab_first);
+                        } else {
+                            buf.extend_from_slice(visual_tab_repeat);
+                        }
+                    }
                 }
+                _ if hidden_len > 0 => buf.resize(buf.len() + char_len - hidden_len, b' '),
+                _ => buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes()),
             }
         }
 
         scroll_offset.column_byte_index = 0;
         set_background_color(buf, background_color);
 
-        if x < ctx.viewport_size.0 as _ {
-            clear_until_new_line(buf);
+        if let Some(fold) = buffer_view.folds.fold_at(line_index as _) {
+            use io::Write;
+
+            set_not_underlined(buf);
+            set_foreground_color(buf, ctx.editor.theme.virtual_text);
+
+            let fold_text_start_index = buf.len();
+            let _ = write!(buf, " ... {} lines", fold.hidden_line_count());
+            let fold_text_len = buf.len() - fold_text_start_index;
+            if x + fold_text_len <= draw_width {
+                x += fold_text_len;
+            } else {
+                buf.truncate(fold_text_start_index);
+            }
         }
 
-        move_cursor_to_next_line(buf);
+        if x < draw_width {
+            clear_until_new_line(buf);
+        }
     }
 
     set_not_underlined(buf);
     set_background_color(buf, ctx.editor.theme.normal_background);
     set_foreground_color(buf, ctx.editor.theme.token_whitespace);
 
-    for _ in lines_drawn_count..draw_height {
+    for line in lines_drawn_count..draw_height {
+        move_cursor_to_rect_line(buf, rect, line);
         buf.extend_from_slice(visual_empty);
         clear_until_new_line(buf);
-        move_cursor_to_next_line(buf);
+    }
+
+    if gutter_width > 0 {
+        draw_gutter(
+            ctx,
+            buffer,
+            gutter_rect,
+            active_line_index,
+            &row_line_indexes,
+            buf,
+        );
     }
 }
 
@@ -542,23 +935,42 @@ fn draw_picker(ctx: &RenderContext, buf: &mut Vec<u8>) {
         fn print_char(buf: &mut Vec<u8>, x: &mut usize, c: char) {
             let mut char_buf = [0; std::mem::size_of::<char>()];
 
-            *x += 1;
             match c {
-                '\t' => buf.push(b' '),
-                c => buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes()),
+                '\t' => {
+                    *x += 1;
+                    buf.push(b' ');
+                }
+                c => {
+                    *x += char_display_len(c) as usize;
+                    buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes());
+                }
+            }
+        }
+
+        fn entry_char_display_len(c: char) -> usize {
+            match c {
+                '\t' => 1,
+                c => char_display_len(c) as _,
             }
         }
 
-        let name_char_count = entry.chars().count();
-        if name_char_count < width {
+        let name_display_len: usize = entry.chars().map(entry_char_display_len).sum();
+        if name_display_len < width {
             for c in entry.chars() {
                 print_char(buf, &mut x, c);
             }
         } else {
             buf.extend_from_slice(b"...");
             x += 3;
-            let name_char_count = name_char_count + 3;
-            for c in entry.chars().skip(name_char_count.saturating_sub(width)) {
+            let mut skip_len = name_display_len + 3 - width;
+            let mut chars = entry.chars();
+            while skip_len > 0 {
+                match chars.next() {
+                    Some(c) => skip_len = skip_len.saturating_sub(entry_char_display_len(c)),
+                    None => break,
+                }
+            }
+            for c in chars {
                 print_char(buf, &mut x, c);
             }
         }
@@ -700,11 +1112,18 @@ fn draw_statusbar(
     };
 
     if let Some(x) = x {
-        fn take_chars(s: &str, char_count: usize) -> (usize, &str) {
-            match s.char_indices().rev().enumerate().take(char_count).last() {
-                Some((char_index, (byte_index, _))) => (char_index + 1, &s[byte_index..]),
-                None => (0, s),
+        fn take_chars(s: &str, max_display_len: usize) -> (usize, &str) {
+            let mut display_len = 0;
+            let mut start_index = s.len();
+            for (i, c) in s.char_indices().rev() {
+                let char_len = char_display_len(c) as usize;
+                if display_len + char_len > max_display_len {
+                    break;
+                }
+                display_len += char_len;
+                start_index = i;
             }
+            (display_len, &s[start_index..])
         }
 
         let available_width = ctx.viewport_size.0 as usize - x;
@@ -723,15 +1142,19 @@ fn draw_statusbar(
             buf.push(b' ');
         }
 
+        for progress in ctx.editor.progresses.iter() {
+            let _ = write!(buf, "[{}] ", progress);
+        }
+
         if needs_save {
             buf.push(b'*');
         }
 
-        let (char_count, view_name) = take_chars(view_name, half_available_width);
-        if char_count == half_available_width {
+        let (_, visible_view_name) = take_chars(view_name, half_available_width);
+        if visible_view_name.len() < view_name.len() {
             buf.extend_from_slice(TOO_LONG_PREFIX);
         }
-        buf.extend_from_slice(view_name.as_bytes());
+        buf.extend_from_slice(visible_view_name.as_bytes());
 
         if !view_name.is_empty() {
             if main_cursor.anchor != main_cursor.position {
@@ -754,25 +1177,26 @@ fn draw_statusbar(
         };
 
         let available_width_minus_prefix = available_width.saturating_sub(TOO_LONG_PREFIX.len());
-        let (char_count, status) = take_chars(status, available_width_minus_prefix);
-
-        let status_len = status.len();
-        let buf_len = status_start_index + status_len + available_width - char_count;
-        buf.resize(buf_len, 0);
+        let (display_len, visible_status) = take_chars(status, available_width_minus_prefix);
+        let status_was_truncated = visible_status.len() < status.len();
+
+        let status_len = visible_status.len();
+        let visible_status_start_index = buf.len() - status_len;
+        let buf_len = status_start_index + status_len + available_width - display_len;
+        if buf.len() < buf_len {
+            buf.resize(buf_len, 0);
+        }
         buf.copy_within(
-            status_start_index..status_start_index + status_len,
+            visible_status_start_index..visible_status_start_index + status_len,
             buf_len - status_len,
         );
+        buf.truncate(buf_len);
         for b in &mut buf[status_start_index..buf_len - status_len] {
             *b = b' ';
         }
-        if char_count == available_width_minus_prefix {
+        if status_was_truncated && TOO_LONG_PREFIX.len() <= available_width {
             let start_index = buf_len - status_len - TOO_LONG_PREFIX.len();
-            let (prefix, rest) = buf[start_index..].split_at_mut(TOO_LONG_PREFIX.len());
-            prefix.copy_from_slice(TOO_LONG_PREFIX);
-            for b in rest.iter_mut().take_while(|b| !b.is_ascii()) {
-                *b = b'.';
-            }
+            buf[start_index..start_index + TOO_LONG_PREFIX.len()].copy_from_slice(TOO_LONG_PREFIX);
         }
     }
 
@@ -0,0 +1,273 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{glob::Glob, pattern::Pattern};
+
+struct IgnoreRule {
+    glob: Glob,
+    negated: bool,
+    only_dirs: bool,
+}
+
+#[derive(Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    // adds the rules of a `.gitignore` file found inside `dir`
+    // which is relative to the search root and uses `/` as separator
+    pub fn add_from_gitignore(&mut self, dir: &str, text: &str) {
+        let mut glob_text = String::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let line = line.strip_prefix('\\').unwrap_or(line);
+            let (only_dirs, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+
+            // patterns with a separator are relative to the `.gitignore` directory
+            // while the others match at any depth
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+
+            glob_text.clear();
+            if !dir.is_empty() {
+                glob_text.push_str(dir);
+                glob_text.push('/');
+            }
+            if !anchored {
+                glob_text.push_str("**/");
+            }
+            glob_text.push_str(line);
+
+            let mut glob = Glob::default();
+            if glob.compile(&glob_text).is_ok() {
+                self.rules.push(IgnoreRule {
+                    glob,
+                    negated,
+                    only_dirs,
+                });
+            }
+        }
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        for rule in self.rules.iter().rev() {
+            if rule.only_dirs && !is_dir {
+                continue;
+            }
+            if rule.glob.matches(path) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+// writes `<path>:<line>:<column>:<line text>` for each line of each file inside `root` that matches `pattern`
+// it skips the `.git` directory, binary files and whatever is ignored by the `.gitignore` files found along the way.
+// symlinks are followed but each directory is only searched once so that symlink cycles terminate
+pub fn search_workspace(root: &Path, pattern: &Pattern, output: &mut dyn Write) -> io::Result<()> {
+    let mut path = String::new();
+    let mut rules = IgnoreRules::default();
+    let mut visited = HashSet::new();
+    search_directory(root, &mut path, &mut rules, &mut visited, pattern, output)
+}
+
+fn search_directory(
+    root: &Path,
+    path: &mut String,
+    rules: &mut IgnoreRules,
+    visited: &mut HashSet<PathBuf>,
+    pattern: &Pattern,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let directory = root.join(&path);
+    match fs::canonicalize(&directory) {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return Ok(());
+            }
+        }
+        Err(_) => return Ok(()),
+    }
+
+    let rules_len = rules.rules.len();
+    if let Ok(text) = fs::read_to_string(directory.join(".gitignore")) {
+        rules.add_from_gitignore(path, &text);
+    }
+
+    let mut entries: Vec<_> = match fs::read_dir(&directory) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(_) => return Ok(()),
+    };
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let file_type = match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata.file_type(),
+                Err(_) => continue,
+            },
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        let file_name = entry.file_name();
+        let name = match file_name.to_str() {
+            Some(".git") | None => continue,
+            Some(name) => name,
+        };
+
+        let path_len = path.len();
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(name);
+
+        let is_dir = file_type.is_dir();
+        if !rules.is_ignored(path, is_dir) {
+            if is_dir {
+                search_directory(root, path, rules, visited, pattern, output)?;
+            } else if file_type.is_file() {
+                search_file(root, path, pattern, output)?;
+            }
+        }
+
+        path.truncate(path_len);
+    }
+
+    rules.rules.truncate(rules_len);
+    Ok(())
+}
+
+fn search_file(
+    root: &Path,
+    path: &str,
+    pattern: &Pattern,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let bytes = match fs::read(root.join(path)) {
+        Ok(bytes) => bytes,
+        Err(_) => return Ok(()),
+    };
+    if bytes.contains(&0) {
+        return Ok(());
+    }
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => text,
+        Err(_) => return Ok(()),
+    };
+
+    let search_anchor = pattern.search_anchor();
+    let mut found = false;
+    for (line_index, line) in text.lines().enumerate() {
+        if let Some(range) = pattern.match_indices(line, search_anchor).next() {
+            found = true;
+            writeln!(
+                output,
+                "{}:{}:{}:{}",
+                path,
+                line_index + 1,
+                range.start + 1,
+                line
+            )?;
+        }
+    }
+
+    if found {
+        output.flush()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn run(pattern: &str) {
+    let mut search_pattern = Pattern::new();
+    if let Err(error) = search_pattern.compile_searcher(pattern) {
+        eprintln!("{}", error);
+        return;
+    }
+
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+    let _ = search_workspace(Path::new("."), &search_pattern, &mut output);
+    let _ = output.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_rules() {
+        let mut rules = IgnoreRules::default();
+        rules.add_from_gitignore(
+            "",
+            "# comment\n\ntarget/\n*.log\n!keep.log\n/build\ndocs/*.html\n",
+        );
+        rules.add_from_gitignore("sub", "local\n!/target\n");
+
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored("target", false));
+        assert!(rules.is_ignored("a/b/target", true));
+        assert!(!rules.is_ignored("sub/target", true));
+
+        assert!(rules.is_ignored("a.log", false));
+        assert!(rules.is_ignored("a/b.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(!rules.is_ignored("a/keep.log", false));
+
+        assert!(rules.is_ignored("build", true));
+        assert!(!rules.is_ignored("a/build", true));
+        assert!(rules.is_ignored("docs/index.html", false));
+        assert!(!rules.is_ignored("docs/api/index.html", false));
+
+        assert!(rules.is_ignored("sub/local", false));
+        assert!(rules.is_ignored("sub/a/local", false));
+        assert!(!rules.is_ignored("local", false));
+
+        assert!(!rules.is_ignored("src/main.rs", false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn search_follows_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let mut root = std::env::temp_dir();
+        root.push(format!("pepper-workspace-search-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir/a.txt"), "match\n").unwrap();
+        symlink(root.join("dir/a.txt"), root.join("b.txt")).unwrap();
+        symlink(&root, root.join("dir/cycle")).unwrap();
+
+        let mut pattern = Pattern::new();
+        pattern.compile_searcher("match").unwrap();
+        let mut output = Vec::new();
+        let result = search_workspace(&root, &pattern, &mut output);
+        let _ = fs::remove_dir_all(&root);
+
+        result.unwrap();
+        assert_eq!(
+            "b.txt:1:1:match\ndir/a.txt:1:1:match\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -1,5 +1,31 @@
 use crate::json::{Json, JsonArray, JsonObject, JsonValue};
 
+pub static SEMANTIC_TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "class",
+    "enum",
+    "interface",
+    "struct",
+    "typeParameter",
+    "parameter",
+    "variable",
+    "property",
+    "enumMember",
+    "event",
+    "function",
+    "method",
+    "macro",
+    "keyword",
+    "modifier",
+    "comment",
+    "string",
+    "number",
+    "regexp",
+    "operator",
+    "decorator",
+];
+
 pub fn client_capabilities(json: &mut Json) -> JsonValue {
     fn symbol_kind(json: &mut Json) -> JsonObject {
         // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_documentSymbol
@@ -99,6 +125,20 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
             workspace_capabilities.set("symbol".into(), symbol.into(), json);
         }
 
+        {
+            let mut inlay_hint = JsonObject::default();
+            inlay_hint.set("refreshSupport".into(), true.into(), json);
+
+            workspace_capabilities.set("inlayHint".into(), inlay_hint.into(), json);
+        }
+
+        {
+            let mut diagnostics = JsonObject::default();
+            diagnostics.set("refreshSupport".into(), true.into(), json);
+
+            workspace_capabilities.set("diagnostics".into(), diagnostics.into(), json);
+        }
+
         capabilities.set("workspace".into(), workspace_capabilities.into(), json);
     }
 
@@ -120,7 +160,7 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
 
             {
                 let mut completion_item = JsonObject::default();
-                completion_item.set("snippetSupport".into(), false.into(), json);
+                completion_item.set("snippetSupport".into(), true.into(), json);
                 completion_item.set("commitCharactersSupport".into(), false.into(), json);
 
                 let mut documentation_formats = JsonArray::default();
@@ -218,6 +258,14 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
         }
 
         text_document_capabilities.set("references".into(), JsonObject::default().into(), json);
+        text_document_capabilities.set(
+            "documentHighlight".into(),
+            JsonObject::default().into(),
+            json,
+        );
+
+        text_document_capabilities.set("callHierarchy".into(), JsonObject::default().into(), json);
+        text_document_capabilities.set("typeHierarchy".into(), JsonObject::default().into(), json);
 
         {
             let mut document_symbol = JsonObject::default();
@@ -266,6 +314,11 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
             JsonObject::default().into(),
             json,
         );
+        text_document_capabilities.set(
+            "onTypeFormatting".into(),
+            JsonObject::default().into(),
+            json,
+        );
 
         {
             let mut rename = JsonObject::default();
@@ -289,8 +342,57 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
             );
         }
 
+        {
+            let mut diagnostic = JsonObject::default();
+            diagnostic.set("dynamicRegistration".into(), false.into(), json);
+            diagnostic.set("relatedDocumentSupport".into(), false.into(), json);
+
+            text_document_capabilities.set("diagnostic".into(), diagnostic.into(), json);
+        }
+
         text_document_capabilities.set("selectionRange".into(), JsonObject::default().into(), json);
 
+        text_document_capabilities.set("inlayHint".into(), JsonObject::default().into(), json);
+
+        {
+            let mut folding_range = JsonObject::default();
+            folding_range.set("lineFoldingOnly".into(), true.into(), json);
+
+            text_document_capabilities.set("foldingRange".into(), folding_range.into(), json);
+        }
+
+        {
+            // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_semanticTokens
+            let mut semantic_tokens = JsonObject::default();
+
+            {
+                let mut requests = JsonObject::default();
+                requests.set("range".into(), false.into(), json);
+
+                let mut full = JsonObject::default();
+                full.set("delta".into(), true.into(), json);
+                requests.set("full".into(), full.into(), json);
+
+                semantic_tokens.set("requests".into(), requests.into(), json);
+            }
+
+            let mut token_types = JsonArray::default();
+            for &token_type in SEMANTIC_TOKEN_TYPES {
+                token_types.push(token_type.into(), json);
+            }
+            semantic_tokens.set("tokenTypes".into(), token_types.into(), json);
+            semantic_tokens.set("tokenModifiers".into(), JsonArray::default().into(), json);
+
+            let mut formats = JsonArray::default();
+            formats.push("relative".into(), json);
+            semantic_tokens.set("formats".into(), formats.into(), json);
+
+            semantic_tokens.set("overlappingTokenSupport".into(), false.into(), json);
+            semantic_tokens.set("multilineTokenSupport".into(), false.into(), json);
+
+            text_document_capabilities.set("semanticTokens".into(), semantic_tokens.into(), json);
+        }
+
         capabilities.set(
             "textDocument".into(),
             text_document_capabilities.into(),
@@ -301,7 +403,7 @@ pub fn client_capabilities(json: &mut Json) -> JsonValue {
     {
         let mut window_capabilities = JsonObject::default();
 
-        window_capabilities.set("workDoneProgress".into(), false.into(), json);
+        window_capabilities.set("workDoneProgress".into(), true.into(), json);
         window_capabilities.set("showMessage".into(), JsonObject::default().into(), json);
 
         {
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt, io,
     ops::Range,
     path::{Path, PathBuf},
@@ -10,6 +11,7 @@ use pepper::{
     buffer_position::{BufferPosition, BufferRange},
     buffer_view::BufferViewHandle,
     client,
+    command::CommandArgs,
     cursor::Cursor,
     editor::{Editor, EditorContext},
     editor_utils::{LogKind, Logger, REGISTER_READLINE_INPUT},
@@ -17,15 +19,17 @@ use pepper::{
     navigation_history::NavigationHistory,
     platform::Platform,
     plugin::PluginHandle,
+    syntax::{SyntaxCollection, TokenKind},
 };
 
 use crate::{
     capabilities,
-    json::{FromJson, Json, JsonArray, JsonConvertError, JsonObject, JsonValue},
+    json::{FromJson, Json, JsonArray, JsonConvertError, JsonObject, JsonString, JsonValue},
     mode::readline,
     protocol::{
-        self, DocumentCodeAction, DocumentDiagnostic, DocumentPosition, DocumentRange,
-        DocumentSymbolInformation, PendingRequestColection, Protocol, ResponseError, Uri,
+        self, DiagnosticSeverity, DocumentCodeAction, DocumentDiagnostic, DocumentHierarchyItem,
+        DocumentPosition, DocumentRange, DocumentSymbolInformation, PendingRequestColection,
+        Protocol, RequestId, ResponseError, Uri,
     },
 };
 
@@ -72,6 +76,56 @@ impl<'json> FromJson<'json> for TriggerCharactersCapability {
     }
 }
 
+#[derive(Default)]
+struct OnTypeFormattingCapability {
+    pub on: bool,
+    pub trigger_characters: String,
+}
+impl<'json> FromJson<'json> for OnTypeFormattingCapability {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        match value {
+            JsonValue::Null => Ok(Self::default()),
+            JsonValue::Object(options) => {
+                let mut trigger_characters = String::new();
+                if let JsonValue::String(c) = options.clone().get("firstTriggerCharacter", json) {
+                    trigger_characters.push_str(c.as_str(json));
+                }
+                for c in options.get("moreTriggerCharacter", json).elements(json) {
+                    if let JsonValue::String(c) = c {
+                        trigger_characters.push_str(c.as_str(json));
+                    }
+                }
+                Ok(Self {
+                    on: true,
+                    trigger_characters,
+                })
+            }
+            _ => Err(JsonConvertError),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DiagnosticCapability {
+    pub on: bool,
+    pub workspace_diagnostics: bool,
+}
+impl<'json> FromJson<'json> for DiagnosticCapability {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        match value {
+            JsonValue::Null => Ok(Self::default()),
+            JsonValue::Object(options) => Ok(Self {
+                on: true,
+                workspace_diagnostics: matches!(
+                    options.get("workspaceDiagnostics", json),
+                    JsonValue::Boolean(true)
+                ),
+            }),
+            _ => Err(JsonConvertError),
+        }
+    }
+}
+
 #[derive(Default)]
 struct RenameCapability {
     pub on: bool,
@@ -100,6 +154,44 @@ impl<'json> FromJson<'json> for RenameCapability {
     }
 }
 
+#[derive(Default)]
+struct SemanticTokensCapability {
+    pub full: bool,
+    pub delta: bool,
+    pub token_types: Vec<String>,
+}
+impl<'json> FromJson<'json> for SemanticTokensCapability {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let options = match value {
+            JsonValue::Null => return Ok(Self::default()),
+            JsonValue::Object(options) => options,
+            _ => return Err(JsonConvertError),
+        };
+
+        let mut this = Self::default();
+        for (key, value) in options.members(json) {
+            match key {
+                "legend" => {
+                    for token_type in value.get("tokenTypes", json).elements(json) {
+                        let token_type: &str = FromJson::from_json(token_type, json)?;
+                        this.token_types.push(token_type.into());
+                    }
+                }
+                "full" => match value {
+                    JsonValue::Boolean(b) => this.full = b,
+                    JsonValue::Object(full) => {
+                        this.full = true;
+                        this.delta = matches!(full.get("delta", json), JsonValue::Boolean(true));
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
 enum TextDocumentSyncKind {
     None,
     Full,
@@ -194,11 +286,21 @@ pub(crate) struct ServerCapabilities {
     definition_provider: GenericCapability,
     implementation_provider: GenericCapability,
     references_provider: GenericCapability,
+    document_highlight_provider: GenericCapability,
     document_symbol_provider: GenericCapability,
     code_action_provider: GenericCapability,
     document_formatting_provider: GenericCapability,
+    document_range_formatting_provider: GenericCapability,
+    document_on_type_formatting_provider: OnTypeFormattingCapability,
     rename_provider: RenameCapability,
     workspace_symbol_provider: GenericCapability,
+    execute_command_provider: GenericCapability,
+    call_hierarchy_provider: GenericCapability,
+    type_hierarchy_provider: GenericCapability,
+    semantic_tokens_provider: SemanticTokensCapability,
+    inlay_hint_provider: GenericCapability,
+    folding_range_provider: GenericCapability,
+    diagnostic_provider: DiagnosticCapability,
 }
 impl<'json> FromJson<'json> for ServerCapabilities {
     fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
@@ -225,6 +327,9 @@ impl<'json> FromJson<'json> for ServerCapabilities {
                 "referencesProvider" => {
                     this.references_provider = FromJson::from_json(value, json)?
                 }
+                "documentHighlightProvider" => {
+                    this.document_highlight_provider = FromJson::from_json(value, json)?
+                }
                 "documentSymbolProvider" => {
                     this.document_symbol_provider = FromJson::from_json(value, json)?
                 }
@@ -234,10 +339,35 @@ impl<'json> FromJson<'json> for ServerCapabilities {
                 "documentFormattingProvider" => {
                     this.document_formatting_provider = FromJson::from_json(value, json)?
                 }
+                "documentRangeFormattingProvider" => {
+                    this.document_range_formatting_provider = FromJson::from_json(value, json)?
+                }
+                "documentOnTypeFormattingProvider" => {
+                    this.document_on_type_formatting_provider = FromJson::from_json(value, json)?
+                }
                 "renameProvider" => this.rename_provider = FromJson::from_json(value, json)?,
                 "workspaceSymbolProvider" => {
                     this.workspace_symbol_provider = FromJson::from_json(value, json)?
                 }
+                "callHierarchyProvider" => {
+                    this.call_hierarchy_provider = FromJson::from_json(value, json)?
+                }
+                "typeHierarchyProvider" => {
+                    this.type_hierarchy_provider = FromJson::from_json(value, json)?
+                }
+                "executeCommandProvider" => {
+                    this.execute_command_provider = FromJson::from_json(value, json)?
+                }
+                "semanticTokensProvider" => {
+                    this.semantic_tokens_provider = FromJson::from_json(value, json)?
+                }
+                "inlayHintProvider" => this.inlay_hint_provider = FromJson::from_json(value, json)?,
+                "foldingRangeProvider" => {
+                    this.folding_range_provider = FromJson::from_json(value, json)?
+                }
+                "diagnosticProvider" => {
+                    this.diagnostic_provider = FromJson::from_json(value, json)?
+                }
                 _ => (),
             }
         }
@@ -319,6 +449,9 @@ struct BufferDiagnosticDataRange {
 pub(crate) struct BufferDiagnosticDataCollection {
     data: Vec<u8>,
     ranges: Vec<BufferDiagnosticDataRange>,
+    // pull diagnostics state
+    pub result_id: String,
+    pub dirty: bool,
 }
 impl BufferDiagnosticDataCollection {
     pub fn clear(&mut self) {
@@ -348,9 +481,56 @@ impl BufferDiagnosticDataCollection {
     }
 }
 
+pub(crate) struct FileDiagnostic {
+    pub position: BufferPosition,
+    pub severity: DiagnosticSeverity,
+    message: Range<u32>,
+}
+
+// the diagnostics of a file that may not be open in the editor
+#[derive(Default)]
+pub(crate) struct FileDiagnostics {
+    pub uri: String,
+    pub result_id: String,
+    messages: String,
+    diagnostics: Vec<FileDiagnostic>,
+}
+impl FileDiagnostics {
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.diagnostics.clear();
+    }
+
+    pub fn add(&mut self, position: BufferPosition, severity: DiagnosticSeverity, message: &str) {
+        let start = self.messages.len() as _;
+        self.messages.push_str(message);
+        let end = self.messages.len() as _;
+
+        self.diagnostics.push(FileDiagnostic {
+            position,
+            severity,
+            message: start..end,
+        });
+    }
+
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_unstable_by_key(|d| d.position);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FileDiagnostic, &str)> {
+        self.diagnostics.iter().map(move |d| {
+            let message = &self.messages[d.message.start as usize..d.message.end as usize];
+            (d, message)
+        })
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct DiagnosticCollection {
     buffer_data_diagnostics: Vec<BufferDiagnosticDataCollection>,
+    files: Vec<FileDiagnostics>,
+    file_indices: HashMap<String, usize>,
+    pending_requests: Vec<(RequestId, BufferHandle)>,
 }
 impl DiagnosticCollection {
     pub fn get_buffer_diagnostics(
@@ -365,8 +545,179 @@ impl DiagnosticCollection {
         &mut self.buffer_data_diagnostics[index]
     }
 
+    pub fn get_file_diagnostics(&mut self, uri: &str) -> &mut FileDiagnostics {
+        let index = match self.file_indices.get(uri) {
+            Some(&index) => index,
+            None => {
+                let index = self.files.len();
+                self.files.push(FileDiagnostics {
+                    uri: uri.into(),
+                    ..Default::default()
+                });
+                self.file_indices.insert(uri.into(), index);
+                index
+            }
+        };
+        &mut self.files[index]
+    }
+
+    pub fn files(&self) -> &[FileDiagnostics] {
+        &self.files
+    }
+
+    pub fn take_pending_request(&mut self, request_id: RequestId) -> Option<BufferHandle> {
+        let index = self
+            .pending_requests
+            .iter()
+            .position(|&(id, _)| id == request_id)?;
+        Some(self.pending_requests.swap_remove(index).1)
+    }
+
+    pub fn on_buffer_edit(&mut self, buffer_handle: BufferHandle) {
+        self.get_buffer_diagnostics(buffer_handle).dirty = true;
+    }
+
     pub(crate) fn on_close_buffer(&mut self, buffer_handle: BufferHandle) {
-        self.get_buffer_diagnostics(buffer_handle).clear();
+        let diagnostics = self.get_buffer_diagnostics(buffer_handle);
+        diagnostics.clear();
+        diagnostics.result_id.clear();
+        diagnostics.dirty = false;
+        self.pending_requests.retain(|&(_, h)| h != buffer_handle);
+    }
+
+    fn is_pending(&self, buffer_handle: BufferHandle) -> bool {
+        self.pending_requests
+            .iter()
+            .any(|&(_, h)| h == buffer_handle)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct BufferSemanticTokens {
+    pub result_id: String,
+    pub data: Vec<u32>,
+    pub dirty: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct SemanticTokensCollection {
+    buffer_semantic_tokens: Vec<BufferSemanticTokens>,
+    pending_requests: Vec<(RequestId, BufferHandle)>,
+}
+impl SemanticTokensCollection {
+    pub fn get_buffer_semantic_tokens(
+        &mut self,
+        buffer_handle: BufferHandle,
+    ) -> &mut BufferSemanticTokens {
+        let index = buffer_handle.0 as usize;
+        if index >= self.buffer_semantic_tokens.len() {
+            self.buffer_semantic_tokens
+                .resize_with(index + 1, BufferSemanticTokens::default);
+        }
+        &mut self.buffer_semantic_tokens[index]
+    }
+
+    pub fn take_pending_request(&mut self, request_id: RequestId) -> Option<BufferHandle> {
+        let index = self
+            .pending_requests
+            .iter()
+            .position(|&(id, _)| id == request_id)?;
+        Some(self.pending_requests.swap_remove(index).1)
+    }
+
+    pub fn on_buffer_edit(&mut self, buffer_handle: BufferHandle) {
+        self.get_buffer_semantic_tokens(buffer_handle).dirty = true;
+    }
+
+    pub fn on_close_buffer(&mut self, buffer_handle: BufferHandle) {
+        let semantic_tokens = self.get_buffer_semantic_tokens(buffer_handle);
+        semantic_tokens.result_id.clear();
+        semantic_tokens.data.clear();
+        semantic_tokens.dirty = false;
+        self.pending_requests.retain(|&(_, h)| h != buffer_handle);
+    }
+
+    fn is_pending(&self, buffer_handle: BufferHandle) -> bool {
+        self.pending_requests
+            .iter()
+            .any(|&(_, h)| h == buffer_handle)
+    }
+}
+
+// buffers whose whole document request (like inlay hints) is sent again once the editor is idle
+#[derive(Default)]
+pub(crate) struct BufferRequestCollection {
+    dirty_buffers: Vec<bool>,
+    pending_requests: Vec<(RequestId, BufferHandle)>,
+}
+impl BufferRequestCollection {
+    pub fn take_pending_request(&mut self, request_id: RequestId) -> Option<BufferHandle> {
+        let index = self
+            .pending_requests
+            .iter()
+            .position(|&(id, _)| id == request_id)?;
+        Some(self.pending_requests.swap_remove(index).1)
+    }
+
+    pub fn is_dirty(&self, buffer_handle: BufferHandle) -> bool {
+        self.dirty_buffers
+            .get(buffer_handle.0 as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn set_dirty(&mut self, buffer_handle: BufferHandle, dirty: bool) {
+        let index = buffer_handle.0 as usize;
+        if index >= self.dirty_buffers.len() {
+            self.dirty_buffers.resize(index + 1, false);
+        }
+        self.dirty_buffers[index] = dirty;
+    }
+
+    pub fn on_buffer_edit(&mut self, buffer_handle: BufferHandle) {
+        self.set_dirty(buffer_handle, true);
+    }
+
+    pub fn on_close_buffer(&mut self, buffer_handle: BufferHandle) {
+        self.set_dirty(buffer_handle, false);
+        self.pending_requests.retain(|&(_, h)| h != buffer_handle);
+    }
+
+    fn is_pending(&self, buffer_handle: BufferHandle) -> bool {
+        self.pending_requests
+            .iter()
+            .any(|&(_, h)| h == buffer_handle)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct DocumentHighlightState {
+    pending_request: Option<(RequestId, BufferHandle)>,
+    // so that the symbol under a cursor that did not move is not requested again
+    last_position: Option<(BufferHandle, BufferPosition)>,
+}
+impl DocumentHighlightState {
+    pub fn take_pending_request(&mut self, request_id: RequestId) -> Option<BufferHandle> {
+        match self.pending_request {
+            Some((id, buffer_handle)) if id == request_id => {
+                self.pending_request = None;
+                Some(buffer_handle)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn on_buffer_edit(&mut self, buffer_handle: BufferHandle) {
+        if matches!(self.last_position, Some((h, _)) if h == buffer_handle) {
+            self.last_position = None;
+        }
+    }
+
+    pub fn on_close_buffer(&mut self, buffer_handle: BufferHandle) {
+        self.on_buffer_edit(buffer_handle);
+        if matches!(self.pending_request, Some((_, h)) if h == buffer_handle) {
+            self.pending_request = None;
+        }
     }
 }
 
@@ -403,13 +754,33 @@ pub(crate) enum RequestState {
     },
     WorkspaceSymbols,
     FinishWorkspaceSymbols,
+    PrepareHierarchy {
+        kind: HierarchyKind,
+    },
+    Hierarchy {
+        kind: HierarchyKind,
+    },
+    FinishHierarchy {
+        kind: HierarchyKind,
+    },
     Formatting {
         buffer_handle: BufferHandle,
+        save: bool,
+    },
+    RangeFormatting {
+        buffer_handle: BufferHandle,
+    },
+    OnTypeFormatting {
+        buffer_handle: BufferHandle,
     },
     Completion {
         client_handle: client::ClientHandle,
         buffer_handle: BufferHandle,
     },
+    WorkspaceDiagnostics {
+        client_handle: client::ClientHandle,
+        min_severity: DiagnosticSeverity,
+    },
 }
 impl RequestState {
     pub fn is_idle(&self) -> bool {
@@ -417,6 +788,80 @@ impl RequestState {
     }
 }
 
+#[derive(Clone, Copy)]
+pub(crate) enum ClientCapability {
+    Completion,
+    Hover,
+    Declaration,
+    Definition,
+    Implementation,
+    References,
+    DocumentHighlight,
+    DocumentSymbols,
+    CodeAction,
+    Formatting,
+    RangeFormatting,
+    OnTypeFormatting,
+    Rename,
+    WorkspaceSymbols,
+    ExecuteCommand,
+    CallHierarchy,
+    TypeHierarchy,
+    SemanticTokens,
+    InlayHints,
+    FoldingRanges,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum HierarchyKind {
+    IncomingCalls,
+    OutgoingCalls,
+    Supertypes,
+    Subtypes,
+}
+impl HierarchyKind {
+    pub fn capability(self) -> ClientCapability {
+        match self {
+            Self::IncomingCalls | Self::OutgoingCalls => ClientCapability::CallHierarchy,
+            Self::Supertypes | Self::Subtypes => ClientCapability::TypeHierarchy,
+        }
+    }
+
+    pub fn prepare_method(self) -> &'static str {
+        match self {
+            Self::IncomingCalls | Self::OutgoingCalls => "textDocument/prepareCallHierarchy",
+            Self::Supertypes | Self::Subtypes => "textDocument/prepareTypeHierarchy",
+        }
+    }
+
+    pub fn method(self) -> &'static str {
+        match self {
+            Self::IncomingCalls => "callHierarchy/incomingCalls",
+            Self::OutgoingCalls => "callHierarchy/outgoingCalls",
+            Self::Supertypes => "typeHierarchy/supertypes",
+            Self::Subtypes => "typeHierarchy/subtypes",
+        }
+    }
+
+    // calls wrap the item of the caller (or callee) together with the ranges of the calls
+    pub fn item(self, result: JsonValue, json: &Json) -> JsonValue {
+        match self {
+            Self::IncomingCalls => result.get("from", json),
+            Self::OutgoingCalls => result.get("to", json),
+            Self::Supertypes | Self::Subtypes => result,
+        }
+    }
+
+    pub fn prompt(self) -> &'static str {
+        match self {
+            Self::IncomingCalls => "incoming calls:",
+            Self::OutgoingCalls => "outgoing calls:",
+            Self::Supertypes => "supertypes:",
+            Self::Subtypes => "subtypes:",
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct ClientHandle(pub(crate) u8);
 impl fmt::Display for ClientHandle {
@@ -442,20 +887,32 @@ pub struct Client {
     pub(crate) pending_requests: PendingRequestColection,
 
     pub(crate) initialized: bool,
+    pub(crate) server_name: String,
     pub(crate) server_capabilities: ServerCapabilities,
 
+    pub(crate) glob: Option<Glob>,
     pub(crate) document_selectors: Vec<Glob>,
     pub(crate) versioned_buffers: VersionedBufferCollection,
     pub(crate) diagnostics: DiagnosticCollection,
+    pub(crate) semantic_tokens: SemanticTokensCollection,
+    pub(crate) semantic_token_kinds: Vec<Option<TokenKind>>,
+    pub(crate) inlay_hints: BufferRequestCollection,
+    pub(crate) folding_ranges: BufferRequestCollection,
+    pub(crate) document_highlights: DocumentHighlightState,
 
     pub(crate) temp_edits: Vec<(BufferRange, BufferRange)>,
+    pub(crate) format_ranges: Vec<BufferRange>,
+    pub(crate) format_ranges_buffer_handle: Option<BufferHandle>,
+    pub(crate) format_on_save_buffer_handles: Vec<BufferHandle>,
+    pub(crate) on_type_format_positions: Vec<(BufferPosition, char)>,
+    pub(crate) on_type_format_buffer_handle: Option<BufferHandle>,
 
     pub(crate) request_state: RequestState,
     pub(crate) request_raw_json: Vec<u8>,
 }
 
 impl Client {
-    pub(crate) fn new(handle: ClientHandle, root: PathBuf) -> Self {
+    pub(crate) fn new(handle: ClientHandle, root: PathBuf, glob: Option<Glob>) -> Self {
         Self {
             handle,
             protocol: Protocol::new(),
@@ -464,15 +921,27 @@ impl Client {
             pending_requests: PendingRequestColection::default(),
 
             initialized: false,
+            server_name: String::new(),
             server_capabilities: ServerCapabilities::default(),
 
+            glob,
             document_selectors: Vec::new(),
             versioned_buffers: VersionedBufferCollection::default(),
             diagnostics: DiagnosticCollection::default(),
+            semantic_tokens: SemanticTokensCollection::default(),
+            semantic_token_kinds: Vec::new(),
+            inlay_hints: BufferRequestCollection::default(),
+            folding_ranges: BufferRequestCollection::default(),
+            document_highlights: DocumentHighlightState::default(),
 
             request_state: RequestState::Idle,
             request_raw_json: Vec::new(),
             temp_edits: Vec::new(),
+            format_ranges: Vec::new(),
+            format_ranges_buffer_handle: None,
+            format_on_save_buffer_handles: Vec::new(),
+            on_type_format_positions: Vec::new(),
+            on_type_format_buffer_handle: None,
         }
     }
 
@@ -481,6 +950,12 @@ impl Client {
     }
 
     pub fn handles_path(&self, path: &str) -> bool {
+        if let Some(glob) = &self.glob {
+            if !glob.matches(path) {
+                return false;
+            }
+        }
+
         if self.document_selectors.is_empty() {
             true
         } else {
@@ -488,6 +963,34 @@ impl Client {
         }
     }
 
+    pub(crate) fn has_capability(&self, capability: ClientCapability) -> bool {
+        let capabilities = &self.server_capabilities;
+        match capability {
+            ClientCapability::Completion => capabilities.completion_provider.on,
+            ClientCapability::Hover => capabilities.hover_provider.0,
+            ClientCapability::Declaration => capabilities.declaration_provider.0,
+            ClientCapability::Definition => capabilities.definition_provider.0,
+            ClientCapability::Implementation => capabilities.implementation_provider.0,
+            ClientCapability::References => capabilities.references_provider.0,
+            ClientCapability::DocumentHighlight => capabilities.document_highlight_provider.0,
+            ClientCapability::DocumentSymbols => capabilities.document_symbol_provider.0,
+            ClientCapability::CodeAction => capabilities.code_action_provider.0,
+            ClientCapability::Formatting => capabilities.document_formatting_provider.0,
+            ClientCapability::RangeFormatting => capabilities.document_range_formatting_provider.0,
+            ClientCapability::OnTypeFormatting => {
+                capabilities.document_on_type_formatting_provider.on
+            }
+            ClientCapability::Rename => capabilities.rename_provider.on,
+            ClientCapability::WorkspaceSymbols => capabilities.workspace_symbol_provider.0,
+            ClientCapability::ExecuteCommand => capabilities.execute_command_provider.0,
+            ClientCapability::CallHierarchy => capabilities.call_hierarchy_provider.0,
+            ClientCapability::TypeHierarchy => capabilities.type_hierarchy_provider.0,
+            ClientCapability::SemanticTokens => capabilities.semantic_tokens_provider.full,
+            ClientCapability::InlayHints => capabilities.inlay_hint_provider.0,
+            ClientCapability::FoldingRanges => capabilities.folding_range_provider.0,
+        }
+    }
+
     pub fn signature_help_triggers(&self) -> &str {
         &self
             .server_capabilities
@@ -782,7 +1285,7 @@ impl Client {
             .lints
             .all()
             .iter()
-            .filter(|l| l.plugin_handle == plugin_handle)
+            .filter(|l| l.plugin_handle == plugin_handle && l.source == self.handle.0 as u32)
             .enumerate()
         {
             if lint.range.from <= range.from && range.from < lint.range.to
@@ -822,7 +1325,12 @@ impl Client {
         );
     }
 
-    pub(crate) fn finish_code_action(&mut self, editor: &mut Editor, index: usize) {
+    pub(crate) fn finish_code_action(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        index: usize,
+    ) {
         match self.request_state {
             RequestState::FinishCodeAction => (),
             _ => return,
@@ -837,15 +1345,50 @@ impl Client {
             Ok(actions) => actions,
             Err(_) => return,
         };
-        if let Some(edit) = code_actions
+        if let Some(code_action) = code_actions
             .elements(&self.json)
             .filter_map(|a| DocumentCodeAction::from_json(a, &self.json).ok())
             .filter(|a| !a.disabled)
-            .map(|a| a.edit)
             .nth(index)
         {
-            edit.apply(editor, &mut self.temp_edits, &self.root, &self.json);
+            if let Err(error) =
+                code_action
+                    .edit
+                    .apply(editor, &mut self.temp_edits, &self.root, &self.json)
+            {
+                editor.logger.write(LogKind::Error).str(&error);
+                return;
+            }
+
+            if let Some(command) = code_action.command {
+                self.execute_command(editor, platform, command.command, command.arguments);
+            }
+        }
+    }
+
+    pub fn execute_command(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        command: JsonString,
+        arguments: JsonArray,
+    ) {
+        if !self.server_capabilities.execute_command_provider.0 {
+            return;
         }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let mut params = JsonObject::default();
+        params.set("command".into(), command.into(), &mut self.json);
+        params.set("arguments".into(), arguments.into(), &mut self.json);
+
+        self.request(
+            platform,
+            "workspace/executeCommand",
+            params,
+            &mut editor.logger,
+        );
     }
 
     pub fn document_symbols(
@@ -1005,52 +1548,304 @@ impl Client {
         }
     }
 
-    pub fn formatting(
+    pub fn hierarchy(
         &mut self,
         editor: &mut Editor,
         platform: &mut Platform,
         buffer_handle: BufferHandle,
+        buffer_position: BufferPosition,
+        kind: HierarchyKind,
     ) {
-        if !self.server_capabilities.document_formatting_provider.0 || !self.request_state.is_idle()
-        {
+        if !self.has_capability(kind.capability()) || !self.request_state.is_idle() {
             return;
         }
 
         util::send_pending_did_change(self, editor, platform);
 
-        let buffer_path = &editor.buffers.get(buffer_handle).path;
-        let text_document = util::text_document_with_id(&self.root, buffer_path, &mut self.json);
-        let mut options = JsonObject::default();
-        options.set(
-            "tabSize".into(),
-            JsonValue::Integer(editor.config.tab_size as _),
-            &mut self.json,
-        );
-        options.set(
-            "insertSpaces".into(),
-            (!editor.config.indent_with_tabs).into(),
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let position = DocumentPosition::from_buffer_position(buffer_position);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set(
+            "position".into(),
+            position.to_json_value(&mut self.json),
             &mut self.json,
         );
-        options.set("trimTrailingWhitespace".into(), true.into(), &mut self.json);
-        options.set("trimFinalNewlines".into(), true.into(), &mut self.json);
 
+        self.request_state = RequestState::PrepareHierarchy { kind };
+        self.request(platform, kind.prepare_method(), params, &mut editor.logger);
+    }
+
+    pub(crate) fn hierarchy_from_item(
+        &mut self,
+        platform: &mut Platform,
+        logger: &mut Logger,
+        item: JsonValue,
+        kind: HierarchyKind,
+    ) {
         let mut params = JsonObject::default();
-        params.set("textDocument".into(), text_document.into(), &mut self.json);
-        params.set("options".into(), options.into(), &mut self.json);
+        params.set("item".into(), item, &mut self.json);
 
-        self.request_state = RequestState::Formatting { buffer_handle };
-        self.request(
-            platform,
-            "textDocument/formatting",
-            params,
-            &mut editor.logger,
-        );
+        self.request_state = RequestState::Hierarchy { kind };
+        self.request(platform, kind.method(), params, logger);
     }
 
-    pub fn completion(
+    pub(crate) fn finish_hierarchy(
         &mut self,
         editor: &mut Editor,
-        platform: &mut Platform,
+        clients: &mut client::ClientManager,
+        client_handle: client::ClientHandle,
+        index: usize,
+    ) {
+        let kind = match self.request_state {
+            RequestState::FinishHierarchy { kind } => kind,
+            _ => return,
+        };
+        self.request_state = RequestState::Idle;
+
+        let mut reader = io::Cursor::new(&self.request_raw_json);
+        let results = match self.json.read(&mut reader) {
+            Ok(results) => results,
+            Err(_) => return,
+        };
+        if let Some(item) = results
+            .elements(&self.json)
+            .map(|r| kind.item(r, &self.json))
+            .filter_map(|i| DocumentHierarchyItem::from_json(i, &self.json).ok())
+            .nth(index)
+        {
+            let path = match Uri::parse(&self.root, item.uri.as_str(&self.json)) {
+                Ok(Uri::Path(path)) => path,
+                Err(_) => return,
+            };
+
+            NavigationHistory::save_snapshot(clients.get_mut(client_handle), &editor.buffer_views);
+            match editor.buffer_view_handle_from_path(
+                client_handle,
+                path,
+                BufferProperties::text(),
+                false,
+            ) {
+                Ok(buffer_view_handle) => {
+                    let client = clients.get_mut(client_handle);
+                    client.set_buffer_view_handle(Some(buffer_view_handle), &editor.buffer_views);
+
+                    let buffer_view = editor.buffer_views.get_mut(buffer_view_handle);
+                    let position = item.selection_range.start.into_buffer_position();
+                    let mut cursors = buffer_view.cursors.mut_guard();
+                    cursors.clear();
+                    cursors.add(Cursor {
+                        anchor: position,
+                        position,
+                    });
+                }
+                Err(error) => editor
+                    .logger
+                    .write(LogKind::Error)
+                    .fmt(format_args!("{}", error)),
+            }
+        }
+    }
+
+    fn formatting_options(&mut self, editor: &Editor) -> JsonObject {
+        let mut options = JsonObject::default();
+        options.set(
+            "tabSize".into(),
+            JsonValue::Integer(editor.config.tab_size as _),
+            &mut self.json,
+        );
+        options.set(
+            "insertSpaces".into(),
+            (!editor.config.indent_with_tabs).into(),
+            &mut self.json,
+        );
+        options.set("trimTrailingWhitespace".into(), true.into(), &mut self.json);
+        options.set("trimFinalNewlines".into(), true.into(), &mut self.json);
+        options
+    }
+
+    // when `save` is true, the buffer is written once the formatting edits are applied
+    pub fn formatting(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+        save: bool,
+    ) {
+        if !self.server_capabilities.document_formatting_provider.0 || !self.request_state.is_idle()
+        {
+            return;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer_path = &editor.buffers.get(buffer_handle).path;
+        let text_document = util::text_document_with_id(&self.root, buffer_path, &mut self.json);
+        let options = self.formatting_options(editor);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set("options".into(), options.into(), &mut self.json);
+
+        self.request_state = RequestState::Formatting {
+            buffer_handle,
+            save,
+        };
+        self.request(
+            platform,
+            "textDocument/formatting",
+            params,
+            &mut editor.logger,
+        );
+    }
+
+    // formats the buffers in `format_on_save_buffer_handles` one at a time
+    // each one is only written once its formatting edits are applied
+    pub fn format_on_save(&mut self, editor: &mut Editor, platform: &mut Platform) {
+        if !self.request_state.is_idle() || self.format_on_save_buffer_handles.is_empty() {
+            return;
+        }
+
+        let buffer_handle = self.format_on_save_buffer_handles.remove(0);
+        if self.initialized && self.server_capabilities.document_formatting_provider.0 {
+            self.formatting(editor, platform, buffer_handle, true);
+        } else {
+            util::save_buffer(editor, buffer_handle);
+        }
+    }
+
+    // writes the buffers still waiting to be formatted as they are
+    pub fn save_without_formatting(&mut self, editor: &mut Editor) {
+        if let RequestState::Formatting {
+            buffer_handle,
+            save: true,
+        } = self.request_state
+        {
+            self.request_state = RequestState::Idle;
+            util::save_buffer(editor, buffer_handle);
+        }
+        for buffer_handle in self.format_on_save_buffer_handles.drain(..) {
+            util::save_buffer(editor, buffer_handle);
+        }
+    }
+
+    // formats each range in `format_ranges` one at a time starting from the last one
+    // so that the edits of a range never move the ranges before it
+    pub fn range_formatting(&mut self, editor: &mut Editor, platform: &mut Platform) {
+        if !self
+            .server_capabilities
+            .document_range_formatting_provider
+            .0
+            || !self.request_state.is_idle()
+        {
+            return;
+        }
+
+        let buffer_handle = match self.format_ranges_buffer_handle {
+            Some(buffer_handle) => buffer_handle,
+            None => return,
+        };
+        let range = match self.format_ranges.pop() {
+            Some(range) => range,
+            None => {
+                self.format_ranges_buffer_handle = None;
+                return;
+            }
+        };
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer_path = &editor.buffers.get(buffer_handle).path;
+        let text_document = util::text_document_with_id(&self.root, buffer_path, &mut self.json);
+        let range = DocumentRange::from_buffer_range(range);
+        let options = self.formatting_options(editor);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set(
+            "range".into(),
+            range.to_json_value(&mut self.json),
+            &mut self.json,
+        );
+        params.set("options".into(), options.into(), &mut self.json);
+
+        self.request_state = RequestState::RangeFormatting { buffer_handle };
+        self.request(
+            platform,
+            "textDocument/rangeFormatting",
+            params,
+            &mut editor.logger,
+        );
+    }
+
+    pub fn is_on_type_formatting_trigger(&self, text: &str) -> bool {
+        let capability = &self
+            .server_capabilities
+            .document_on_type_formatting_provider;
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => capability.on && capability.trigger_characters.contains(c),
+            _ => false,
+        }
+    }
+
+    // formats at each position in `on_type_format_positions` one at a time starting from the last one
+    pub fn on_type_formatting(&mut self, editor: &mut Editor, platform: &mut Platform) {
+        if !self
+            .server_capabilities
+            .document_on_type_formatting_provider
+            .on
+            || !self.request_state.is_idle()
+        {
+            return;
+        }
+
+        let buffer_handle = match self.on_type_format_buffer_handle {
+            Some(buffer_handle) => buffer_handle,
+            None => return,
+        };
+        let (buffer_position, c) = match self.on_type_format_positions.pop() {
+            Some(position) => position,
+            None => {
+                self.on_type_format_buffer_handle = None;
+                return;
+            }
+        };
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer_path = &editor.buffers.get(buffer_handle).path;
+        let text_document = util::text_document_with_id(&self.root, buffer_path, &mut self.json);
+        let position = DocumentPosition::from_buffer_position(buffer_position);
+        let mut buf = [0; std::mem::size_of::<char>()];
+        let c = self.json.create_string(c.encode_utf8(&mut buf));
+        let options = self.formatting_options(editor);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set(
+            "position".into(),
+            position.to_json_value(&mut self.json),
+            &mut self.json,
+        );
+        params.set("ch".into(), c.into(), &mut self.json);
+        params.set("options".into(), options.into(), &mut self.json);
+
+        self.request_state = RequestState::OnTypeFormatting { buffer_handle };
+        self.request(
+            platform,
+            "textDocument/onTypeFormatting",
+            params,
+            &mut editor.logger,
+        );
+    }
+
+    pub fn completion(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
         client_handle: client::ClientHandle,
         buffer_handle: BufferHandle,
         buffer_position: BufferPosition,
@@ -1086,15 +1881,287 @@ impl Client {
         );
     }
 
+    pub(crate) fn update_semantic_token_kinds(&mut self, syntaxes: &mut SyntaxCollection) {
+        self.semantic_token_kinds.clear();
+        for token_type in &self
+            .server_capabilities
+            .semantic_tokens_provider
+            .token_types
+        {
+            let kind = util::semantic_token_kind(token_type, syntaxes);
+            self.semantic_token_kinds.push(kind);
+        }
+    }
+
+    pub fn semantic_tokens(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+    ) {
+        let semantic_tokens = self
+            .semantic_tokens
+            .get_buffer_semantic_tokens(buffer_handle);
+        semantic_tokens.dirty = false;
+        if !self.server_capabilities.semantic_tokens_provider.full
+            || self.semantic_tokens.is_pending(buffer_handle)
+        {
+            return;
+        }
+
+        let buffer = editor.buffers.get(buffer_handle);
+        if !buffer.properties.saving_enabled {
+            return;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+
+        let semantic_tokens = self
+            .semantic_tokens
+            .get_buffer_semantic_tokens(buffer_handle);
+        let method = if self.server_capabilities.semantic_tokens_provider.delta
+            && !semantic_tokens.result_id.is_empty()
+        {
+            let result_id = self.json.create_string(&semantic_tokens.result_id);
+            params.set("previousResultId".into(), result_id.into(), &mut self.json);
+            "textDocument/semanticTokens/full/delta"
+        } else {
+            "textDocument/semanticTokens/full"
+        };
+
+        if let Some(id) = self.request(platform, method, params, &mut editor.logger) {
+            self.semantic_tokens
+                .pending_requests
+                .push((id, buffer_handle));
+        }
+    }
+
+    pub fn document_highlight(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+        buffer_position: BufferPosition,
+    ) {
+        let state = &mut self.document_highlights;
+        if !self.server_capabilities.document_highlight_provider.0
+            || state.pending_request.is_some()
+            || state.last_position == Some((buffer_handle, buffer_position))
+        {
+            return;
+        }
+        state.last_position = Some((buffer_handle, buffer_position));
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let position = DocumentPosition::from_buffer_position(buffer_position);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set(
+            "position".into(),
+            position.to_json_value(&mut self.json),
+            &mut self.json,
+        );
+
+        if let Some(id) = self.request(
+            platform,
+            "textDocument/documentHighlight",
+            params,
+            &mut editor.logger,
+        ) {
+            self.document_highlights.pending_request = Some((id, buffer_handle));
+        }
+    }
+
+    pub fn pull_diagnostics(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+    ) {
+        self.diagnostics.get_buffer_diagnostics(buffer_handle).dirty = false;
+        if !self.server_capabilities.diagnostic_provider.on
+            || self.diagnostics.is_pending(buffer_handle)
+        {
+            return;
+        }
+
+        let buffer = editor.buffers.get(buffer_handle);
+        if !buffer.properties.saving_enabled {
+            return;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+
+        let diagnostics = self.diagnostics.get_buffer_diagnostics(buffer_handle);
+        if !diagnostics.result_id.is_empty() {
+            let result_id = self.json.create_string(&diagnostics.result_id);
+            params.set("previousResultId".into(), result_id.into(), &mut self.json);
+        }
+
+        if let Some(id) = self.request(
+            platform,
+            "textDocument/diagnostic",
+            params,
+            &mut editor.logger,
+        ) {
+            self.diagnostics.pending_requests.push((id, buffer_handle));
+        }
+    }
+
+    pub fn supports_workspace_diagnostics(&self) -> bool {
+        self.server_capabilities
+            .diagnostic_provider
+            .workspace_diagnostics
+    }
+
+    // returns true if the diagnostics will be listed once the server responds
+    pub fn workspace_diagnostics(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        client_handle: client::ClientHandle,
+        min_severity: DiagnosticSeverity,
+    ) -> bool {
+        if !self.supports_workspace_diagnostics() || !self.request_state.is_idle() {
+            return false;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let mut previous_result_ids = JsonArray::default();
+        for file in self.diagnostics.files() {
+            if file.result_id.is_empty() {
+                continue;
+            }
+            let uri = self.json.create_string(&file.uri);
+            let value = self.json.create_string(&file.result_id);
+            let mut previous_result_id = JsonObject::default();
+            previous_result_id.set("uri".into(), uri.into(), &mut self.json);
+            previous_result_id.set("value".into(), value.into(), &mut self.json);
+            previous_result_ids.push(previous_result_id.into(), &mut self.json);
+        }
+
+        let mut params = JsonObject::default();
+        params.set(
+            "previousResultIds".into(),
+            previous_result_ids.into(),
+            &mut self.json,
+        );
+
+        let request_id = self.request(platform, "workspace/diagnostic", params, &mut editor.logger);
+        if request_id.is_none() {
+            return false;
+        }
+        self.request_state = RequestState::WorkspaceDiagnostics {
+            client_handle,
+            min_severity,
+        };
+        true
+    }
+
+    pub fn inlay_hints(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+    ) {
+        self.inlay_hints.set_dirty(buffer_handle, false);
+        if !self.server_capabilities.inlay_hint_provider.0
+            || self.inlay_hints.is_pending(buffer_handle)
+        {
+            return;
+        }
+
+        let buffer = editor.buffers.get(buffer_handle);
+        if !buffer.properties.saving_enabled {
+            return;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let range = BufferRange::between(BufferPosition::zero(), buffer.content().end());
+        let range = DocumentRange::from_buffer_range(range);
+
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+        params.set(
+            "range".into(),
+            range.to_json_value(&mut self.json),
+            &mut self.json,
+        );
+
+        if let Some(id) = self.request(
+            platform,
+            "textDocument/inlayHint",
+            params,
+            &mut editor.logger,
+        ) {
+            self.inlay_hints.pending_requests.push((id, buffer_handle));
+        }
+    }
+
+    pub fn folding_ranges(
+        &mut self,
+        editor: &mut Editor,
+        platform: &mut Platform,
+        buffer_handle: BufferHandle,
+    ) {
+        self.folding_ranges.set_dirty(buffer_handle, false);
+        if !self.server_capabilities.folding_range_provider.0
+            || self.folding_ranges.is_pending(buffer_handle)
+        {
+            return;
+        }
+
+        let buffer = editor.buffers.get(buffer_handle);
+        if !buffer.properties.saving_enabled {
+            return;
+        }
+
+        util::send_pending_did_change(self, editor, platform);
+
+        let buffer = editor.buffers.get(buffer_handle);
+        let text_document = util::text_document_with_id(&self.root, &buffer.path, &mut self.json);
+        let mut params = JsonObject::default();
+        params.set("textDocument".into(), text_document.into(), &mut self.json);
+
+        if let Some(id) = self.request(
+            platform,
+            "textDocument/foldingRange",
+            params,
+            &mut editor.logger,
+        ) {
+            self.folding_ranges
+                .pending_requests
+                .push((id, buffer_handle));
+        }
+    }
+
     fn request(
         &mut self,
         platform: &mut Platform,
         method: &'static str,
         params: JsonObject,
         logger: &mut Logger,
-    ) {
+    ) -> Option<RequestId> {
         if !self.initialized {
-            return;
+            return None;
         }
 
         let params = params.into();
@@ -1114,6 +2181,7 @@ impl Client {
             .request(platform, &mut self.json, method, params);
 
         self.pending_requests.add(id, method);
+        Some(id)
     }
 
     pub(crate) fn respond(
@@ -1240,6 +2308,20 @@ pub(crate) mod util {
         id
     }
 
+    // each argument is parsed as json falling back to a plain string
+    pub fn parse_command_arguments(mut args: CommandArgs, json: &mut Json) -> JsonArray {
+        let mut arguments = JsonArray::default();
+        while let Some(arg) = args.try_next() {
+            let mut reader = io::Cursor::new(arg.as_bytes());
+            let argument = match json.read(&mut reader) {
+                Ok(argument) => argument,
+                Err(_) => json.create_string(arg).into(),
+            };
+            arguments.push(argument, json);
+        }
+        arguments
+    }
+
     pub fn extract_markup_content(content: JsonValue, json: &Json) -> &str {
         match content {
             JsonValue::String(s) => s.as_str(json),
@@ -1391,6 +2473,93 @@ pub(crate) mod util {
         std::mem::swap(&mut client.versioned_buffers, &mut versioned_buffers);
     }
 
+    pub fn send_pending_semantic_tokens(
+        client: &mut Client,
+        editor: &mut Editor,
+        platform: &mut Platform,
+    ) {
+        for i in 0..client.semantic_tokens.buffer_semantic_tokens.len() {
+            if client.semantic_tokens.buffer_semantic_tokens[i].dirty {
+                client.semantic_tokens(editor, platform, BufferHandle(i as _));
+            }
+        }
+    }
+
+    pub fn send_pending_pull_diagnostics(
+        client: &mut Client,
+        editor: &mut Editor,
+        platform: &mut Platform,
+    ) {
+        for i in 0..client.diagnostics.buffer_data_diagnostics.len() {
+            if client.diagnostics.buffer_data_diagnostics[i].dirty {
+                client.pull_diagnostics(editor, platform, BufferHandle(i as _));
+            }
+        }
+    }
+
+    pub fn send_pending_inlay_hints(
+        client: &mut Client,
+        editor: &mut Editor,
+        platform: &mut Platform,
+    ) {
+        for i in 0..client.inlay_hints.dirty_buffers.len() {
+            if client.inlay_hints.dirty_buffers[i] {
+                client.inlay_hints(editor, platform, BufferHandle(i as _));
+            }
+        }
+    }
+
+    pub fn send_pending_folding_ranges(
+        client: &mut Client,
+        editor: &mut Editor,
+        platform: &mut Platform,
+    ) {
+        for i in 0..client.folding_ranges.dirty_buffers.len() {
+            if client.folding_ranges.dirty_buffers[i] {
+                client.folding_ranges(editor, platform, BufferHandle(i as _));
+            }
+        }
+    }
+
+    // maps the server token types to the editor token kinds
+    // types that would just color as text are left to the syntax rules
+    pub fn semantic_token_kind(
+        token_type: &str,
+        syntaxes: &mut SyntaxCollection,
+    ) -> Option<TokenKind> {
+        match token_type {
+            "namespace" | "type" | "class" | "enum" | "interface" | "struct" | "typeParameter" => {
+                Some(TokenKind::Type)
+            }
+            "keyword" | "modifier" => Some(TokenKind::Keyword),
+            "comment" => Some(TokenKind::Comment),
+            "string" | "regexp" => Some(TokenKind::String),
+            "number" => Some(TokenKind::Literal),
+            "operator" => Some(TokenKind::Symbol),
+            "function" => syntaxes.custom_token_kind("function"),
+            "method" => syntaxes.custom_token_kind("function.method"),
+            "macro" => syntaxes.custom_token_kind("macro"),
+            "decorator" => syntaxes.custom_token_kind("attribute"),
+            "enumMember" => syntaxes.custom_token_kind("constant"),
+            _ => None,
+        }
+    }
+
+    // writes a buffer whose save was deferred by `on_buffer_save`
+    pub fn save_buffer(editor: &mut Editor, buffer_handle: BufferHandle) {
+        let buffer = editor.buffers.get_mut(buffer_handle);
+        match buffer.write_to_file(None, false, editor.events.writer()) {
+            Ok(()) => editor
+                .logger
+                .write(LogKind::Status)
+                .fmt(format_args!("buffer saved to {:?}", &buffer.path)),
+            Err(error) => editor
+                .logger
+                .write(LogKind::Error)
+                .fmt(format_args!("{}", error)),
+        }
+    }
+
     pub fn send_did_save(
         client: &mut Client,
         editor: &mut Editor,
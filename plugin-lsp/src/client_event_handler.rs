@@ -5,31 +5,36 @@ use pepper::{
     buffer_position::{BufferPosition, BufferRange},
     client,
     cursor::Cursor,
-    editor::EditorContext,
+    editor::{Editor, EditorContext},
     editor_utils::LogKind,
     glob::Glob,
     mode::ModeKind,
     picker::Picker,
     plugin::PluginHandle,
+    syntax::Token,
     word_database::WordKind,
 };
 
 use crate::{
-    client::{util, Client, RequestState, ServerCapabilities},
+    client::{util, Client, ClientCapability, RequestState, ServerCapabilities},
     json::{
         FromJson, Json, JsonArray, JsonConvertError, JsonInteger, JsonObject, JsonString, JsonValue,
     },
     mode::{picker, readline},
     protocol::{
-        DocumentCodeAction, DocumentCompletionItem, DocumentDiagnostic, DocumentLocation,
-        DocumentPosition, DocumentRange, DocumentSymbolInformation, ProtocolError,
-        ServerNotification, ServerRequest, ServerResponse, TextEdit, Uri, WorkspaceEdit,
+        DiagnosticSeverity, DocumentCodeAction, DocumentCompletionItem, DocumentDiagnostic,
+        DocumentDiagnosticReport, DocumentFoldingRange, DocumentHierarchyItem, DocumentInlayHint,
+        DocumentLocation, DocumentPosition, DocumentRange, DocumentSemanticTokenIter,
+        DocumentSemanticTokensEdit, DocumentSymbolInformation, ProtocolError, ServerNotification,
+        ServerRequest, ServerResponse, TextEdit, Uri, WorkspaceEdit,
     },
+    LspPlugin,
 };
 
 pub(crate) fn on_request(
     client: &mut Client,
     ctx: &mut EditorContext,
+    plugin_handle: PluginHandle,
     request: ServerRequest,
 ) -> Result<JsonValue, ProtocolError> {
     {
@@ -114,6 +119,50 @@ pub(crate) fn on_request(
             }
             Ok(JsonValue::Null)
         }
+        "window/workDoneProgress/create" => Ok(JsonValue::Null),
+        "workspace/applyEdit" => {
+            let edit = request.params.get("edit", &client.json);
+            let edit = WorkspaceEdit::from_json(edit, &client.json)?;
+            let result = edit.apply(
+                &mut ctx.editor,
+                &mut client.temp_edits,
+                &client.root,
+                &client.json,
+            );
+
+            let mut response = JsonObject::default();
+            response.set("applied".into(), result.is_ok().into(), &mut client.json);
+            if let Err(error) = result {
+                let error = client.json.create_string(&error);
+                response.set("failureReason".into(), error.into(), &mut client.json);
+            }
+            Ok(response.into())
+        }
+        "workspace/diagnostic/refresh" => {
+            for buffer in ctx.editor.buffers.iter() {
+                match buffer.path.to_str() {
+                    Some(path) if client.handles_path(path) => (),
+                    _ => continue,
+                }
+                client.diagnostics.on_buffer_edit(buffer.handle());
+            }
+            util::send_pending_pull_diagnostics(client, &mut ctx.editor, &mut ctx.platform);
+            Ok(JsonValue::Null)
+        }
+        "workspace/inlayHint/refresh" => {
+            let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+            for buffer in ctx.editor.buffers.iter() {
+                let path = match buffer.path.to_str() {
+                    Some(path) => path,
+                    None => continue,
+                };
+                if lsp.is_provider(client, path, ClientCapability::InlayHints) {
+                    client.inlay_hints.on_buffer_edit(buffer.handle());
+                }
+            }
+            util::send_pending_inlay_hints(client, &mut ctx.editor, &mut ctx.platform);
+            Ok(JsonValue::Null)
+        }
         "window/showMessage" => {
             fn parse_params(
                 params: JsonValue,
@@ -274,6 +323,64 @@ pub(crate) fn on_notification(
             }
             Ok(())
         }
+        "$/progress" => {
+            use fmt::Write;
+
+            let json = &client.json;
+            let mut token = ctx.editor.string_pool.acquire();
+            match notification.params.clone().get("token", json) {
+                JsonValue::String(value) => token.push_str(value.as_str(json)),
+                JsonValue::Integer(value) => {
+                    let _ = write!(token, "{}", value);
+                }
+                _ => {
+                    ctx.editor.string_pool.release(token);
+                    return Err(ProtocolError::ParseError);
+                }
+            }
+
+            let value = notification.params.get("value", json);
+            let get_str = |key| match value.clone().get(key, json) {
+                JsonValue::String(value) => Some(value.as_str(json)),
+                _ => None,
+            };
+            let percentage = match value.clone().get("percentage", json) {
+                JsonValue::Integer(value) => Some(value as _),
+                JsonValue::Number(value) => Some(value as _),
+                _ => None,
+            };
+
+            let source = client.handle().0 as _;
+            let progresses = &mut ctx.editor.progresses;
+            match get_str("kind") {
+                Some("begin") => {
+                    let progress = progresses.begin(plugin_handle, source, &token);
+                    match &client.server_name[..] {
+                        "" => progress.name.push_str("lsp"),
+                        name => progress.name.push_str(name),
+                    }
+                    progress.title.push_str(get_str("title").unwrap_or(""));
+                    progress.message.push_str(get_str("message").unwrap_or(""));
+                    progress.percentage = percentage;
+                }
+                Some("report") => {
+                    if let Some(progress) = progresses.get_mut(plugin_handle, source, &token) {
+                        if let Some(message) = get_str("message") {
+                            progress.message.clear();
+                            progress.message.push_str(message);
+                        }
+                        if percentage.is_some() {
+                            progress.percentage = percentage;
+                        }
+                    }
+                }
+                Some("end") => progresses.end(plugin_handle, source, &token),
+                _ => (),
+            }
+
+            ctx.editor.string_pool.release(token);
+            Ok(())
+        }
         "textDocument/publishDiagnostics" => {
             #[derive(Default)]
             struct Params {
@@ -298,48 +405,206 @@ pub(crate) fn on_notification(
             }
 
             let params = Params::from_json(notification.params, &client.json)?;
-            let uri = params.uri.as_str(&client.json);
-            let Uri::Path(path) = Uri::parse(&client.root, uri)?;
+            update_diagnostics(
+                client,
+                &mut ctx.editor,
+                plugin_handle,
+                &params.uri,
+                params.diagnostics,
+            )?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
 
-            let mut buffer_handle = None;
-            for buffer in ctx.editor.buffers.iter() {
-                if util::is_editor_path_equals_to_lsp_path(
-                    &ctx.editor.current_directory,
-                    &buffer.path,
-                    &client.root,
-                    path,
-                ) {
-                    buffer_handle = Some(buffer.handle());
-                    break;
-                }
-            }
-            if let Some(buffer_handle) = buffer_handle {
-                let mut lints = ctx
-                    .editor
-                    .buffers
-                    .get_mut(buffer_handle)
-                    .lints
-                    .mut_guard(plugin_handle);
-                lints.clear();
+// replaces the diagnostics of the file at `uri` and, if it's open, the lints of its buffer
+fn update_diagnostics(
+    client: &mut Client,
+    editor: &mut Editor,
+    plugin_handle: PluginHandle,
+    uri: &JsonString,
+    items: JsonArray,
+) -> Result<(), ProtocolError> {
+    let source = client.handle().0 as _;
+    let uri = uri.as_str(&client.json);
+    let Uri::Path(path) = Uri::parse(&client.root, uri)?;
+
+    let mut buffer_handle = None;
+    for buffer in editor.buffers.iter() {
+        if util::is_editor_path_equals_to_lsp_path(
+            &editor.current_directory,
+            &buffer.path,
+            &client.root,
+            path,
+        ) {
+            buffer_handle = Some(buffer.handle());
+            break;
+        }
+    }
+    if let Some(buffer_handle) = buffer_handle {
+        let mut lints = editor
+            .buffers
+            .get_mut(buffer_handle)
+            .lints
+            .source_mut_guard(plugin_handle, source);
+        lints.clear();
+
+        let diagnostics = client.diagnostics.get_buffer_diagnostics(buffer_handle);
+        diagnostics.clear();
+
+        for diagnostic in items.clone().elements(&client.json) {
+            let diagnostic = DocumentDiagnostic::from_json(diagnostic, &client.json)?;
+            let range = diagnostic.range.into_buffer_range();
+
+            lints.add(
+                diagnostic.message.as_str(&client.json),
+                range,
+                diagnostic.severity.to_lint_severity(),
+            );
+            diagnostics.add(range.from, &diagnostic.data, &client.json);
+        }
 
-                let diagnostics = client.diagnostics.get_buffer_diagnostics(buffer_handle);
-                diagnostics.clear();
+        diagnostics.sort();
+    }
 
-                for diagnostic in params.diagnostics.elements(&client.json) {
-                    let diagnostic = DocumentDiagnostic::from_json(diagnostic, &client.json)?;
-                    let range = diagnostic.range.into_buffer_range();
+    let file = client.diagnostics.get_file_diagnostics(uri);
+    file.clear();
+    for diagnostic in items.elements(&client.json) {
+        let diagnostic = DocumentDiagnostic::from_json(diagnostic, &client.json)?;
+        let position = diagnostic.range.start.into_buffer_position();
+        file.add(
+            position,
+            diagnostic.severity,
+            diagnostic.message.as_str(&client.json),
+        );
+    }
+    file.sort();
 
-                    lints.add(diagnostic.message.as_str(&client.json), range);
-                    diagnostics.add(range.from, &diagnostic.data, &client.json);
-                }
+    Ok(())
+}
 
-                diagnostics.sort();
+// lists the diagnostics of all files from every server that are at least as severe as `min_severity`
+// in a `diagnostics.refs` buffer. `acquired_client` is the one currently taken out of the plugin, if any
+pub(crate) fn list_diagnostics(
+    ctx: &mut EditorContext,
+    plugin_handle: PluginHandle,
+    acquired_client: Option<&Client>,
+    client_handle: client::ClientHandle,
+    min_severity: DiagnosticSeverity,
+) {
+    let buffer_view_handle = ctx.editor.buffer_view_handle_from_path(
+        client_handle,
+        Path::new("diagnostics.refs"),
+        BufferProperties::scratch(),
+        true,
+    );
+    let buffer_view_handle = match buffer_view_handle {
+        Ok(handle) => handle,
+        Err(error) => {
+            ctx.editor
+                .logger
+                .write(LogKind::Error)
+                .fmt(format_args!("{}", error));
+            return;
+        }
+    };
+
+    let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+    let mut files: Vec<_> = lsp
+        .clients()
+        .chain(acquired_client)
+        .flat_map(|c| c.diagnostics.files().iter().map(move |f| (&c.root, f)))
+        .collect();
+    files.sort_unstable_by(|(_, a), (_, b)| a.uri.cmp(&b.uri));
+
+    let mut count = 0;
+    let mut text = ctx.editor.string_pool.acquire();
+    for (root, file) in files {
+        let path = match Uri::parse(root, &file.uri) {
+            Ok(Uri::Path(path)) => path,
+            Err(_) => continue,
+        };
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => continue,
+        };
+
+        for (diagnostic, message) in file.iter() {
+            if diagnostic.severity > min_severity {
+                continue;
             }
 
-            Ok(())
+            use fmt::Write;
+            let _ = write!(
+                text,
+                "{}:{}:{}:",
+                path,
+                diagnostic.position,
+                diagnostic.severity.name()
+            );
+            for line in message.lines() {
+                text.push(' ');
+                text.push_str(line.trim());
+            }
+            text.push('\n');
+            count += 1;
         }
-        _ => Ok(()),
     }
+
+    {
+        use fmt::Write;
+        let mut header = ctx.editor.string_pool.acquire();
+        match count {
+            1 => header.push_str("1 diagnostic found\n\n"),
+            _ => {
+                let _ = writeln!(header, "{} diagnostics found\n", count);
+            }
+        }
+        text.insert_str(0, &header);
+        ctx.editor.string_pool.release(header);
+    }
+
+    let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+    let buffer = ctx.editor.buffers.get_mut(buffer_view.buffer_handle);
+    buffer.properties = BufferProperties::scratch();
+
+    let range = BufferRange::between(BufferPosition::zero(), buffer.content().end());
+    buffer.delete_range(
+        &mut ctx.editor.word_database,
+        range,
+        &mut ctx
+            .editor
+            .events
+            .writer()
+            .buffer_range_deletes_mut_guard(buffer.handle()),
+    );
+    buffer.insert_text(
+        &mut ctx.editor.word_database,
+        BufferPosition::zero(),
+        &text,
+        &mut ctx
+            .editor
+            .events
+            .writer()
+            .buffer_text_inserts_mut_guard(buffer.handle()),
+    );
+    ctx.editor.string_pool.release(text);
+
+    let client = ctx.clients.get_mut(client_handle);
+    client.set_buffer_view_handle(Some(buffer_view_handle), &ctx.editor.buffer_views);
+
+    let mut cursors = ctx
+        .editor
+        .buffer_views
+        .get_mut(buffer_view_handle)
+        .cursors
+        .mut_guard();
+    cursors.clear();
+    cursors.add(Cursor {
+        anchor: BufferPosition::zero(),
+        position: BufferPosition::zero(),
+    });
 }
 
 pub(crate) fn on_response(
@@ -348,7 +613,8 @@ pub(crate) fn on_response(
     plugin_handle: PluginHandle,
     response: ServerResponse,
 ) -> Result<(), ProtocolError> {
-    let method = match client.pending_requests.take(response.id) {
+    let request_id = response.id;
+    let method = match client.pending_requests.take(request_id) {
         Some(method) => method,
         None => return Ok(()),
     };
@@ -379,7 +645,28 @@ pub(crate) fn on_response(
     let result = match response.result {
         Ok(result) => result,
         Err(error) => {
+            if let RequestState::Formatting {
+                buffer_handle,
+                save: true,
+            } = client.request_state
+            {
+                util::save_buffer(&mut ctx.editor, buffer_handle);
+            }
             client.request_state = RequestState::Idle;
+            client.format_ranges.clear();
+            client.on_type_format_positions.clear();
+            client.format_on_save(&mut ctx.editor, &mut ctx.platform);
+            client.inlay_hints.take_pending_request(request_id);
+            client.folding_ranges.take_pending_request(request_id);
+            client.document_highlights.take_pending_request(request_id);
+            client.diagnostics.take_pending_request(request_id);
+            if let Some(buffer_handle) = client.semantic_tokens.take_pending_request(request_id) {
+                client
+                    .semantic_tokens
+                    .get_buffer_semantic_tokens(buffer_handle)
+                    .result_id
+                    .clear();
+            }
             ctx.editor
                 .logger
                 .write(LogKind::Error)
@@ -406,6 +693,9 @@ pub(crate) fn on_response(
                 }
             }
 
+            client.server_name.clear();
+            client.server_name.push_str(server_name);
+
             match server_name {
                 "" => ctx
                     .editor
@@ -427,7 +717,13 @@ pub(crate) fn on_response(
                 &mut ctx.editor.logger,
             );
 
+            client.update_semantic_token_kinds(&mut ctx.editor.syntaxes);
+
             for buffer in ctx.editor.buffers.iter() {
+                match buffer.path.to_str() {
+                    Some(path) if client.handles_path(path) => (),
+                    _ => continue,
+                }
                 util::send_did_open(
                     client,
                     &ctx.editor.buffers,
@@ -435,7 +731,14 @@ pub(crate) fn on_response(
                     buffer.handle(),
                     &mut ctx.editor.logger,
                 );
+                client.diagnostics.on_buffer_edit(buffer.handle());
             }
+            let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+            lsp.refresh_provided_buffers(client, &ctx.editor.buffers);
+            util::send_pending_semantic_tokens(client, &mut ctx.editor, &mut ctx.platform);
+            util::send_pending_inlay_hints(client, &mut ctx.editor, &mut ctx.platform);
+            util::send_pending_folding_ranges(client, &mut ctx.editor, &mut ctx.platform);
+            util::send_pending_pull_diagnostics(client, &mut ctx.editor, &mut ctx.platform);
 
             Ok(())
         }
@@ -651,7 +954,7 @@ pub(crate) fn on_response(
                         .min((location.range.start.line as usize).saturating_sub(context_len - 1));
                     let end = line_count.min(location.range.end.line as usize + context_len);
 
-                    for line in &context_buffer.lines()[start..end] {
+                    for line in context_buffer.lines().range(start..end) {
                         let line = line.as_str();
                         if line.is_empty() {
                             text.push('~');
@@ -778,12 +1081,14 @@ pub(crate) fn on_response(
             }
 
             let edit = WorkspaceEdit::from_json(result, &client.json)?;
-            edit.apply(
+            if let Err(error) = edit.apply(
                 &mut ctx.editor,
                 &mut client.temp_edits,
                 &client.root,
                 &client.json,
-            );
+            ) {
+                ctx.editor.logger.write(LogKind::Error).str(&error);
+            }
             Ok(())
         }
         "textDocument/codeAction" => {
@@ -909,36 +1214,351 @@ pub(crate) fn on_response(
 
             Ok(())
         }
+        "textDocument/prepareCallHierarchy" | "textDocument/prepareTypeHierarchy" => {
+            let kind = match client.request_state {
+                RequestState::PrepareHierarchy { kind } => kind,
+                _ => return Ok(()),
+            };
+            client.request_state = RequestState::Idle;
+            let item = match result {
+                JsonValue::Array(items) => match items.elements(&client.json).next() {
+                    Some(item) => item,
+                    None => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+
+            client.hierarchy_from_item(&mut ctx.platform, &mut ctx.editor.logger, item, kind);
+            Ok(())
+        }
+        "callHierarchy/incomingCalls"
+        | "callHierarchy/outgoingCalls"
+        | "typeHierarchy/supertypes"
+        | "typeHierarchy/subtypes" => {
+            let kind = match client.request_state {
+                RequestState::Hierarchy { kind } => kind,
+                _ => return Ok(()),
+            };
+            client.request_state = RequestState::Idle;
+            let results = match result {
+                JsonValue::Array(results) => results,
+                _ => return Ok(()),
+            };
+
+            ctx.editor.picker.clear();
+            for item in results
+                .clone()
+                .elements(&client.json)
+                .map(|r| kind.item(r, &client.json))
+                .filter_map(|i| DocumentHierarchyItem::from_json(i, &client.json).ok())
+            {
+                let name = item.name.as_str(&client.json);
+                match item.detail {
+                    Some(detail) => {
+                        let detail = detail.as_str(&client.json);
+                        ctx.editor
+                            .picker
+                            .add_custom_entry_fmt(format_args!("{} ({})", name, detail));
+                    }
+                    None => ctx.editor.picker.add_custom_entry(name),
+                }
+            }
+
+            picker::enter_hierarchy_mode(ctx, plugin_handle, client, kind);
+
+            client.request_state = RequestState::FinishHierarchy { kind };
+            client.request_raw_json.clear();
+            let _ = client
+                .json
+                .write(&mut client.request_raw_json, &results.into());
+
+            Ok(())
+        }
         "textDocument/formatting" => {
+            let (buffer_handle, save) = match client.request_state {
+                RequestState::Formatting {
+                    buffer_handle,
+                    save,
+                } => (buffer_handle, save),
+                _ => return Ok(()),
+            };
+            client.request_state = RequestState::Idle;
+
+            if let JsonValue::Array(edits) = result {
+                TextEdit::apply_edits(
+                    &mut ctx.editor,
+                    buffer_handle,
+                    &mut client.temp_edits,
+                    edits,
+                    &client.json,
+                );
+
+                for buffer_view in ctx.editor.buffer_views.iter() {
+                    let position = buffer_view.cursors.main_cursor().position;
+                    let mut fix_cursor = ctx
+                        .editor
+                        .events
+                        .writer()
+                        .fix_cursors_mut_guard(buffer_view.handle());
+                    fix_cursor.add(Cursor {
+                        anchor: position,
+                        position,
+                    });
+                }
+            }
+
+            if save {
+                util::save_buffer(&mut ctx.editor, buffer_handle);
+            }
+            client.format_on_save(&mut ctx.editor, &mut ctx.platform);
+
+            Ok(())
+        }
+        "textDocument/rangeFormatting" | "textDocument/onTypeFormatting" => {
             let buffer_handle = match client.request_state {
-                RequestState::Formatting { buffer_handle } => buffer_handle,
+                RequestState::RangeFormatting { buffer_handle }
+                | RequestState::OnTypeFormatting { buffer_handle } => buffer_handle,
                 _ => return Ok(()),
             };
             client.request_state = RequestState::Idle;
-            let edits = match result {
-                JsonValue::Array(edits) => edits,
+
+            // the remaining ranges are only formatted at the next `Idle` event
+            // after the server was notified of these edits
+            if let JsonValue::Array(edits) = result {
+                TextEdit::apply_edits(
+                    &mut ctx.editor,
+                    buffer_handle,
+                    &mut client.temp_edits,
+                    edits,
+                    &client.json,
+                );
+            }
+
+            Ok(())
+        }
+        "textDocument/diagnostic" => {
+            let buffer_handle = match client.diagnostics.take_pending_request(request_id) {
+                Some(buffer_handle) => buffer_handle,
+                None => return Ok(()),
+            };
+            let report = DocumentDiagnosticReport::from_json(result, &client.json)?;
+
+            if report.full {
+                let buffer = ctx.editor.buffers.get(buffer_handle);
+                let text_document =
+                    util::text_document_with_id(&client.root, &buffer.path, &mut client.json);
+                if let JsonValue::String(uri) = text_document.get("uri", &client.json) {
+                    update_diagnostics(client, &mut ctx.editor, plugin_handle, &uri, report.items)?;
+                }
+            }
+
+            let result_id = report.result_id.as_str(&client.json);
+            let diagnostics = client.diagnostics.get_buffer_diagnostics(buffer_handle);
+            diagnostics.result_id.clear();
+            diagnostics.result_id.push_str(result_id);
+
+            Ok(())
+        }
+        "workspace/diagnostic" => {
+            let (client_handle, min_severity) = match client.request_state {
+                RequestState::WorkspaceDiagnostics {
+                    client_handle,
+                    min_severity,
+                } => (client_handle, min_severity),
                 _ => return Ok(()),
             };
+            client.request_state = RequestState::Idle;
 
-            TextEdit::apply_edits(
-                &mut ctx.editor,
-                buffer_handle,
-                &mut client.temp_edits,
-                edits,
-                &client.json,
+            let reports: Vec<_> = result
+                .get("items", &client.json)
+                .elements(&client.json)
+                .map(|r| DocumentDiagnosticReport::from_json(r, &client.json))
+                .collect::<Result<_, _>>()?;
+            for report in reports {
+                if report.full {
+                    update_diagnostics(
+                        client,
+                        &mut ctx.editor,
+                        plugin_handle,
+                        &report.uri,
+                        report.items,
+                    )?;
+                }
+
+                let uri = report.uri.as_str(&client.json);
+                let result_id = report.result_id.as_str(&client.json);
+                let file = client.diagnostics.get_file_diagnostics(uri);
+                file.result_id.clear();
+                file.result_id.push_str(result_id);
+            }
+
+            list_diagnostics(
+                ctx,
+                plugin_handle,
+                Some(client),
+                client_handle,
+                min_severity,
             );
+            Ok(())
+        }
+        "textDocument/semanticTokens/full" | "textDocument/semanticTokens/full/delta" => {
+            let buffer_handle = match client.semantic_tokens.take_pending_request(request_id) {
+                Some(buffer_handle) => buffer_handle,
+                None => return Ok(()),
+            };
+            let semantic_tokens = client
+                .semantic_tokens
+                .get_buffer_semantic_tokens(buffer_handle);
+
+            match result {
+                JsonValue::Object(result) => {
+                    semantic_tokens.result_id.clear();
+                    for (key, value) in result.members(&client.json) {
+                        match key {
+                            "resultId" => {
+                                let result_id: &str = FromJson::from_json(value, &client.json)?;
+                                semantic_tokens.result_id.push_str(result_id);
+                            }
+                            "data" => {
+                                semantic_tokens.data.clear();
+                                for value in value.elements(&client.json) {
+                                    semantic_tokens
+                                        .data
+                                        .push(u32::from_json(value, &client.json)?);
+                                }
+                            }
+                            "edits" => {
+                                // edits refer to the previous data so they're applied from last to first
+                                let mut edits: Vec<_> = value
+                                    .elements(&client.json)
+                                    .map(|e| DocumentSemanticTokensEdit::from_json(e, &client.json))
+                                    .collect::<Result<_, _>>()?;
+                                edits.sort_unstable_by_key(|e| e.start);
+                                for edit in edits.into_iter().rev() {
+                                    let data_len = semantic_tokens.data.len();
+                                    let start = data_len.min(edit.start as _);
+                                    let end = data_len.min(start + edit.delete_count as usize);
+                                    let mut values = Vec::new();
+                                    for value in edit.data.elements(&client.json) {
+                                        values.push(u32::from_json(value, &client.json)?);
+                                    }
+                                    semantic_tokens.data.splice(start..end, values);
+                                }
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => {
+                    semantic_tokens.result_id.clear();
+                    semantic_tokens.data.clear();
+                }
+            }
 
-            for buffer_view in ctx.editor.buffer_views.iter() {
-                let position = buffer_view.cursors.main_cursor().position;
-                let mut fix_cursor = ctx
-                    .editor
-                    .events
-                    .writer()
-                    .fix_cursors_mut_guard(buffer_view.handle());
-                fix_cursor.add(Cursor {
-                    anchor: position,
-                    position,
-                });
+            // the buffer changed since the request so these tokens are already stale
+            if semantic_tokens.dirty {
+                client.semantic_tokens(&mut ctx.editor, &mut ctx.platform, buffer_handle);
+                return Ok(());
+            }
+
+            let buffer = ctx.editor.buffers.get_mut(buffer_handle);
+            let mut buffer_semantic_tokens = buffer.semantic_tokens_mut_guard();
+            buffer_semantic_tokens.clear();
+            for token in DocumentSemanticTokenIter::new(&semantic_tokens.data) {
+                if let Some(&Some(kind)) =
+                    client.semantic_token_kinds.get(token.token_type as usize)
+                {
+                    buffer_semantic_tokens.add(
+                        token.line as _,
+                        Token {
+                            kind,
+                            from: token.start as _,
+                            to: (token.start + token.len) as _,
+                        },
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        "textDocument/documentHighlight" => {
+            let buffer_handle = match client.document_highlights.take_pending_request(request_id) {
+                Some(buffer_handle) => buffer_handle,
+                None => return Ok(()),
+            };
+
+            for buffer in ctx.editor.buffers.iter_mut() {
+                buffer.range_highlights.mut_guard(plugin_handle).clear();
+            }
+
+            let mut highlights = ctx
+                .editor
+                .buffers
+                .get_mut(buffer_handle)
+                .range_highlights
+                .mut_guard(plugin_handle);
+            for highlight in result.elements(&client.json) {
+                let range = highlight.get("range", &client.json);
+                let range = DocumentRange::from_json(range, &client.json)?;
+                highlights.add(range.into_buffer_range());
+            }
+
+            Ok(())
+        }
+        "textDocument/inlayHint" => {
+            let buffer_handle = match client.inlay_hints.take_pending_request(request_id) {
+                Some(buffer_handle) => buffer_handle,
+                None => return Ok(()),
+            };
+
+            // the buffer changed since the request so these hints are already stale
+            if client.inlay_hints.is_dirty(buffer_handle) {
+                client.inlay_hints(&mut ctx.editor, &mut ctx.platform, buffer_handle);
+                return Ok(());
+            }
+
+            let mut annotations = ctx
+                .editor
+                .buffers
+                .get_mut(buffer_handle)
+                .annotations
+                .mut_guard(plugin_handle);
+            annotations.clear();
+
+            let mut text = String::new();
+            for hint in result.elements(&client.json) {
+                let hint = DocumentInlayHint::from_json(hint, &client.json)?;
+                text.clear();
+                hint.write_text(&mut text, &client.json);
+                annotations.add(&text, hint.position.into_buffer_position());
+            }
+
+            Ok(())
+        }
+        "textDocument/foldingRange" => {
+            let buffer_handle = match client.folding_ranges.take_pending_request(request_id) {
+                Some(buffer_handle) => buffer_handle,
+                None => return Ok(()),
+            };
+
+            if client.folding_ranges.is_dirty(buffer_handle) {
+                client.folding_ranges(&mut ctx.editor, &mut ctx.platform, buffer_handle);
+                return Ok(());
+            }
+
+            let mut fold_ranges = ctx
+                .editor
+                .buffers
+                .get_mut(buffer_handle)
+                .fold_ranges
+                .mut_guard(plugin_handle);
+            fold_ranges.clear();
+
+            if let JsonValue::Array(ranges) = result {
+                for range in ranges.elements(&client.json) {
+                    let range = DocumentFoldingRange::from_json(range, &client.json)?;
+                    fold_ranges.add(range.start_line, range.end_line);
+                }
             }
 
             Ok(())
@@ -981,7 +1601,15 @@ pub(crate) fn on_response(
                 if let Ok(completion) = DocumentCompletionItem::from_json(completion, &client.json)
                 {
                     let text = completion.text.as_str(&client.json);
-                    ctx.editor.picker.add_custom_entry(text);
+                    match completion.snippet {
+                        Some(snippet) => {
+                            let snippet = snippet.as_str(&client.json);
+                            ctx.editor
+                                .picker
+                                .add_custom_entry_with_snippet(text, snippet);
+                        }
+                        None => ctx.editor.picker.add_custom_entry(text),
+                    }
                 }
             }
 
@@ -8,7 +8,9 @@ use pepper::{
 };
 
 use crate::{
-    client::Client,
+    client::{util, Client, ClientCapability, ClientHandle, HierarchyKind},
+    client_event_handler,
+    protocol::DiagnosticSeverity,
     {ClientGuard, LspPlugin},
 };
 
@@ -42,7 +44,7 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
 
         let plugin_handle = io.plugin_handle();
         let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
-        lsp.start(&mut ctx.platform, plugin_handle, command, root);
+        lsp.start(&mut ctx.platform, plugin_handle, command, root, None);
         Ok(())
     });
 
@@ -51,7 +53,7 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
 
         let buffer_handle = io.current_buffer_handle(ctx).ok();
         let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
-        let any_stopped = match find_lsp_client_for_buffer(lsp, &ctx.editor, buffer_handle) {
+        let any_stopped = match find_lsp_client_for_buffer(lsp, &ctx.editor, buffer_handle, None) {
             Some(client) => {
                 let handle = client.handle();
                 lsp.release(client);
@@ -81,15 +83,21 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         io.args.assert_empty()?;
 
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.hover(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                buffer_handle,
-                cursor.position,
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::Hover,
+            |ctx, client| {
+                let op = client.hover(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    buffer_handle,
+                    cursor.position,
+                );
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-definition", &[], |ctx, io| {
@@ -98,16 +106,22 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let client_handle = io.client_handle()?;
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.definition(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                buffer_handle,
-                cursor.position,
-                client_handle,
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::Definition,
+            |ctx, client| {
+                let op = client.definition(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    buffer_handle,
+                    cursor.position,
+                    client_handle,
+                );
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-declaration", &[], |ctx, io| {
@@ -116,32 +130,44 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let client_handle = io.client_handle()?;
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.declaration(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                buffer_handle,
-                cursor.position,
-                client_handle,
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::Declaration,
+            |ctx, client| {
+                let op = client.declaration(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    buffer_handle,
+                    cursor.position,
+                    client_handle,
+                );
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-implementation", &[], |ctx, io| {
         io.args.assert_empty()?;
         let client_handle = io.client_handle()?;
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.implementation(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                buffer_handle,
-                cursor.position,
-                client_handle,
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::Implementation,
+            |ctx, client| {
+                let op = client.implementation(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    buffer_handle,
+                    cursor.position,
+                    client_handle,
+                );
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-references", &[], |ctx, io| {
@@ -162,17 +188,43 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let client_handle = io.client_handle()?;
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.references(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                buffer_handle,
-                cursor.position,
-                context_len,
-                client_handle,
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::References,
+            |ctx, client| {
+                let op = client.references(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    buffer_handle,
+                    cursor.position,
+                    context_len,
+                    client_handle,
+                );
+                Ok(op)
+            },
+        )
+    });
+
+    r("lsp-incoming-calls", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        hierarchy(ctx, io, HierarchyKind::IncomingCalls)
+    });
+
+    r("lsp-outgoing-calls", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        hierarchy(ctx, io, HierarchyKind::OutgoingCalls)
+    });
+
+    r("lsp-supertypes", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        hierarchy(ctx, io, HierarchyKind::Supertypes)
+    });
+
+    r("lsp-subtypes", &[], |ctx, io| {
+        io.args.assert_empty()?;
+        hierarchy(ctx, io, HierarchyKind::Subtypes)
     });
 
     r("lsp-rename", &[], |ctx, io| {
@@ -181,10 +233,16 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
         let plugin_handle = io.plugin_handle();
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.rename(ctx, plugin_handle, buffer_handle, cursor.position);
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::Rename,
+            |ctx, client| {
+                let op = client.rename(ctx, plugin_handle, buffer_handle, cursor.position);
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-code-action", &[], |ctx, io| {
@@ -193,16 +251,22 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
         let plugin_handle = io.plugin_handle();
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.code_action(
-                &mut ctx.editor,
-                &mut ctx.platform,
-                plugin_handle,
-                buffer_handle,
-                cursor.to_range(),
-            );
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::CodeAction,
+            |ctx, client| {
+                let op = client.code_action(
+                    &mut ctx.editor,
+                    &mut ctx.platform,
+                    plugin_handle,
+                    buffer_handle,
+                    cursor.to_range(),
+                );
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-document-symbols", &[], |ctx, io| {
@@ -211,10 +275,16 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         let view_handle = io.current_buffer_view_handle(ctx)?;
         let buffer_handle = ctx.editor.buffer_views.get(view_handle).buffer_handle;
 
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.document_symbols(&mut ctx.editor, &mut ctx.platform, view_handle);
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::DocumentSymbols,
+            |ctx, client| {
+                let op = client.document_symbols(&mut ctx.editor, &mut ctx.platform, view_handle);
+                Ok(op)
+            },
+        )
     });
 
     r("lsp-workspace-symbols", &[], |ctx, io| {
@@ -222,20 +292,188 @@ pub fn register_commands(commands: &mut CommandManager, plugin_handle: PluginHan
         io.args.assert_empty()?;
 
         let buffer_handle = io.current_buffer_handle(ctx).ok();
-        access(ctx, io, buffer_handle, |ctx, client| {
-            let op = client.workspace_symbols(&mut ctx.editor, &mut ctx.platform, query);
-            Ok(op)
-        })
+        access(
+            ctx,
+            io,
+            buffer_handle,
+            ClientCapability::WorkspaceSymbols,
+            |ctx, client| {
+                let op = client.workspace_symbols(&mut ctx.editor, &mut ctx.platform, query);
+                Ok(op)
+            },
+        )
+    });
+
+    r("lsp-execute-command", &[], |ctx, io| {
+        let command = io.args.next()?;
+        let arguments = io.args;
+
+        let buffer_handle = io.current_buffer_handle(ctx).ok();
+        let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
+        let mut client = match find_lsp_client_for_buffer(
+            lsp,
+            &ctx.editor,
+            buffer_handle,
+            Some(ClientCapability::ExecuteCommand),
+        ) {
+            Some(client) => client,
+            None => {
+                return Err(CommandError::OtherStatic(
+                    "no lsp server running that can execute commands",
+                ))
+            }
+        };
+
+        let command = client.json.create_string(command);
+        let arguments = util::parse_command_arguments(arguments, &mut client.json);
+        client.execute_command(&mut ctx.editor, &mut ctx.platform, command, arguments);
+
+        let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
+        lsp.release(client);
+        Ok(())
     });
 
     r("lsp-format", &[], |ctx, io| {
         io.args.assert_empty()?;
 
-        let buffer_handle = io.current_buffer_handle(ctx)?;
-        access(ctx, io, Some(buffer_handle), |ctx, client| {
-            let op = client.formatting(&mut ctx.editor, &mut ctx.platform, buffer_handle);
-            Ok(op)
-        })
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+        let buffer_handle = buffer_view.buffer_handle;
+        if buffer_view.cursors[..]
+            .iter()
+            .all(|c| c.anchor == c.position)
+        {
+            return access(
+                ctx,
+                io,
+                Some(buffer_handle),
+                ClientCapability::Formatting,
+                |ctx, client| {
+                    client.formatting(&mut ctx.editor, &mut ctx.platform, buffer_handle, false);
+                    Ok(())
+                },
+            );
+        }
+
+        access(
+            ctx,
+            io,
+            Some(buffer_handle),
+            ClientCapability::RangeFormatting,
+            |ctx, client| {
+                client.format_ranges.clear();
+                let buffer_view = ctx.editor.buffer_views.get(buffer_view_handle);
+                for cursor in &buffer_view.cursors[..] {
+                    if cursor.anchor != cursor.position {
+                        client.format_ranges.push(cursor.to_range());
+                    }
+                }
+                client.format_ranges_buffer_handle = Some(buffer_handle);
+                client.range_formatting(&mut ctx.editor, &mut ctx.platform);
+                Ok(())
+            },
+        )
+    });
+
+    r("lsp-select-highlights", &[], |ctx, io| {
+        io.args.assert_empty()?;
+
+        let plugin_handle = io.plugin_handle();
+        let buffer_view_handle = io.current_buffer_view_handle(ctx)?;
+        let buffer_view = ctx.editor.buffer_views.get_mut(buffer_view_handle);
+        let buffer = ctx.editor.buffers.get(buffer_view.buffer_handle);
+
+        let mut highlights = buffer
+            .range_highlights
+            .all()
+            .iter()
+            .filter(|h| h.plugin_handle == plugin_handle)
+            .peekable();
+        if highlights.peek().is_none() {
+            return Err(CommandError::OtherStatic("no symbol highlighted"));
+        }
+
+        let main_cursor_position = buffer_view.cursors.main_cursor().position;
+        let mut cursors = buffer_view.cursors.mut_guard();
+        cursors.clear();
+        for highlight in highlights {
+            cursors.add(Cursor {
+                anchor: highlight.range.from,
+                position: highlight.range.to,
+            });
+        }
+        cursors.set_main_cursor_near_position(main_cursor_position);
+        Ok(())
+    });
+
+    r("lsp-diagnostics", &[], |ctx, io| {
+        let min_severity = match io.args.try_next() {
+            Some(name) => match DiagnosticSeverity::from_name(name) {
+                Some(severity) => severity,
+                None => {
+                    return Err(CommandError::OtherOwned(format!(
+                        "invalid diagnostic severity '{}'",
+                        name
+                    )))
+                }
+            },
+            None => DiagnosticSeverity::Hint,
+        };
+        io.args.assert_empty()?;
+
+        let client_handle = io.client_handle()?;
+        let plugin_handle = io.plugin_handle();
+
+        // servers that support workspace diagnostics list them again once they respond
+        let mut has_clients = false;
+        let mut is_pending = false;
+        let client_count = ctx
+            .plugins
+            .get_as::<LspPlugin>(plugin_handle)
+            .client_count();
+        for i in 0..client_count {
+            let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+            let mut client = match lsp.acquire(ClientHandle(i as _)) {
+                Some(client) => client,
+                None => continue,
+            };
+
+            has_clients = true;
+            is_pending |= client.workspace_diagnostics(
+                &mut ctx.editor,
+                &mut ctx.platform,
+                client_handle,
+                min_severity,
+            );
+
+            let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+            lsp.release(client);
+        }
+
+        if !has_clients {
+            return Err(CommandError::OtherStatic("no lsp server running"));
+        }
+        if !is_pending {
+            client_event_handler::list_diagnostics(
+                ctx,
+                plugin_handle,
+                None,
+                client_handle,
+                min_severity,
+            );
+        }
+        Ok(())
+    });
+
+    r("lsp-format-on-save", &[], |ctx, io| {
+        let glob = io.args.next()?;
+        io.args.assert_empty()?;
+
+        let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
+        match lsp.add_format_on_save(glob) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(CommandError::InvalidGlob(error)),
+        }
     });
 }
 
@@ -251,17 +489,46 @@ fn current_buffer_and_main_cursor(
     Ok((buffer_handle, cursor))
 }
 
+fn hierarchy(
+    ctx: &mut EditorContext,
+    io: &mut CommandIO,
+    kind: HierarchyKind,
+) -> Result<(), CommandError> {
+    let (buffer_handle, cursor) = current_buffer_and_main_cursor(ctx, io)?;
+    access(
+        ctx,
+        io,
+        Some(buffer_handle),
+        kind.capability(),
+        |ctx, client| {
+            client.hierarchy(
+                &mut ctx.editor,
+                &mut ctx.platform,
+                buffer_handle,
+                cursor.position,
+                kind,
+            );
+            Ok(())
+        },
+    )
+}
+
 fn find_lsp_client_for_buffer(
     lsp: &mut LspPlugin,
     editor: &Editor,
     buffer_handle: Option<BufferHandle>,
+    capability: Option<ClientCapability>,
 ) -> Option<ClientGuard> {
+    let has_capability = |c: &Client| match capability {
+        Some(capability) => c.has_capability(capability),
+        None => true,
+    };
     match buffer_handle {
         Some(buffer_handle) => {
             let buffer_path = editor.buffers.get(buffer_handle).path.to_str()?;
-            lsp.find_client(|c| c.handles_path(buffer_path))
+            lsp.find_client(|c| c.handles_path(buffer_path) && has_capability(c))
         }
-        None => lsp.find_client(|_| true),
+        None => lsp.find_client(has_capability),
     }
 }
 
@@ -269,13 +536,16 @@ fn access<A>(
     ctx: &mut EditorContext,
     io: &mut CommandIO,
     buffer_handle: Option<BufferHandle>,
+    capability: ClientCapability,
     accessor: A,
 ) -> Result<(), CommandError>
 where
     A: FnOnce(&mut EditorContext, &mut Client) -> Result<(), CommandError>,
 {
     let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
-    if let Some(mut client) = find_lsp_client_for_buffer(lsp, &ctx.editor, buffer_handle) {
+    if let Some(mut client) =
+        find_lsp_client_for_buffer(lsp, &ctx.editor, buffer_handle, Some(capability))
+    {
         let result = accessor(ctx, &mut client);
         let lsp = ctx.plugins.get_as::<LspPlugin>(io.plugin_handle());
         lsp.release(client);
@@ -5,11 +5,13 @@ use std::{
 };
 
 use pepper::{
-    buffer_position::BufferRange,
-    editor::EditorContext,
+    buffer::{BufferCollection, BufferHandle},
+    buffer_position::{BufferPosition, BufferRange},
+    editor::{Editor, EditorContext},
     editor_utils::{hash_bytes, parse_process_command, LogKind, Logger},
     events::{EditorEvent, EditorEventIter},
     glob::{Glob, InvalidGlobError},
+    mode::ModeKind,
     platform::{Platform, PlatformProcessHandle, PlatformRequest, ProcessTag},
     plugin::{CompletionContext, Plugin, PluginDefinition, PluginHandle},
     ResourceFile,
@@ -23,7 +25,7 @@ mod json;
 mod mode;
 mod protocol;
 
-use client::{util, Client, ClientHandle};
+use client::{util, Client, ClientCapability, ClientHandle};
 use json::{JsonObject, JsonValue};
 use protocol::{ProtocolError, ResponseError, ServerEvent};
 
@@ -47,6 +49,8 @@ pub static DEFINITION: PluginDefinition = PluginDefinition {
             on_process_exit,
 
             on_completion,
+            on_buffer_save,
+            on_flush_saves,
 
             ..Default::default()
         })
@@ -58,7 +62,7 @@ pub static DEFINITION: PluginDefinition = PluginDefinition {
 };
 
 struct ClientRecipe {
-    glob_hash: u64,
+    hash: u64,
     glob: Glob,
     command: String,
     root: PathBuf,
@@ -109,18 +113,28 @@ pub(crate) struct LspPlugin {
     entries: Vec<ClientEntry>,
     recipes: Vec<ClientRecipe>,
     current_client_handle: Option<ClientHandle>,
+    format_on_save_globs: Vec<Glob>,
 }
 
 impl LspPlugin {
+    pub fn add_format_on_save(&mut self, glob: &str) -> Result<(), InvalidGlobError> {
+        let mut format_on_save_glob = Glob::default();
+        format_on_save_glob.compile(glob)?;
+        self.format_on_save_globs.push(format_on_save_glob);
+        Ok(())
+    }
+
     pub fn add_recipe(
         &mut self,
         glob: &str,
         command: &str,
         root: Option<&str>,
     ) -> Result<(), InvalidGlobError> {
-        let glob_hash = hash_bytes(glob.as_bytes());
+        // several servers can handle the same glob so a recipe is only replaced
+        // when both its glob and command are the same
+        let hash = hash_bytes(glob.as_bytes()) ^ hash_bytes(command.as_bytes()).rotate_left(1);
         for recipe in &mut self.recipes {
-            if recipe.glob_hash == glob_hash {
+            if recipe.hash == hash {
                 recipe.command.clear();
                 recipe.command.push_str(command);
                 recipe.root.clear();
@@ -135,7 +149,7 @@ impl LspPlugin {
         let mut recipe_glob = Glob::default();
         recipe_glob.compile(glob)?;
         self.recipes.push(ClientRecipe {
-            glob_hash,
+            hash,
             glob: recipe_glob,
             command: command.into(),
             root: root.unwrap_or("").into(),
@@ -150,6 +164,7 @@ impl LspPlugin {
         plugin_handle: PluginHandle,
         mut command: Command,
         root: PathBuf,
+        glob: Option<Glob>,
     ) -> ClientHandle {
         fn find_vacant_entry(lsp: &mut LspPlugin) -> ClientHandle {
             for (i, entry) in lsp.entries.iter_mut().enumerate() {
@@ -178,7 +193,7 @@ impl LspPlugin {
             buf_len: SERVER_PROCESS_BUFFER_LEN,
         });
 
-        let client = Client::new(handle, root);
+        let client = Client::new(handle, root, glob);
         self.entries[handle.0 as usize] = ClientEntry::Occupied(Box::new(client));
         handle
     }
@@ -227,6 +242,18 @@ impl LspPlugin {
         }
     }
 
+    // the clients that are not acquired
+    pub(crate) fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.entries.iter().filter_map(|e| match e {
+            ClientEntry::Occupied(client) => Some(client.deref()),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn client_count(&self) -> usize {
+        self.entries.len()
+    }
+
     pub(crate) fn acquire(&mut self, handle: ClientHandle) -> Option<ClientGuard> {
         self.entries[handle.0 as usize]
             .reserve_and_take()
@@ -241,6 +268,46 @@ impl LspPlugin {
         self.entries[index] = ClientEntry::Occupied(client);
     }
 
+    // whether `client` is the running client that should answer `capability` requests
+    // for the buffer at `path` which is the first one that handles it and declares the capability
+    pub(crate) fn is_provider(
+        &self,
+        client: &Client,
+        path: &str,
+        capability: ClientCapability,
+    ) -> bool {
+        if !client.has_capability(capability) || !client.handles_path(path) {
+            return false;
+        }
+
+        let index = client.handle().0 as usize;
+        !self.entries[..index].iter().any(|e| match e {
+            ClientEntry::Occupied(c) => {
+                c.initialized && c.has_capability(capability) && c.handles_path(path)
+            }
+            _ => false,
+        })
+    }
+
+    // marks the buffers whose semantic tokens and inlay hints come from `client` to be requested again
+    pub(crate) fn refresh_provided_buffers(&self, client: &mut Client, buffers: &BufferCollection) {
+        for buffer in buffers.iter() {
+            let path = match buffer.path.to_str() {
+                Some(path) => path,
+                None => continue,
+            };
+            if self.is_provider(client, path, ClientCapability::SemanticTokens) {
+                client.semantic_tokens.on_buffer_edit(buffer.handle());
+            }
+            if self.is_provider(client, path, ClientCapability::InlayHints) {
+                client.inlay_hints.on_buffer_edit(buffer.handle());
+            }
+            if self.is_provider(client, path, ClientCapability::FoldingRanges) {
+                client.folding_ranges.on_buffer_edit(buffer.handle());
+            }
+        }
+    }
+
     pub(crate) fn find_client<P>(&mut self, mut predicate: P) -> Option<ClientGuard>
     where
         P: FnMut(&Client) -> bool,
@@ -268,107 +335,293 @@ fn on_editor_events(plugin_handle: PluginHandle, ctx: &mut EditorContext) {
                 Some(path) => path,
                 None => continue,
             };
-            let (index, recipe) = match lsp
-                .recipes
-                .iter_mut()
-                .enumerate()
-                .find(|(_, r)| r.glob.matches(buffer_path))
-            {
-                Some(recipe) => recipe,
-                None => continue,
-            };
-            if recipe.running_client.is_some() {
-                continue;
-            }
-            let command = match parse_process_command(&recipe.command) {
-                Some(command) => command,
-                None => {
-                    ctx.editor
-                        .logger
-                        .write(LogKind::Error)
-                        .fmt(format_args!("invalid lsp command '{}'", &recipe.command));
+            for index in 0..lsp.recipes.len() {
+                let recipe = &lsp.recipes[index];
+                if recipe.running_client.is_some() || !recipe.glob.matches(buffer_path) {
                     continue;
                 }
-            };
+                let command = match parse_process_command(&recipe.command) {
+                    Some(command) => command,
+                    None => {
+                        ctx.editor
+                            .logger
+                            .write(LogKind::Error)
+                            .fmt(format_args!("invalid lsp command '{}'", &recipe.command));
+                        continue;
+                    }
+                };
+
+                let root = if recipe.root.as_os_str().is_empty() {
+                    ctx.editor.current_directory.clone()
+                } else {
+                    recipe.root.clone()
+                };
+                let glob = Some(recipe.glob.clone());
+
+                let client_handle =
+                    lsp.start(&mut ctx.platform, plugin_handle, command, root, glob);
+                lsp.recipes[index].running_client = Some(client_handle);
+            }
+        }
+    }
 
-            let root = if recipe.root.as_os_str().is_empty() {
-                ctx.editor.current_directory.clone()
-            } else {
-                recipe.root.clone()
-            };
+    let main_cursor = ctx
+        .clients
+        .focused_client()
+        .and_then(|h| ctx.clients.get(h).buffer_view_handle())
+        .map(|h| {
+            let buffer_view = ctx.editor.buffer_views.get(h);
+            (
+                buffer_view.buffer_handle,
+                buffer_view.cursors.main_cursor().position,
+            )
+        });
 
-            let client_handle = lsp.start(&mut ctx.platform, plugin_handle, command, root);
-            lsp.recipes[index].running_client = Some(client_handle);
+    for i in 0..lsp.entries.len() {
+        let mut client = match lsp.acquire(ClientHandle(i as _)) {
+            Some(client) => client,
+            None => continue,
+        };
+        if client.initialized {
+            on_client_editor_events(
+                lsp,
+                &mut client,
+                &mut ctx.editor,
+                &mut ctx.platform,
+                main_cursor,
+            );
         }
+        lsp.release(client);
     }
+}
 
-    for entry in &mut lsp.entries {
-        let client = match entry {
-            ClientEntry::Occupied(client) => client,
-            _ => continue,
-        };
-        if !client.initialized {
-            continue;
+fn on_client_editor_events(
+    lsp: &LspPlugin,
+    client: &mut Client,
+    editor: &mut Editor,
+    platform: &mut Platform,
+    main_cursor: Option<(BufferHandle, BufferPosition)>,
+) {
+    fn handled_buffer_path<'a>(
+        client: &Client,
+        editor: &'a Editor,
+        buffer_handle: BufferHandle,
+    ) -> Option<&'a str> {
+        let path = editor.buffers.get(buffer_handle).path.to_str()?;
+        if Some(path) != editor.logger.log_file_path() && client.handles_path(path) {
+            Some(path)
+        } else {
+            None
         }
+    }
 
-        let mut events = EditorEventIter::new();
-        while let Some(event) = events.next(ctx.editor.events.reader()) {
-            client.json.clear();
+    let mut events = EditorEventIter::new();
+    while let Some(event) = events.next(editor.events.reader()) {
+        client.json.clear();
 
-            match *event {
-                EditorEvent::Idle => {
-                    util::send_pending_did_change(client, &mut ctx.editor, &mut ctx.platform);
+        match *event {
+            EditorEvent::Idle => {
+                util::send_pending_did_change(client, editor, platform);
+                util::send_pending_semantic_tokens(client, editor, platform);
+                util::send_pending_inlay_hints(client, editor, platform);
+                util::send_pending_folding_ranges(client, editor, platform);
+                util::send_pending_pull_diagnostics(client, editor, platform);
+                if client.format_ranges_buffer_handle.is_some() {
+                    client.range_formatting(editor, platform);
                 }
-                EditorEvent::BufferTextInserts { handle, inserts } => {
-                    let buffer = ctx.editor.buffers.get(handle);
-                    if buffer.path.to_str() != ctx.editor.logger.log_file_path() {
-                        for insert in inserts.as_slice(ctx.editor.events.reader()) {
-                            let text = insert.text(ctx.editor.events.reader());
-                            let range = BufferRange::between(insert.range.from, insert.range.from);
-                            client.versioned_buffers.add_edit(handle, range, text);
+                if client.on_type_format_buffer_handle.is_some() {
+                    client.on_type_formatting(editor, platform);
+                }
+                client.format_on_save(editor, platform);
+                if let Some((buffer_handle, position)) = main_cursor {
+                    if let Some(path) = handled_buffer_path(client, editor, buffer_handle) {
+                        if lsp.is_provider(client, path, ClientCapability::DocumentHighlight) {
+                            client.document_highlight(editor, platform, buffer_handle, position);
                         }
                     }
                 }
-                EditorEvent::BufferRangeDeletes { handle, deletes } => {
-                    let buffer = ctx.editor.buffers.get(handle);
-                    if buffer.path.to_str() != ctx.editor.logger.log_file_path() {
-                        for &range in deletes.as_slice(ctx.editor.events.reader()) {
-                            client.versioned_buffers.add_edit(handle, range, "");
+            }
+            EditorEvent::BufferTextInserts { handle, inserts } => {
+                if let Some(path) = handled_buffer_path(client, editor, handle) {
+                    let is_on_type_formatting_provider =
+                        matches!(editor.mode.kind(), ModeKind::Insert)
+                            && lsp.is_provider(client, path, ClientCapability::OnTypeFormatting);
+                    for insert in inserts.as_slice(editor.events.reader()) {
+                        let text = insert.text(editor.events.reader());
+                        let range = BufferRange::between(insert.range.from, insert.range.from);
+                        client.versioned_buffers.add_edit(handle, range, text);
+
+                        if client.on_type_format_buffer_handle == Some(handle) {
+                            for (position, _) in &mut client.on_type_format_positions {
+                                *position = position.insert(insert.range);
+                            }
                         }
+                        if is_on_type_formatting_provider
+                            && client.is_on_type_formatting_trigger(text)
+                        {
+                            if client.on_type_format_buffer_handle != Some(handle) {
+                                client.on_type_format_positions.clear();
+                                client.on_type_format_buffer_handle = Some(handle);
+                            }
+                            if let Some(c) = text.chars().next() {
+                                client.on_type_format_positions.push((insert.range.to, c));
+                            }
+                        }
+                    }
+                    client
+                        .on_type_format_positions
+                        .sort_unstable_by_key(|&(position, _)| position);
+                    client.document_highlights.on_buffer_edit(handle);
+                    client.diagnostics.on_buffer_edit(handle);
+                    if lsp.is_provider(client, path, ClientCapability::SemanticTokens) {
+                        client.semantic_tokens.on_buffer_edit(handle);
+                    }
+                    if lsp.is_provider(client, path, ClientCapability::InlayHints) {
+                        client.inlay_hints.on_buffer_edit(handle);
+                    }
+                    if lsp.is_provider(client, path, ClientCapability::FoldingRanges) {
+                        client.folding_ranges.on_buffer_edit(handle);
                     }
                 }
-                EditorEvent::BufferRead { handle } => {
-                    let buffer = ctx.editor.buffers.get(handle);
-                    if buffer.path.to_str() != ctx.editor.logger.log_file_path() {
-                        client.versioned_buffers.dispose(handle);
-                        util::send_did_open(
-                            client,
-                            &ctx.editor.buffers,
-                            &mut ctx.platform,
-                            handle,
-                            &mut ctx.editor.logger,
-                        );
+            }
+            EditorEvent::BufferRangeDeletes { handle, deletes } => {
+                if let Some(path) = handled_buffer_path(client, editor, handle) {
+                    for &range in deletes.as_slice(editor.events.reader()) {
+                        client.versioned_buffers.add_edit(handle, range, "");
+
+                        if client.on_type_format_buffer_handle == Some(handle) {
+                            for (position, _) in &mut client.on_type_format_positions {
+                                *position = position.delete(range);
+                            }
+                        }
+                    }
+                    client.document_highlights.on_buffer_edit(handle);
+                    client.diagnostics.on_buffer_edit(handle);
+                    if lsp.is_provider(client, path, ClientCapability::SemanticTokens) {
+                        client.semantic_tokens.on_buffer_edit(handle);
+                    }
+                    if lsp.is_provider(client, path, ClientCapability::InlayHints) {
+                        client.inlay_hints.on_buffer_edit(handle);
+                    }
+                    if lsp.is_provider(client, path, ClientCapability::FoldingRanges) {
+                        client.folding_ranges.on_buffer_edit(handle);
                     }
                 }
-                EditorEvent::BufferWrite { handle, .. } => {
-                    let buffer = ctx.editor.buffers.get(handle);
-                    if buffer.path.to_str() != ctx.editor.logger.log_file_path() {
-                        util::send_pending_did_change(client, &mut ctx.editor, &mut ctx.platform);
-                        util::send_did_save(client, &mut ctx.editor, &mut ctx.platform, handle);
+            }
+            EditorEvent::BufferRead { handle } => {
+                if let Some(path) = handled_buffer_path(client, editor, handle) {
+                    let is_semantic_tokens_provider =
+                        lsp.is_provider(client, path, ClientCapability::SemanticTokens);
+                    let is_inlay_hints_provider =
+                        lsp.is_provider(client, path, ClientCapability::InlayHints);
+                    let is_folding_ranges_provider =
+                        lsp.is_provider(client, path, ClientCapability::FoldingRanges);
+
+                    client.versioned_buffers.dispose(handle);
+                    util::send_did_open(
+                        client,
+                        &editor.buffers,
+                        platform,
+                        handle,
+                        &mut editor.logger,
+                    );
+                    client.semantic_tokens.on_close_buffer(handle);
+                    if is_semantic_tokens_provider {
+                        client.semantic_tokens(editor, platform, handle);
+                    }
+                    client.inlay_hints.on_close_buffer(handle);
+                    if is_inlay_hints_provider {
+                        client.inlay_hints(editor, platform, handle);
                     }
+                    client.folding_ranges.on_close_buffer(handle);
+                    if is_folding_ranges_provider {
+                        client.folding_ranges(editor, platform, handle);
+                    }
+                    client
+                        .diagnostics
+                        .get_buffer_diagnostics(handle)
+                        .result_id
+                        .clear();
+                    client.pull_diagnostics(editor, platform, handle);
+                }
+            }
+            EditorEvent::BufferWrite { handle, .. } => {
+                if handled_buffer_path(client, editor, handle).is_some() {
+                    util::send_pending_did_change(client, editor, platform);
+                    util::send_did_save(client, editor, platform, handle);
                 }
-                EditorEvent::BufferClose { handle } => {
-                    let buffer = ctx.editor.buffers.get(handle);
-                    if buffer.path.to_str() != ctx.editor.logger.log_file_path() {
-                        client.versioned_buffers.dispose(handle);
-                        client.diagnostics.on_close_buffer(handle);
-                        util::send_pending_did_change(client, &mut ctx.editor, &mut ctx.platform);
-                        util::send_did_close(client, &mut ctx.editor, &mut ctx.platform, handle);
+            }
+            EditorEvent::BufferClose { handle } => {
+                if handled_buffer_path(client, editor, handle).is_some() {
+                    client.versioned_buffers.dispose(handle);
+                    client.diagnostics.on_close_buffer(handle);
+                    client.semantic_tokens.on_close_buffer(handle);
+                    client.inlay_hints.on_close_buffer(handle);
+                    client.folding_ranges.on_close_buffer(handle);
+                    client.document_highlights.on_close_buffer(handle);
+                    client
+                        .format_on_save_buffer_handles
+                        .retain(|&h| h != handle);
+                    if client.on_type_format_buffer_handle == Some(handle) {
+                        client.on_type_format_positions.clear();
+                        client.on_type_format_buffer_handle = None;
                     }
+                    util::send_pending_did_change(client, editor, platform);
+                    util::send_did_close(client, editor, platform, handle);
                 }
-                EditorEvent::FixCursors { .. } => (),
-                EditorEvent::BufferBreakpointsChanged { .. } => (),
             }
+            EditorEvent::FixCursors { .. } => (),
+            EditorEvent::BufferBreakpointsChanged { .. } => (),
+        }
+    }
+
+    if client.on_type_format_buffer_handle.is_some() {
+        client.on_type_formatting(editor, platform);
+    }
+}
+
+fn on_buffer_save(
+    plugin_handle: PluginHandle,
+    ctx: &mut EditorContext,
+    buffer_handle: BufferHandle,
+) -> bool {
+    let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+    let buffer_path = match ctx.editor.buffers.get(buffer_handle).path.to_str() {
+        Some(path) => path,
+        None => return false,
+    };
+    if !lsp
+        .format_on_save_globs
+        .iter()
+        .any(|g| g.matches(buffer_path))
+    {
+        return false;
+    }
+
+    let mut client = match lsp.find_client(|c| {
+        c.handles_path(buffer_path) && c.has_capability(ClientCapability::Formatting)
+    }) {
+        Some(client) => client,
+        None => return false,
+    };
+
+    // the buffer is only saved after the server responds with the formatting edits
+    if !client
+        .format_on_save_buffer_handles
+        .contains(&buffer_handle)
+    {
+        client.format_on_save_buffer_handles.push(buffer_handle);
+    }
+    client.format_on_save(&mut ctx.editor, &mut ctx.platform);
+    lsp.release(client);
+    true
+}
+
+fn on_flush_saves(plugin_handle: PluginHandle, ctx: &mut EditorContext) {
+    let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
+    for entry in &mut lsp.entries {
+        if let ClientEntry::Occupied(client) = entry {
+            client.save_without_formatting(&mut ctx.editor);
         }
     }
 }
@@ -422,7 +675,7 @@ fn on_process_output(
             }
             ServerEvent::Request(request) => {
                 let request_id = request.id.clone();
-                match client_event_handler::on_request(client, ctx, request) {
+                match client_event_handler::on_request(client, ctx, plugin_handle, request) {
                     Ok(value) => client.respond(
                         &mut ctx.platform,
                         request_id,
@@ -476,12 +729,43 @@ fn on_process_output(
 }
 
 fn on_process_exit(plugin_handle: PluginHandle, ctx: &mut EditorContext, client_index: u32) {
+    let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
     for buffer in ctx.editor.buffers.iter_mut() {
-        let mut lints = buffer.lints.mut_guard(plugin_handle);
-        lints.clear();
+        buffer
+            .lints
+            .source_mut_guard(plugin_handle, client_index)
+            .clear();
+
+        // only clear what this server provided as the others may still be serving the buffer
+        let client = match &lsp.entries[client_index as usize] {
+            ClientEntry::Occupied(client) => client,
+            _ => continue,
+        };
+        let path = match buffer.path.to_str() {
+            Some(path) => path,
+            None => continue,
+        };
+        let provides = |capability| lsp.is_provider(client, path, capability);
+        let semantic_tokens = provides(ClientCapability::SemanticTokens);
+        let inlay_hints = provides(ClientCapability::InlayHints);
+        let folding_ranges = provides(ClientCapability::FoldingRanges);
+        let document_highlights = provides(ClientCapability::DocumentHighlight);
+
+        if semantic_tokens {
+            buffer.semantic_tokens_mut_guard().clear();
+        }
+        if inlay_hints {
+            buffer.annotations.mut_guard(plugin_handle).clear();
+        }
+        if folding_ranges {
+            buffer.fold_ranges.mut_guard(plugin_handle).clear();
+        }
+        if document_highlights {
+            buffer.range_highlights.mut_guard(plugin_handle).clear();
+        }
     }
+    ctx.editor.progresses.clear(plugin_handle, client_index);
 
-    let lsp = ctx.plugins.get_as::<LspPlugin>(plugin_handle);
     if let ClientEntry::Occupied(client) = &mut lsp.entries[client_index as usize] {
         {
             let mut log_writer = ctx.editor.logger.write(LogKind::Diagnostic);
@@ -489,6 +773,11 @@ fn on_process_exit(plugin_handle: PluginHandle, ctx: &mut EditorContext, client_
             log_writer.str("lsp server stopped");
         }
 
+        client.initialized = false;
+        client.save_without_formatting(&mut ctx.editor);
+        client.on_type_format_positions.clear();
+        client.on_type_format_buffer_handle = None;
+
         let client_handle = client.handle();
         for recipe in &mut lsp.recipes {
             if recipe.running_client == Some(client_handle) {
@@ -496,6 +785,18 @@ fn on_process_exit(plugin_handle: PluginHandle, ctx: &mut EditorContext, client_
             }
         }
     }
+
+    // the other servers take over the semantic tokens and inlay hints this one provided
+    for i in 0..lsp.entries.len() {
+        let mut client = match lsp.acquire(ClientHandle(i as _)) {
+            Some(client) => client,
+            None => continue,
+        };
+        if client.initialized {
+            lsp.refresh_provided_buffers(&mut client, &ctx.editor.buffers);
+        }
+        lsp.release(client);
+    }
 }
 
 fn on_completion(
@@ -511,6 +812,17 @@ fn on_completion(
         };
         client.json.clear();
 
+        let buffer_path = ctx
+            .editor
+            .buffers
+            .get(completion_ctx.buffer_handle)
+            .path
+            .to_str();
+        match buffer_path {
+            Some(path) if client.initialized && client.handles_path(path) => (),
+            _ => continue,
+        }
+
         let mut should_complete = completion_ctx.completion_requested;
 
         if !should_complete {
@@ -537,7 +849,7 @@ fn on_completion(
             }
         }
 
-        if should_complete {
+        if should_complete && client.has_capability(ClientCapability::Completion) {
             client.completion(
                 &mut ctx.editor,
                 &mut ctx.platform,
@@ -2,14 +2,13 @@ use std::{
     convert::From,
     fmt, fs, io,
     ops::Range,
-    path::{Component, Path, Prefix},
+    path::{Component, Path, PathBuf, Prefix},
 };
 
 use pepper::{
-    buffer::{BufferHandle, BufferProperties},
+    buffer::{BufferHandle, BufferLintSeverity, BufferProperties},
     buffer_position::{BufferPosition, BufferRange},
     editor::Editor,
-    editor_utils::LogKind,
     glob::InvalidGlobError,
     platform::{Platform, PlatformProcessHandle, PlatformRequest},
 };
@@ -607,88 +606,256 @@ impl<'json> FromJson<'json> for WorkspaceEditChange {
     }
 }
 
+// changes that were already applied and that need to be reverted if a later one fails
+enum AppliedWorkspaceEditChange<'a> {
+    BufferEdit(BufferHandle),
+    FileEdit {
+        path: &'a Path,
+        previous_content: Option<Vec<u8>>,
+    },
+    CreateFile {
+        path: &'a Path,
+        previous_content: Option<Vec<u8>>,
+    },
+    // an overwritten file is kept at `backup_path` until every change is applied
+    RenameFile {
+        old_path: &'a Path,
+        new_path: &'a Path,
+        backup_path: Option<PathBuf>,
+        moved_buffer_handles: Vec<BufferHandle>,
+    },
+    // deleted directories can not be restored
+    DeleteFile {
+        path: &'a Path,
+        previous_content: Option<Vec<u8>>,
+        // the buffers of the deleted files and whether they needed saving before
+        buffers_needs_save: Vec<(BufferHandle, bool)>,
+    },
+}
+impl<'a> AppliedWorkspaceEditChange<'a> {
+    fn undo(self, editor: &mut Editor) {
+        fn restore_file(path: &Path, previous_content: Option<Vec<u8>>) {
+            let _ = match previous_content {
+                Some(content) => fs::write(path, content),
+                None => fs::remove_file(path),
+            };
+        }
+
+        match self {
+            Self::BufferEdit(buffer_handle) => {
+                let _ = editor
+                    .buffers
+                    .get_mut(buffer_handle)
+                    .undo(&mut editor.word_database, &mut editor.events.writer());
+            }
+            Self::FileEdit {
+                path,
+                previous_content,
+            } => restore_file(path, previous_content),
+            Self::CreateFile {
+                path,
+                previous_content,
+            } => restore_file(path, previous_content),
+            Self::RenameFile {
+                old_path,
+                new_path,
+                backup_path,
+                moved_buffer_handles,
+            } => {
+                let _ = fs::rename(new_path, old_path);
+                if let Some(backup_path) = backup_path {
+                    let _ = fs::rename(backup_path, new_path);
+                }
+                move_buffer_paths(editor, &moved_buffer_handles, new_path, old_path);
+            }
+            Self::DeleteFile {
+                path,
+                previous_content,
+                buffers_needs_save,
+            } => {
+                if let Some(content) = previous_content {
+                    let _ = fs::write(path, content);
+                }
+                for (buffer_handle, needs_save) in buffers_needs_save {
+                    editor
+                        .buffers
+                        .get_mut(buffer_handle)
+                        .set_needs_save(needs_save);
+                }
+            }
+        }
+    }
+
+    fn finish(self) {
+        if let Self::RenameFile {
+            backup_path: Some(backup_path),
+            ..
+        } = self
+        {
+            let _ = fs::remove_file(backup_path);
+        }
+    }
+}
+
+// the buffers of `path` or of the files inside it if it's a directory
+fn buffers_inside(editor: &Editor, path: &Path) -> Vec<BufferHandle> {
+    let path = path.strip_prefix(&editor.current_directory).unwrap_or(path);
+    editor
+        .buffers
+        .iter()
+        .filter(|b| {
+            let buffer_path = b.path.as_path();
+            let buffer_path = buffer_path
+                .strip_prefix(&editor.current_directory)
+                .unwrap_or(buffer_path);
+            buffer_path.starts_with(path)
+        })
+        .map(|b| b.handle())
+        .collect()
+}
+
+// makes buffers inside `from` follow it after it was renamed to `to`
+fn move_buffer_paths(editor: &mut Editor, buffer_handles: &[BufferHandle], from: &Path, to: &Path) {
+    let from = from.strip_prefix(&editor.current_directory).unwrap_or(from);
+    let to = to.strip_prefix(&editor.current_directory).unwrap_or(to);
+    for &buffer_handle in buffer_handles {
+        let buffer = editor.buffers.get_mut(buffer_handle);
+        let buffer_path = buffer.path.as_path();
+        let buffer_path = buffer_path
+            .strip_prefix(&editor.current_directory)
+            .unwrap_or(buffer_path);
+        if let Ok(inner_path) = buffer_path.strip_prefix(from) {
+            let path = to.join(inner_path);
+            buffer.set_path(&path);
+            buffer.refresh_syntax(&editor.syntaxes);
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct WorkspaceEdit {
     changes: JsonObject,
     document_changes: JsonArray,
 }
 impl WorkspaceEdit {
+    // applies all changes or none of them (as in the `undo` failure handling)
+    // in which case it returns the reason of the failure
     pub fn apply(
         &self,
         editor: &mut Editor,
         temp_edits: &mut Vec<(BufferRange, BufferRange)>,
         root: &Path,
         json: &Json,
-    ) {
-        for (uri, text_edits) in self.changes.clone().members(json) {
-            let path = match Uri::parse(&root, uri) {
-                Ok(Uri::Path(path)) => path,
-                Err(_) => return,
-            };
-            let text_edits = match text_edits {
-                JsonValue::Array(array) => array,
-                _ => return,
-            };
+    ) -> Result<(), String> {
+        let mut applied_changes = Vec::new();
+        let result = self.apply_changes(editor, temp_edits, root, json, &mut applied_changes);
+        if result.is_err() {
+            for change in applied_changes.into_iter().rev() {
+                change.undo(editor);
+            }
+        } else {
+            for change in applied_changes {
+                change.finish();
+            }
+        }
+        result
+    }
+
+    fn apply_changes<'a>(
+        &self,
+        editor: &mut Editor,
+        temp_edits: &mut Vec<(BufferRange, BufferRange)>,
+        root: &'a Path,
+        json: &'a Json,
+        applied_changes: &mut Vec<AppliedWorkspaceEditChange<'a>>,
+    ) -> Result<(), String> {
+        fn parse_path<'a>(root: &'a Path, uri: &'a str) -> Result<&'a Path, String> {
+            match Uri::parse(root, uri) {
+                Ok(Uri::Path(path)) => Ok(path),
+                Err(_) => Err(format!("invalid uri '{}'", uri)),
+            }
+        }
+
+        fn apply_text_edits<'a>(
+            editor: &mut Editor,
+            temp_edits: &mut Vec<(BufferRange, BufferRange)>,
+            path: &'a Path,
+            text_edits: JsonArray,
+            json: &'a Json,
+            applied_changes: &mut Vec<AppliedWorkspaceEditChange<'a>>,
+        ) -> Result<(), String> {
+            if !text_edits
+                .clone()
+                .elements(json)
+                .all(|e| TextEdit::from_json(e, json).is_ok())
+            {
+                return Err(format!("invalid text edits for file {:?}", path));
+            }
 
             let mut buffer_properties = BufferProperties::log();
             buffer_properties.saving_enabled = true;
             let result = editor.buffer_handle_from_path(path, buffer_properties);
 
-            TextEdit::apply_edits(editor, result.buffer_handle, temp_edits, text_edits, json);
-
-            if result.is_new {
-                let _ = editor
-                    .buffers
-                    .get_mut(result.buffer_handle)
-                    .write_to_file(None, editor.events.writer());
+            if !result.is_new {
+                // only edits that made it into the history can be undone
+                let buffer = editor.buffers.get(result.buffer_handle);
+                let history_state = buffer.history_current_state();
+                TextEdit::apply_edits(editor, result.buffer_handle, temp_edits, text_edits, json);
+                let buffer = editor.buffers.get(result.buffer_handle);
+                if buffer.history_current_state() != history_state {
+                    applied_changes
+                        .push(AppliedWorkspaceEditChange::BufferEdit(result.buffer_handle));
+                }
+                return Ok(());
+            }
 
-                editor
-                    .buffers
-                    .defer_remove(result.buffer_handle, editor.events.writer());
+            let previous_content = fs::read(path).ok();
+            TextEdit::apply_edits(editor, result.buffer_handle, temp_edits, text_edits, json);
+            let write_result = editor.buffers.get_mut(result.buffer_handle).write_to_file(
+                None,
+                false,
+                editor.events.writer(),
+            );
+            editor
+                .buffers
+                .defer_remove(result.buffer_handle, editor.events.writer());
+
+            applied_changes.push(AppliedWorkspaceEditChange::FileEdit {
+                path,
+                previous_content,
+            });
+            match write_result {
+                Ok(()) => Ok(()),
+                Err(_) => Err(format!("could not write to file {:?}", path)),
             }
         }
 
+        for (uri, text_edits) in self.changes.clone().members(json) {
+            let path = parse_path(root, uri)?;
+            let text_edits = match text_edits {
+                JsonValue::Array(array) => array,
+                _ => return Err(format!("invalid text edits for file {:?}", path)),
+            };
+            apply_text_edits(editor, temp_edits, path, text_edits, json, applied_changes)?;
+        }
+
         for change in self.document_changes.clone().elements(json) {
             let change = match WorkspaceEditChange::from_json(change, json) {
                 Ok(change) => change,
-                Err(_) => return,
+                Err(_) => return Err("invalid document change".into()),
             };
             match change {
                 WorkspaceEditChange::DocumentEdit(edit) => {
-                    let path = match Uri::parse(&root, edit.uri.as_str(json)) {
-                        Ok(Uri::Path(path)) => path,
-                        Err(_) => return,
-                    };
-
-                    let mut buffer_properties = BufferProperties::log();
-                    buffer_properties.saving_enabled = true;
-                    let result = editor.buffer_handle_from_path(path, buffer_properties);
-
-                    TextEdit::apply_edits(
-                        editor,
-                        result.buffer_handle,
-                        temp_edits,
-                        edit.edits,
-                        json,
-                    );
-
-                    if result.is_new {
-                        let _ = editor
-                            .buffers
-                            .get_mut(result.buffer_handle)
-                            .write_to_file(None, editor.events.writer());
-
-                        editor
-                            .buffers
-                            .defer_remove(result.buffer_handle, editor.events.writer());
-                    }
+                    let path = parse_path(root, edit.uri.as_str(json))?;
+                    apply_text_edits(editor, temp_edits, path, edit.edits, json, applied_changes)?;
                 }
                 WorkspaceEditChange::CreateFile(op) => {
-                    let path = match Uri::parse(&root, op.uri.as_str(json)) {
-                        Ok(Uri::Path(path)) => path,
-                        Err(_) => return,
-                    };
+                    let path = parse_path(root, op.uri.as_str(json))?;
+                    if path.exists() && op.ignore_if_exists && !op.overwrite {
+                        continue;
+                    }
 
+                    let previous_content = fs::read(path).ok();
                     let mut open_options = fs::OpenOptions::new();
                     open_options.write(true);
                     if op.overwrite {
@@ -696,56 +863,98 @@ impl WorkspaceEdit {
                     } else {
                         open_options.create_new(true);
                     }
-                    if open_options.open(path).is_err() && !op.ignore_if_exists {
-                        editor
-                            .logger
-                            .write(LogKind::Error)
-                            .fmt(format_args!("could not create file {:?}", path));
+                    if open_options.open(path).is_err() {
+                        return Err(format!("could not create file {:?}", path));
                     }
+
+                    applied_changes.push(AppliedWorkspaceEditChange::CreateFile {
+                        path,
+                        previous_content,
+                    });
                 }
                 WorkspaceEditChange::RenameFile(op) => {
-                    let old_path = match Uri::parse(&root, op.old_uri.as_str(json)) {
-                        Ok(Uri::Path(path)) => path,
-                        Err(_) => return,
-                    };
-                    let new_path = match Uri::parse(&root, op.new_uri.as_str(json)) {
-                        Ok(Uri::Path(path)) => path,
-                        Err(_) => return,
-                    };
+                    let old_path = parse_path(root, op.old_uri.as_str(json))?;
+                    let new_path = parse_path(root, op.new_uri.as_str(json))?;
+                    if new_path.exists() && !op.overwrite {
+                        if op.ignore_if_exists {
+                            continue;
+                        }
+                        return Err(format!("file {:?} already exists", new_path));
+                    }
+
+                    let mut backup_path = None;
+                    if new_path.is_file() && old_path != new_path {
+                        let mut path = new_path.as_os_str().to_owned();
+                        path.push(".pepper-backup");
+                        let path = PathBuf::from(path);
+                        if fs::rename(new_path, &path).is_err() {
+                            return Err(format!("could not overwrite file {:?}", new_path));
+                        }
+                        backup_path = Some(path);
+                    }
 
-                    if op.overwrite || !new_path.exists() || !op.ignore_if_exists {
-                        if fs::rename(old_path, new_path).is_err() && !op.ignore_if_exists {
-                            editor.logger.write(LogKind::Error).fmt(format_args!(
-                                "could not rename file {:?} to {:?}",
-                                old_path, new_path
-                            ));
+                    if fs::rename(old_path, new_path).is_err() {
+                        if let Some(backup_path) = backup_path {
+                            let _ = fs::rename(backup_path, new_path);
                         }
+                        return Err(format!(
+                            "could not rename file {:?} to {:?}",
+                            old_path, new_path
+                        ));
                     }
+
+                    // open buffers follow their files so that saving them does not bring back the old ones
+                    let moved_buffer_handles = buffers_inside(editor, old_path);
+                    move_buffer_paths(editor, &moved_buffer_handles, old_path, new_path);
+
+                    applied_changes.push(AppliedWorkspaceEditChange::RenameFile {
+                        old_path,
+                        new_path,
+                        backup_path,
+                        moved_buffer_handles,
+                    });
                 }
                 WorkspaceEditChange::DeleteFile(op) => {
-                    let path = match Uri::parse(&root, op.uri.as_str(json)) {
-                        Ok(Uri::Path(path)) => path,
-                        Err(_) => return,
-                    };
-
-                    if op.recursive {
-                        if fs::remove_dir_all(path).is_err() && !op.ignore_if_not_exists {
-                            editor
-                                .logger
-                                .write(LogKind::Error)
-                                .fmt(format_args!("could not delete directory {:?}", path));
+                    let path = parse_path(root, op.uri.as_str(json))?;
+                    if !path.exists() {
+                        if op.ignore_if_not_exists {
+                            continue;
                         }
+                        return Err(format!("file {:?} does not exist", path));
+                    }
+
+                    let result = if !path.is_dir() {
+                        let previous_content = fs::read(path).ok();
+                        fs::remove_file(path).map(|()| previous_content)
+                    } else if op.recursive {
+                        fs::remove_dir_all(path).map(|()| None)
                     } else {
-                        if fs::remove_file(path).is_err() && !op.ignore_if_not_exists {
-                            editor
-                                .logger
-                                .write(LogKind::Error)
-                                .fmt(format_args!("could not delete file {:?}", path));
-                        }
+                        fs::remove_dir(path).map(|()| None)
+                    };
+
+                    let previous_content = match result {
+                        Ok(previous_content) => previous_content,
+                        Err(_) => return Err(format!("could not delete {:?}", path)),
+                    };
+
+                    // open buffers of deleted files are kept but need to be saved to exist again
+                    let mut buffers_needs_save = Vec::new();
+                    for buffer_handle in buffers_inside(editor, path) {
+                        let buffer = editor.buffers.get_mut(buffer_handle);
+                        buffers_needs_save.push((buffer_handle, buffer.needs_save()));
+                        buffer.set_needs_save(true);
                     }
+
+                    applied_changes.push(AppliedWorkspaceEditChange::DeleteFile {
+                        path,
+                        previous_content,
+                        buffers_needs_save,
+                    });
                 }
             }
         }
+
+        Ok(())
     }
 }
 impl<'json> FromJson<'json> for WorkspaceEdit {
@@ -766,10 +975,61 @@ impl<'json> FromJson<'json> for WorkspaceEdit {
     }
 }
 
+// diagnostics without a severity are treated as errors
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticSeverity {
+    #[default]
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+impl DiagnosticSeverity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Information => "info",
+            Self::Hint => "hint",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "info" => Some(Self::Information),
+            "hint" => Some(Self::Hint),
+            _ => None,
+        }
+    }
+
+    pub fn to_lint_severity(self) -> BufferLintSeverity {
+        match self {
+            Self::Error => BufferLintSeverity::Error,
+            Self::Warning => BufferLintSeverity::Warning,
+            Self::Information => BufferLintSeverity::Info,
+            Self::Hint => BufferLintSeverity::Hint,
+        }
+    }
+}
+impl<'json> FromJson<'json> for DiagnosticSeverity {
+    fn from_json(value: JsonValue, _: &'json Json) -> Result<Self, JsonConvertError> {
+        match value {
+            JsonValue::Integer(1) => Ok(Self::Error),
+            JsonValue::Integer(2) => Ok(Self::Warning),
+            JsonValue::Integer(3) => Ok(Self::Information),
+            JsonValue::Integer(4) => Ok(Self::Hint),
+            _ => Err(JsonConvertError),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct DocumentDiagnostic {
     pub message: JsonString,
     pub range: DocumentRange,
+    pub severity: DiagnosticSeverity,
     pub data: JsonValue,
 }
 impl DocumentDiagnostic {
@@ -806,6 +1066,7 @@ impl<'json> FromJson<'json> for DocumentDiagnostic {
             match key {
                 "message" => this.message = JsonString::from_json(value, json)?,
                 "range" => this.range = DocumentRange::from_json(value, json)?,
+                "severity" => this.severity = DiagnosticSeverity::from_json(value, json)?,
                 "data" => this.data = value,
                 _ => (),
             }
@@ -814,10 +1075,63 @@ impl<'json> FromJson<'json> for DocumentDiagnostic {
     }
 }
 
+// a report is either `full` with all the `items` or `unchanged` since the last `result_id`
+// `uri` is only set for reports that come from a workspace diagnostic request
+#[derive(Default)]
+pub struct DocumentDiagnosticReport {
+    pub uri: JsonString,
+    pub full: bool,
+    pub result_id: JsonString,
+    pub items: JsonArray,
+}
+impl<'json> FromJson<'json> for DocumentDiagnosticReport {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "uri" => this.uri = JsonString::from_json(value, json)?,
+                "kind" => this.full = <&str>::from_json(value, json)? == "full",
+                "resultId" => this.result_id = JsonString::from_json(value, json)?,
+                "items" => this.items = JsonArray::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
+#[derive(Default)]
+pub struct DocumentCommand {
+    pub command: JsonString,
+    pub arguments: JsonArray,
+}
+impl<'json> FromJson<'json> for DocumentCommand {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "command" => this.command = JsonString::from_json(value, json)?,
+                "arguments" => this.arguments = FromJson::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
 #[derive(Default)]
 pub struct DocumentCodeAction {
     pub title: JsonString,
     pub edit: WorkspaceEdit,
+    pub command: Option<DocumentCommand>,
     pub disabled: bool,
 }
 impl<'json> FromJson<'json> for DocumentCodeAction {
@@ -827,10 +1141,19 @@ impl<'json> FromJson<'json> for DocumentCodeAction {
             _ => return Err(JsonConvertError),
         };
         let mut this = Self::default();
-        for (key, value) in value.members(json) {
+        for (key, member) in value.clone().members(json) {
             match key {
-                "title" => this.title = JsonString::from_json(value, json)?,
-                "edit" => this.edit = WorkspaceEdit::from_json(value, json)?,
+                "title" => this.title = JsonString::from_json(member, json)?,
+                "edit" => this.edit = WorkspaceEdit::from_json(member, json)?,
+                // a code action can also be just a command
+                "command" => {
+                    this.command = match member {
+                        JsonValue::String(_) => {
+                            Some(DocumentCommand::from_json(value.clone().into(), json)?)
+                        }
+                        member => FromJson::from_json(member, json)?,
+                    }
+                }
                 "disabled" => this.disabled = true,
                 _ => (),
             }
@@ -872,9 +1195,38 @@ impl<'json> FromJson<'json> for DocumentSymbolInformation {
     }
 }
 
+// both `CallHierarchyItem` and `TypeHierarchyItem`
+#[derive(Default)]
+pub struct DocumentHierarchyItem {
+    pub name: JsonString,
+    pub detail: Option<JsonString>,
+    pub uri: JsonString,
+    pub selection_range: DocumentRange,
+}
+impl<'json> FromJson<'json> for DocumentHierarchyItem {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "name" => this.name = JsonString::from_json(value, json)?,
+                "detail" => this.detail = FromJson::from_json(value, json)?,
+                "uri" => this.uri = JsonString::from_json(value, json)?,
+                "selectionRange" => this.selection_range = DocumentRange::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
 #[derive(Default)]
 pub struct DocumentCompletionItem {
     pub text: JsonString,
+    pub snippet: Option<JsonString>,
 }
 impl<'json> FromJson<'json> for DocumentCompletionItem {
     fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
@@ -882,21 +1234,175 @@ impl<'json> FromJson<'json> for DocumentCompletionItem {
             JsonValue::Object(value) => value,
             _ => return Err(JsonConvertError),
         };
-        let mut this = Self::default();
+        let mut label = JsonString::default();
+        let mut insert_text = None;
+        let mut new_text = None;
+        let mut is_snippet = false;
         for (key, value) in value.members(json) {
             match key {
-                "label" => this.text = JsonString::from_json(value, json)?,
-                "insertText" => {
-                    this.text = JsonString::from_json(value, json)?;
-                    break;
+                "label" => label = JsonString::from_json(value, json)?,
+                "insertText" => insert_text = Some(JsonString::from_json(value, json)?),
+                "textEdit" => {
+                    if let JsonValue::String(text) = value.get("newText", json) {
+                        new_text = Some(text);
+                    }
                 }
+                // 2 means the insert text is a snippet
+                "insertTextFormat" => is_snippet = matches!(value, JsonValue::Integer(2)),
                 _ => (),
             }
         }
+
+        let mut this = Self::default();
+        if is_snippet {
+            this.text = label;
+            this.snippet = new_text.or(insert_text);
+        } else {
+            this.text = insert_text.unwrap_or(label);
+        }
         Ok(this)
     }
 }
 
+#[derive(Default)]
+pub struct DocumentInlayHint {
+    pub position: DocumentPosition,
+    pub label: JsonValue,
+    pub padding_left: bool,
+    pub padding_right: bool,
+}
+impl DocumentInlayHint {
+    // the label is either a string or an array of label parts
+    pub fn write_text(&self, text: &mut String, json: &Json) {
+        if self.padding_left {
+            text.push(' ');
+        }
+        match self.label.clone() {
+            JsonValue::String(label) => text.push_str(label.as_str(json)),
+            JsonValue::Array(parts) => {
+                for part in parts.elements(json) {
+                    if let JsonValue::String(value) = part.get("value", json) {
+                        text.push_str(value.as_str(json));
+                    }
+                }
+            }
+            _ => (),
+        }
+        if self.padding_right {
+            text.push(' ');
+        }
+    }
+}
+impl<'json> FromJson<'json> for DocumentInlayHint {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "position" => this.position = DocumentPosition::from_json(value, json)?,
+                "label" => this.label = value,
+                "paddingLeft" => this.padding_left = bool::from_json(value, json)?,
+                "paddingRight" => this.padding_right = bool::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
+#[derive(Default)]
+pub struct DocumentFoldingRange {
+    pub start_line: u32,
+    pub end_line: u32,
+}
+impl<'json> FromJson<'json> for DocumentFoldingRange {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "startLine" => this.start_line = u32::from_json(value, json)?,
+                "endLine" => this.end_line = u32::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
+#[derive(Default)]
+pub struct DocumentSemanticTokensEdit {
+    pub start: u32,
+    pub delete_count: u32,
+    pub data: JsonArray,
+}
+impl<'json> FromJson<'json> for DocumentSemanticTokensEdit {
+    fn from_json(value: JsonValue, json: &'json Json) -> Result<Self, JsonConvertError> {
+        let value = match value {
+            JsonValue::Object(value) => value,
+            _ => return Err(JsonConvertError),
+        };
+        let mut this = Self::default();
+        for (key, value) in value.members(json) {
+            match key {
+                "start" => this.start = u32::from_json(value, json)?,
+                "deleteCount" => this.delete_count = u32::from_json(value, json)?,
+                "data" => this.data = JsonArray::from_json(value, json)?,
+                _ => (),
+            }
+        }
+        Ok(this)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DocumentSemanticToken {
+    pub line: u32,
+    pub start: u32,
+    pub len: u32,
+    pub token_type: u32,
+}
+
+// decodes the relative `[delta_line, delta_start, len, token_type, token_modifiers]` encoding
+// https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_semanticTokens
+pub struct DocumentSemanticTokenIter<'a> {
+    data: std::slice::ChunksExact<'a, u32>,
+    line: u32,
+    start: u32,
+}
+impl<'a> DocumentSemanticTokenIter<'a> {
+    pub fn new(data: &'a [u32]) -> Self {
+        Self {
+            data: data.chunks_exact(5),
+            line: 0,
+            start: 0,
+        }
+    }
+}
+impl<'a> Iterator for DocumentSemanticTokenIter<'a> {
+    type Item = DocumentSemanticToken;
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.data.next()?;
+        if token[0] > 0 {
+            self.line += token[0];
+            self.start = 0;
+        }
+        self.start += token[1];
+        Some(DocumentSemanticToken {
+            line: self.line,
+            start: self.start,
+            len: token[2],
+            token_type: token[3],
+        })
+    }
+}
+
 fn try_get_content_range(buf: &[u8]) -> Option<Range<usize>> {
     fn find_pattern_end(buf: &[u8], pattern: &[u8]) -> Option<usize> {
         let len = pattern.len();
@@ -1183,4 +1689,24 @@ mod tests {
             assert_uri("c:/file.rs", "file:///c:/file.rs");
         }
     }
+
+    #[test]
+    fn decode_semantic_tokens() {
+        let data = [0, 2, 3, 1, 0, 0, 4, 2, 0, 0, 2, 1, 5, 3, 1, 0, 7, 1, 2];
+        let mut tokens = DocumentSemanticTokenIter::new(&data);
+
+        let mut assert_next = |line, start, len, token_type| {
+            let expect = Some(DocumentSemanticToken {
+                line,
+                start,
+                len,
+                token_type,
+            });
+            assert_eq!(expect, tokens.next());
+        };
+        assert_next(0, 2, 3, 1);
+        assert_next(0, 6, 2, 0);
+        assert_next(2, 1, 5, 3);
+        assert_eq!(None, tokens.next());
+    }
 }